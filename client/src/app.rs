@@ -0,0 +1,1495 @@
+use crate::{
+    channel_list::{ChannelList, ListEntry},
+    chat_log::ChatLog,
+    command, ctcp,
+    dcc::Dcc,
+    exec::Exec,
+    formatting,
+    highlight::{Highlighter, Incoming, Notification},
+    history::History,
+    last_session::{LastSession, SavedNetwork},
+    members::{self, Members},
+    script::Scripts,
+    theme::Theme,
+    whois::Whois,
+};
+use chrono::{DateTime, Local};
+use client::{
+    Lag, Prefix,
+    message::{Command, Message},
+};
+use linkify::{LinkFinder, LinkKind};
+use shared::{
+    mask,
+    mode::{ModeArgs, ModeChange},
+};
+use std::collections::{HashMap, VecDeque};
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Name of the buffer for server replies and anything that doesn't belong to a channel or query
+pub const STATUS_BUFFER: &str = "*status*";
+
+/// Name of the buffer the channels listed by `/list` are browsed in
+pub const LIST_BUFFER: &str = "*list*";
+
+/// One line shown in a buffer.
+#[derive(Debug)]
+pub struct Line {
+    pub time: DateTime<Local>,
+    pub kind: LineKind,
+    /// Who a message is from, as `<alice>`, `-alice-` (a notice) or `* alice` (an action). Shown
+    /// in its own column.
+    pub sender: Option<String>,
+    pub text: String,
+    /// Numbers of the links in the text, which `/open` takes. See [`Buffer::urls`].
+    pub urls: Vec<usize>,
+}
+
+impl Line {
+    /// The nickname in the sender
+    pub fn nick(&self) -> Option<&str> {
+        let sender = self.sender.as_deref()?;
+        Some(
+            sender
+                .trim_start_matches(['<', '-', '*', ' '])
+                .trim_end_matches(['>', '-']),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineKind {
+    /// A message from someone, shown as `<nick> text`
+    Message,
+    /// A message mentioning the user
+    Highlight,
+    /// Joins, parts, nick changes and other events
+    Event,
+    /// Server replies and client notes
+    Info,
+    Error,
+}
+
+/// Most panes the screen is split into
+const MAX_PANES: usize = 4;
+
+/// Links kept in each buffer for `/open`
+const MAX_URLS: usize = 100;
+
+/// Lines kept in each buffer unless configured otherwise
+pub const DEFAULT_SCROLLBACK: usize = 5000;
+
+/// A server the client is connected to, named after its profile.
+#[derive(Debug)]
+pub struct Network {
+    pub name: String,
+    /// Our nickname on this network
+    pub nick: String,
+    pub connected: bool,
+    /// Whether the server welcomed us since connecting
+    pub registered: bool,
+    pub chat_log: Option<ChatLog>,
+    /// Lines typed in the network's buffers
+    pub history: History,
+    pub lag: Lag,
+    /// Lines waiting to be sent because of flood protection
+    pub queued: usize,
+    /// Whether the server marked us away, with RPL_NOWAWAY
+    pub away: bool,
+    /// Our user modes without the `+`, like `iw`, from RPL_UMODEIS and MODE
+    pub user_modes: String,
+    /// Batches of messages from the server's history being received, by their reference
+    history_batches: HashMap<String, HistoryBatch>,
+    /// Replies to WHOIS being collected, by the nickname in lowercase
+    whois: HashMap<String, Whois>,
+}
+
+impl Network {
+    /// Whether we're an IRC operator, shown in the status bar
+    pub fn is_oper(&self) -> bool {
+        self.user_modes.contains(['o', 'O'])
+    }
+
+    /// Apply a MODE on ourselves, like `+w-i`
+    fn apply_user_modes(&mut self, changes: &[ModeChange]) {
+        for change in changes {
+            if !change.adding {
+                self.user_modes.retain(|m| m != change.mode);
+            } else if !self.user_modes.contains(change.mode) {
+                self.user_modes.push(change.mode);
+            }
+        }
+    }
+}
+
+/// Messages the server sends from its history in a `chathistory` batch after we join a channel,
+/// which are added above the live messages once the batch ends.
+#[derive(Debug)]
+struct HistoryBatch {
+    target: String,
+    lines: Vec<Line>,
+}
+
+/// The scrollback of a channel, private conversation (query) or the status of a network.
+#[derive(Debug)]
+pub struct Buffer {
+    /// Index of the network in [`App::networks`]
+    pub network: usize,
+    pub name: String,
+    /// The newest lines, up to the scrollback limit
+    pub lines: VecDeque<Line>,
+    /// Messages that arrived while another buffer was shown
+    pub unread: usize,
+    /// How many of the unread messages mention the user
+    pub highlights: usize,
+    /// How many lines the view is scrolled up from the bottom
+    pub scroll: usize,
+    /// The last `/search` and the index of the line it found
+    pub search: Option<(String, usize)>,
+    /// The users in a channel, as far as we know
+    pub members: Members,
+    /// The channels shown instead of lines in the list buffer
+    pub list: Option<ChannelList>,
+    /// The newest links in messages, numbered in the order they arrived
+    pub urls: VecDeque<(usize, String)>,
+    /// A channel's topic, empty if it has none, and who set it if the server said
+    pub topic: String,
+    pub topic_set_by: Option<String>,
+    /// When the newest message in the buffer arrived as we last joined the channel. The server's
+    /// history up to then was already seen, in the chat log's backlog or before reconnecting.
+    seen_before_join: Option<DateTime<Local>>,
+}
+
+impl Buffer {
+    fn new(network: usize, name: &str) -> Self {
+        Buffer {
+            network,
+            name: name.to_string(),
+            lines: VecDeque::new(),
+            unread: 0,
+            highlights: 0,
+            scroll: 0,
+            search: None,
+            members: Members::default(),
+            list: None,
+            urls: VecDeque::new(),
+            topic: String::new(),
+            topic_set_by: None,
+            seen_before_join: None,
+        }
+    }
+
+    /// Number a link and keep it, dropping the oldest one if there are too many.
+    fn add_url(&mut self, url: String) -> usize {
+        let number = self.urls.back().map_or(1, |(number, _)| number + 1);
+        self.urls.push_back((number, url));
+        if self.urls.len() > MAX_URLS {
+            self.urls.pop_front();
+        }
+        number
+    }
+
+    /// A link by its number, or the newest one if no number is given
+    pub fn url(&self, number: Option<usize>) -> Option<&str> {
+        let (_, url) = match number {
+            Some(number) => self.urls.iter().find(|(n, _)| *n == number)?,
+            None => self.urls.back()?,
+        };
+        Some(url)
+    }
+
+    /// Whether messages typed in this buffer have somewhere to go
+    pub fn is_target(&self) -> bool {
+        self.name != STATUS_BUFFER && self.name != LIST_BUFFER
+    }
+}
+
+/// A message to notify the user of, outside the client.
+#[derive(Debug)]
+pub struct Alert {
+    /// The buffer's title, which is the network and buffer name with several networks
+    pub buffer: String,
+    /// The message with its sender, as shown
+    pub line: String,
+    /// The sender's nickname
+    pub nick: String,
+    /// The message without its formatting
+    pub text: String,
+    pub notification: Notification,
+}
+
+/// State of the terminal interface.
+#[derive(Debug)]
+pub struct App {
+    pub networks: Vec<Network>,
+    /// Buffers of every network, grouped by network with its status buffer first
+    pub buffers: Vec<Buffer>,
+    /// Index of the buffer being typed in, which is the one in the focused pane
+    pub active: usize,
+    /// Buffers shown in each pane, top to bottom. There's always at least one.
+    pub panes: Vec<usize>,
+    /// Index of the focused pane in `panes`
+    pub pane: usize,
+    pub input: String,
+    /// Cursor position in `input`, in characters
+    pub cursor: usize,
+    /// Tab completion in progress
+    completion: Option<Completion>,
+    /// How many lines back Up has gone in the input history, and what was typed before that
+    history_position: Option<(usize, String)>,
+    /// Lines pasted at once, waiting for the user to send them or not
+    pub pending_paste: Option<Vec<String>>,
+    pub should_quit: bool,
+    pub dcc: Dcc,
+    pub exec: Exec,
+    pub scripts: Scripts,
+    pub highlighter: Highlighter,
+    /// Messages to notify the user of
+    pub alerts: Vec<Alert>,
+    /// Masks of users whose messages aren't shown
+    pub ignored: Vec<String>,
+    /// Show messages without their colors and other formatting
+    pub strip_formatting: bool,
+    /// Most lines kept in each buffer
+    pub scrollback: usize,
+    pub theme: Theme,
+    /// Show the users of channels beside their messages
+    pub show_nicklist: bool,
+    /// Show the topic of channels above their messages
+    pub show_topic: bool,
+    /// Lines scrolled by Page Up and Page Down, which is about a screen
+    pub page_size: usize,
+    /// When the server got the message being handled, which the lines it adds are shown at
+    message_time: Option<DateTime<Local>>,
+}
+
+/// State kept between presses of Tab, which cycle through the candidates.
+#[derive(Debug)]
+struct Completion {
+    /// Where the completed word starts in the input, in characters
+    start: usize,
+    /// Length of the text currently in place of the word
+    length: usize,
+    candidates: Vec<String>,
+    index: usize,
+}
+
+impl App {
+    /// Networks are added with [`App::add_network`], which has to happen at least once before
+    /// the app is shown.
+    pub fn new() -> Self {
+        App {
+            networks: vec![],
+            buffers: vec![],
+            active: 0,
+            panes: vec![0],
+            pane: 0,
+            input: String::new(),
+            cursor: 0,
+            completion: None,
+            history_position: None,
+            pending_paste: None,
+            should_quit: false,
+            dcc: Dcc::new(),
+            exec: Exec::new(),
+            scripts: Scripts::new(),
+            highlighter: Highlighter::default(),
+            alerts: vec![],
+            ignored: vec![],
+            strip_formatting: false,
+            scrollback: DEFAULT_SCROLLBACK,
+            theme: Theme::default(),
+            show_nicklist: true,
+            show_topic: true,
+            page_size: 10,
+            message_time: None,
+        }
+    }
+
+    /// Start keeping the state of a newly connected network, with its own status buffer, and
+    /// return its index.
+    pub fn add_network(&mut self, name: &str, nick: &str, chat_log: Option<ChatLog>) -> usize {
+        self.networks.push(Network {
+            name: name.to_string(),
+            nick: nick.to_string(),
+            connected: true,
+            registered: false,
+            chat_log,
+            history: History::default(),
+            lag: Lag::Unknown,
+            queued: 0,
+            away: false,
+            user_modes: String::new(),
+            history_batches: HashMap::new(),
+            whois: HashMap::new(),
+        });
+        let network = self.networks.len() - 1;
+        self.open(network, STATUS_BUFFER);
+        network
+    }
+
+    /// Find a network by name, ignoring case.
+    pub fn find_network(&self, name: &str) -> Option<usize> {
+        self.networks
+            .iter()
+            .position(|network| network.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn active_buffer(&self) -> &Buffer {
+        &self.buffers[self.active]
+    }
+
+    /// Index of the network of the buffer being shown, which typed commands are sent to
+    pub fn network(&self) -> usize {
+        self.active_buffer().network
+    }
+
+    /// Our nickname on the network being shown
+    pub fn nick(&self) -> &str {
+        &self.networks[self.network()].nick
+    }
+
+    /// Name of a buffer as shown to the user. With several networks connected, it starts with
+    /// the name of the network, as in `libera/#rust`.
+    pub fn title(&self, buffer: &Buffer) -> String {
+        if self.networks.len() > 1 {
+            format!("{}/{}", self.networks[buffer.network].name, buffer.name)
+        } else {
+            buffer.name.clone()
+        }
+    }
+
+    /// The networks and the channels and private conversations open in each, to be restored
+    /// on the next run
+    pub fn session(&self) -> LastSession {
+        let active = self.active_buffer();
+        LastSession {
+            networks: self
+                .networks
+                .iter()
+                .enumerate()
+                .map(|(index, network)| SavedNetwork {
+                    name: network.name.clone(),
+                    channels: self.channels(index),
+                    queries: self
+                        .buffers
+                        .iter()
+                        .filter(|buffer| {
+                            buffer.network == index
+                                && buffer.is_target()
+                                && !buffer.name.starts_with(['#', '&'])
+                        })
+                        .map(|buffer| buffer.name.clone())
+                        .collect(),
+                })
+                .collect(),
+            active_network: Some(self.networks[active.network].name.clone()),
+            active_buffer: Some(active.name.clone()),
+        }
+    }
+
+    /// Open the buffers of a network from the last session, with the lines logged in them.
+    pub fn restore_buffers(&mut self, network: usize, saved: &SavedNetwork) {
+        for name in saved.channels.iter().chain(&saved.queries) {
+            self.buffer_index(network, name);
+        }
+    }
+
+    /// Names of the open channel buffers of a network
+    pub fn channels(&self, network: usize) -> Vec<String> {
+        self.buffers
+            .iter()
+            .filter(|buffer| buffer.network == network && buffer.name.starts_with(['#', '&']))
+            .map(|buffer| buffer.name.clone())
+            .collect()
+    }
+
+    /// Name of the channel or user that plain text is sent to
+    pub fn target(&self) -> Option<&str> {
+        let buffer = self.active_buffer();
+        buffer.is_target().then_some(buffer.name.as_str())
+    }
+
+    /// Find a network's buffer by name, ignoring case.
+    fn find_buffer(&self, network: usize, name: &str) -> Option<usize> {
+        self.buffers
+            .iter()
+            .position(|buffer| buffer.network == network && buffer.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Find a network's buffer by name, ignoring case, creating it if it doesn't exist yet.
+    fn buffer_index(&mut self, network: usize, name: &str) -> usize {
+        match self.find_buffer(network, name) {
+            Some(index) => index,
+            None => {
+                let mut buffer = Buffer::new(network, name);
+                if let Some(chat_log) = &self.networks[network].chat_log
+                    && buffer.is_target()
+                {
+                    buffer.lines = chat_log.tail(name).into();
+                }
+                // After the network's other buffers
+                let index = self
+                    .buffers
+                    .iter()
+                    .rposition(|buffer| buffer.network == network)
+                    .map_or(self.buffers.len(), |last| last + 1);
+                self.buffers.insert(index, buffer);
+                if self.buffers.len() > 1 {
+                    // Keep showing the same buffers
+                    for shown in &mut self.panes {
+                        if index <= *shown {
+                            *shown += 1;
+                        }
+                    }
+                    self.active = self.panes[self.pane];
+                }
+                index
+            }
+        }
+    }
+
+    /// Add a line to a buffer, counting it as unread if the buffer isn't being shown.
+    pub fn push(&mut self, network: usize, buffer: &str, kind: LineKind, text: String) {
+        self.push_line(network, buffer, kind, None, text);
+    }
+
+    /// Add a message from someone to a buffer. See [`Line::sender`].
+    pub fn push_message(
+        &mut self,
+        network: usize,
+        buffer: &str,
+        kind: LineKind,
+        sender: String,
+        text: String,
+    ) {
+        self.push_line(network, buffer, kind, Some(sender), text);
+    }
+
+    fn push_line(
+        &mut self,
+        network: usize,
+        buffer: &str,
+        kind: LineKind,
+        sender: Option<String>,
+        text: String,
+    ) {
+        let index = self.buffer_index(network, buffer);
+        let buffer = &mut self.buffers[index];
+        let network = &mut self.networks[network];
+        let urls = match kind {
+            LineKind::Message | LineKind::Highlight => find_urls(&formatting::strip(&text))
+                .into_iter()
+                .map(|url| buffer.add_url(url))
+                .collect(),
+            _ => vec![],
+        };
+        let line = Line {
+            time: self.message_time.unwrap_or_else(Local::now),
+            kind,
+            sender,
+            text,
+            urls,
+        };
+        if let Some(chat_log) = &mut network.chat_log
+            && buffer.is_target()
+            && matches!(
+                kind,
+                LineKind::Message | LineKind::Highlight | LineKind::Event
+            )
+            && let Err(e) = chat_log.write(&buffer.name, &line)
+        {
+            // Don't keep failing on every line
+            network.chat_log = None;
+            buffer.lines.push_back(Line {
+                time: Local::now(),
+                kind: LineKind::Error,
+                sender: None,
+                text: format!("Stopped logging after failing to write the log: {e}"),
+                urls: vec![],
+            });
+        }
+        buffer.lines.push_back(line);
+        if buffer.lines.len() > self.scrollback.max(1) {
+            buffer.lines.pop_front();
+            buffer.search = buffer
+                .search
+                .take()
+                .and_then(|(query, index)| Some((query, index.checked_sub(1)?)));
+        }
+        if !self.panes.contains(&index) && matches!(kind, LineKind::Message | LineKind::Highlight) {
+            buffer.unread += 1;
+            buffer.highlights += usize::from(kind == LineKind::Highlight);
+        }
+        // Keep the view still when scrolled up
+        if buffer.scroll > 0 {
+            buffer.scroll = (buffer.scroll + 1).min(buffer.lines.len() - 1);
+        }
+    }
+
+    /// Add a line to the buffer being shown.
+    pub fn push_active(&mut self, kind: LineKind, text: String) {
+        let name = self.active_buffer().name.clone();
+        self.push(self.network(), &name, kind, text);
+    }
+
+    /// Show a network's buffer, creating it if needed.
+    pub fn open(&mut self, network: usize, name: &str) {
+        let index = self.buffer_index(network, name);
+        self.select(index);
+    }
+
+    pub fn select(&mut self, index: usize) {
+        if index < self.buffers.len() {
+            self.active = index;
+            self.panes[self.pane] = index;
+            self.history_position = None;
+            self.buffers[index].unread = 0;
+            self.buffers[index].highlights = 0;
+        }
+    }
+
+    pub fn next_buffer(&mut self) {
+        self.select((self.active + 1) % self.buffers.len());
+    }
+
+    pub fn previous_buffer(&mut self) {
+        self.select((self.active + self.buffers.len() - 1) % self.buffers.len());
+    }
+
+    /// Close the active buffer. The status buffer can't be closed.
+    pub fn close_active(&mut self) {
+        if self.active_buffer().name != STATUS_BUFFER {
+            let closed = self.active;
+            self.buffers.remove(closed);
+            // Panes showing the closed buffer show the one after it instead, or the one before
+            // it if it was the last
+            for shown in &mut self.panes {
+                if *shown > closed || *shown == self.buffers.len() {
+                    *shown -= 1;
+                }
+            }
+            self.select(self.panes[self.pane]);
+        }
+    }
+
+    /// A buffer by its number in the status bar, or by name on the active network
+    pub fn lookup_buffer(&self, name: &str) -> Option<usize> {
+        match name.parse::<usize>() {
+            Ok(number) => (1..=self.buffers.len())
+                .contains(&number)
+                .then(|| number - 1),
+            Err(_) => self.find_buffer(self.network(), name),
+        }
+    }
+
+    /// Show a buffer in a new pane below the focused one, and focus it.
+    pub fn split(&mut self, index: usize) -> Result<(), String> {
+        if self.panes.len() >= MAX_PANES {
+            return Err(format!("There can be at most {MAX_PANES} panes."));
+        }
+        self.pane += 1;
+        self.panes.insert(self.pane, index);
+        self.select(index);
+        Ok(())
+    }
+
+    /// Close the focused pane, focusing the one above it if there is one. The last pane can't be
+    /// closed.
+    pub fn unsplit(&mut self) -> Result<(), String> {
+        if self.panes.len() == 1 {
+            return Err("There's only one pane.".to_string());
+        }
+        self.panes.remove(self.pane);
+        self.focus_pane(self.pane.saturating_sub(1));
+        Ok(())
+    }
+
+    pub fn focus_pane(&mut self, pane: usize) {
+        if pane < self.panes.len() {
+            self.pane = pane;
+            self.select(self.panes[pane]);
+        }
+    }
+
+    pub fn next_pane(&mut self) {
+        self.focus_pane((self.pane + 1) % self.panes.len());
+    }
+
+    pub fn previous_pane(&mut self) {
+        self.focus_pane((self.pane + self.panes.len() - 1) % self.panes.len());
+    }
+
+    pub fn scroll_up(&mut self, lines: usize) {
+        let buffer = &mut self.buffers[self.active];
+        buffer.scroll = (buffer.scroll + lines).min(buffer.lines.len().saturating_sub(1));
+    }
+
+    pub fn scroll_down(&mut self, lines: usize) {
+        let buffer = &mut self.buffers[self.active];
+        buffer.scroll = buffer.scroll.saturating_sub(lines);
+        if buffer.scroll == 0 {
+            buffer.search = None; // Back to the live view
+        }
+    }
+
+    /// Scroll to the closest line above the view containing the text, ignoring case. Without any
+    /// text, this finds the next older match of the previous search, starting over from the
+    /// newest line once there are no more.
+    pub fn search(&mut self, text: Option<&str>) -> Result<(), String> {
+        let buffer = &mut self.buffers[self.active];
+        let (query, before) = match (text, &buffer.search) {
+            (Some(text), _) => (text.to_lowercase(), buffer.lines.len() - buffer.scroll),
+            (None, Some((query, index))) => (query.clone(), *index),
+            (None, None) => return Err("Usage: /search <text>".to_string()),
+        };
+        let matches = |line: &Line| {
+            let text = format!(
+                "{} {}",
+                line.sender.as_deref().unwrap_or_default(),
+                line.text
+            );
+            formatting::strip(&text).to_lowercase().contains(&query)
+        };
+
+        let found = buffer
+            .lines
+            .range(..before)
+            .rposition(matches)
+            .or_else(|| buffer.lines.iter().rposition(matches)); // Wrap around
+        match found {
+            Some(index) => {
+                buffer.scroll = buffer.lines.len() - 1 - index;
+                buffer.search = Some((query, index));
+                Ok(())
+            }
+            None => Err(format!("No lines contain \"{query}\".")),
+        }
+    }
+
+    /// Whether a message comes from an ignored user. Only messages and notices are ignored, not
+    /// joins and other events.
+    pub fn is_ignored(&self, message: &Message) -> bool {
+        matches!(message.command, Command::PrivMsg | Command::Notice)
+            && message.prefix.as_ref().is_some_and(|prefix| {
+                // Servers aren't ignored
+                let prefix = match prefix {
+                    Prefix::User { .. } => prefix.to_string(),
+                    Prefix::Server(_) => return false,
+                };
+                self.ignored.iter().any(|m| mask::matches(m, &prefix))
+            })
+    }
+
+    /// Show something the user sent to a network, since the server doesn't echo it back.
+    pub fn handle_sent(&mut self, network: usize, message: &Message) {
+        let nick = self.networks[network].nick.clone();
+        let param = |i: usize| message.params.get(i).map_or("", String::as_str);
+        match message.command {
+            Command::PrivMsg => {
+                let (sender, text) = match ctcp::parse(param(1)) {
+                    Some(("ACTION", action)) => (format!("* {nick}"), action),
+                    Some(_) => return,
+                    None => (format!("<{nick}>"), param(1)),
+                };
+                self.push_message(
+                    network,
+                    param(0),
+                    LineKind::Message,
+                    sender,
+                    text.to_string(),
+                );
+            }
+            Command::Join => self.open(network, param(0)),
+            // Start over with an empty list, which the replies fill
+            Command::List => {
+                self.open(network, LIST_BUFFER);
+                self.buffers[self.active].list = Some(ChannelList::default());
+            }
+            Command::Part => {
+                if let Some(index) = self.find_buffer(network, param(0)) {
+                    self.select(index);
+                    self.close_active();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Route a message from a network's server to the buffer it belongs to.
+    pub fn handle_message(&mut self, network: usize, message: &Message) {
+        // The lines it adds are shown at the time the server got it, if it said
+        self.message_time = server_time(message);
+        self.apply_message(network, message);
+        self.message_time = None;
+    }
+
+    fn apply_message(&mut self, network: usize, message: &Message) {
+        let nick = message.nick().to_string();
+        let param = |i: usize| message.params.get(i).map_or("", String::as_str);
+        let is_me = nick.eq_ignore_ascii_case(&self.networks[network].nick);
+
+        if let Some(reference) = message.tag("batch")
+            && let Some(batch) = self.networks[network].history_batches.get_mut(reference)
+        {
+            batch.lines.extend(history_line(message));
+            return;
+        }
+
+        match message.command {
+            Command::PrivMsg | Command::Notice => {
+                // CTCP requests and replies other than ACTION aren't part of the conversation
+                match (&message.command, ctcp::parse(param(1))) {
+                    (_, Some(("ACTION", _))) | (_, None) => {}
+                    _ if is_me => return, // Our own request or reply, echoed back
+                    (Command::PrivMsg, Some(("DCC", _))) => return, // Shown by the DCC handling
+                    (Command::PrivMsg, Some((command, _))) => {
+                        let text = format!("{nick} sent a CTCP {command} request");
+                        self.push(network, STATUS_BUFFER, LineKind::Event, text);
+                        return;
+                    }
+                    (_, Some((command, args))) => {
+                        let text = format!("CTCP {command} reply from {nick}: {args}");
+                        self.push(network, STATUS_BUFFER, LineKind::Info, text);
+                        return;
+                    }
+                }
+
+                // Private messages go to a buffer named after the sender
+                let target = param(0);
+                let buffer = if target.starts_with(['#', '&']) {
+                    target.to_string()
+                } else if nick.contains('.') || nick.is_empty() {
+                    STATUS_BUFFER.to_string() // Server notices
+                } else if is_me {
+                    target.to_string() // Our own message, echoed back with echo-message
+                } else {
+                    nick.clone()
+                };
+                if buffer.starts_with(['#', '&']) {
+                    let index = self.buffer_index(network, &buffer);
+                    self.buffers[index].members.add(&nick);
+                }
+                let (sender, text) = match ctcp::parse(param(1)) {
+                    Some((_, action)) => (format!("* {nick}"), action),
+                    None if message.command == Command::Notice => (format!("-{nick}-"), param(1)),
+                    None => (format!("<{nick}>"), param(1)),
+                };
+                let text = text.to_string();
+                let notification = if is_me {
+                    Notification::default()
+                } else {
+                    let incoming = Incoming {
+                        buffer: &buffer,
+                        sender: &nick,
+                        text: &formatting::strip(&text),
+                    };
+                    self.highlighter
+                        .check(&self.networks[network].nick, &incoming)
+                };
+                let kind = if notification.highlight {
+                    LineKind::Highlight
+                } else {
+                    LineKind::Message
+                };
+                if notification.alerts() {
+                    let index = self.buffer_index(network, &buffer);
+                    self.alerts.push(Alert {
+                        buffer: self.title(&self.buffers[index]),
+                        line: format!("{sender} {text}"),
+                        nick: nick.clone(),
+                        text: formatting::strip(&text),
+                        notification,
+                    });
+                }
+                self.push_message(network, &buffer, kind, sender, text);
+            }
+            Command::Join => {
+                let index = self.buffer_index(network, param(0));
+                if is_me {
+                    self.open(network, param(0));
+                    let buffer = &mut self.buffers[index];
+                    buffer.seen_before_join = buffer
+                        .lines
+                        .iter()
+                        .filter(|line| line.sender.is_some())
+                        .map(|line| line.time)
+                        .max();
+                }
+                self.buffers[index].members.add(&nick);
+                self.push(
+                    network,
+                    param(0),
+                    LineKind::Event,
+                    format!("--> {nick} joined"),
+                );
+            }
+            Command::Part => {
+                let reason = Some(param(1)).filter(|r| !r.is_empty());
+                let text = match reason {
+                    Some(reason) => format!("<-- {nick} left ({reason})"),
+                    None => format!("<-- {nick} left"),
+                };
+                let index = self.buffer_index(network, param(0));
+                self.buffers[index].members.remove(&nick);
+                self.push(network, param(0), LineKind::Event, text);
+            }
+            Command::Kick => {
+                let text = format!("<-- {nick} kicked {} ({})", param(1), param(2));
+                let index = self.buffer_index(network, param(0));
+                self.buffers[index].members.remove(param(1));
+                self.push(network, param(0), LineKind::Event, text);
+            }
+            Command::Quit => {
+                // Shown in every channel they were in
+                let text = format!("<-- {nick} quit ({})", param(0));
+                for name in self.forget_member(network, &nick) {
+                    self.push(network, &name, LineKind::Event, text.clone());
+                }
+            }
+            Command::Nick => {
+                if is_me {
+                    self.networks[network].nick = param(0).to_string();
+                }
+                let text = format!("{nick} is now known as {}", param(0));
+                let mut channels = vec![];
+                for buffer in &mut self.buffers {
+                    if buffer.network == network && buffer.members.contains(&nick) {
+                        buffer.members.rename(&nick, param(0));
+                        channels.push(buffer.name.clone());
+                    }
+                }
+                for name in &channels {
+                    self.push(network, name, LineKind::Event, text.clone());
+                }
+                // The conversation with them carries on under the new nickname
+                if !is_me
+                    && self.find_buffer(network, param(0)).is_none()
+                    && let Some(index) = self.find_buffer(network, &nick)
+                {
+                    self.buffers[index].name = param(0).to_string();
+                    self.push(network, param(0), LineKind::Event, text.clone());
+                }
+                if is_me {
+                    self.push(network, STATUS_BUFFER, LineKind::Event, text);
+                }
+            }
+            Command::Topic => {
+                let index = self.buffer_index(network, param(0));
+                self.buffers[index].topic = param(1).to_string();
+                self.buffers[index].topic_set_by = Some(nick.clone());
+                let text = format!("{nick} changed the topic to: {}", param(1));
+                self.push(network, param(0), LineKind::Event, text);
+            }
+            Command::Mode => {
+                let is_channel = param(0).starts_with(['#', '&']);
+                // User modes don't take arguments
+                let mode_args = if is_channel {
+                    members::mode_args()
+                } else {
+                    ModeArgs::default()
+                };
+                let args = message.params.iter().skip(2).map(String::as_str);
+                let changes = ModeChange::parse(param(1), args, &mode_args);
+                let modes = ModeChange::format(&changes).join(" ");
+                if is_channel {
+                    let index = self.buffer_index(network, param(0));
+                    self.buffers[index].members.apply_modes(&changes);
+                } else if param(0).eq_ignore_ascii_case(&self.networks[network].nick) {
+                    self.networks[network].apply_user_modes(&changes);
+                    let text = format!("{nick} set your mode {modes}");
+                    self.push(network, STATUS_BUFFER, LineKind::Event, text);
+                    return;
+                }
+                let text = format!("{nick} set mode {modes}");
+                self.push(network, param(0), LineKind::Event, text);
+            }
+            Command::Error => {
+                self.push(
+                    network,
+                    STATUS_BUFFER,
+                    LineKind::Error,
+                    param(0).to_string(),
+                );
+            }
+            Command::Numeric(code) => {
+                // RPL_WELCOME is addressed to the nickname we got, which may be an alternate one
+                if code == 1 {
+                    self.networks[network].nick = param(0).to_string();
+                    self.networks[network].registered = true;
+                    self.networks[network].away = false;
+                    self.networks[network].user_modes.clear();
+                }
+                // Skip our own nickname, which every reply starts with
+                let params = match message.params.first() {
+                    Some(first) if first.eq_ignore_ascii_case(&self.networks[network].nick) => {
+                        &message.params[1..]
+                    }
+                    _ => &message.params[..],
+                };
+                // RPL_UNAWAY and RPL_NOWAWAY, about ourselves
+                if code == 305 || code == 306 {
+                    let me = self.networks[network].nick.clone();
+                    self.set_away(network, &me, code == 306);
+                    self.networks[network].away = code == 306;
+                }
+                // RPL_UMODEIS, which servers also send after registering: `+iw`
+                if code == 221 {
+                    let modes = &mut self.networks[network].user_modes;
+                    modes.clear();
+                    modes.extend(param(1).chars().filter(char::is_ascii_alphabetic));
+                }
+                // RPL_YOUREOPER, in case the server doesn't send the mode as well
+                if code == 381 {
+                    self.networks[network].apply_user_modes(&[ModeChange::new(true, 'o', None)]);
+                }
+                // Servers without capabilities answer CAP LS, sent while registering, with
+                // ERR_UNKNOWNCOMMAND or ERR_NOTREGISTERED
+                if !self.networks[network].registered && matches!(code, 421 | 451) {
+                    return;
+                }
+                if self.collect_whois(network, code, params) {
+                    return;
+                }
+                // RPL_NAMREPLY: `= #channel :nick1 @nick2`
+                if code == 353 && params.len() >= 3 {
+                    let index = self.buffer_index(network, &params[1]);
+                    let members = &mut self.buffers[index].members;
+                    for name in params[2].split_whitespace() {
+                        members.add_name(name);
+                    }
+                }
+
+                // RPL_NOTOPIC, RPL_TOPIC and RPL_TOPICWHOTIME (`#channel setter time`), for the
+                // topic bar of channels we're in
+                if matches!(code, 331..=333)
+                    && let Some(channel) = params.first()
+                    && let Some(index) = self.find_buffer(network, channel)
+                {
+                    let buffer = &mut self.buffers[index];
+                    match code {
+                        331 => buffer.topic.clear(),
+                        332 => buffer.topic = params.get(1).cloned().unwrap_or_default(),
+                        // The setter may be a full `nick!user@host`
+                        _ => {
+                            buffer.topic_set_by = params
+                                .get(1)
+                                .and_then(|setter| setter.split('!').next())
+                                .map(str::to_string)
+                        }
+                    }
+                }
+                // RPL_LIST: `#channel users :topic`, where servers differ in sending the topic
+                if code == 322 && !params.is_empty() {
+                    let entry = ListEntry {
+                        channel: params[0].clone(),
+                        users: params
+                            .get(1)
+                            .and_then(|users| users.parse().ok())
+                            .unwrap_or(0),
+                        topic: params.get(2).cloned().unwrap_or_default(),
+                    };
+                    self.channel_list(network).add(entry);
+                    return;
+                }
+                // RPL_LISTSTART, which old servers send, and RPL_LISTEND
+                if code == 321 || code == 323 {
+                    if code == 323 {
+                        self.channel_list(network).finish();
+                    }
+                    return;
+                }
+
+                let kind = if code >= 400 {
+                    LineKind::Error
+                } else {
+                    LineKind::Info
+                };
+                // Replies about a channel go to its buffer if it's open
+                let buffer = params
+                    .iter()
+                    .take(params.len().saturating_sub(1))
+                    .find(|param| param.starts_with(['#', '&']))
+                    .filter(|channel| self.find_buffer(network, channel).is_some())
+                    .map_or(STATUS_BUFFER.to_string(), String::clone);
+                if let Some(text) = describe_reply(code, params) {
+                    self.push(network, &buffer, kind, text);
+                }
+            }
+            // BATCH +reference chathistory #channel, and BATCH -reference at the end
+            Command::Batch => {
+                if let Some(reference) = param(0).strip_prefix('+')
+                    && param(1) == "chathistory"
+                {
+                    let batch = HistoryBatch {
+                        target: param(2).to_string(),
+                        lines: vec![],
+                    };
+                    self.networks[network]
+                        .history_batches
+                        .insert(reference.to_string(), batch);
+                } else if let Some(reference) = param(0).strip_prefix('-')
+                    && let Some(batch) = self.networks[network].history_batches.remove(reference)
+                {
+                    self.add_history(network, batch);
+                }
+            }
+            // AWAY with a message when they leave and without one when they're back, sent with
+            // the away-notify capability
+            Command::Away => self.set_away(network, &nick, !param(0).is_empty()),
+            Command::Ping | Command::Pong | Command::Cap | Command::TagMsg => {}
+            _ => self.push(network, STATUS_BUFFER, LineKind::Info, message.to_string()),
+        }
+    }
+
+    /// Add the messages of a finished history batch to their buffer, among the older lines by
+    /// time and so above the live messages, leaving out the ones seen before.
+    fn add_history(&mut self, network: usize, batch: HistoryBatch) {
+        let index = self.buffer_index(network, &batch.target);
+        let buffer = &mut self.buffers[index];
+        for line in batch.lines {
+            if buffer
+                .seen_before_join
+                .is_some_and(|seen| line.time <= seen)
+            {
+                continue;
+            }
+            let at = buffer
+                .lines
+                .partition_point(|other| other.time <= line.time);
+            buffer.lines.insert(at, line);
+        }
+        while buffer.lines.len() > self.scrollback.max(1) {
+            buffer.lines.pop_front();
+        }
+        // The line found by the search may have moved
+        buffer.search = None;
+    }
+
+    /// Collect the replies to WHOIS, showing them together in the active buffer at
+    /// RPL_ENDOFWHOIS. Returns whether the reply was one of them.
+    fn collect_whois(&mut self, network: usize, code: u16, params: &[String]) -> bool {
+        let Some(nick) = params.first() else {
+            return false;
+        };
+        let key = nick.to_lowercase();
+        let pending = &mut self.networks[network].whois;
+        match code {
+            // RPL_WHOISUSER comes first
+            311 => {
+                let mut whois = Whois::new(nick);
+                whois.add(code, params);
+                pending.insert(key, whois);
+            }
+            // RPL_ENDOFWHOIS
+            318 => {
+                let Some(whois) = pending.remove(&key) else {
+                    return false;
+                };
+                let active = self.active_buffer();
+                let buffer = if active.network == network && active.name != LIST_BUFFER {
+                    active.name.clone()
+                } else {
+                    STATUS_BUFFER.to_string()
+                };
+                for line in whois.lines() {
+                    self.push(network, &buffer, LineKind::Info, line);
+                }
+            }
+            _ => {
+                return pending
+                    .get_mut(&key)
+                    .is_some_and(|whois| whois.add(code, params));
+            }
+        }
+        true
+    }
+
+    /// The list of a network's list buffer, created if the server lists channels without having
+    /// been asked by `/list`.
+    fn channel_list(&mut self, network: usize) -> &mut ChannelList {
+        let index = self.buffer_index(network, LIST_BUFFER);
+        self.buffers[index].list.get_or_insert_default()
+    }
+
+    /// The typed text that filters the channels in the list buffer. Commands don't.
+    pub fn list_filter(&self) -> &str {
+        if self.input.starts_with('/') {
+            ""
+        } else {
+            &self.input
+        }
+    }
+
+    /// The channel selected in the list buffer, if it's being shown
+    pub fn selected_channel(&self) -> Option<String> {
+        let list = self.active_buffer().list.as_ref()?;
+        let entry = list.selected(self.list_filter())?;
+        Some(entry.channel.clone())
+    }
+
+    /// Move the selection in the list buffer up (negative) or down.
+    pub fn move_list_selection(&mut self, offset: isize) {
+        let filter = self.list_filter().to_string();
+        if let Some(list) = &mut self.buffers[self.active].list {
+            list.move_selection(offset, &filter);
+        }
+    }
+
+    /// Switch the list buffer between sorting by users and by name.
+    pub fn toggle_list_sort(&mut self) {
+        if let Some(list) = &mut self.buffers[self.active].list {
+            list.toggle_sort();
+        }
+    }
+
+    /// Mark a user as away or back in every channel of a network.
+    fn set_away(&mut self, network: usize, nick: &str, away: bool) {
+        for buffer in &mut self.buffers {
+            if buffer.network == network
+                && let Some(member) = buffer.members.get_mut(nick)
+            {
+                member.away = away;
+            }
+        }
+    }
+
+    /// Remove a user from every channel of a network, returning the names of the ones they were
+    /// in, or the status buffer if none.
+    fn forget_member(&mut self, network: usize, nick: &str) -> Vec<String> {
+        let channels = self
+            .buffers
+            .iter_mut()
+            .filter(|buffer| buffer.network == network)
+            .filter_map(|buffer| {
+                let member = buffer.members.remove(nick);
+                member.map(|_| buffer.name.clone())
+            })
+            .collect::<Vec<_>>();
+        if channels.is_empty() {
+            vec![STATUS_BUFFER.to_string()]
+        } else {
+            channels
+        }
+    }
+
+    /// Complete the word before the cursor, or switch to the next candidate if called again.
+    /// Commands are completed at the start of the line, channels when the word starts with `#` or
+    /// `&`, and nicknames of the current channel otherwise.
+    pub fn complete(&mut self) {
+        if self.completion.is_none() {
+            self.completion = self.start_completion();
+        } else if let Some(completion) = &mut self.completion {
+            completion.index = (completion.index + 1) % completion.candidates.len();
+        }
+        let Some(completion) = &mut self.completion else {
+            return;
+        };
+
+        // Replace the word (or the previous candidate) with the current candidate
+        let candidate = &completion.candidates[completion.index];
+        let mut input = self.input.chars().collect::<Vec<_>>();
+        input.splice(
+            completion.start..completion.start + completion.length,
+            candidate.chars(),
+        );
+        self.input = input.into_iter().collect();
+        completion.length = candidate.chars().count();
+        self.cursor = completion.start + completion.length;
+    }
+
+    /// Stop cycling through completions, keeping the current one.
+    pub fn reset_completion(&mut self) {
+        self.completion = None;
+    }
+
+    fn start_completion(&self) -> Option<Completion> {
+        let before_cursor = self.input.chars().take(self.cursor).collect::<String>();
+        let word = before_cursor.rsplit(' ').next().unwrap_or_default();
+        let start = self.cursor - word.chars().count();
+        let matches = |candidate: &str, prefix: &str| {
+            candidate.to_lowercase().starts_with(&prefix.to_lowercase())
+        };
+
+        let candidates = if start == 0 && word.starts_with('/') {
+            command::COMMANDS
+                .iter()
+                .filter(|name| matches(name, &word[1..]))
+                .map(|name| format!("/{name} "))
+                .collect()
+        } else if word.starts_with(['#', '&']) {
+            self.channels(self.network())
+                .into_iter()
+                .filter(|channel| matches(channel, word))
+                .map(|channel| format!("{channel} "))
+                .collect()
+        } else {
+            // Address the user if their name starts the line
+            let suffix = if start == 0 { ": " } else { " " };
+            let buffer = self.active_buffer();
+            let mut nicks = buffer
+                .members
+                .nicks()
+                .map(str::to_string)
+                .collect::<Vec<_>>();
+            if buffer.is_target() && !buffer.name.starts_with(['#', '&']) {
+                nicks.push(buffer.name.clone()); // A query
+            }
+            nicks
+                .into_iter()
+                .filter(|nick| matches(nick, word) && !nick.eq_ignore_ascii_case(self.nick()))
+                .map(|nick| format!("{nick}{suffix}"))
+                .collect::<Vec<_>>()
+        };
+
+        (!candidates.is_empty()).then(|| Completion {
+            start,
+            length: word.chars().count(),
+            candidates,
+            index: 0,
+        })
+    }
+
+    pub fn insert_char(&mut self, c: char) {
+        let index = self.byte_index();
+        self.input.insert(index, c);
+        self.cursor += 1;
+    }
+
+    /// Insert a nickname at the cursor, addressed to them if it starts the line.
+    pub fn insert_nick(&mut self, nick: &str) {
+        let suffix = if self.input.is_empty() { ": " } else { " " };
+        for c in nick.chars().chain(suffix.chars()) {
+            self.insert_char(c);
+        }
+    }
+
+    /// Delete the character before the cursor, along with any marks combined with it.
+    pub fn delete_before_cursor(&mut self) {
+        let end = self.byte_index();
+        self.move_cursor(-1);
+        let start = self.byte_index();
+        self.input.replace_range(start..end, "");
+    }
+
+    /// Delete the character at the cursor, along with any marks combined with it.
+    pub fn delete_at_cursor(&mut self) {
+        let (cursor, start) = (self.cursor, self.byte_index());
+        self.move_cursor(1);
+        let end = self.byte_index();
+        self.cursor = cursor;
+        self.input.replace_range(start..end, "");
+    }
+
+    /// Move the cursor by a number of characters as they're seen, which can each be made up of
+    /// several chars, like a letter and an accent or an emoji sequence.
+    pub fn move_cursor(&mut self, offset: isize) {
+        // Where each of them starts, counted in chars, and the end of the input
+        let mut boundaries = vec![0];
+        for grapheme in self.input.graphemes(true) {
+            boundaries.push(boundaries[boundaries.len() - 1] + grapheme.chars().count());
+        }
+        let current = boundaries
+            .iter()
+            .rposition(|&boundary| boundary <= self.cursor)
+            .unwrap_or(0);
+        let target = current.saturating_add_signed(offset);
+        self.cursor = boundaries[target.min(boundaries.len() - 1)];
+    }
+
+    pub fn cursor_to_end(&mut self) {
+        self.cursor = self.input.chars().count();
+    }
+
+    /// Take in pasted text. A single line is inserted at the cursor, while several lines wait for
+    /// the user to send them as they are, join them into one line or drop them.
+    pub fn paste(&mut self, text: &str) {
+        let text = text.replace("\r\n", "\n").replace('\r', "\n");
+        let text = text.trim_end_matches('\n');
+        if !text.contains('\n') {
+            text.chars().for_each(|c| self.insert_char(c));
+            return;
+        }
+
+        // Blank lines can't be sent
+        let lines = text
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+        let note = format!(
+            "Pasted {} lines. Enter sends them, Tab joins them into one line and Esc drops \
+             them.",
+            lines.len()
+        );
+        self.push_active(LineKind::Info, note);
+        self.pending_paste = Some(lines);
+    }
+
+    /// Put the pasted lines on the input line, joined by spaces.
+    pub fn collapse_paste(&mut self) {
+        let Some(lines) = self.pending_paste.take() else {
+            return;
+        };
+        let lines = lines.iter().map(|line| line.trim()).collect::<Vec<_>>();
+        lines.join(" ").chars().for_each(|c| self.insert_char(c));
+    }
+
+    /// Take the typed line, leaving the input empty.
+    /// Put a channel's topic in the input as a `/topic` command, to be changed and sent.
+    pub fn edit_topic(&mut self, channel: &str) {
+        let topic = self
+            .find_buffer(self.network(), channel)
+            .map_or("", |index| self.buffers[index].topic.as_str());
+        self.input = format!("/topic {channel} {topic}");
+        self.cursor = self.input.chars().count();
+        self.history_position = None;
+        self.reset_completion();
+    }
+
+    pub fn take_input(&mut self) -> String {
+        self.cursor = 0;
+        self.history_position = None;
+        std::mem::take(&mut self.input)
+    }
+
+    /// Add a line typed in the active buffer to its input history.
+    pub fn remember_input(&mut self, line: &str) {
+        let network = self.network();
+        let buffer = self.active_buffer().name.clone();
+        if let Err(e) = self.networks[network].history.add(&buffer, line) {
+            let text = format!("Stopped saving the input history after failing to write it: {e}");
+            self.push_active(LineKind::Error, text);
+        }
+    }
+
+    /// Replace the input with the previous line typed in the active buffer.
+    pub fn history_previous(&mut self) {
+        self.browse_history(1);
+    }
+
+    /// Replace the input with the next line typed in the active buffer, or what was being typed
+    /// before going through the history.
+    pub fn history_next(&mut self) {
+        self.browse_history(-1);
+    }
+
+    fn browse_history(&mut self, step: isize) {
+        let lines = self.networks[self.network()]
+            .history
+            .lines(&self.active_buffer().name);
+        let (position, draft) = self
+            .history_position
+            .take()
+            .unwrap_or_else(|| (0, self.input.clone()));
+        let position = position.saturating_add_signed(step).min(lines.len());
+        self.input = match position {
+            0 => draft.clone(),
+            _ => lines[lines.len() - position].clone(),
+        };
+        self.history_position = (position > 0).then_some((position, draft));
+        self.cursor_to_end();
+    }
+
+    fn byte_index(&self) -> usize {
+        self.input
+            .char_indices()
+            .nth(self.cursor)
+            .map_or(self.input.len(), |(index, _)| index)
+    }
+}
+
+/// Describe a numeric reply, without the nickname it starts with. Returns `None` for replies that
+/// only mark the end of a list. Most replies end with text meant for people, which is shown after
+/// the other parameters, e.g. `#rust: Cannot send to channel`.
+/// A message from the server's history as a line, dimmed like the chat log's backlog. Only
+/// messages, notices and actions are shown.
+fn history_line(message: &Message) -> Option<Line> {
+    if !matches!(message.command, Command::PrivMsg | Command::Notice) {
+        return None;
+    }
+    let nick = message.nick();
+    let text = message.params.get(1)?;
+    let (sender, text) = match ctcp::parse(text) {
+        Some(("ACTION", action)) => (format!("* {nick}"), action),
+        Some(_) => return None,
+        None if message.command == Command::Notice => (format!("-{nick}-"), text.as_str()),
+        None => (format!("<{nick}>"), text.as_str()),
+    };
+    // Without the server's time, the history arrived just now
+    Some(Line {
+        time: server_time(message).unwrap_or_else(Local::now),
+        kind: LineKind::Event,
+        sender: Some(sender),
+        text: text.to_string(),
+        urls: vec![],
+    })
+}
+
+/// When the server got a message, from the `time` tag of the server-time capability
+fn server_time(message: &Message) -> Option<DateTime<Local>> {
+    let time = DateTime::parse_from_rfc3339(message.tag("time")?).ok()?;
+    Some(time.with_timezone(&Local))
+}
+
+/// The web links in a message, like `https://example.com` and `www.example.com`.
+fn find_urls(text: &str) -> Vec<String> {
+    let mut finder = LinkFinder::new();
+    finder.kinds(&[LinkKind::Url]).url_must_have_scheme(false);
+    finder
+        .links(text)
+        .map(|link| link.as_str().to_string())
+        .collect()
+}
+
+fn describe_reply(code: u16, params: &[String]) -> Option<String> {
+    let param = |i: usize| params.get(i).map_or("", String::as_str);
+    let time = |timestamp: &str| {
+        timestamp
+            .parse()
+            .ok()
+            .and_then(|seconds| DateTime::from_timestamp(seconds, 0))
+            .map_or(timestamp.to_string(), |time| {
+                time.with_timezone(&Local)
+                    .format("%Y-%m-%d %H:%M:%S")
+                    .to_string()
+            })
+    };
+    let text = match code {
+        // RPL_ISUPPORT: the tokens are followed by "are supported by this server"
+        5 => format!(
+            "Server supports: {}",
+            params[..params.len().saturating_sub(1)].join(" ")
+        ),
+        // RPL_AWAY
+        301 => format!("{} is away: {}", param(0), param(1)),
+        // RPL_CHANNELMODEIS
+        324 => format!(
+            "Modes of {}: {}",
+            param(0),
+            params.get(1..).unwrap_or_default().join(" ")
+        ),
+        // RPL_CREATIONTIME
+        329 => format!("{} was created on {}", param(0), time(param(1))),
+        // RPL_NOTOPIC
+        331 => format!("{} has no topic", param(0)),
+        // RPL_TOPIC
+        332 => format!("Topic of {}: {}", param(0), param(1)),
+        // RPL_TOPICWHOTIME
+        333 => format!("Topic set by {} on {}", param(1), time(param(2))),
+        // RPL_NAMREPLY: `= #channel :nick1 @nick2`
+        353 => format!("Users on {}: {}", param(1), param(2)),
+        // RPL_ENDOFWHO, RPL_ENDOFWHOIS, RPL_ENDOFNAMES, RPL_ENDOFBANLIST
+        315 | 318 | 366 | 368 => return None,
+        // RPL_MOTD lines start with "- "
+        372 => param(0).strip_prefix("- ").unwrap_or(param(0)).to_string(),
+        // Replies that are only text, like RPL_WELCOME and RPL_MOTDSTART
+        _ if params.len() <= 1 => param(0).to_string(),
+        // The parameters the text is about, like the nickname in ERR_NICKNAMEINUSE
+        _ => {
+            let (text, about) = params.split_last().unwrap();
+            format!("{}: {text}", about.join(" "))
+        }
+    };
+    Some(text)
+}
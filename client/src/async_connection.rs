@@ -0,0 +1,383 @@
+use crate::{
+    connection::{Event, LINE_PACING, Lag, MAX_BACKOFF},
+    message::Message,
+    profile::Profile,
+    session::{self, Session},
+    throttle::Throttle,
+    transport,
+};
+use futures_core::Stream;
+use futures_sink::Sink;
+use shared::{codec::IrcCodec, isupport::ISupport};
+use std::{
+    collections::VecDeque,
+    io::{self, ErrorKind},
+    pin::Pin,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, WriteHalf},
+    net::TcpStream,
+    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    time,
+};
+use tokio_rustls::{TlsConnector, rustls::pki_types::ServerName};
+
+/// A plain or TLS connection to a server
+trait Socket: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Socket for T {}
+
+/// How the connection's task stopped using a socket.
+enum Ended {
+    /// The server closed the connection, or it failed
+    Disconnected,
+    /// The [`AsyncConnection`] was dropped
+    Dropped,
+}
+
+/// The async counterpart of [`Connection`](crate::Connection), for use with tokio. Events are
+/// read from it as a [`Stream`], and messages are sent with it as a [`Sink`] or with
+/// [`AsyncConnection::send_line`].
+///
+/// The connection is kept going by a task of its own, which registers, answers PINGs,
+/// authenticates with SASL, joins the profile's channels and reconnects when the connection
+/// drops. The task stops when the `AsyncConnection` is dropped. Lines sent while reconnecting are
+/// sent once the new connection is open. With the profile's flood protection, lines are paced
+/// like with [`Connection::send`](crate::Connection::send).
+///
+/// ```no_run
+/// use client::{AsyncConnection, Event, Profile};
+/// use futures::StreamExt;
+///
+/// # async fn run() -> std::io::Result<()> {
+/// let profile = Profile {
+///     host: "irc.libera.chat".to_string(),
+///     nick: "rustbot".to_string(),
+///     autojoin: vec!["#irc_rs".to_string()],
+///     ..Profile::default()
+/// };
+/// let mut connection = AsyncConnection::connect(profile).await?;
+/// while let Some(event) = connection.next().await {
+///     if let Event::Join { nick, channel } = event {
+///         connection.privmsg(&channel, &format!("Welcome, {nick}!"))?;
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct AsyncConnection {
+    session: Arc<Mutex<Session>>,
+    events: UnboundedReceiver<Event>,
+    /// Lines for the task to send
+    lines: UnboundedSender<String>,
+    /// How many lines the task is holding back because of flood protection
+    queued: Arc<AtomicUsize>,
+}
+
+impl AsyncConnection {
+    /// Connect to the profile's server and start registering. This has to be called from within
+    /// a tokio runtime, which the connection's task is spawned on.
+    pub async fn connect(profile: Profile) -> io::Result<Self> {
+        let socket = open(&profile).await?;
+        let session = Arc::new(Mutex::new(Session::new(profile)));
+        let (event_sender, events) = mpsc::unbounded_channel();
+        let (lines, line_receiver) = mpsc::unbounded_channel();
+        let queued = Arc::new(AtomicUsize::new(0));
+        tokio::spawn(run(
+            socket,
+            session.clone(),
+            event_sender,
+            line_receiver,
+            queued.clone(),
+        ));
+        Ok(AsyncConnection {
+            session,
+            events,
+            lines,
+            queued,
+        })
+    }
+
+    pub fn profile(&self) -> Profile {
+        self.session.lock().unwrap().profile.clone()
+    }
+
+    /// Our nickname on the server
+    pub fn nick(&self) -> String {
+        self.session.lock().unwrap().nick().to_string()
+    }
+
+    /// Whether the server acknowledged a capability. See
+    /// [`Connection::has_cap`](crate::Connection::has_cap).
+    pub fn has_cap(&self, cap: &str) -> bool {
+        self.session.lock().unwrap().has_cap(cap)
+    }
+
+    /// What the server supports. See [`Connection::isupport`](crate::Connection::isupport).
+    pub fn isupport(&self) -> ISupport {
+        self.session.lock().unwrap().isupport().clone()
+    }
+
+    pub fn lag(&self) -> Lag {
+        self.session.lock().unwrap().lag()
+    }
+
+    /// How many lines are waiting to be sent because of flood protection
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    /// Queue a raw line to be sent, without its line ending. Named so as not to hide
+    /// [`SinkExt::send`](https://docs.rs/futures/0.3/futures/sink/trait.SinkExt.html#method.send).
+    pub fn send_line(&self, line: &str) -> io::Result<()> {
+        self.lines
+            .send(line.to_string())
+            .map_err(|_| io::Error::new(ErrorKind::NotConnected, "The connection was closed."))
+    }
+
+    /// Queue a message, or fail if it can't be sent as it is. See [`Message::validate`].
+    pub fn send_message(&self, message: &Message) -> io::Result<()> {
+        message
+            .validate()
+            .map_err(|e| io::Error::new(ErrorKind::InvalidInput, e))?;
+        self.send_line(&message.to_string())
+    }
+
+    /// Send a message to a channel or user.
+    pub fn privmsg(&self, target: &str, text: &str) -> io::Result<()> {
+        self.send_message(&Message::privmsg(target, text))
+    }
+
+    pub fn join(&self, channel: &str) -> io::Result<()> {
+        self.send_message(&Message::join(channel))
+    }
+
+    /// Take back the profile's nickname. See
+    /// [`Connection::reclaim_nick`](crate::Connection::reclaim_nick).
+    pub fn reclaim_nick(&self) -> io::Result<()> {
+        let line = self.session.lock().unwrap().reclaim_nick();
+        self.send_line(&line)
+    }
+}
+
+/// Events as they happen. The connection is reopened when it drops, so the stream only ends if
+/// the connection's task is gone.
+impl Stream for AsyncConnection {
+    type Item = Event;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Event>> {
+        self.get_mut().events.poll_recv(cx)
+    }
+}
+
+/// Messages are queued for the connection's task, so the sink is always ready.
+impl Sink<Message> for AsyncConnection {
+    type Error = io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, message: Message) -> io::Result<()> {
+        self.send_message(&message)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Open a connection to the profile's server. Proxies are spoken to with blocking sockets, so the
+/// socket is opened on a blocking thread and then handed to tokio.
+async fn open(profile: &Profile) -> io::Result<Box<dyn Socket>> {
+    let connect_to = profile.clone();
+    let socket = tokio::task::spawn_blocking(move || transport::open_socket(&connect_to))
+        .await
+        .map_err(io::Error::other)??;
+    socket.set_nonblocking(true)?;
+    let socket = TcpStream::from_std(socket)?;
+    if !profile.tls {
+        return Ok(Box::new(socket));
+    }
+
+    let server_name = ServerName::try_from(profile.host.clone())
+        .map_err(|e| io::Error::new(ErrorKind::InvalidInput, e))?;
+    let connector = TlsConnector::from(transport::tls_config(profile)?);
+    Ok(Box::new(connector.connect(server_name, socket).await?))
+}
+
+/// The connection's task: use the socket until it drops, then reconnect and start over.
+async fn run(
+    mut socket: Box<dyn Socket>,
+    session: Arc<Mutex<Session>>,
+    events: UnboundedSender<Event>,
+    mut lines: UnboundedReceiver<String>,
+    queued: Arc<AtomicUsize>,
+) {
+    // Kept across reconnecting, like the lines not yet taken from `lines`
+    let mut outgoing = Outgoing {
+        throttle: Throttle::new(),
+        queue: VecDeque::new(),
+        queued,
+    };
+    loop {
+        match drive(socket, &session, &events, &mut lines, &mut outgoing).await {
+            Ended::Dropped => return,
+            Ended::Disconnected => {
+                if events.send(Event::Disconnected).is_err() {
+                    return;
+                }
+            }
+        }
+
+        let profile = session.lock().unwrap().profile.clone();
+        let Some(new_socket) = reconnect_with_backoff(&profile, &events).await else {
+            return;
+        };
+        socket = new_socket;
+        if events.send(Event::Reconnected).is_err() {
+            return;
+        }
+    }
+}
+
+/// Lines held back by flood protection
+struct Outgoing {
+    throttle: Throttle,
+    queue: VecDeque<String>,
+    /// The length of the queue, for [`AsyncConnection::queued`]
+    queued: Arc<AtomicUsize>,
+}
+
+/// Register on a socket, then read from it, send what's queued and PING the server until the
+/// connection drops.
+async fn drive(
+    socket: Box<dyn Socket>,
+    session: &Mutex<Session>,
+    events: &UnboundedSender<Event>,
+    lines: &mut UnboundedReceiver<String>,
+    outgoing: &mut Outgoing,
+) -> Ended {
+    let (mut reader, mut writer) = tokio::io::split(socket);
+    let flood_protection = session.lock().unwrap().profile.flood_protection;
+    let registration = session.lock().unwrap().register();
+    if send_paced(&mut writer, &registration).await.is_err() {
+        return Ended::Disconnected;
+    }
+
+    // Wake up in time for the next PING
+    let mut ticks = time::interval(Duration::from_secs(1));
+    let decoding = session.lock().unwrap().profile.decoding;
+    let mut codec = IrcCodec::new().with_decoding(decoding);
+    let mut buffer = [0; 4096];
+    loop {
+        tokio::select! {
+            read = reader.read(&mut buffer) => {
+                match read {
+                    Ok(n) if n > 0 => codec.feed(&buffer[..n]),
+                    _ => return Ended::Disconnected,
+                }
+                // Lines that can't be parsed are skipped
+                while let Some(decoded) = codec.next_message() {
+                    let Ok(message) = decoded else {
+                        continue;
+                    };
+
+                    let replies = session.lock().unwrap().handle(&message);
+                    if send_paced(&mut writer, &replies).await.is_err() {
+                        return Ended::Disconnected;
+                    }
+                    for event in session::events_for(message) {
+                        if events.send(event).is_err() {
+                            return Ended::Dropped;
+                        }
+                    }
+                }
+            }
+            line = lines.recv() => {
+                let Some(line) = line else {
+                    return Ended::Dropped;
+                };
+                if !flood_protection {
+                    if send(&mut writer, &line).await.is_err() {
+                        return Ended::Disconnected;
+                    }
+                    continue;
+                }
+                outgoing.queue.push_back(line);
+            }
+            // Wake up when the next queued line can be sent
+            _ = time::sleep(outgoing.throttle.wait()), if !outgoing.queue.is_empty() => {}
+            _ = ticks.tick() => {
+                let ping = session.lock().unwrap().ping_if_due();
+                if let Some(ping) = ping
+                    && send(&mut writer, &ping).await.is_err()
+                {
+                    return Ended::Disconnected;
+                }
+            }
+        }
+
+        while let Some(line) = outgoing.queue.front()
+            && outgoing.throttle.try_take()
+        {
+            if send(&mut writer, line).await.is_err() {
+                return Ended::Disconnected;
+            }
+            outgoing.queue.pop_front();
+        }
+        outgoing
+            .queued
+            .store(outgoing.queue.len(), Ordering::Relaxed);
+    }
+}
+
+/// Try to connect until it works, waiting twice as long after each failure. Gives up only if the
+/// [`AsyncConnection`] was dropped.
+async fn reconnect_with_backoff(
+    profile: &Profile,
+    events: &UnboundedSender<Event>,
+) -> Option<Box<dyn Socket>> {
+    let mut delay = Duration::from_secs(1);
+    loop {
+        let status = format!("Reconnecting in {} seconds...", delay.as_secs());
+        events.send(Event::Status(status)).ok()?;
+        time::sleep(delay).await;
+
+        match open(profile).await {
+            Ok(socket) => return Some(socket),
+            Err(e) => {
+                events
+                    .send(Event::Status(format!("Couldn't reconnect: {e}")))
+                    .ok()?;
+                delay = (delay * 2).min(MAX_BACKOFF);
+            }
+        }
+    }
+}
+
+async fn send(writer: &mut WriteHalf<Box<dyn Socket>>, line: &str) -> io::Result<()> {
+    writer.write_all(format!("{line}\r\n").as_bytes()).await?;
+    writer.flush().await
+}
+
+/// Send several lines, pausing between them. See [`LINE_PACING`].
+async fn send_paced(writer: &mut WriteHalf<Box<dyn Socket>>, lines: &[String]) -> io::Result<()> {
+    for (i, line) in lines.iter().enumerate() {
+        if i > 0 {
+            time::sleep(LINE_PACING).await;
+        }
+        send(writer, line).await?;
+    }
+    Ok(())
+}
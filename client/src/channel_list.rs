@@ -0,0 +1,102 @@
+use std::cmp::Reverse;
+
+/// A channel in the server's reply to LIST
+#[derive(Debug, Clone)]
+pub struct ListEntry {
+    pub channel: String,
+    pub users: usize,
+    pub topic: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ListSort {
+    /// Busiest channels first
+    #[default]
+    Users,
+    Name,
+}
+
+/// Channels listed by the server after `/list`, browsed in their own buffer. What's typed in that
+/// buffer filters the channels, Up and Down move the selection and Enter joins the selected
+/// channel.
+#[derive(Debug, Default)]
+pub struct ChannelList {
+    /// In the sort order, apart from those added since the list was last sorted
+    entries: Vec<ListEntry>,
+    pub sort: ListSort,
+    /// Index of the selected channel among the ones shown
+    selected: usize,
+    /// Whether the server finished sending the list (RPL_LISTEND)
+    pub complete: bool,
+}
+
+impl ChannelList {
+    pub fn add(&mut self, entry: ListEntry) {
+        self.entries.push(entry);
+    }
+
+    /// Mark the list as complete, sorting the channels.
+    pub fn finish(&mut self) {
+        self.complete = true;
+        self.sort_entries();
+    }
+
+    /// Switch between sorting by users and by name.
+    pub fn toggle_sort(&mut self) {
+        self.sort = match self.sort {
+            ListSort::Users => ListSort::Name,
+            ListSort::Name => ListSort::Users,
+        };
+        self.sort_entries();
+    }
+
+    /// Number of channels listed, filtered or not
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// The channels whose name or topic contains the filter, ignoring case, in the sort order
+    pub fn shown(&self, filter: &str) -> Vec<&ListEntry> {
+        let filter = filter.to_lowercase();
+        let mut shown = self
+            .entries
+            .iter()
+            .filter(|entry| {
+                entry.channel.to_lowercase().contains(&filter)
+                    || entry.topic.to_lowercase().contains(&filter)
+            })
+            .collect::<Vec<_>>();
+        // Already in order once the list is complete, which makes this quick
+        match self.sort {
+            ListSort::Users => shown.sort_by_key(|entry| Reverse(entry.users)),
+            ListSort::Name => shown.sort_by_cached_key(|entry| entry.channel.to_lowercase()),
+        }
+        shown
+    }
+
+    /// Index of the selected channel among the ones shown with the filter
+    pub fn selected_index(&self, filter: &str) -> usize {
+        self.selected
+            .min(self.shown(filter).len().saturating_sub(1))
+    }
+
+    pub fn selected(&self, filter: &str) -> Option<&ListEntry> {
+        self.shown(filter).get(self.selected_index(filter)).copied()
+    }
+
+    /// Move the selection up (negative) or down by a number of channels.
+    pub fn move_selection(&mut self, offset: isize, filter: &str) {
+        self.selected = self.selected_index(filter).saturating_add_signed(offset);
+        self.selected = self.selected_index(filter);
+    }
+
+    fn sort_entries(&mut self) {
+        match self.sort {
+            ListSort::Users => self.entries.sort_by_key(|entry| Reverse(entry.users)),
+            ListSort::Name => self
+                .entries
+                .sort_by_cached_key(|entry| entry.channel.to_lowercase()),
+        }
+        self.selected = 0;
+    }
+}
@@ -0,0 +1,121 @@
+use crate::{
+    app::{Line, LineKind},
+    config::LoggingConfig,
+    export::Range,
+};
+use chrono::{Local, NaiveDateTime, TimeZone};
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::PathBuf,
+};
+
+const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// Writes the lines of each channel and query to its own file, e.g.
+/// `logs/irc.libera.chat/#rust.log`, one `[2024-05-01 18:30:00] <alice> hi` line at a time.
+#[derive(Debug)]
+pub struct ChatLog {
+    directory: PathBuf,
+    /// Lines of the log loaded into a buffer when it's opened
+    pub backlog: usize,
+    files: HashMap<String, File>,
+}
+
+impl ChatLog {
+    /// Logs of a server go in their own directory, named after it.
+    pub fn new(config: &LoggingConfig, server: &str) -> Self {
+        ChatLog {
+            directory: config.directory().join(file_name(server)),
+            backlog: config.backlog,
+            files: HashMap::new(),
+        }
+    }
+
+    fn path(&self, target: &str) -> PathBuf {
+        self.directory
+            .join(format!("{}.log", file_name(&target.to_lowercase())))
+    }
+
+    pub fn write(&mut self, target: &str, line: &Line) -> io::Result<()> {
+        let key = target.to_lowercase();
+        if !self.files.contains_key(&key) {
+            fs::create_dir_all(&self.directory)?;
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.path(target))?;
+            self.files.insert(key.clone(), file);
+        }
+        let file = self.files.get_mut(&key).unwrap();
+        let time = line.time.format(TIME_FORMAT);
+        match &line.sender {
+            Some(sender) => writeln!(file, "[{time}] {sender} {}", line.text),
+            None => writeln!(file, "[{time}] {}", line.text),
+        }
+    }
+
+    /// The last lines logged for a target, oldest first, to show as history.
+    pub fn tail(&self, target: &str) -> Vec<Line> {
+        if self.backlog == 0 {
+            return vec![];
+        }
+        let Ok(file) = File::open(self.path(target)) else {
+            return vec![];
+        };
+        let lines = BufReader::new(file)
+            .lines()
+            .map_while(Result::ok)
+            .collect::<Vec<_>>();
+        lines[lines.len().saturating_sub(self.backlog)..]
+            .iter()
+            .filter_map(|line| parse_line(line))
+            .collect()
+    }
+
+    /// All the lines logged for a target within a range of time, oldest first.
+    pub fn lines_in(&self, target: &str, range: &Range) -> io::Result<Vec<Line>> {
+        let file = File::open(self.path(target))?;
+        let mut lines = vec![];
+        for line in BufReader::new(file).lines() {
+            if let Some(line) = parse_line(&line?).filter(|line| range.contains(line.time)) {
+                lines.push(line);
+            }
+        }
+        Ok(lines)
+    }
+}
+
+fn parse_line(line: &str) -> Option<Line> {
+    let (time, text) = line.strip_prefix('[')?.split_once("] ")?;
+    let time = NaiveDateTime::parse_from_str(time, TIME_FORMAT).ok()?;
+
+    // Split off the sender of messages, notices and actions
+    let sender_end = if let Some(rest) = text.strip_prefix('<') {
+        rest.find("> ").map(|end| end + 2)
+    } else if let Some(rest) = text.strip_prefix('-') {
+        rest.find("- ").map(|end| end + 2)
+    } else if let Some(rest) = text.strip_prefix("* ") {
+        rest.find(' ').map(|end| end + 2)
+    } else {
+        None
+    };
+    let (sender, text) = match sender_end {
+        Some(end) => (Some(text[..end].to_string()), &text[end + 1..]),
+        None => (None, text),
+    };
+
+    Some(Line {
+        time: Local.from_local_datetime(&time).earliest()?,
+        kind: LineKind::Event, // Shown dimmed to set it apart from new messages
+        sender,
+        text: text.to_string(),
+        urls: vec![],
+    })
+}
+
+/// Replace characters that can't be in file names.
+pub fn file_name(name: &str) -> String {
+    name.replace(['/', '\\', ':', '\0'], "_")
+}
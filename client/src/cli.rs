@@ -0,0 +1,77 @@
+use crate::config::Config;
+use clap::Parser;
+use client::Profile;
+
+/// A terminal IRC client. Connection settings come from a profile in the config file, and any
+/// given here take their place.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// Profile of the config file to use, instead of the default one
+    #[arg(long, value_name = "NAME")]
+    profile: Option<String>,
+
+    /// Same as --profile, kept for `client <profile>`
+    #[arg(value_name = "PROFILE", conflicts_with = "profile", hide = true)]
+    profile_name: Option<String>,
+
+    /// Host name or address of the server. Without a profile, the nickname and names of the
+    /// default profile are used.
+    #[arg(short, long, value_name = "HOST")]
+    server: Option<String>,
+
+    /// Port of the server. Defaults to 6697 with TLS and 6667 without.
+    #[arg(short, long, value_parser = clap::value_parser!(u16).range(1..))]
+    port: Option<u16>,
+
+    /// Connect with TLS
+    #[arg(long)]
+    tls: bool,
+
+    /// Nickname to register with
+    #[arg(short, long, value_parser = parse_nick)]
+    nick: Option<String>,
+
+    /// Server password, sent with PASS before registering
+    #[arg(long)]
+    password: Option<String>,
+}
+
+impl Cli {
+    /// The name and settings of the network to connect to at startup.
+    pub fn profile(&self, config: &Config) -> Result<(String, Profile), String> {
+        let name = self.profile.as_deref().or(self.profile_name.as_deref());
+        let (name, mut profile) = match (name, &self.server) {
+            (None, Some(host)) => (host.clone(), config.profile_for_host(host)),
+            _ => config.profile(name)?,
+        };
+
+        if let Some(host) = &self.server {
+            profile.host = host.clone();
+        }
+        profile.port = self.port.or(profile.port);
+        profile.tls |= self.tls;
+        if let Some(nick) = &self.nick {
+            profile.nick = nick.clone();
+        }
+        if let Some(password) = &self.password {
+            profile.password = Some(password.clone());
+        }
+        Ok((name, profile))
+    }
+}
+
+/// Reject nicknames that couldn't be sent in a NICK command.
+fn parse_nick(nick: &str) -> Result<String, String> {
+    if nick.is_empty() {
+        Err("the nickname can't be empty".to_string())
+    } else if nick.contains([' ', ',', '*', '?', '!', '@']) {
+        Err("the nickname can't contain spaces or any of , * ? ! @".to_string())
+    } else if nick.starts_with(['#', '&', ':', '$'])
+        || nick.starts_with(|c: char| c.is_ascii_digit())
+    {
+        Err("the nickname can't start with a digit or any of # & : $".to_string())
+    } else {
+        Ok(nick.to_string())
+    }
+}
@@ -0,0 +1,617 @@
+use crate::{
+    ctcp,
+    export::{Format, Range},
+};
+use client::message::{Command, Message};
+use std::{collections::BTreeMap, path::PathBuf};
+
+/// What to send to the server for a line typed by the user.
+#[derive(Debug)]
+pub enum Input {
+    Message(Message),
+    /// Sent exactly as typed, for commands the client doesn't know about
+    Raw(String),
+    /// Open a private conversation with a user, sending them a message if one is given
+    Query {
+        nick: String,
+        message: Option<Message>,
+    },
+    /// Connect to another network, by profile name or host name
+    Connect(String),
+    Dcc(DccCommand),
+    Script(ScriptCommand),
+    /// Ignore a user, or list the ignored masks if none is given
+    Ignore(Option<String>),
+    Unignore(String),
+    /// Jump to the previous line containing the text, or stop searching if none is given
+    Search(Option<String>),
+    /// Switch to a theme, or list the themes if none is given
+    Theme(Option<String>),
+    /// Take back the profile's nickname, given as `/nick` without a nickname
+    ReclaimNick,
+    /// Open a link in the active buffer by its number, or the newest one if none is given
+    OpenUrl(Option<usize>),
+    /// List the links in the active buffer
+    Urls,
+    /// Show a buffer, by number or name, in a new pane, or the active buffer if none is given
+    Split(Option<String>),
+    /// Close the focused pane
+    Unsplit,
+    /// Open the networks and buffers from the last session again
+    Restore,
+    /// Ask for a channel's topic and put it in the input to be changed
+    Topic(String),
+    /// Write the active buffer to a file, or what was logged in a range of time if one is given.
+    /// Without a path, the file goes in the download directory.
+    Export {
+        range: Option<Range>,
+        path: Option<PathBuf>,
+        format: Format,
+    },
+    /// Run a shell command and show its output in the active buffer, or send the output to the
+    /// channel or user being talked to
+    Exec {
+        command: String,
+        send: bool,
+    },
+}
+
+/// File transfers with `/dcc`, which the client handles itself.
+#[derive(Debug)]
+pub enum DccCommand {
+    /// Offer a file to a user
+    Send {
+        nick: String,
+        path: PathBuf,
+    },
+    /// Accept a file offered by a user, the newest one if no file name is given
+    Get {
+        nick: String,
+        file: Option<String>,
+    },
+    /// Accept a file offered by a user, continuing the partial download of it
+    Resume {
+        nick: String,
+        file: Option<String>,
+    },
+    /// Cancel a transfer or reject an offer
+    Close {
+        nick: String,
+        file: Option<String>,
+    },
+    List,
+}
+
+/// Scripts managed with `/script`.
+#[derive(Debug)]
+pub enum ScriptCommand {
+    /// Load a script by path or by name from the scripts directory
+    Load(PathBuf),
+    Unload(String),
+    List,
+}
+
+/// Names of the supported commands, used for tab completion
+pub const COMMANDS: &[&str] = &[
+    "join", "msg", "query", "me", "nick", "part", "topic", "whois", "list", "dcc", "script",
+    "ignore", "unignore", "search", "theme", "open", "urls", "split", "unsplit", "connect",
+    "restore", "export", "exec", "quit", "raw",
+];
+
+/// Turn a line typed by the user into an IRC message. Lines starting with `/` are commands, e.g.
+/// `/join #rust`, and anything else is sent as a PRIVMSG to the active target (the channel or
+/// user being talked to). A line starting with `//` sends the rest as a message, starting with a
+/// single `/`.
+///
+/// Supported commands: `/join`, `/msg`, `/query`, `/me`, `/nick`, `/part`, `/topic`, `/whois`,
+/// `/list`, `/dcc`, `/script`, `/ignore`, `/unignore`, `/search`, `/theme`, `/open`, `/urls`,
+/// `/split`, `/unsplit`, `/connect`, `/restore`, `/export`, `/exec`, `/quit` and `/raw`.
+pub fn parse(line: &str, target: Option<&str>) -> Result<Input, String> {
+    let line = line.trim_end_matches(['\r', '\n']);
+
+    let Some(command_line) = line.strip_prefix('/').filter(|rest| !rest.starts_with('/')) else {
+        // Plain text, without the escaping slash if there was one
+        let text = line.strip_prefix('/').unwrap_or(line);
+        if text.is_empty() {
+            return Err("Nothing to send.".to_string());
+        }
+        let target = target.ok_or("You aren't talking to anyone. Use /join or /query first.")?;
+        return Ok(Input::Message(message(Command::PrivMsg, &[target, text])));
+    };
+
+    let (name, args) = command_line.split_once(' ').unwrap_or((command_line, ""));
+    let args = args.trim_start();
+
+    match name.to_lowercase().as_str() {
+        "join" | "j" => {
+            let (channel, key) = split_word(args);
+            let channel = required(channel, "/join <channel> [key]")?;
+            let mut params = vec![channel];
+            params.extend(key.map(str::trim));
+            Ok(Input::Message(message(Command::Join, &params)))
+        }
+        "query" => {
+            let (nick, text) = split_word(args);
+            let nick = required(nick, "/query <nick> [text]")?;
+            if nick.starts_with(['#', '&']) {
+                return Err("Use /join to talk in a channel.".to_string());
+            }
+            Ok(Input::Query {
+                nick: nick.to_string(),
+                message: text.map(|text| message(Command::PrivMsg, &[nick, text])),
+            })
+        }
+        "msg" => {
+            let (nick, text) = split_word(args);
+            let nick = required(nick, "/msg <target> <text>")?;
+            let text = required(text, "/msg <target> <text>")?;
+            Ok(Input::Message(message(Command::PrivMsg, &[nick, text])))
+        }
+        "me" => {
+            let text = required(Some(args).filter(|a| !a.is_empty()), "/me <text>")?;
+            let target =
+                target.ok_or("You aren't talking to anyone. Use /join or /query first.")?;
+            let action = ctcp::encode("ACTION", text);
+            Ok(Input::Message(message(
+                Command::PrivMsg,
+                &[target, &action],
+            )))
+        }
+        "nick" => match split_word(args).0 {
+            Some(nick) => Ok(Input::Message(message(Command::Nick, &[nick]))),
+            None => Ok(Input::ReclaimNick),
+        },
+        "part" | "leave" => {
+            let (channel, reason) = channel_and_rest(args, target);
+            let channel = required(channel, "/part [channel] [reason]")?;
+            let mut params = vec![channel];
+            params.extend(reason);
+            Ok(Input::Message(message(Command::Part, &params)))
+        }
+        // `-delete` removes the topic, which an empty argument can't be typed for
+        "topic" => {
+            let (channel, topic) = channel_and_rest(args, target);
+            let channel = required(channel, "/topic [channel] [topic|-delete]")?;
+            match topic {
+                Some("-delete") => Ok(Input::Message(message(Command::Topic, &[channel, ""]))),
+                Some(topic) => Ok(Input::Message(message(Command::Topic, &[channel, topic]))),
+                None => Ok(Input::Topic(channel.to_string())),
+            }
+        }
+        "whois" => {
+            let nick = required(Some(args).filter(|a| !a.is_empty()), "/whois <nickname>")?;
+            Ok(Input::Message(message(Command::Whois, &[nick])))
+        }
+        // Any arguments, like a mask or `>100` for channels with more users, go to the server
+        "list" => Ok(Input::Message(message(
+            Command::List,
+            &args.split_whitespace().collect::<Vec<_>>(),
+        ))),
+        "dcc" => parse_dcc(args).map(Input::Dcc),
+        "script" => {
+            const USAGE: &str = "/script load|unload|list [script]";
+            let (subcommand, name) = split_word(args);
+            match subcommand.map(str::to_lowercase).as_deref() {
+                Some("load") => {
+                    let path = required(name, "/script load <path or name>")?;
+                    Ok(Input::Script(ScriptCommand::Load(PathBuf::from(path))))
+                }
+                Some("unload") => {
+                    let name = required(name, "/script unload <name>")?;
+                    Ok(Input::Script(ScriptCommand::Unload(name.to_string())))
+                }
+                Some("list") | None => Ok(Input::Script(ScriptCommand::List)),
+                Some(_) => Err(format!("Usage: {USAGE}")),
+            }
+        }
+        "ignore" => match split_word(args).0 {
+            Some("-list") | None => Ok(Input::Ignore(None)),
+            Some(mask) => Ok(Input::Ignore(Some(mask.to_string()))),
+        },
+        "unignore" => {
+            let mask = required(split_word(args).0, "/unignore <nick or mask>")?;
+            Ok(Input::Unignore(mask.to_string()))
+        }
+        "search" => Ok(Input::Search(
+            Some(args).filter(|a| !a.is_empty()).map(str::to_string),
+        )),
+        "theme" => Ok(Input::Theme(split_word(args).0.map(str::to_string))),
+        "open" => match split_word(args).0 {
+            Some(number) => number
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .parse()
+                .map(|number| Input::OpenUrl(Some(number)))
+                .map_err(|_| "Usage: /open [number]".to_string()),
+            None => Ok(Input::OpenUrl(None)),
+        },
+        "urls" => Ok(Input::Urls),
+        "split" => Ok(Input::Split(split_word(args).0.map(str::to_string))),
+        "unsplit" => Ok(Input::Unsplit),
+        "connect" | "server" => {
+            let name = required(split_word(args).0, "/connect <profile or host>")?;
+            Ok(Input::Connect(name.to_string()))
+        }
+        "restore" => Ok(Input::Restore),
+        "export" => parse_export(args),
+        "exec" => {
+            let (send, command) = match split_word(args) {
+                (Some("-o"), command) => (true, command),
+                _ => (false, Some(args).filter(|a| !a.is_empty())),
+            };
+            let command = required(command, "/exec [-o] <command>")?;
+            if send && target.is_none() {
+                return Err("You aren't talking to anyone. Use /join or /query first.".to_string());
+            }
+            Ok(Input::Exec {
+                command: command.to_string(),
+                send,
+            })
+        }
+        "quit" | "exit" => {
+            let params = if args.is_empty() { vec![] } else { vec![args] };
+            Ok(Input::Message(message(Command::Quit, &params)))
+        }
+        "raw" | "quote" => {
+            let raw = required(Some(args).filter(|a| !a.is_empty()), "/raw <line>")?;
+            Ok(Input::Raw(raw.to_string()))
+        }
+        _ => Err(format!("Unknown command: /{name}")),
+    }
+}
+
+/// Expand a user-defined alias at the start of a line, e.g. `/ns identify hunter2` into
+/// `/msg NickServ identify hunter2` with `ns = "/msg NickServ $*"`. In the alias, `$1` to `$9` are
+/// replaced by the arguments, `$2-` by the arguments from the second on and `$*` by all of them.
+/// Without any of those, the arguments are added at the end. Aliases aren't expanded again, so
+/// one can be named after the command it runs.
+pub fn expand_alias(line: &str, aliases: &BTreeMap<String, String>) -> String {
+    let Some(command_line) = line.strip_prefix('/').filter(|rest| !rest.starts_with('/')) else {
+        return line.to_string();
+    };
+    let (name, args) = command_line.split_once(' ').unwrap_or((command_line, ""));
+    let Some(alias) = aliases
+        .iter()
+        .find_map(|(alias, expansion)| alias.eq_ignore_ascii_case(name).then_some(expansion))
+    else {
+        return line.to_string();
+    };
+
+    let mut expanded = String::new();
+    let mut substituted = false;
+    let mut chars = alias.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            expanded.push(c);
+            continue;
+        }
+        match chars.peek() {
+            Some('*') => {
+                chars.next();
+                expanded.push_str(args_from(args, 0));
+            }
+            Some(&digit @ '1'..='9') => {
+                chars.next();
+                let rest = args_from(args, digit.to_digit(10).unwrap() as usize - 1);
+                if chars.next_if_eq(&'-').is_some() {
+                    expanded.push_str(rest);
+                } else {
+                    expanded.push_str(split_word(rest).0.unwrap_or_default());
+                }
+            }
+            _ => {
+                expanded.push('$');
+                continue;
+            }
+        }
+        substituted = true;
+    }
+
+    let args = args.trim();
+    if !substituted && !args.is_empty() {
+        expanded.push(' ');
+        expanded.push_str(args);
+    }
+    expanded
+}
+
+/// The arguments from the `n`th one (counting from 0) on, as typed
+fn args_from(args: &str, n: usize) -> &str {
+    let mut rest = args.trim();
+    for _ in 0..n {
+        rest = split_word(rest).1.unwrap_or_default();
+    }
+    rest
+}
+
+/// `/dcc send <nick> <file>`, `/dcc get <nick> [file]`, `/dcc resume <nick> [file]`,
+/// `/dcc close <nick> [file]` or `/dcc list`
+fn parse_dcc(args: &str) -> Result<DccCommand, String> {
+    const USAGE: &str = "/dcc send|get|resume|close|list [nick] [file]";
+    let (subcommand, rest) = split_word(args);
+    let (nick, file) = split_word(rest.unwrap_or_default());
+    let nick = nick.map(str::to_string);
+    let file = file.map(str::to_string);
+
+    match subcommand.map(str::to_lowercase).as_deref() {
+        Some("send") => Ok(DccCommand::Send {
+            nick: required(nick.as_deref(), "/dcc send <nick> <file>")?.to_string(),
+            path: PathBuf::from(required(file.as_deref(), "/dcc send <nick> <file>")?),
+        }),
+        Some("get" | "accept") => Ok(DccCommand::Get {
+            nick: required(nick.as_deref(), "/dcc get <nick> [file]")?.to_string(),
+            file,
+        }),
+        Some("resume") => Ok(DccCommand::Resume {
+            nick: required(nick.as_deref(), "/dcc resume <nick> [file]")?.to_string(),
+            file,
+        }),
+        Some("close" | "reject") => Ok(DccCommand::Close {
+            nick: required(nick.as_deref(), "/dcc close <nick> [file]")?.to_string(),
+            file,
+        }),
+        Some("list") | None => Ok(DccCommand::List),
+        Some(_) => Err(format!("Usage: {USAGE}")),
+    }
+}
+
+/// `/export [range] [file]`, where the file name ends in `.html` or `.md`, or `/export [range]
+/// [html|md]` to pick a format without naming the file. Ranges are described in [`Range::parse`].
+fn parse_export(args: &str) -> Result<Input, String> {
+    const USAGE: &str = "Usage: /export [range] [file.html|file.md]";
+    let mut range = None;
+    let mut path = None;
+    let mut format = None;
+    for word in args.split_whitespace() {
+        let file = PathBuf::from(word);
+        if let Some(file_format) = Format::from_path(&file) {
+            format = Some(file_format);
+            path = Some(file);
+        } else if let Some(named) = Format::from_name(word) {
+            format = Some(named);
+        } else if range.is_none() {
+            range = Some(Range::parse(word)?);
+        } else {
+            return Err(USAGE.to_string());
+        }
+    }
+    Ok(Input::Export {
+        range,
+        path,
+        format: format.unwrap_or(Format::Html),
+    })
+}
+
+/// Most bytes in a message, including the CRLF it ends with
+const MAX_MESSAGE_LENGTH: usize = 512;
+
+/// Split a PRIVMSG or NOTICE that's too long for one message into several, between words where
+/// possible. The server relays it with `:nick!user@host ` in front, which has to fit as well, so
+/// room is left for a long username and host name. Actions are split into several actions, and
+/// other messages are returned as they are.
+pub fn split_message(message: Message, nick: &str) -> Vec<Message> {
+    let (Command::PrivMsg | Command::Notice, [target, text]) =
+        (&message.command, &message.params[..])
+    else {
+        return vec![message];
+    };
+    let (action, text) = match ctcp::parse(text) {
+        Some(("ACTION", action)) => (true, action),
+        Some(_) => return vec![message],
+        None => (false, text.as_str()),
+    };
+
+    let prefix = format!(":{nick}!{}@{} ", "u".repeat(10), "h".repeat(63));
+    let command = format!("{} {target} :", message.command);
+    let wrapping = if action {
+        ctcp::encode("ACTION", "").len() + 1
+    } else {
+        0
+    };
+    let available = MAX_MESSAGE_LENGTH
+        .saturating_sub(prefix.len() + command.len() + wrapping + 2)
+        .max(1);
+
+    let mut parts = vec![];
+    let mut rest = text;
+    while rest.len() > available {
+        // The last space that fits, or as many whole characters as fit in a word that's too long
+        let mut end = available;
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        let end = match rest[..end].rfind(' ') {
+            Some(space) if space > 0 => space,
+            _ if end == 0 => rest.chars().next().map_or(1, char::len_utf8),
+            _ => end,
+        };
+        parts.push(&rest[..end]);
+        rest = rest[end..].trim_start_matches(' ');
+    }
+    if !rest.is_empty() || parts.is_empty() {
+        parts.push(rest);
+    }
+
+    parts
+        .into_iter()
+        .map(|part| {
+            let text = if action {
+                ctcp::encode("ACTION", part)
+            } else {
+                part.to_string()
+            };
+            Message {
+                tags: vec![],
+                prefix: None,
+                command: message.command.clone(),
+                params: vec![target.clone(), text],
+            }
+        })
+        .collect()
+}
+
+fn message(command: Command, params: &[&str]) -> Message {
+    Message {
+        tags: vec![],
+        prefix: None,
+        command,
+        params: params.iter().map(|s| s.to_string()).collect(),
+    }
+}
+
+/// Split off the first word, returning `None` for missing parts.
+fn split_word(input: &str) -> (Option<&str>, Option<&str>) {
+    let (word, rest) = input.split_once(' ').unwrap_or((input, ""));
+    let rest = rest.trim_start();
+    (
+        Some(word).filter(|w| !w.is_empty()),
+        Some(rest).filter(|r| !r.is_empty()),
+    )
+}
+
+/// Split arguments that start with an optional channel name, which defaults to the active target.
+fn channel_and_rest<'a>(
+    args: &'a str,
+    target: Option<&'a str>,
+) -> (Option<&'a str>, Option<&'a str>) {
+    if args.starts_with(['#', '&']) {
+        split_word(args)
+    } else {
+        (target, Some(args).filter(|a| !a.is_empty()))
+    }
+}
+
+fn required<'a>(arg: Option<&'a str>, usage: &str) -> Result<&'a str, String> {
+    arg.ok_or_else(|| format!("Usage: {usage}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alias_table(aliases: &[(&str, &str)]) -> BTreeMap<String, String> {
+        aliases
+            .iter()
+            .map(|(name, expansion)| (name.to_string(), expansion.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn aliases_put_arguments_in_place() {
+        let aliases = alias_table(&[
+            ("ns", "/msg NickServ $*"),
+            ("kb", "/kick $1 $2 :$3-"),
+            ("greet", "/me waves at $1"),
+            ("j", "/join"),
+        ]);
+        assert_eq!(
+            expand_alias("/ns identify hunter2", &aliases),
+            "/msg NickServ identify hunter2"
+        );
+        assert_eq!(
+            expand_alias("/KB #rust bob go  away now", &aliases),
+            "/kick #rust bob :go  away now"
+        );
+        // Arguments that weren't given are left empty
+        assert_eq!(expand_alias("/kb #rust", &aliases), "/kick #rust  :");
+        assert_eq!(expand_alias("/greet", &aliases), "/me waves at ");
+        // Without any `$`s, the arguments go at the end
+        assert_eq!(expand_alias("/j #rust", &aliases), "/join #rust");
+        assert_eq!(expand_alias("/j", &aliases), "/join");
+        // A `$` that isn't followed by anything it stands for is kept
+        let aliases = alias_table(&[("price", "/say $5 is $$0")]);
+        assert_eq!(expand_alias("/price a b c d e", &aliases), "/say e is $$0");
+    }
+
+    #[test]
+    fn aliases_are_only_expanded_once() {
+        let aliases = alias_table(&[("msg", "/msg $1 [relayed] $2-"), ("a", "/b"), ("b", "/a")]);
+        assert_eq!(
+            expand_alias("/msg bob hello", &aliases),
+            "/msg bob [relayed] hello"
+        );
+        assert_eq!(expand_alias("/a x", &aliases), "/b x");
+        // Lines that aren't commands, and unknown commands, are left alone
+        assert_eq!(expand_alias("//a", &aliases), "//a");
+        assert_eq!(expand_alias("a x", &aliases), "a x");
+        assert_eq!(expand_alias("/c x", &aliases), "/c x");
+    }
+
+    /// How long a message is once the server relays it with a prefix, CRLF included
+    fn relayed_length(message: &Message, nick: &str) -> usize {
+        let prefix = format!(":{nick}!{}@{} ", "u".repeat(10), "h".repeat(63));
+        prefix.len() + message.to_string().len() + 2
+    }
+
+    fn texts(messages: &[Message]) -> Vec<&str> {
+        messages
+            .iter()
+            .map(|message| message.params[1].as_str())
+            .collect()
+    }
+
+    #[test]
+    fn short_messages_are_not_split() {
+        let message = Message::privmsg("#rust", "hello");
+        assert_eq!(split_message(message.clone(), "alice"), [message]);
+    }
+
+    #[test]
+    fn long_messages_are_split_between_words_to_fit_when_relayed() {
+        let words = (0..200).map(|n| format!("word{n}")).collect::<Vec<_>>();
+        let text = words.join(" ");
+        let parts = split_message(Message::privmsg("#rust", &text), "alice");
+
+        assert!(parts.len() > 1);
+        for part in &parts {
+            assert!(relayed_length(part, "alice") <= MAX_MESSAGE_LENGTH);
+            assert_eq!(part.command, Command::PrivMsg);
+            assert_eq!(part.params[0], "#rust");
+        }
+        assert_eq!(texts(&parts).join(" "), text);
+        // As much as fits goes in each part but the last
+        let first = &parts[0];
+        let next_word = texts(&parts)[1].split(' ').next().unwrap();
+        assert!(relayed_length(first, "alice") + 1 + next_word.len() > MAX_MESSAGE_LENGTH);
+    }
+
+    #[test]
+    fn words_too_long_for_a_line_are_split_between_characters() {
+        let text = "é".repeat(400);
+        let parts = split_message(Message::privmsg("#rust", &text), "alice");
+
+        assert_eq!(parts.len(), 2);
+        for part in &parts {
+            assert!(relayed_length(part, "alice") <= MAX_MESSAGE_LENGTH);
+        }
+        assert_eq!(texts(&parts).concat(), text);
+        // The first part stops at the last whole character that fits, with room for the colon
+        // that's left out before a single word
+        assert!(relayed_length(&parts[0], "alice") + 1 + "é".len() > MAX_MESSAGE_LENGTH);
+    }
+
+    #[test]
+    fn longer_nicknames_leave_less_room() {
+        let text = "a".repeat(800);
+        let short = split_message(Message::privmsg("#rust", &text), "al");
+        let long = split_message(Message::privmsg("#rust", &text), &"a".repeat(30));
+        assert!(texts(&long)[0].len() < texts(&short)[0].len());
+        assert_eq!(texts(&short)[0].len() - texts(&long)[0].len(), 28);
+    }
+
+    #[test]
+    fn actions_are_split_into_actions() {
+        let text = "dances ".repeat(100);
+        let action = ctcp::encode("ACTION", text.trim_end());
+        let parts = split_message(Message::privmsg("bob", &action), "alice");
+
+        assert!(parts.len() > 1);
+        for part in &parts {
+            assert!(relayed_length(part, "alice") <= MAX_MESSAGE_LENGTH);
+            assert!(matches!(ctcp::parse(&part.params[1]), Some(("ACTION", _))));
+        }
+        // Other CTCP messages are sent as they are
+        let version = Message::privmsg("bob", &ctcp::encode("VERSION", &"x".repeat(600)));
+        assert_eq!(split_message(version.clone(), "alice"), [version]);
+    }
+}
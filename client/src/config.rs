@@ -0,0 +1,340 @@
+use crate::{app::DEFAULT_SCROLLBACK, theme::ThemeConfig};
+use client::Profile;
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    env, fs, io,
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
+use toml_edit::{Array, DocumentMut};
+
+/// Client settings, loaded from `~/.config/irc_rs/config.toml` such as:
+///
+/// ```toml
+/// default_profile = "local"
+/// ctcp_replies = true
+/// ignore = ["troll", "*!*@spam.example"]
+/// strip_formatting = false
+/// scrollback_lines = 5000
+/// save_history = true
+/// save_session = true
+/// history_backfill = 50
+/// scripts = ["greeter", "~/irc/auto_op.rhai"]
+/// theme = "default"
+/// mouse = true
+/// nicklist = true
+/// topic_bar = true
+///
+/// [aliases]
+/// ns = "/msg NickServ $*"
+/// slap = "/me slaps $1 around a bit with a large trout"
+///
+/// [themes.mine]
+/// base = "light"
+/// own_nick = { fg = "magenta", bold = true }
+///
+/// [highlight]
+/// words = ["rust"]
+/// patterns = ["^alice[:,]"]
+/// bell = true
+///
+/// [[notify]]
+/// channel = "^#ops$"
+/// text = "deploy (failed|broken)"
+/// actions = ["highlight", "desktop"]
+///
+/// [logging]
+/// enabled = true
+/// backlog = 50
+///
+/// [dcc]
+/// download_directory = "~/Downloads/irc"
+/// max_file_size = 104857600
+///
+/// [profiles.local]
+/// host = "127.0.0.1"
+/// nick = "alice"
+/// autojoin = ["#general"]
+///
+/// [profiles.libera]
+/// host = "irc.libera.chat"
+/// tls = true
+/// nick = "alice"
+/// realname = "Alice Liddell"
+/// autojoin = ["#rust", "#irc_rs"]
+///
+/// [profiles.libera.sasl]
+/// username = "alice"
+/// password = "hunter2"
+///
+/// [profiles.tor]
+/// host = "libera75jm6of4wxpxt4aynol3xjmbtxgfyjpu34ss4d7r7q2v5zrpyd.onion"
+/// nick = "alice"
+///
+/// [profiles.tor.proxy]
+/// type = "socks5"
+/// host = "127.0.0.1"
+/// port = 9050
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Profile used when none is given on the command line. Defaults to the first one.
+    pub default_profile: Option<String>,
+    /// Whether to answer CTCP requests such as VERSION and PING
+    pub ctcp_replies: bool,
+    /// Masks of users whose messages aren't shown, managed with `/ignore` and `/unignore`
+    pub ignore: Vec<String>,
+    /// Show messages without mIRC colors, bold and other formatting
+    pub strip_formatting: bool,
+    /// Lines kept in each buffer, older ones are dropped
+    pub scrollback_lines: usize,
+    /// Keep the lines typed in each buffer between runs
+    pub save_history: bool,
+    /// Remember the networks and buffers open when quitting, to be opened again with `/restore`
+    pub save_session: bool,
+    /// Messages to fetch from the server's history when joining a channel, if it supports
+    /// CHATHISTORY. 0 turns this off.
+    pub history_backfill: usize,
+    /// Lines of output `/exec -o` sends at most, so a command printing a lot doesn't flood the
+    /// channel. The rest are only shown.
+    pub exec_max_lines: usize,
+    /// Commands of the user's own, by name. See [`crate::command::expand_alias`].
+    pub aliases: BTreeMap<String, String>,
+    /// Scripts loaded at startup. See [`crate::script::Scripts::load`].
+    pub scripts: Vec<PathBuf>,
+    /// Colors of the interface, by the name of a built-in theme or one in `themes`. Switched with
+    /// `/theme`.
+    pub theme: String,
+    /// Themes of the user's own. See [`crate::theme::ThemeConfig`].
+    pub themes: BTreeMap<String, ThemeConfig>,
+    /// Scroll with the mouse wheel and click buffers and nicknames. Turned off, the terminal
+    /// selects text with the mouse as usual.
+    pub mouse: bool,
+    /// Show the users of channels in a sidebar, toggled with Alt+N
+    pub nicklist: bool,
+    /// Show the topic of channels above their messages, toggled with Alt+T
+    pub topic_bar: bool,
+    pub highlight: HighlightConfig,
+    /// What to do about other messages, besides highlighting ones that mention the user
+    pub notify: Vec<NotifyRule>,
+    pub logging: LoggingConfig,
+    pub dcc: DccConfig,
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+/// Messages that mention the user, which always includes ones containing their nickname.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct HighlightConfig {
+    /// Other words to highlight, matched as whole words ignoring case
+    pub words: Vec<String>,
+    /// Regular expressions to highlight, ignoring case
+    pub patterns: Vec<String>,
+    /// Ring the terminal bell on highlights
+    pub bell: bool,
+    /// Show a desktop notification on highlights
+    pub desktop_notification: bool,
+}
+
+/// A notification rule, for messages matching all of its patterns. The patterns are regular
+/// expressions, matched ignoring case, and a missing one matches anything.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct NotifyRule {
+    /// Matched against the message, without its formatting
+    pub text: Option<String>,
+    /// Matched against the sender's nickname
+    pub sender: Option<String>,
+    /// Matched against the channel, or the sender's nickname for private messages
+    pub channel: Option<String>,
+    pub actions: Vec<NotifyAction>,
+    /// Shell command run by the `command` action
+    pub command: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NotifyAction {
+    /// Show the message highlighted, and the buffer as highlighted in the status bar
+    Highlight,
+    /// Ring the terminal bell
+    Bell,
+    /// Show a desktop notification
+    Desktop,
+    /// Run the rule's command, with the message in `IRC_BUFFER`, `IRC_SENDER` and `IRC_TEXT`
+    Command,
+}
+
+/// Logs of channels and private conversations.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    pub enabled: bool,
+    /// Defaults to `irc_rs/logs` in the system's data directory
+    pub directory: Option<PathBuf>,
+    /// Lines of the log to show in a buffer when it's opened
+    pub backlog: usize,
+}
+
+/// File transfers with other users.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct DccConfig {
+    /// Where received files are saved. Defaults to the system's download directory.
+    pub download_directory: Option<PathBuf>,
+    /// Offers of larger files (in bytes) are ignored
+    pub max_file_size: u64,
+    /// Ask the receiver to listen for the connection when sending files, for when other users
+    /// can't connect to us (e.g. behind NAT)
+    pub passive: bool,
+    /// Address other users connect to, if it isn't the one used to connect to the server
+    pub address: Option<IpAddr>,
+}
+
+impl Config {
+    /// Default location of the config file
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("irc_rs").join("config.toml"))
+    }
+
+    /// Read and parse a config file.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let text = fs::read_to_string(path)?;
+        Ok(toml::from_str(&text)?)
+    }
+
+    /// Replace the ignore list in a config file, creating the file if needed. The rest of the
+    /// file, comments included, stays as it is.
+    pub fn save_ignore_list(
+        path: &Path,
+        masks: &[String],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let mut document = text.parse::<DocumentMut>()?;
+        document["ignore"] = toml_edit::value(masks.iter().collect::<Array>());
+
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+        fs::write(path, document.to_string())?;
+        Ok(())
+    }
+
+    /// Find a profile by name, or the default profile if no name is given, along with its name.
+    /// Without any profiles configured, the default profile connects to a local server and is
+    /// named after its host.
+    pub fn profile(&self, name: Option<&str>) -> Result<(String, Profile), String> {
+        match name.or(self.default_profile.as_deref()) {
+            Some(name) => match self.profiles.get(name) {
+                Some(profile) => Ok((name.to_string(), profile.clone())),
+                None => {
+                    let names = self.profiles.keys().cloned().collect::<Vec<_>>();
+                    Err(format!(
+                        "No profile named {name}. Available profiles: {}",
+                        names.join(", ")
+                    ))
+                }
+            },
+            None => Ok(match self.profiles.iter().next() {
+                Some((name, profile)) => (name.clone(), profile.clone()),
+                None => {
+                    let profile = Profile::default();
+                    (profile.host.clone(), profile)
+                }
+            }),
+        }
+    }
+
+    /// Settings to register with a server that has no profile, like with the default profile but
+    /// without its channels or credentials.
+    pub fn profile_for_host(&self, host: &str) -> Profile {
+        let default = self.profile(None).map(|(_, profile)| profile);
+        let default = default.unwrap_or_default();
+        Profile {
+            host: host.to_string(),
+            nick: default.nick,
+            alt_nicks: default.alt_nicks,
+            username: default.username,
+            realname: default.realname,
+            flood_protection: default.flood_protection,
+            decoding: default.decoding,
+            ..Profile::default()
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            default_profile: None,
+            ctcp_replies: true,
+            ignore: vec![],
+            strip_formatting: false,
+            scrollback_lines: DEFAULT_SCROLLBACK,
+            save_history: true,
+            save_session: true,
+            history_backfill: 50,
+            exec_max_lines: 10,
+            aliases: BTreeMap::new(),
+            scripts: vec![],
+            theme: "default".to_string(),
+            themes: BTreeMap::new(),
+            mouse: true,
+            nicklist: true,
+            topic_bar: true,
+            highlight: HighlightConfig::default(),
+            notify: vec![],
+            logging: LoggingConfig::default(),
+            dcc: DccConfig::default(),
+            profiles: BTreeMap::new(),
+        }
+    }
+}
+
+impl LoggingConfig {
+    pub fn directory(&self) -> PathBuf {
+        match &self.directory {
+            Some(directory) => expand_home(directory),
+            None => dirs::data_dir()
+                .unwrap_or_default()
+                .join("irc_rs")
+                .join("logs"),
+        }
+    }
+}
+
+impl DccConfig {
+    pub fn download_directory(&self) -> PathBuf {
+        match &self.download_directory {
+            Some(directory) => expand_home(directory),
+            None => dirs::download_dir()
+                .or_else(dirs::home_dir)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Replace a leading `~` with the home directory.
+pub fn expand_home(path: &Path) -> PathBuf {
+    match path.strip_prefix("~") {
+        Ok(rest) => dirs::home_dir().unwrap_or_default().join(rest),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+impl Default for DccConfig {
+    fn default() -> Self {
+        DccConfig {
+            download_directory: None,
+            max_file_size: 1024 * 1024 * 1024, // 1 GB
+            passive: false,
+            address: None,
+        }
+    }
+}
@@ -0,0 +1,424 @@
+use crate::{
+    message::Message,
+    profile::Profile,
+    session::{self, Session},
+    throttle::Throttle,
+    transport::Transport,
+};
+use shared::{
+    codec::{Decoding, IrcCodec},
+    isupport::ISupport,
+};
+use std::{
+    collections::VecDeque,
+    io::{self, ErrorKind, Read, Write},
+    net::IpAddr,
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Wait before the first attempt to reconnect
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Longest wait between attempts to reconnect
+pub(crate) const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// How long to wait between lines sent together. Some servers read one message at a time, so
+/// they're given a moment to handle each before the next.
+pub(crate) const LINE_PACING: Duration = Duration::from_millis(100);
+
+/// Something that happened on a connection.
+///
+/// Every line from the server is reported as [`Event::Message`]. The ones that have a more
+/// specific event, such as JOIN, are followed by it, so a bot can match on the events it cares
+/// about and a client can show every message.
+///
+/// Servers with the echo-message capability, which is requested when offered, send our own
+/// PRIVMSGs and NOTICEs back as well, from our nickname.
+#[derive(Debug)]
+pub enum Event {
+    Message(Message),
+    Join {
+        nick: String,
+        channel: String,
+    },
+    Nick {
+        old: String,
+        new: String,
+    },
+    /// Numeric reply, without the nickname it starts with
+    Numeric {
+        code: u16,
+        params: Vec<String>,
+    },
+    /// The connection dropped. Reconnecting starts right away.
+    Disconnected,
+    /// Progress of reconnecting
+    Status(String),
+    /// A new connection was made and registration started
+    Reconnected,
+    /// Something couldn't be sent to the server
+    Error(String),
+}
+
+/// Round-trip time to a server, measured with PINGs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Lag {
+    /// No PING has been answered yet
+    Unknown,
+    Measured(Duration),
+    /// The server hasn't answered a PING for this long
+    Stalled(Duration),
+}
+
+/// What the network threads pass to the connection.
+enum Incoming {
+    Message(Message),
+    Disconnected,
+    /// A new connection was made after being disconnected, with the wait before trying again
+    /// if it drops before the server says anything
+    Reconnected(Transport, Duration),
+    Status(String),
+}
+
+/// The connection to a server, which is reopened in the background whenever it drops.
+///
+/// Connecting registers with the server, and the connection takes care of what keeps it going
+/// while events are read: answering PINGs, authenticating with SASL, joining the profile's
+/// channels, and rejoining channels after reconnecting.
+///
+/// Lines sent with [`Connection::send`] and the methods built on it are paced when the profile
+/// has flood protection, going out a few at once and then about one a second. The rest wait in a
+/// queue, which is sent from while events are read.
+///
+/// ```no_run
+/// use client::{Connection, Event, Profile};
+///
+/// let profile = Profile {
+///     host: "irc.libera.chat".to_string(),
+///     nick: "rustbot".to_string(),
+///     autojoin: vec!["#irc_rs".to_string()],
+///     ..Profile::default()
+/// };
+/// let mut connection = Connection::connect(profile)?;
+/// while let Some(event) = connection.next_event() {
+///     if let Event::Join { nick, channel } = event {
+///         connection.privmsg(&channel, &format!("Welcome, {nick}!"))?;
+///     }
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct Connection {
+    session: Session,
+    /// Used for sending. Its reading half belongs to the receiving thread.
+    transport: Option<Transport>,
+    sender: Sender<Incoming>,
+    receiver: Receiver<Incoming>,
+    /// Events not yet taken by [`Connection::poll`]
+    events: VecDeque<Event>,
+    throttle: Throttle,
+    /// Lines waiting for the throttle to let them through
+    queue: VecDeque<String>,
+    /// Lines sent with [`Connection::send_paced`] that are waiting for their turn
+    paced: VecDeque<String>,
+    /// When the next paced line can be sent
+    paced_at: Instant,
+    /// Wait before the next attempt to reconnect. It grows while new connections keep dropping,
+    /// and starts over once a server answers.
+    backoff: Duration,
+}
+
+impl Connection {
+    /// Connect to the profile's server and register.
+    pub fn connect(profile: Profile) -> io::Result<Self> {
+        let transport = Transport::connect(&profile)?;
+        let (sender, receiver) = mpsc::channel();
+        let mut connection = Connection {
+            session: Session::new(profile),
+            transport: None,
+            sender,
+            receiver,
+            events: VecDeque::new(),
+            throttle: Throttle::new(),
+            queue: VecDeque::new(),
+            paced: VecDeque::new(),
+            paced_at: Instant::now(),
+            backoff: FIRST_BACKOFF,
+        };
+        connection.attach(transport);
+        let registration = connection.session.register();
+        connection.send_paced(&registration)?;
+        Ok(connection)
+    }
+
+    /// The next event, if there is one yet.
+    pub fn poll(&mut self) -> Option<Event> {
+        if let Some(ping) = self.session.ping_if_due() {
+            self.send_or_report(&ping);
+        }
+        if let Err(e) = self.send_due_paced().and_then(|()| self.send_queued()) {
+            self.events.push_back(Event::Error(e.to_string()));
+        }
+        while self.events.is_empty() {
+            let incoming = self.receiver.try_recv().ok()?;
+            self.handle(incoming);
+        }
+        self.events.pop_front()
+    }
+
+    /// Wait for the next event. The connection is reopened when it drops, so this only returns
+    /// `None` if the network threads are gone.
+    pub fn next_event(&mut self) -> Option<Event> {
+        loop {
+            if let Some(event) = self.poll() {
+                return Some(event);
+            }
+            // Wake up in time for the next PING, or the next paced or queued line
+            let mut timeout = Duration::from_secs(1);
+            if !self.paced.is_empty() {
+                timeout = timeout.min(self.paced_at.saturating_duration_since(Instant::now()));
+            } else if !self.queue.is_empty() {
+                timeout = timeout.min(self.throttle.wait());
+            }
+            match self.receiver.recv_timeout(timeout) {
+                Ok(incoming) => self.handle(incoming),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return None,
+            }
+        }
+    }
+
+    /// Events as they happen, waiting for each. See [`Connection::next_event`].
+    pub fn events(&mut self) -> impl Iterator<Item = Event> + '_ {
+        std::iter::from_fn(|| self.next_event())
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.transport.is_some()
+    }
+
+    pub fn profile(&self) -> &Profile {
+        &self.session.profile
+    }
+
+    /// Our nickname on the server
+    pub fn nick(&self) -> &str {
+        self.session.nick()
+    }
+
+    /// The address we connect to the server from
+    pub fn local_ip(&self) -> Option<IpAddr> {
+        let transport = self.transport.as_ref()?;
+        transport.local_addr().ok().map(|address| address.ip())
+    }
+
+    /// Whether the server acknowledged a capability, such as `server-time`. The connection asks
+    /// for the ones it knows how to use.
+    pub fn has_cap(&self, cap: &str) -> bool {
+        self.session.has_cap(cap)
+    }
+
+    /// What the server supports, like the longest nickname and how names are compared, from
+    /// what it said after welcoming us
+    pub fn isupport(&self) -> &ISupport {
+        self.session.isupport()
+    }
+
+    pub fn lag(&self) -> Lag {
+        self.session.lag()
+    }
+
+    /// How many lines are waiting to be sent because of flood protection
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Send a raw line, without its line ending. With flood protection, it may wait in the queue
+    /// for a while. It also waits for lines being paced, like the registration, to go first.
+    pub fn send(&mut self, line: &str) -> io::Result<()> {
+        if !self.is_connected() {
+            return Err(not_connected());
+        }
+        if self.session.profile.flood_protection {
+            self.queue.push_back(line.to_string());
+            return self.send_queued();
+        }
+        if !self.paced.is_empty() {
+            // Kept in order behind lines like the registration
+            self.paced.push_back(line.to_string());
+            return Ok(());
+        }
+        self.send_now(line)
+    }
+
+    /// Send a raw line right away, ahead of any queued lines. For lines that can't wait, like a
+    /// QUIT before closing the client.
+    pub fn send_now(&mut self, line: &str) -> io::Result<()> {
+        match &mut self.transport {
+            Some(transport) => transport.writer.write_all(format!("{line}\r\n").as_bytes()),
+            None => Err(not_connected()),
+        }
+    }
+
+    /// Send a message, or fail if it can't be sent as it is. See [`Message::validate`].
+    pub fn send_message(&mut self, message: &Message) -> io::Result<()> {
+        message
+            .validate()
+            .map_err(|e| io::Error::new(ErrorKind::InvalidInput, e))?;
+        self.send(&message.to_string())
+    }
+
+    /// Send a message to a channel or user.
+    pub fn privmsg(&mut self, target: &str, text: &str) -> io::Result<()> {
+        self.send_message(&Message::privmsg(target, text))
+    }
+
+    pub fn join(&mut self, channel: &str) -> io::Result<()> {
+        self.send_message(&Message::join(channel))
+    }
+
+    /// Take back the profile's nickname, which happens by itself if it was taken while
+    /// registering. If someone has it, it's taken back as soon as they leave or change it, as
+    /// far as we can see them in our channels.
+    pub fn reclaim_nick(&mut self) -> io::Result<()> {
+        let line = self.session.reclaim_nick();
+        self.send(&line)
+    }
+
+    /// Send several lines, pausing between them. See [`LINE_PACING`]. The first goes out right
+    /// away unless other paced lines are waiting, and the rest are sent while events are read.
+    pub fn send_paced(&mut self, lines: &[String]) -> io::Result<()> {
+        if !lines.is_empty() && !self.is_connected() {
+            return Err(not_connected());
+        }
+        self.paced.extend(lines.iter().cloned());
+        self.send_due_paced()
+    }
+
+    /// Send the paced lines whose turn has come.
+    fn send_due_paced(&mut self) -> io::Result<()> {
+        while !self.paced.is_empty() && Instant::now() >= self.paced_at {
+            let line = self.paced.pop_front().unwrap();
+            self.send_now(&line)?;
+            self.paced_at = Instant::now() + LINE_PACING;
+        }
+        Ok(())
+    }
+
+    /// Send as many queued lines as the throttle allows, once the paced lines are out.
+    fn send_queued(&mut self) -> io::Result<()> {
+        while self.paced.is_empty() && !self.queue.is_empty() && self.throttle.try_take() {
+            let line = self.queue.pop_front().unwrap();
+            self.send_now(&line)?;
+        }
+        Ok(())
+    }
+
+    /// Start using a newly opened connection, reading from it on its own thread.
+    fn attach(&mut self, mut transport: Transport) {
+        let reader = std::mem::replace(&mut transport.reader, Box::new(io::empty()));
+        let sender = self.sender.clone();
+        let decoding = self.session.profile.decoding;
+        thread::spawn(move || recv_handler(reader, decoding, sender));
+        self.transport = Some(transport);
+    }
+
+    /// Close what's left of the connection and start trying to reconnect in the background.
+    fn reconnect(&mut self) {
+        if let Some(transport) = self.transport.take() {
+            transport.shutdown();
+        }
+
+        let profile = self.session.profile.clone();
+        let delay = self.backoff;
+        let sender = self.sender.clone();
+        thread::spawn(move || reconnect_with_backoff(&profile, delay, sender));
+    }
+
+    fn handle(&mut self, incoming: Incoming) {
+        match incoming {
+            Incoming::Message(message) => {
+                self.backoff = FIRST_BACKOFF;
+                let replies = self.session.handle(&message);
+                if let Err(e) = self.send_paced(&replies) {
+                    self.events.push_back(Event::Error(e.to_string()));
+                }
+                self.events.extend(session::events_for(message));
+            }
+            Incoming::Disconnected => {
+                // Lines meant for this connection, which may not make sense on the next
+                self.queue.clear();
+                self.paced.clear();
+                self.events.push_back(Event::Disconnected);
+                self.reconnect();
+            }
+            Incoming::Status(status) => self.events.push_back(Event::Status(status)),
+            Incoming::Reconnected(transport, backoff) => {
+                self.backoff = backoff;
+                self.attach(transport);
+                let registration = self.session.register();
+                match self.send_paced(&registration) {
+                    Ok(()) => self.events.push_back(Event::Reconnected),
+                    Err(e) => {
+                        self.events.push_back(Event::Error(e.to_string()));
+                        // Its reading thread then reports it disconnected, which reconnects
+                        if let Some(transport) = self.transport.take() {
+                            transport.shutdown();
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Send a line the connection needs, reporting a failure as an event.
+    fn send_or_report(&mut self, line: &str) {
+        if let Err(e) = self.send_now(line) {
+            self.events.push_back(Event::Error(e.to_string()));
+        }
+    }
+}
+
+/// Try to connect until it works, waiting twice as long after each failure.
+fn reconnect_with_backoff(profile: &Profile, mut delay: Duration, sender: Sender<Incoming>) {
+    loop {
+        let status = format!("Reconnecting in {} seconds...", delay.as_secs());
+        if sender.send(Incoming::Status(status)).is_err() {
+            return; // The connection was dropped
+        }
+        thread::sleep(delay);
+        delay = (delay * 2).min(MAX_BACKOFF);
+
+        match Transport::connect(profile) {
+            Ok(transport) => {
+                let _ = sender.send(Incoming::Reconnected(transport, delay));
+                return;
+            }
+            Err(e) => {
+                let _ = sender.send(Incoming::Status(format!("Couldn't reconnect: {e}")));
+            }
+        }
+    }
+}
+
+fn not_connected() -> io::Error {
+    io::Error::new(ErrorKind::NotConnected, "Not connected to the server.")
+}
+
+fn recv_handler(mut reader: Box<dyn Read + Send>, decoding: Decoding, sender: Sender<Incoming>) {
+    let mut codec = IrcCodec::new().with_decoding(decoding);
+    // Until the connection is closed or fails
+    while let Ok(1..) = codec.read_from(&mut reader) {
+        // Lines that can't be parsed are skipped
+        while let Some(decoded) = codec.next_message() {
+            if let Ok(message) = decoded
+                && sender.send(Incoming::Message(message)).is_err()
+            {
+                return; // The connection was dropped
+            }
+        }
+    }
+
+    let _ = sender.send(Incoming::Disconnected);
+}
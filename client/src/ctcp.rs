@@ -0,0 +1,17 @@
+use chrono::Local;
+pub use shared::ctcp::{encode, parse};
+
+/// CTCP requests the client answers, as listed in reply to CLIENTINFO
+const SUPPORTED: &[&str] = &["ACTION", "CLIENTINFO", "DCC", "PING", "TIME", "VERSION"];
+
+/// The text of the NOTICE answering a CTCP request, or `None` for requests that aren't answered.
+pub fn reply(command: &str, args: &str) -> Option<String> {
+    let args = match command.to_uppercase().as_str() {
+        "VERSION" => format!("irc_rs client {}", env!("CARGO_PKG_VERSION")),
+        "PING" => args.to_string(),
+        "TIME" => Local::now().to_rfc2822(),
+        "CLIENTINFO" => SUPPORTED.join(" "),
+        _ => return None,
+    };
+    Some(encode(&command.to_uppercase(), &args))
+}
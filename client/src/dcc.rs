@@ -0,0 +1,677 @@
+use crate::config::DccConfig;
+use shared::ctcp::{DccOffer, DccResume};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, ErrorKind, Read, Seek, SeekFrom, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{self, Receiver, Sender},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How long to wait for the other side to connect to a listening socket
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// How long to wait for the receiver to acknowledge the end of a file
+const ACK_TIMEOUT: Duration = Duration::from_secs(30);
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    Send,
+    Receive,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum State {
+    /// Offered to us and waiting for `/dcc get`, or offered by us and waiting for the other side
+    Offered,
+    /// Waiting for the sender to agree to continue a partial download, after `/dcc resume`
+    Resuming,
+    Transferring,
+    Done,
+    Failed(String),
+}
+
+/// A file being sent or received over a direct connection to another user.
+#[derive(Debug)]
+pub struct Transfer {
+    pub nick: String,
+    pub direction: Direction,
+    pub file_name: String,
+    pub size: u64,
+    /// Bytes sent or received so far
+    pub transferred: u64,
+    pub state: State,
+    /// Where the file is read from when sending, or written to when receiving
+    path: PathBuf,
+    /// The port of the offer, which RESUME and ACCEPT refer to it by. It's 0 for passive offers,
+    /// which they refer to by token instead.
+    port: u16,
+    /// Where in the file the transfer starts, after agreeing to resume it. Shared with the thread
+    /// waiting for the receiver to connect, which is started before that.
+    position: Arc<AtomicU64>,
+    /// Address to connect to for receiving. `None` for passive offers, where we listen instead.
+    address: Option<SocketAddr>,
+    /// Identifies a passive offer, which the receiver answers with the address to connect to
+    token: Option<String>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Transfer {
+    pub fn is_finished(&self) -> bool {
+        matches!(self.state, State::Done | State::Failed(_))
+    }
+
+    /// Progress in percent
+    pub fn percent(&self) -> u64 {
+        (self.transferred * 100)
+            .checked_div(self.size)
+            .unwrap_or(100)
+    }
+
+    /// Whether a RESUME or ACCEPT is about this transfer's offer, by its port or token
+    fn is_resumed_by(&self, request: &DccResume) -> bool {
+        if request.port == 0 {
+            request.token.is_some() && request.token == self.token
+        } else {
+            request.port == self.port
+        }
+    }
+}
+
+/// Progress reported by the threads doing the transfers, identified by their index in
+/// [`Dcc::transfers`].
+#[derive(Debug)]
+enum Event {
+    Started(usize),
+    Progress(usize, u64),
+    Finished(usize),
+    Failed(usize, String),
+}
+
+/// All DCC transfers of the session, finished ones included.
+#[derive(Debug)]
+pub struct Dcc {
+    pub transfers: Vec<Transfer>,
+    sender: Sender<Event>,
+    receiver: Receiver<Event>,
+}
+
+impl Dcc {
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Dcc {
+            transfers: vec![],
+            sender,
+            receiver,
+        }
+    }
+
+    /// Offer a file to a user, returning the CTCP message to send them. Unless the config asks
+    /// for passive DCC, this listens for them to connect.
+    pub fn offer(
+        &mut self,
+        config: &DccConfig,
+        nick: &str,
+        path: &Path,
+        local_ip: IpAddr,
+    ) -> Result<String, String> {
+        let size = std::fs::metadata(path)
+            .map_err(|e| format!("Can't send {}: {e}", path.display()))?
+            .len();
+        let file_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .ok_or_else(|| format!("Can't send {}: not a file", path.display()))?;
+
+        let id = self.transfers.len();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let position = Arc::new(AtomicU64::new(0));
+        let mut offer = DccOffer {
+            file_name: file_name.clone(),
+            ip: config.address.unwrap_or(local_ip),
+            port: 0,
+            size,
+            token: None,
+        };
+
+        if config.passive {
+            offer.token = Some(id.to_string());
+        } else {
+            let listener = TcpListener::bind(any_address(local_ip))
+                .map_err(|e| format!("Couldn't listen for DCC: {e}"))?;
+            offer.port = listener.local_addr().map_err(|e| e.to_string())?.port();
+            let (path, sender) = (path.to_path_buf(), self.sender.clone());
+            let (cancelled, position) = (cancelled.clone(), position.clone());
+            thread::spawn(move || {
+                // The receiver only connects once any RESUME was agreed to
+                let result = accept(&listener, &cancelled).and_then(|stream| {
+                    let position = position.load(Ordering::Relaxed);
+                    send_file(id, stream, &path, position, size, &sender, &cancelled)
+                });
+                report(id, result, &sender);
+            });
+        }
+
+        self.transfers.push(Transfer {
+            nick: nick.to_string(),
+            direction: Direction::Send,
+            file_name,
+            size,
+            transferred: 0,
+            state: State::Offered,
+            path: path.to_path_buf(),
+            port: offer.port,
+            position,
+            address: None,
+            token: offer.token.clone(),
+            cancelled,
+        });
+        Ok(offer.encode())
+    }
+
+    /// Handle a `DCC SEND` from another user. This is either a new offer, which is kept until
+    /// accepted with [`Dcc::accept`], or the answer to one of our passive offers, in which case
+    /// sending starts. Returns a description of what happened.
+    pub fn handle_offer(&mut self, config: &DccConfig, nick: &str, offer: DccOffer) -> String {
+        let address = SocketAddr::new(offer.ip, offer.port);
+
+        // The answer to a passive offer carries our token and the address to send to
+        if let Some(token) = &offer.token
+            && offer.port != 0
+            && let Some(id) = self.transfers.iter().position(|transfer| {
+                transfer.direction == Direction::Send
+                    && transfer.state == State::Offered
+                    && transfer.token.as_ref() == Some(token)
+                    && transfer.nick.eq_ignore_ascii_case(nick)
+            })
+        {
+            let transfer = &self.transfers[id];
+            let (path, size) = (transfer.path.clone(), transfer.size);
+            let position = transfer.position.load(Ordering::Relaxed);
+            let (sender, cancelled) = (self.sender.clone(), transfer.cancelled.clone());
+            thread::spawn(move || {
+                let result =
+                    TcpStream::connect_timeout(&address, CONNECT_TIMEOUT).and_then(|stream| {
+                        send_file(id, stream, &path, position, size, &sender, &cancelled)
+                    });
+                report(id, result, &sender);
+            });
+            return format!("{nick} accepted {}.", transfer.file_name);
+        }
+
+        if offer.size > config.max_file_size {
+            return format!(
+                "Ignored {nick}'s offer of {} ({}), which is larger than the limit of {}.",
+                offer.file_name,
+                format_size(offer.size),
+                format_size(config.max_file_size)
+            );
+        }
+
+        let mut description = format!(
+            "{nick} offers {} ({}). Type /dcc get {nick} to accept it",
+            offer.file_name,
+            format_size(offer.size)
+        );
+        match partial_download(config, &offer.file_name, offer.size) {
+            Some((path, _)) => description.push_str(&format!(
+                ", or /dcc resume {nick} to continue the partial download in {}.",
+                path.display()
+            )),
+            None => description.push('.'),
+        }
+        self.transfers.push(Transfer {
+            nick: nick.to_string(),
+            direction: Direction::Receive,
+            file_name: offer.file_name,
+            size: offer.size,
+            transferred: 0,
+            state: State::Offered,
+            path: PathBuf::new(),
+            port: offer.port,
+            position: Arc::new(AtomicU64::new(0)),
+            address: (offer.port != 0).then_some(address),
+            token: offer.token,
+            cancelled: Arc::new(AtomicBool::new(false)),
+        });
+        description
+    }
+
+    /// Find the newest transfer with a user that's still waiting or in progress, optionally by
+    /// file name.
+    pub fn find(&self, nick: &str, file_name: Option<&str>) -> Option<usize> {
+        self.transfers.iter().rposition(|transfer| {
+            !transfer.is_finished()
+                && transfer.nick.eq_ignore_ascii_case(nick)
+                && file_name.is_none_or(|name| transfer.file_name == name)
+        })
+    }
+
+    /// Accept a file offered to us, saving it in the download directory. For a passive offer,
+    /// this returns the CTCP message telling the sender where to connect.
+    pub fn accept(
+        &mut self,
+        config: &DccConfig,
+        id: usize,
+        local_ip: IpAddr,
+    ) -> Result<Option<String>, String> {
+        let transfer = &mut self.transfers[id];
+        if transfer.direction != Direction::Receive || transfer.state != State::Offered {
+            return Err(format!(
+                "{} isn't waiting to be accepted.",
+                transfer.file_name
+            ));
+        }
+
+        let directory = config.download_directory();
+        fs::create_dir_all(&directory)
+            .map_err(|e| format!("Can't create {}: {e}", directory.display()))?;
+        let path = unique_path(&directory, &transfer.file_name);
+        let file =
+            File::create(&path).map_err(|e| format!("Can't create {}: {e}", path.display()))?;
+        transfer.path = path;
+        self.receive(config, id, file, local_ip)
+    }
+
+    /// Ask to continue a file offered to us from the end of the partial download of it in the
+    /// download directory, returning the `DCC RESUME` to send. Receiving starts once the sender
+    /// agrees with [`Dcc::handle_accept`].
+    pub fn resume(&mut self, config: &DccConfig, id: usize) -> Result<String, String> {
+        let transfer = &mut self.transfers[id];
+        if transfer.direction != Direction::Receive || transfer.state != State::Offered {
+            return Err(format!(
+                "{} isn't waiting to be accepted.",
+                transfer.file_name
+            ));
+        }
+        let (path, position) = partial_download(config, &transfer.file_name, transfer.size)
+            .ok_or_else(|| {
+                format!(
+                    "There's no partial download of {} to resume. Use /dcc get to start over.",
+                    transfer.file_name
+                )
+            })?;
+        transfer.path = path;
+        transfer.position.store(position, Ordering::Relaxed);
+        transfer.transferred = position;
+        transfer.state = State::Resuming;
+        let request = DccResume {
+            file_name: transfer.file_name.clone(),
+            port: transfer.port,
+            position,
+            token: transfer.token.clone(),
+        };
+        Ok(request.encode("RESUME"))
+    }
+
+    /// Handle a `DCC RESUME` from a user we offered a file to, returning the `DCC ACCEPT` to
+    /// send if it's about one of our offers, and a description of what happened.
+    pub fn handle_resume(&mut self, nick: &str, request: DccResume) -> (Option<String>, String) {
+        let Some(transfer) = self.transfers.iter_mut().rev().find(|transfer| {
+            transfer.direction == Direction::Send
+                && transfer.state == State::Offered
+                && transfer.nick.eq_ignore_ascii_case(nick)
+                && transfer.is_resumed_by(&request)
+        }) else {
+            return (
+                None,
+                format!("{nick} asked to resume a file we didn't offer."),
+            );
+        };
+        if request.position > transfer.size {
+            return (
+                None,
+                format!(
+                    "{nick} asked to resume {} past its end.",
+                    transfer.file_name
+                ),
+            );
+        }
+        transfer.position.store(request.position, Ordering::Relaxed);
+        transfer.transferred = request.position;
+        let description = format!(
+            "{nick} is resuming {} from {}.",
+            transfer.file_name,
+            format_size(request.position)
+        );
+        (Some(request.encode("ACCEPT")), description)
+    }
+
+    /// Handle a `DCC ACCEPT` from a user we asked to resume a file, and start receiving the rest
+    /// of it. For a passive offer, this returns the CTCP message telling the sender where to
+    /// connect.
+    pub fn handle_accept(
+        &mut self,
+        config: &DccConfig,
+        nick: &str,
+        answer: DccResume,
+        local_ip: IpAddr,
+    ) -> Result<Option<String>, String> {
+        let id = self
+            .transfers
+            .iter()
+            .rposition(|transfer| {
+                transfer.direction == Direction::Receive
+                    && transfer.state == State::Resuming
+                    && transfer.nick.eq_ignore_ascii_case(nick)
+                    && transfer.is_resumed_by(&answer)
+            })
+            .ok_or_else(|| format!("{nick} agreed to resume a file we didn't ask for."))?;
+        let transfer = &mut self.transfers[id];
+        if answer.position != transfer.position.load(Ordering::Relaxed) {
+            let error = "The sender would resume from elsewhere than asked".to_string();
+            transfer.state = State::Failed(error.clone());
+            return Err(format!("Resuming {} failed: {error}", transfer.file_name));
+        }
+        // Receiving carries on from where the file is positioned
+        let position = answer.position;
+        let file = OpenOptions::new()
+            .write(true)
+            .open(&transfer.path)
+            .and_then(|mut file| {
+                file.set_len(position)?;
+                file.seek(SeekFrom::Start(position))?;
+                Ok(file)
+            })
+            .map_err(|e| format!("Can't open {}: {e}", transfer.path.display()))?;
+        self.receive(config, id, file, local_ip)
+    }
+
+    /// Start receiving a file accepted or resumed, connecting to the sender or, for a passive
+    /// offer, listening and returning the CTCP message telling the sender where to connect.
+    fn receive(
+        &mut self,
+        config: &DccConfig,
+        id: usize,
+        file: File,
+        local_ip: IpAddr,
+    ) -> Result<Option<String>, String> {
+        let transfer = &mut self.transfers[id];
+        transfer.state = State::Transferring;
+        let size = transfer.size;
+        let max_size = config.max_file_size;
+        let (sender, cancelled) = (self.sender.clone(), transfer.cancelled.clone());
+        if let Some(address) = transfer.address {
+            thread::spawn(move || {
+                let result =
+                    TcpStream::connect_timeout(&address, CONNECT_TIMEOUT).and_then(|stream| {
+                        receive_file(id, stream, file, size, max_size, &sender, &cancelled)
+                    });
+                report(id, result, &sender);
+            });
+            return Ok(None);
+        }
+
+        // Passive offer: listen, and tell the sender where to connect
+        let listener = TcpListener::bind(any_address(local_ip))
+            .map_err(|e| format!("Couldn't listen for DCC: {e}"))?;
+        let answer = DccOffer {
+            file_name: transfer.file_name.clone(),
+            ip: config.address.unwrap_or(local_ip),
+            port: listener.local_addr().map_err(|e| e.to_string())?.port(),
+            size,
+            token: transfer.token.clone(),
+        };
+        thread::spawn(move || {
+            let result = accept(&listener, &cancelled).and_then(|stream| {
+                receive_file(id, stream, file, size, max_size, &sender, &cancelled)
+            });
+            report(id, result, &sender);
+        });
+        Ok(Some(answer.encode()))
+    }
+
+    /// Cancel a transfer, or reject an offer.
+    pub fn close(&mut self, id: usize) {
+        let transfer = &mut self.transfers[id];
+        transfer.cancelled.store(true, Ordering::Relaxed);
+        if !transfer.is_finished() {
+            transfer.state = State::Failed("Cancelled".to_string());
+        }
+    }
+
+    /// Apply the progress reported by the transfer threads, returning messages about transfers
+    /// that started or ended, and whether they're errors.
+    pub fn poll(&mut self) -> Vec<(String, bool)> {
+        let mut messages = vec![];
+        while let Ok(event) = self.receiver.try_recv() {
+            let id = match event {
+                Event::Started(id) | Event::Progress(id, _) => id,
+                Event::Finished(id) | Event::Failed(id, _) => id,
+            };
+            let transfer = &mut self.transfers[id];
+            if transfer.is_finished() {
+                continue; // Cancelled
+            }
+
+            let (verb, preposition) = match transfer.direction {
+                Direction::Send => ("Sending", "to"),
+                Direction::Receive => ("Receiving", "from"),
+            };
+            match event {
+                Event::Started(_) => {
+                    transfer.state = State::Transferring;
+                    // Resumed transfers start where the last one stopped
+                    let text = match transfer.transferred {
+                        0 => format!(
+                            "{verb} {} {preposition} {}...",
+                            transfer.file_name, transfer.nick
+                        ),
+                        position => format!(
+                            "Resuming {} {preposition} {} at {}...",
+                            transfer.file_name,
+                            transfer.nick,
+                            format_size(position)
+                        ),
+                    };
+                    messages.push((text, false));
+                }
+                Event::Progress(_, transferred) => transfer.transferred = transferred,
+                Event::Finished(_) => {
+                    transfer.state = State::Done;
+                    let text = match transfer.direction {
+                        Direction::Send => {
+                            format!("Sent {} to {}.", transfer.file_name, transfer.nick)
+                        }
+                        Direction::Receive => format!(
+                            "Received {} from {}, saved to {}.",
+                            transfer.file_name,
+                            transfer.nick,
+                            transfer.path.display()
+                        ),
+                    };
+                    messages.push((text, false));
+                }
+                Event::Failed(_, error) => {
+                    messages.push((
+                        format!(
+                            "{verb} {} {preposition} {} failed: {error}",
+                            transfer.file_name, transfer.nick
+                        ),
+                        true,
+                    ));
+                    transfer.state = State::Failed(error);
+                }
+            }
+        }
+        messages
+    }
+}
+
+/// Wait for the other side to connect, giving up after a while or when cancelled.
+fn accept(listener: &TcpListener, cancelled: &AtomicBool) -> io::Result<TcpStream> {
+    listener.set_nonblocking(true)?;
+    let deadline = Instant::now() + CONNECT_TIMEOUT;
+    loop {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                stream.set_nonblocking(false)?;
+                return Ok(stream);
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                if cancelled.load(Ordering::Relaxed) {
+                    return Err(io::Error::new(ErrorKind::Interrupted, "Cancelled"));
+                }
+                if Instant::now() > deadline {
+                    return Err(io::Error::new(ErrorKind::TimedOut, "Nobody connected"));
+                }
+                thread::sleep(Duration::from_millis(100));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Send a file from a position in it, which is past the start when resuming.
+fn send_file(
+    id: usize,
+    mut stream: TcpStream,
+    path: &Path,
+    position: u64,
+    size: u64,
+    sender: &Sender<Event>,
+    cancelled: &AtomicBool,
+) -> io::Result<()> {
+    let _ = sender.send(Event::Started(id));
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(position))?;
+    let mut buffer = vec![0; CHUNK_SIZE];
+    let mut sent = position;
+    loop {
+        let length = file.read(&mut buffer)?;
+        if length == 0 {
+            break;
+        }
+        if cancelled.load(Ordering::Relaxed) {
+            return Err(io::Error::new(ErrorKind::Interrupted, "Cancelled"));
+        }
+        stream.write_all(&buffer[..length])?;
+        sent += length as u64;
+        let _ = sender.send(Event::Progress(id, sent));
+    }
+
+    // The receiver acknowledges how much of the file it has, as a 32-bit count, so wait for the
+    // last one before closing the connection
+    stream.set_read_timeout(Some(ACK_TIMEOUT))?;
+    let mut ack = [0; 4];
+    while u32::from_be_bytes(ack) != sent as u32 {
+        match stream.read_exact(&mut ack) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break, // Closed once it had it all
+            Err(e) => return Err(e),
+        }
+    }
+    if sent != size {
+        return Err(io::Error::other("The file changed while sending it"));
+    }
+    Ok(())
+}
+
+/// Receive a file, writing from where the file is positioned, which is its end when resuming.
+fn receive_file(
+    id: usize,
+    mut stream: TcpStream,
+    mut file: File,
+    size: u64,
+    max_size: u64,
+    sender: &Sender<Event>,
+    cancelled: &AtomicBool,
+) -> io::Result<()> {
+    let _ = sender.send(Event::Started(id));
+    let mut buffer = vec![0; CHUNK_SIZE];
+    let mut received = file.stream_position()?;
+    while received < size {
+        let length = stream.read(&mut buffer)?;
+        if length == 0 {
+            return Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "The sender closed the connection",
+            ));
+        }
+        if cancelled.load(Ordering::Relaxed) {
+            return Err(io::Error::new(ErrorKind::Interrupted, "Cancelled"));
+        }
+        received += length as u64;
+        if received > size.min(max_size) {
+            return Err(io::Error::other("The sender sent more than offered"));
+        }
+        file.write_all(&buffer[..length])?;
+        stream.write_all(&(received as u32).to_be_bytes())?;
+        let _ = sender.send(Event::Progress(id, received));
+    }
+    Ok(())
+}
+
+fn report(id: usize, result: io::Result<()>, sender: &Sender<Event>) {
+    let event = match result {
+        Ok(()) => Event::Finished(id),
+        Err(e) => Event::Failed(id, e.to_string()),
+    };
+    let _ = sender.send(event);
+}
+
+/// Listen on every interface, since the address other users reach us at may not be the one
+/// connected to the server (e.g. with port forwarding).
+fn any_address(local_ip: IpAddr) -> SocketAddr {
+    let ip = match local_ip {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    SocketAddr::new(ip, 0)
+}
+
+/// A path in the directory for a file name sent by someone else, keeping only the name and
+/// adding a number if a file with that name already exists.
+fn unique_path(directory: &Path, file_name: &str) -> PathBuf {
+    let name = safe_file_name(file_name);
+    let mut path = directory.join(&name);
+    let mut number = 1;
+    while path.exists() {
+        path = directory.join(format!("{name}.{number}"));
+        number += 1;
+    }
+    path
+}
+
+/// Only the name of a file sent by someone else, without any directories, and not hidden
+fn safe_file_name(file_name: &str) -> String {
+    Path::new(file_name)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .filter(|name| !name.starts_with('.'))
+        .unwrap_or_else(|| "download".to_string())
+}
+
+/// A file in the download directory with the name of an offered file but smaller, which
+/// receiving it was interrupted before finishing, along with its size.
+fn partial_download(config: &DccConfig, file_name: &str, size: u64) -> Option<(PathBuf, u64)> {
+    let path = config.download_directory().join(safe_file_name(file_name));
+    let length = fs::metadata(&path)
+        .ok()
+        .filter(|data| data.is_file())?
+        .len();
+    (length < size).then_some((path, length))
+}
+
+/// Size in bytes for people, e.g. `1.5 MB`
+pub fn format_size(size: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if size < 1024 {
+        return format!("{size} B");
+    }
+    let mut value = size as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1} {}", UNITS[unit])
+}
@@ -0,0 +1,76 @@
+use std::{
+    process,
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+};
+
+/// Shell commands started with `/exec`, which run in the background so the interface doesn't
+/// wait for them.
+#[derive(Debug)]
+pub struct Exec {
+    sender: Sender<Output>,
+    receiver: Receiver<Output>,
+}
+
+/// What a command printed once it has finished
+#[derive(Debug)]
+pub struct Output {
+    pub command: String,
+    /// Where the command was run from: the index of the network and the buffer's name
+    pub network: usize,
+    pub buffer: String,
+    /// Whether to send the output to the buffer's channel or user, rather than only show it
+    pub send: bool,
+    /// The lines written to stdout, then those written to stderr, or why the command couldn't
+    /// be run
+    pub lines: Result<Vec<String>, String>,
+    /// The exit code, if the command wasn't killed by a signal
+    pub status: Option<i32>,
+}
+
+impl Exec {
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Exec { sender, receiver }
+    }
+
+    /// Run a command with the system's shell, `sh` or `cmd`, without input.
+    pub fn run(&self, command: &str, network: usize, buffer: &str, send: bool) {
+        let mut output = Output {
+            command: command.to_string(),
+            network,
+            buffer: buffer.to_string(),
+            send,
+            lines: Ok(vec![]),
+            status: None,
+        };
+        let sender = self.sender.clone();
+        thread::spawn(move || {
+            let mut shell = if cfg!(windows) {
+                let mut shell = process::Command::new("cmd");
+                shell.args(["/C", &output.command]);
+                shell
+            } else {
+                let mut shell = process::Command::new("sh");
+                shell.args(["-c", &output.command]);
+                shell
+            };
+            match shell.stdin(process::Stdio::null()).output() {
+                Ok(result) => {
+                    let stdout = String::from_utf8_lossy(&result.stdout);
+                    let stderr = String::from_utf8_lossy(&result.stderr);
+                    let lines = stdout.lines().chain(stderr.lines());
+                    output.lines = Ok(lines.map(str::to_string).collect());
+                    output.status = result.status.code();
+                }
+                Err(e) => output.lines = Err(format!("Can't run {:?}: {e}", output.command)),
+            }
+            let _ = sender.send(output);
+        });
+    }
+
+    /// The output of the commands that finished since the last call
+    pub fn poll(&mut self) -> Vec<Output> {
+        self.receiver.try_iter().collect()
+    }
+}
@@ -0,0 +1,253 @@
+use crate::{app::Line, formatting, ui};
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone};
+use ratatui::style::Color;
+use std::{fs, io, path::Path};
+
+/// What `/export` writes, picked by the file's extension
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Html,
+    Markdown,
+}
+
+impl Format {
+    /// The format named `html`, `htm`, `md` or `markdown`, ignoring case
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "html" | "htm" => Some(Format::Html),
+            "md" | "markdown" => Some(Format::Markdown),
+            _ => None,
+        }
+    }
+
+    /// The format for a file name with one of those as its extension
+    pub fn from_path(path: &Path) -> Option<Self> {
+        Self::from_name(path.extension()?.to_str()?)
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Html => "html",
+            Format::Markdown => "md",
+        }
+    }
+}
+
+/// A span of time to export from the logs. Either end can be left open.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Range {
+    pub from: Option<DateTime<Local>>,
+    /// The end, which isn't included
+    pub to: Option<DateTime<Local>>,
+}
+
+impl Range {
+    /// Parse a range as typed after `/export`:
+    /// - `today` or `yesterday`
+    /// - `30m`, `12h` or `7d`: the last minutes, hours or days
+    /// - `2024-05-01`: the whole day
+    /// - `2024-05-01T18:00`: from then on
+    /// - `2024-05-01..2024-05-03` or `2024-05-01T18:00..2024-05-01T20:00`: from the first to the
+    ///   second, including the whole of the last day. Either end can be left out.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let now = Local::now();
+        let invalid = || {
+            format!(
+                "Invalid range {text:?}. Use today, yesterday, 7d, 2024-05-01 or \
+                 2024-05-01..2024-05-03."
+            )
+        };
+        let today = now.date_naive();
+        match text.to_lowercase().as_str() {
+            "today" => return Ok(Range::days(today, today)),
+            "yesterday" => {
+                let yesterday = today.pred_opt().ok_or_else(invalid)?;
+                return Ok(Range::days(yesterday, yesterday));
+            }
+            _ => {}
+        }
+        if let Some(duration) = parse_duration(text) {
+            return Ok(Range {
+                from: Some(now - duration),
+                to: None,
+            });
+        }
+
+        let (from, to) = match text.split_once("..") {
+            Some((from, to)) => (from, Some(to)),
+            None => (text, None),
+        };
+        let from = (!from.is_empty())
+            .then(|| parse_time(from, false).ok_or_else(invalid))
+            .transpose()?;
+        let to = match to {
+            Some("") => None,
+            Some(to) => Some(parse_time(to, true).ok_or_else(invalid)?),
+            // A day on its own is the whole day, but a time is from then on
+            None if NaiveDate::parse_from_str(text, "%Y-%m-%d").is_ok() => parse_time(text, true),
+            None => None,
+        };
+        Ok(Range { from, to })
+    }
+
+    /// From the start of the first day to the end of the last
+    fn days(first: NaiveDate, last: NaiveDate) -> Self {
+        Range {
+            from: local(first.and_time(NaiveTime::MIN)),
+            to: last
+                .succ_opt()
+                .and_then(|next| local(next.and_time(NaiveTime::MIN))),
+        }
+    }
+
+    pub fn contains(&self, time: DateTime<Local>) -> bool {
+        self.from.is_none_or(|from| time >= from) && self.to.is_none_or(|to| time < to)
+    }
+}
+
+/// `30m`, `12h` or `7d`
+fn parse_duration(text: &str) -> Option<Duration> {
+    let unit = text.chars().last()?;
+    let amount = text[..text.len() - unit.len_utf8()].parse().ok()?;
+    match unit {
+        'm' => Duration::try_minutes(amount),
+        'h' => Duration::try_hours(amount),
+        'd' => Duration::try_days(amount),
+        _ => None,
+    }
+}
+
+/// `2024-05-01` or `2024-05-01T18:00`. At the end of a range, a day means the end of that day.
+fn parse_time(text: &str, end: bool) -> Option<DateTime<Local>> {
+    if let Ok(day) = NaiveDate::parse_from_str(text, "%Y-%m-%d") {
+        let day = if end { day.succ_opt()? } else { day };
+        return local(day.and_time(NaiveTime::MIN));
+    }
+    ["%Y-%m-%dT%H:%M", "%Y-%m-%dT%H:%M:%S"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+        .and_then(local)
+}
+
+fn local(time: NaiveDateTime) -> Option<DateTime<Local>> {
+    Local.from_local_datetime(&time).earliest()
+}
+
+/// Write lines to a file as a page titled after the buffer, with a heading for each day.
+pub fn write(path: &Path, format: Format, title: &str, lines: &[&Line]) -> io::Result<()> {
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory)?;
+    }
+    let text = match format {
+        Format::Html => html(title, lines),
+        Format::Markdown => markdown(title, lines),
+    };
+    fs::write(path, text)
+}
+
+const STYLE: &str = "body { background: #1e1e1e; color: #d4d4d4; font-family: monospace; }
+h2 { color: #808080; font-size: 1em; margin-top: 1.5em; }
+.line { white-space: pre-wrap; }
+.time { color: #808080; }
+.event { color: #a0a0a0; }
+.nick { font-weight: bold; }";
+
+fn html(title: &str, lines: &[&Line]) -> String {
+    let title = escape_html(title);
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n\
+         <style>\n{STYLE}\n</style>\n</head>\n<body>\n<h1>{title}</h1>\n"
+    );
+    let mut day = None;
+    for line in lines {
+        let date = line.time.date_naive();
+        if day != Some(date) {
+            day = Some(date);
+            html.push_str(&format!("<h2>{}</h2>\n", date.format("%A, %Y-%m-%d")));
+        }
+        let text = escape_html(&formatting::strip(&line.text));
+        let time = line.time.format("%H:%M:%S");
+        match (&line.sender, line.nick()) {
+            (Some(sender), Some(nick)) => html.push_str(&format!(
+                "<div class=\"line\"><span class=\"time\">{time}</span> \
+                 <span class=\"nick\" style=\"color: {}\">{}</span> {text}</div>\n",
+                ui::nick_style(nick).fg.map_or("inherit", css_color),
+                escape_html(sender),
+            )),
+            _ => html.push_str(&format!(
+                "<div class=\"line event\"><span class=\"time\">{time}</span> {text}</div>\n"
+            )),
+        }
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+/// Markdown has no colors, so nicknames are only in bold.
+fn markdown(title: &str, lines: &[&Line]) -> String {
+    let mut markdown = format!("# {}\n", escape_markdown(title));
+    let mut day = None;
+    for line in lines {
+        let date = line.time.date_naive();
+        if day != Some(date) {
+            day = Some(date);
+            markdown.push_str(&format!("\n## {}\n\n", date.format("%A, %Y-%m-%d")));
+        }
+        let text = escape_markdown(&formatting::strip(&line.text));
+        let time = line.time.format("%H:%M:%S");
+        // Two spaces at the end break the line without starting a new paragraph
+        match &line.sender {
+            Some(sender) => markdown.push_str(&format!(
+                "`{time}` **{}** {text}  \n",
+                escape_markdown(sender)
+            )),
+            None => markdown.push_str(&format!("`{time}` *{text}*  \n")),
+        }
+    }
+    markdown
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Put a backslash before characters that mean something in Markdown. Angle brackets become
+/// entities instead, since `<alice>` would be taken for HTML.
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '&' => escaped.push_str("&amp;"),
+            '\\' | '`' | '*' | '_' | '[' | ']' | '#' | '|' | '~' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// The terminal colors nicknames are shown in, as the common xterm palette has them
+fn css_color(color: Color) -> &'static str {
+    match color {
+        Color::Red => "#cd3131",
+        Color::Green => "#0dbc79",
+        Color::Yellow => "#e5e510",
+        Color::Blue => "#2472c8",
+        Color::Magenta => "#bc3fbc",
+        Color::Cyan => "#11a8cd",
+        Color::LightRed => "#f14c4c",
+        Color::LightGreen => "#23d18b",
+        Color::LightYellow => "#f5f543",
+        Color::LightBlue => "#3b8eea",
+        Color::LightMagenta => "#d670d6",
+        Color::LightCyan => "#29b8db",
+        _ => "inherit",
+    }
+}
@@ -0,0 +1,324 @@
+use ratatui::{
+    style::{Color, Modifier, Style},
+    text::Span,
+};
+use std::{iter::Peekable, str::Chars};
+
+const BOLD: char = '\x02';
+const COLOR: char = '\x03';
+const HEX_COLOR: char = '\x04';
+const RESET: char = '\x0f';
+const MONOSPACE: char = '\x11';
+const REVERSE: char = '\x16';
+const ITALIC: char = '\x1d';
+const STRIKETHROUGH: char = '\x1e';
+const UNDERLINE: char = '\x1f';
+
+/// The 16 standard mIRC colors. Codes from 16 to 98 aren't widely supported and are ignored.
+const COLORS: [Color; 16] = [
+    Color::White,
+    Color::Black,
+    Color::Blue,
+    Color::Green,
+    Color::LightRed,
+    Color::Red,
+    Color::Magenta,
+    Color::Yellow,
+    Color::LightYellow,
+    Color::LightGreen,
+    Color::Cyan,
+    Color::LightCyan,
+    Color::LightBlue,
+    Color::LightMagenta,
+    Color::DarkGray,
+    Color::Gray,
+];
+
+/// Split text containing mIRC formatting codes into styled spans, e.g. `\x02bold\x02 plain` or
+/// `\x034,1red on black`. Formatting applies on top of the base style.
+pub fn parse(text: &str, base: Style) -> Vec<Span<'static>> {
+    let mut spans = vec![];
+    let mut style = base;
+    let mut current = String::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let previous = style;
+        match c {
+            BOLD => style = toggle(style, Modifier::BOLD),
+            ITALIC => style = toggle(style, Modifier::ITALIC),
+            UNDERLINE => style = toggle(style, Modifier::UNDERLINED),
+            STRIKETHROUGH => style = toggle(style, Modifier::CROSSED_OUT),
+            REVERSE => style = toggle(style, Modifier::REVERSED),
+            RESET => style = base,
+            MONOSPACE => {}
+            COLOR => {
+                // `\x03` alone resets the colors
+                let colors = take_colors(&mut chars, 2, |digits| {
+                    digits
+                        .parse::<usize>()
+                        .ok()
+                        .and_then(|i| COLORS.get(i).copied())
+                });
+                style = apply_colors(style, base, colors);
+            }
+            HEX_COLOR => {
+                let colors = take_colors(&mut chars, 6, |hex| {
+                    let rgb = u32::from_str_radix(hex, 16).ok()?;
+                    Some(Color::Rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8))
+                });
+                style = apply_colors(style, base, colors);
+            }
+            _ => {
+                current.push(c);
+                continue;
+            }
+        }
+        if style != previous && !current.is_empty() {
+            spans.push(Span::styled(std::mem::take(&mut current), previous));
+        }
+    }
+    if !current.is_empty() {
+        spans.push(Span::styled(current, style));
+    }
+    spans
+}
+
+/// Remove all formatting codes, leaving the plain text.
+pub fn strip(text: &str) -> String {
+    parse(text, Style::default())
+        .into_iter()
+        .map(|span| span.content)
+        .collect()
+}
+
+fn toggle(style: Style, modifier: Modifier) -> Style {
+    if style.add_modifier.contains(modifier) {
+        style.remove_modifier(modifier)
+    } else {
+        style.add_modifier(modifier)
+    }
+}
+
+/// Colors set by a color code. A color that couldn't be parsed is `None`, which resets it.
+struct Colors {
+    foreground: Option<Color>,
+    /// Only changed if the code has a background color
+    background: Option<Option<Color>>,
+}
+
+/// Read the `fg[,bg]` after a color code, each up to `length` digits long. Returns `None` if
+/// there's no foreground, which resets the colors.
+fn take_colors(
+    chars: &mut Peekable<Chars>,
+    length: usize,
+    parse: impl Fn(&str) -> Option<Color>,
+) -> Option<Colors> {
+    let is_digit = |c: &char| {
+        if length == 2 {
+            c.is_ascii_digit()
+        } else {
+            c.is_ascii_hexdigit()
+        }
+    };
+    let take = |chars: &mut Peekable<Chars>| {
+        let mut value = String::new();
+        while value.len() < length
+            && let Some(c) = chars.next_if(is_digit)
+        {
+            value.push(c);
+        }
+        value
+    };
+
+    let foreground = take(chars);
+    if foreground.is_empty() {
+        return None;
+    }
+    // Only treat the comma as part of the code if a background color follows it
+    let mut lookahead = chars.clone();
+    let background = if lookahead.next() == Some(',') && lookahead.peek().is_some_and(is_digit) {
+        chars.next();
+        Some(parse(&take(chars)))
+    } else {
+        None
+    };
+    Some(Colors {
+        foreground: parse(&foreground),
+        background,
+    })
+}
+
+fn apply_colors(mut style: Style, base: Style, colors: Option<Colors>) -> Style {
+    match colors {
+        None => {
+            style.fg = base.fg;
+            style.bg = base.bg;
+        }
+        Some(colors) => {
+            style.fg = colors.foreground.or(base.fg);
+            if let Some(background) = colors.background {
+                style.bg = background.or(base.bg);
+            }
+        }
+    }
+    style
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Each span's text and style, to compare against in one assertion. Modifiers that were
+    /// toggled back off are also marked as removed, which doesn't change how the text looks, so
+    /// that's left out.
+    fn spans(text: &str, base: Style) -> Vec<(String, Style)> {
+        parse(text, base)
+            .into_iter()
+            .map(|span| {
+                let style = Style {
+                    sub_modifier: Modifier::empty(),
+                    ..span.style
+                };
+                (span.content.into_owned(), style)
+            })
+            .collect()
+    }
+
+    fn colored(fg: Color) -> Style {
+        Style::default().fg(fg)
+    }
+
+    #[test]
+    fn colors_take_up_to_two_digits() {
+        let plain = Style::default();
+        assert_eq!(
+            spans("\x034red \x0302blue \x03042", plain),
+            [
+                ("red ".to_string(), colored(Color::LightRed)),
+                ("blue ".to_string(), colored(Color::Blue)),
+                // Only two digits belong to the code
+                ("2".to_string(), colored(Color::LightRed)),
+            ]
+        );
+        // Without any digits, the colors are reset
+        assert_eq!(
+            spans("\x0312blue\x03 plain", plain),
+            [
+                ("blue".to_string(), colored(Color::LightBlue)),
+                (" plain".to_string(), plain),
+            ]
+        );
+    }
+
+    #[test]
+    fn colors_can_have_a_background() {
+        let plain = Style::default();
+        assert_eq!(
+            spans("\x034,1red on black\x032 blue on black", plain),
+            [
+                (
+                    "red on black".to_string(),
+                    Style::default().fg(Color::LightRed).bg(Color::Black)
+                ),
+                // Changing only the foreground keeps the background
+                (
+                    " blue on black".to_string(),
+                    Style::default().fg(Color::Blue).bg(Color::Black)
+                ),
+            ]
+        );
+        // A comma without a background color after it is just text
+        assert_eq!(
+            spans("\x034,red", plain),
+            [(",red".to_string(), colored(Color::LightRed))]
+        );
+    }
+
+    #[test]
+    fn unsupported_colors_fall_back_to_the_base_style() {
+        let base = Style::default().fg(Color::Gray);
+        assert_eq!(
+            spans("\x0350,4text", base),
+            [(
+                "text".to_string(),
+                Style::default().fg(Color::Gray).bg(Color::LightRed)
+            )]
+        );
+    }
+
+    #[test]
+    fn attributes_toggle() {
+        let plain = Style::default();
+        assert_eq!(
+            spans("\x02bold\x1d both\x02 italic\x1d plain", plain),
+            [
+                ("bold".to_string(), plain.add_modifier(Modifier::BOLD)),
+                (
+                    " both".to_string(),
+                    plain.add_modifier(Modifier::BOLD | Modifier::ITALIC)
+                ),
+                (" italic".to_string(), plain.add_modifier(Modifier::ITALIC)),
+                (" plain".to_string(), plain),
+            ]
+        );
+        assert_eq!(
+            spans("\x1funderlined\x1estruck\x16reversed", plain),
+            [
+                (
+                    "underlined".to_string(),
+                    plain.add_modifier(Modifier::UNDERLINED)
+                ),
+                (
+                    "struck".to_string(),
+                    plain.add_modifier(Modifier::UNDERLINED | Modifier::CROSSED_OUT)
+                ),
+                (
+                    "reversed".to_string(),
+                    plain.add_modifier(
+                        Modifier::UNDERLINED | Modifier::CROSSED_OUT | Modifier::REVERSED
+                    )
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn reset_returns_to_the_base_style() {
+        let base = Style::default().fg(Color::Gray);
+        assert_eq!(
+            spans("\x02\x1f\x034,1styled\x0f plain", base),
+            [
+                (
+                    "styled".to_string(),
+                    Style::default()
+                        .fg(Color::LightRed)
+                        .bg(Color::Black)
+                        .add_modifier(Modifier::BOLD | Modifier::UNDERLINED)
+                ),
+                (" plain".to_string(), base),
+            ]
+        );
+    }
+
+    #[test]
+    fn hex_colors_take_six_digits() {
+        assert_eq!(
+            spans("\x04ff8000,000000orange", Style::default()),
+            [(
+                "orange".to_string(),
+                Style::default()
+                    .fg(Color::Rgb(0xff, 0x80, 0x00))
+                    .bg(Color::Rgb(0, 0, 0))
+            )]
+        );
+    }
+
+    #[test]
+    fn stripping_leaves_the_plain_text() {
+        assert_eq!(
+            strip("\x02bold\x02 \x034,1red\x03 \x04ff0000hex\x0f \x11mono"),
+            "bold red hex mono"
+        );
+    }
+}
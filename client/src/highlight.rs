@@ -0,0 +1,157 @@
+use crate::{
+    app::STATUS_BUFFER,
+    config::{HighlightConfig, NotifyAction, NotifyRule},
+};
+use regex::{Regex, RegexBuilder};
+
+/// Decides what to do about messages from others. Ones containing the user's nickname or one of
+/// the configured words as a whole word, or matching one of the configured patterns, mention the
+/// user and are highlighted. After that, every notification rule that matches adds its actions.
+#[derive(Debug, Default)]
+pub struct Highlighter {
+    words: Vec<String>,
+    patterns: Vec<Regex>,
+    /// What mentions do besides being highlighted
+    bell: bool,
+    desktop_notification: bool,
+    rules: Vec<Rule>,
+}
+
+/// A [`NotifyRule`] with its patterns compiled
+#[derive(Debug)]
+struct Rule {
+    text: Option<Regex>,
+    sender: Option<Regex>,
+    channel: Option<Regex>,
+    actions: Vec<NotifyAction>,
+    command: Option<String>,
+}
+
+/// A message to decide about
+#[derive(Debug)]
+pub struct Incoming<'a> {
+    /// Where the message is shown: a channel, the sender's nickname or the status buffer
+    pub buffer: &'a str,
+    pub sender: &'a str,
+    /// The message without its formatting
+    pub text: &'a str,
+}
+
+/// What to do about a message
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Notification {
+    pub highlight: bool,
+    pub bell: bool,
+    pub desktop: bool,
+    /// Shell commands to run
+    pub commands: Vec<String>,
+}
+
+impl Notification {
+    /// Whether the user is told about the message other than by how it looks
+    pub fn alerts(&self) -> bool {
+        self.bell || self.desktop || !self.commands.is_empty()
+    }
+}
+
+impl Highlighter {
+    pub fn new(config: &HighlightConfig, rules: &[NotifyRule]) -> Result<Self, String> {
+        let patterns = config
+            .patterns
+            .iter()
+            .map(|pattern| compile(pattern))
+            .collect::<Result<_, _>>()?;
+        let rules = rules.iter().map(Rule::new).collect::<Result<_, _>>()?;
+        Ok(Highlighter {
+            words: config.words.clone(),
+            patterns,
+            bell: config.bell,
+            desktop_notification: config.desktop_notification,
+            rules,
+        })
+    }
+
+    /// Whether a message mentions the user, by their nickname or the configured words and
+    /// patterns
+    fn mentions(&self, nick: &str, text: &str) -> bool {
+        std::iter::once(nick)
+            .chain(self.words.iter().map(String::as_str))
+            .any(|word| contains_word(text, word))
+            || self.patterns.iter().any(|pattern| pattern.is_match(text))
+    }
+
+    /// What to do about a message from someone else, as the user with the nickname. Server
+    /// notices in the status buffer can match rules, but don't count as mentions.
+    pub fn check(&self, nick: &str, message: &Incoming) -> Notification {
+        let mut notification = Notification::default();
+        if message.buffer != STATUS_BUFFER && self.mentions(nick, message.text) {
+            notification.highlight = true;
+            notification.bell = self.bell;
+            notification.desktop = self.desktop_notification;
+        }
+        for rule in self.rules.iter().filter(|rule| rule.matches(message)) {
+            for action in &rule.actions {
+                match action {
+                    NotifyAction::Highlight => notification.highlight = true,
+                    NotifyAction::Bell => notification.bell = true,
+                    NotifyAction::Desktop => notification.desktop = true,
+                    NotifyAction::Command => {
+                        notification.commands.extend(rule.command.clone());
+                    }
+                }
+            }
+        }
+        notification
+    }
+}
+
+impl Rule {
+    fn new(rule: &NotifyRule) -> Result<Self, String> {
+        let compile_option = |pattern: &Option<String>| pattern.as_deref().map(compile).transpose();
+        if rule.actions.contains(&NotifyAction::Command) && rule.command.is_none() {
+            return Err("A notification rule with the command action needs a command.".to_string());
+        }
+        Ok(Rule {
+            text: compile_option(&rule.text)?,
+            sender: compile_option(&rule.sender)?,
+            channel: compile_option(&rule.channel)?,
+            actions: rule.actions.clone(),
+            command: rule.command.clone(),
+        })
+    }
+
+    fn matches(&self, message: &Incoming) -> bool {
+        let matches = |pattern: &Option<Regex>, text: &str| {
+            pattern
+                .as_ref()
+                .is_none_or(|pattern| pattern.is_match(text))
+        };
+        matches(&self.text, message.text)
+            && matches(&self.sender, message.sender)
+            && matches(&self.channel, message.buffer)
+    }
+}
+
+/// A pattern from the config, matched ignoring case
+fn compile(pattern: &str) -> Result<Regex, String> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .build()
+        .map_err(|e| format!("Invalid pattern {pattern:?}: {e}"))
+}
+
+/// Whether the word appears in the text, ignoring case, and not as part of a longer word (so
+/// `al` isn't found in `always`).
+fn contains_word(text: &str, word: &str) -> bool {
+    if word.is_empty() {
+        return false;
+    }
+    let is_word_char = |c: char| c.is_alphanumeric() || "_-[]\\`^{}|".contains(c);
+    let text = text.to_lowercase();
+    let word = word.to_lowercase();
+    text.match_indices(&word).any(|(start, _)| {
+        let before = text[..start].chars().next_back();
+        let after = text[start + word.len()..].chars().next();
+        !before.is_some_and(is_word_char) && !after.is_some_and(is_word_char)
+    })
+}
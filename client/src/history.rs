@@ -0,0 +1,96 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::PathBuf,
+};
+
+/// Lines kept for each buffer
+const LIMIT: usize = 500;
+
+/// Lines typed by the user, kept separately for each buffer so that Up in `#rust` doesn't recall
+/// what was typed in a query. Saved to `irc_rs/history/<network>.history` in the system's data
+/// directory, one `<buffer>\t<line>` line at a time.
+#[derive(Debug, Default)]
+pub struct History {
+    /// Where the history is saved, if it is
+    path: Option<PathBuf>,
+    /// Oldest first, by lowercase buffer name
+    lines: HashMap<String, Vec<String>>,
+}
+
+impl History {
+    /// Load a network's saved history, which is then saved as lines are added.
+    pub fn load(network: &str) -> io::Result<Self> {
+        let directory = dirs::data_dir()
+            .unwrap_or_default()
+            .join("irc_rs")
+            .join("history");
+        let path = directory.join(format!("{}.history", network.replace(['/', '\\'], "_")));
+        let mut history = History {
+            path: Some(path.clone()),
+            lines: HashMap::new(),
+        };
+
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(history),
+            Err(e) => return Err(e),
+        };
+        let mut count = 0;
+        for line in BufReader::new(file).lines() {
+            if let Some((buffer, line)) = line?.split_once('\t') {
+                history.remember(buffer, line);
+                count += 1;
+            }
+        }
+        // Drop the lines that are no longer kept from the file
+        if count > history.lines.values().map(Vec::len).sum::<usize>() {
+            fs::create_dir_all(&directory)?;
+            let mut file = File::create(&path)?;
+            for (buffer, lines) in &history.lines {
+                for line in lines {
+                    writeln!(file, "{buffer}\t{line}")?;
+                }
+            }
+        }
+        Ok(history)
+    }
+
+    /// The lines typed in a buffer, oldest first
+    pub fn lines(&self, buffer: &str) -> &[String] {
+        self.lines
+            .get(&buffer.to_lowercase())
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// Add a line typed in a buffer, unless it repeats the last one. After failing to save a
+    /// line, the history is only kept in memory.
+    pub fn add(&mut self, buffer: &str, line: &str) -> io::Result<()> {
+        if self.lines(buffer).last().is_some_and(|last| last == line) {
+            return Ok(());
+        }
+        self.remember(buffer, line);
+
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let result = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| OpenOptions::new().create(true).append(true).open(path))
+            .and_then(|mut file| writeln!(file, "{}\t{line}", buffer.to_lowercase()));
+        if result.is_err() {
+            self.path = None;
+        }
+        result
+    }
+
+    fn remember(&mut self, buffer: &str, line: &str) {
+        let lines = self.lines.entry(buffer.to_lowercase()).or_default();
+        lines.push(line.to_string());
+        if lines.len() > LIMIT {
+            lines.remove(0);
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use serde::{Deserialize, Serialize};
+use std::{fs, io, path::PathBuf};
+
+/// The networks and buffers that were open when the client last quit, which `/restore` opens
+/// again. Saved to `irc_rs/session.toml` in the system's data directory:
+///
+/// ```toml
+/// active_network = "libera"
+/// active_buffer = "#rust"
+///
+/// [[networks]]
+/// name = "libera"
+/// channels = ["#rust", "#irc_rs"]
+/// queries = ["alice"]
+/// ```
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LastSession {
+    pub networks: Vec<SavedNetwork>,
+    /// The buffer being shown, and the name of its network
+    pub active_network: Option<String>,
+    pub active_buffer: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SavedNetwork {
+    /// Name of the profile, or the host if it had none
+    pub name: String,
+    pub channels: Vec<String>,
+    /// Private conversations, by nickname
+    pub queries: Vec<String>,
+}
+
+impl LastSession {
+    fn path() -> PathBuf {
+        dirs::data_dir()
+            .unwrap_or_default()
+            .join("irc_rs")
+            .join("session.toml")
+    }
+
+    /// Read the saved session, if there is one.
+    pub fn load() -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        let text = match fs::read_to_string(Self::path()) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Some(toml::from_str(&text)?))
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let path = Self::path();
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+        fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// A short description, like `#rust, #irc_rs and alice on libera`
+    pub fn summary(&self) -> String {
+        let networks = self
+            .networks
+            .iter()
+            .map(|network| {
+                let buffers = network
+                    .channels
+                    .iter()
+                    .chain(&network.queries)
+                    .cloned()
+                    .collect::<Vec<_>>();
+                match buffers.split_last() {
+                    None => network.name.clone(),
+                    Some((last, [])) => format!("{last} on {}", network.name),
+                    Some((last, rest)) => {
+                        format!("{} and {last} on {}", rest.join(", "), network.name)
+                    }
+                }
+            })
+            .collect::<Vec<_>>();
+        networks.join("; ")
+    }
+}
@@ -0,0 +1,26 @@
+//! The connection to an IRC server, for building clients and bots without the terminal UI.
+//!
+//! A [`Connection`] is opened from a [`Profile`] and reports what happens on it as [`Event`]s. See
+//! [`Connection`] for an example.
+//!
+//! With the `async` feature, [`AsyncConnection`] does the same with tokio.
+
+#[cfg(feature = "async")]
+pub mod async_connection;
+pub mod connection;
+pub mod profile;
+mod proxy;
+mod session;
+mod throttle;
+mod transport;
+
+#[cfg(feature = "async")]
+pub use async_connection::AsyncConnection;
+pub use connection::{Connection, Event, Lag};
+pub use profile::{Profile, ProxyConfig, ProxyKind, SaslConfig};
+pub use shared::{
+    codec::Decoding,
+    isupport::{ChanModes, ISupport},
+    message::{self, Command, Message},
+    prefix::Prefix,
+};
@@ -1,14 +1,68 @@
 #![allow(unused)]
-mod message;
+mod app;
+mod channel_list;
+mod chat_log;
+mod cli;
+mod command;
+mod config;
+mod ctcp;
+mod dcc;
+mod exec;
+mod export;
+mod formatting;
+mod highlight;
+mod history;
+mod last_session;
+mod members;
+mod script;
+mod theme;
+mod ui;
+mod whois;
 
-use message::Message;
-use rustyline::Editor;
+use app::{Alert, App, LineKind};
+use chat_log::ChatLog;
+use chrono::Local;
+use clap::Parser;
+use cli::Cli;
+use client::{
+    Connection, Event as NetworkEvent, Profile,
+    message::{Command, Message},
+};
+use command::{DccCommand, Input, ScriptCommand};
+use config::Config;
+use dcc::{Direction, State};
+use export::{Format, Range};
+use highlight::Highlighter;
+use history::History;
+use last_session::LastSession;
+use ratatui::{
+    crossterm::{
+        event::{
+            self, DisableBracketedPaste, DisableMouseCapture, EnableBracketedPaste,
+            EnableMouseCapture, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, MouseButton,
+            MouseEvent, MouseEventKind,
+        },
+        execute,
+    },
+    layout::{Position, Rect},
+};
+use script::Action;
+use shared::{
+    ctcp::{DccOffer, DccResume},
+    mask,
+};
 use std::{
-    env,
-    io::{self, Error, ErrorKind, Read, Write},
-    net::TcpStream,
-    process, str, thread,
+    io::{self, Write},
+    net::{IpAddr, Ipv4Addr},
+    path::PathBuf,
+    process, thread,
+    time::Duration,
 };
+use theme::Theme;
+use ui::Click;
+
+/// Lines scrolled by each step of the mouse wheel
+const SCROLL_LINES: isize = 3;
 
 // fn main() {
 //     // let m = Message::from(":arvind!arvind@localhost JOIN #foo").unwrap();
@@ -39,100 +93,1182 @@ use std::{
 
 #[quit::main]
 fn main() {
-    env_logger::init();
-
-    // Get username from command-line arguments
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        println!("Usage: client <username>");
+    let cli = Cli::parse();
+    let config = match Config::path().filter(|path| path.exists()) {
+        Some(path) => Config::load(&path).unwrap_or_else(|e| {
+            println!("Couldn't load {}: {e}", path.display());
+            quit::with_code(1);
+        }),
+        None => Config::default(),
+    };
+    // The server profile to use, with the settings given on the command line
+    let (name, profile) = cli.profile(&config).unwrap_or_else(|e| {
+        println!("{e}");
         quit::with_code(1);
-    }
-    let hostname = "127.0.0.1:6667";
-    let username = &args[1];
+    });
 
     // Connect to the server
-    let mut reader = TcpStream::connect(hostname).unwrap_or_else(|_| {
-        println!("Failed to connect to the server.");
+    let mut app = App::new();
+    app.ignored = config.ignore.clone();
+    app.strip_formatting = config.strip_formatting;
+    app.scrollback = config.scrollback_lines;
+    app.show_nicklist = config.nicklist;
+    app.show_topic = config.topic_bar;
+    app.highlighter = Highlighter::new(&config.highlight, &config.notify).unwrap_or_else(|e| {
+        println!("{e}");
+        quit::with_code(1);
+    });
+    app.theme = Theme::load(&config.theme, &config.themes).unwrap_or_else(|e| {
+        println!("{e}");
+        quit::with_code(1);
+    });
+    let connection = connect(&mut app, &config, &name, profile).unwrap_or_else(|e| {
+        println!("Failed to connect to the server: {e}");
         quit::with_code(1);
     });
-    let mut writer = reader.try_clone().expect("Failed to clone stream.");
-
-    // Create send and receive threads
-    let send_thread = thread::spawn(move || send_handler(writer));
-    let recv_thread = thread::spawn(move || recv_handler(reader));
-
-    // Wait for both threads to terminate
-    send_thread.join();
-    recv_thread.join();
-}
-
-fn send_handler(mut writer: TcpStream) {
-    let mut editor = Editor::<()>::new();
-
-    loop {
-        // let mut message = match editor.readline("> ") {
-        //     Ok(line) => {
-        //         editor.add_history_entry(line.as_str());
-        //         line
-        //     }
-        //     Err(err) => {
-        //         panic!("{err:?}");
-        //     }
-        // };
-        // Read from stdin
-        // let mut message = String::new();
-        // print!("> ");
-        // io::stdout().flush().expect("Failed to flush stdout.");
-        // io::stdin()
-        //     .read_line(&mut message)
-        //     .expect("Failed to read from stdin.");
-
-        // Read input from stdin using readline
-        let mut message = editor.readline("> ").expect("Failed to read from stdin");
-        editor.add_history_entry(&message);
-        // println!("{message:?}");
-
-        // Build message from input
-        // let msg = message_from_input(message.trim_end());
-
-        // Send message to server
-        writer
-            .write_all(message.as_bytes())
-            .expect("Failed to send message to the server.");
-
-        // Exit if user wishes to
-        if message.to_lowercase() == "quit" || message.to_lowercase() == "exit" {
-            break;
-        }
-    }
-}
-
-fn recv_handler(mut reader: TcpStream) {
-    loop {
-        // Read response from server
-        let mut response = vec![0; shared::MESSAGE_SIZE];
-        match reader.read(&mut response) {
-            Ok(bytes) => {
-                if bytes == 0 {
-                    print!("\r");
-                    io::stdout().flush().expect("Failed to flush stdout.");
-                    break;
+    let mut connections = vec![connection];
+    for path in &config.scripts {
+        if let Err(e) = app.scripts.load(path) {
+            app.push(0, app::STATUS_BUFFER, LineKind::Error, e);
+        }
+    }
+    if config.save_session {
+        offer_last_session(&mut app);
+    }
+
+    let mut terminal = ratatui::init();
+    // Pasted text arrives as a whole instead of as key presses, so that a newline in it doesn't
+    // send what came before
+    let _ = execute!(io::stdout(), EnableBracketedPaste);
+    if config.mouse {
+        let _ = execute!(io::stdout(), EnableMouseCapture);
+    }
+    let result = run(&mut terminal, &mut app, &config, &mut connections);
+    if config.mouse {
+        let _ = execute!(io::stdout(), DisableMouseCapture);
+    }
+    let _ = execute!(io::stdout(), DisableBracketedPaste);
+    ratatui::restore();
+
+    if config.save_session
+        && let Err(e) = app.session().save()
+    {
+        println!("Couldn't save the session: {e}");
+    }
+
+    if let Err(err) = result {
+        println!("{err}");
+        quit::with_code(1);
+    }
+}
+
+/// Connect to a server and register, adding its network to the app.
+fn connect(app: &mut App, config: &Config, name: &str, profile: Profile) -> io::Result<Connection> {
+    let connection = Connection::connect(profile)?;
+    let chat_log = config
+        .logging
+        .enabled
+        .then(|| ChatLog::new(&config.logging, &connection.profile().host));
+    let nick = connection.profile().nick.clone();
+    let network = app.add_network(name, &nick, chat_log);
+    if config.save_history {
+        match History::load(name) {
+            Ok(history) => app.networks[network].history = history,
+            Err(e) => {
+                let text = format!("Couldn't load the input history: {e}");
+                app.push(network, app::STATUS_BUFFER, LineKind::Error, text);
+            }
+        }
+    }
+    Ok(connection)
+}
+
+/// Connect to another network, named by its profile or its host, or switch to it if it's
+/// already connected. The connections are kept in the same order as the app's networks.
+fn connect_to(app: &mut App, config: &Config, connections: &mut Vec<Connection>, name: &str) {
+    if let Some(network) = app.find_network(name) {
+        app.open(network, app::STATUS_BUFFER);
+        return;
+    }
+    let (name, profile) = profile_named(config, name);
+    match connect(app, config, &name, profile) {
+        Ok(connection) => connections.push(connection),
+        Err(e) => app.push_active(LineKind::Error, format!("Couldn't connect to {name}: {e}")),
+    }
+}
+
+/// A profile by name, or settings for connecting to a host without one.
+fn profile_named(config: &Config, name: &str) -> (String, Profile) {
+    config
+        .profile(Some(name))
+        .unwrap_or_else(|_| (name.to_string(), config.profile_for_host(name)))
+}
+
+/// Say what was open when the client last quit, if anything, and how to open it again.
+fn offer_last_session(app: &mut App) {
+    let text = match LastSession::load() {
+        Ok(Some(session)) if !session.networks.is_empty() => format!(
+            "Last time you had {} open. Type /restore to open them again.",
+            session.summary()
+        ),
+        Ok(_) => return,
+        Err(e) => format!("Couldn't load the last session: {e}"),
+    };
+    app.push(0, app::STATUS_BUFFER, LineKind::Info, text);
+}
+
+/// Connect to the networks from the last session and open their channels and private
+/// conversations again, switching to the buffer that was being shown.
+fn restore_session(app: &mut App, config: &Config, connections: &mut Vec<Connection>) {
+    let session = match LastSession::load() {
+        Ok(Some(session)) => session,
+        Ok(None) => {
+            app.push_active(
+                LineKind::Error,
+                "There's no session to restore.".to_string(),
+            );
+            return;
+        }
+        Err(e) => {
+            let text = format!("Couldn't load the last session: {e}");
+            app.push_active(LineKind::Error, text);
+            return;
+        }
+    };
+
+    for saved in &session.networks {
+        let network = match app.find_network(&saved.name) {
+            Some(network) => {
+                let open = app.channels(network);
+                for channel in &saved.channels {
+                    if !open.iter().any(|name| name.eq_ignore_ascii_case(channel)) {
+                        send(app, &mut connections[network], &format!("JOIN {channel}"));
+                    }
+                }
+                network
+            }
+            None => {
+                // Joined once registered, along with the profile's own channels
+                let (name, mut profile) = profile_named(config, &saved.name);
+                for channel in &saved.channels {
+                    if !profile
+                        .autojoin
+                        .iter()
+                        .any(|c| c.eq_ignore_ascii_case(channel))
+                    {
+                        profile.autojoin.push(channel.clone());
+                    }
+                }
+                match connect(app, config, &name, profile) {
+                    Ok(connection) => connections.push(connection),
+                    Err(e) => {
+                        let text = format!("Couldn't connect to {name}: {e}");
+                        app.push_active(LineKind::Error, text);
+                        continue;
+                    }
+                }
+                app.networks.len() - 1
+            }
+        };
+        app.restore_buffers(network, saved);
+    }
+
+    if let (Some(network), Some(buffer)) = (&session.active_network, &session.active_buffer)
+        && let Some(network) = app.find_network(network)
+    {
+        app.open(network, buffer);
+    }
+}
+
+/// Answer a CTCP request sent to us or to a channel we're in.
+fn reply_to_ctcp(app: &mut App, connection: &mut Connection, message: &Message) {
+    if message.command != Command::PrivMsg {
+        return;
+    }
+    let Some((command, args)) = message.params.get(1).and_then(|text| ctcp::parse(text)) else {
+        return;
+    };
+    // Requests only come from users, never servers
+    let Some(nick) = message.prefix.as_ref().and_then(|prefix| prefix.nick()) else {
+        return;
+    };
+
+    if let Some(reply) = ctcp::reply(command, args) {
+        send(app, connection, &format!("NOTICE {nick} :{reply}"));
+    }
+}
+
+/// Show a file offered with `DCC SEND`, or start sending a file if it answers our passive offer.
+/// Requests to resume a file we offered are agreed to, and agreement to resume one offered to us
+/// starts receiving it.
+fn handle_dcc_request(
+    app: &mut App,
+    config: &Config,
+    connection: &mut Connection,
+    network: usize,
+    message: &Message,
+) {
+    if message.command != Command::PrivMsg {
+        return;
+    }
+    let Some(("DCC", args)) = message.params.get(1).and_then(|text| ctcp::parse(text)) else {
+        return;
+    };
+    let nick = message.nick();
+
+    let (kind, text) = if let Some(offer) = DccOffer::parse(args) {
+        (
+            LineKind::Info,
+            app.dcc.handle_offer(&config.dcc, nick, offer),
+        )
+    } else if let Some(request) = DccResume::parse(args, "RESUME") {
+        let (answer, text) = app.dcc.handle_resume(nick, request);
+        if let Some(answer) = answer {
+            send(app, connection, &format!("PRIVMSG {nick} :{answer}"));
+        }
+        (LineKind::Info, text)
+    } else if let Some(answer) = DccResume::parse(args, "ACCEPT") {
+        match app
+            .dcc
+            .handle_accept(&config.dcc, nick, answer, local_ip(connection))
+        {
+            Ok(answer) => {
+                if let Some(answer) = answer {
+                    send(app, connection, &format!("PRIVMSG {nick} :{answer}"));
+                }
+                return;
+            }
+            Err(err) => (LineKind::Error, err),
+        }
+    } else {
+        let text = format!("{nick} sent an unsupported DCC request: {args}");
+        (LineKind::Info, text)
+    };
+    app.push(network, app::STATUS_BUFFER, kind, text);
+}
+
+/// The address DCC offers are made from unless the config gives one
+fn local_ip(connection: &Connection) -> IpAddr {
+    // Only used to pick between IPv4 and IPv6 while disconnected
+    connection
+        .local_ip()
+        .unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST))
+}
+
+fn handle_dcc_command(
+    app: &mut App,
+    config: &Config,
+    connection: &mut Connection,
+    command: DccCommand,
+) {
+    let local_ip = local_ip(connection);
+    match command {
+        DccCommand::Send { nick, path } => {
+            match app.dcc.offer(&config.dcc, &nick, &path, local_ip) {
+                Ok(offer) => {
+                    send(app, connection, &format!("PRIVMSG {nick} :{offer}"));
+                    let text = format!("Offered {} to {nick}.", path.display());
+                    app.push_active(LineKind::Info, text);
+                }
+                Err(err) => app.push_active(LineKind::Error, err),
+            }
+        }
+        DccCommand::Get { nick, file } => {
+            let Some(id) = app.dcc.find(&nick, file.as_deref()) else {
+                app.push_active(LineKind::Error, format!("No file offered by {nick}."));
+                return;
+            };
+            match app.dcc.accept(&config.dcc, id, local_ip) {
+                Ok(Some(answer)) => send(app, connection, &format!("PRIVMSG {nick} :{answer}")),
+                Ok(None) => {}
+                Err(err) => app.push_active(LineKind::Error, err),
+            }
+        }
+        DccCommand::Resume { nick, file } => {
+            let Some(id) = app.dcc.find(&nick, file.as_deref()) else {
+                app.push_active(LineKind::Error, format!("No file offered by {nick}."));
+                return;
+            };
+            match app.dcc.resume(&config.dcc, id) {
+                Ok(request) => send(app, connection, &format!("PRIVMSG {nick} :{request}")),
+                Err(err) => app.push_active(LineKind::Error, err),
+            }
+        }
+        DccCommand::Close { nick, file } => match app.dcc.find(&nick, file.as_deref()) {
+            Some(id) => {
+                app.dcc.close(id);
+                let text = format!("Closed {}.", app.dcc.transfers[id].file_name);
+                app.push_active(LineKind::Info, text);
+            }
+            None => app.push_active(LineKind::Error, format!("No transfer with {nick}.")),
+        },
+        DccCommand::List => {
+            if app.dcc.transfers.is_empty() {
+                app.push_active(LineKind::Info, "No DCC transfers.".to_string());
+            }
+            let lines = app
+                .dcc
+                .transfers
+                .iter()
+                .map(|transfer| {
+                    let direction = match transfer.direction {
+                        Direction::Send => "to",
+                        Direction::Receive => "from",
+                    };
+                    let state = match &transfer.state {
+                        State::Offered => "waiting".to_string(),
+                        State::Resuming => "waiting to resume".to_string(),
+                        State::Transferring => format!("{}%", transfer.percent()),
+                        State::Done => "done".to_string(),
+                        State::Failed(error) => format!("failed: {error}"),
+                    };
+                    format!(
+                        "{} ({}) {direction} {}: {state}",
+                        transfer.file_name,
+                        dcc::format_size(transfer.size),
+                        transfer.nick
+                    )
+                })
+                .collect::<Vec<_>>();
+            for line in lines {
+                app.push_active(LineKind::Info, line);
+            }
+        }
+    }
+}
+
+/// Draw the interface and handle key presses and server messages until the user quits.
+fn run(
+    terminal: &mut ratatui::DefaultTerminal,
+    app: &mut App,
+    config: &Config,
+    connections: &mut Vec<Connection>,
+) -> io::Result<()> {
+    // Areas of the screen that can be clicked, as last drawn
+    let mut targets = vec![];
+    while !app.should_quit {
+        terminal.draw(|frame| targets = ui::draw(frame, app))?;
+        // Page by the height of the message area, leaving out the status bar and input
+        let height = usize::from(terminal.size()?.height.saturating_sub(3));
+        app.page_size = (height / app.panes.len()).max(1);
+
+        if event::poll(Duration::from_millis(50))? {
+            match event::read()? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => {
+                    handle_key(app, key, config, connections);
+                }
+                Event::Paste(text) => app.paste(&text),
+                Event::Mouse(mouse) => handle_mouse(app, mouse, &targets),
+                _ => {}
+            }
+        }
+        app.scripts.tick();
+        run_script_actions(app, config, connections, app.network());
+
+        for network in 0..connections.len() {
+            while let Some(event) = connections[network].poll() {
+                handle_event(app, config, network, &mut connections[network], event);
+                run_script_actions(app, config, connections, network);
+            }
+            app.networks[network].lag = connections[network].lag();
+            app.networks[network].queued = connections[network].queued();
+        }
+
+        for alert in std::mem::take(&mut app.alerts) {
+            notify(&alert);
+        }
+        for (text, is_error) in app.dcc.poll() {
+            let kind = if is_error {
+                LineKind::Error
+            } else {
+                LineKind::Info
+            };
+            app.push(app.network(), app::STATUS_BUFFER, kind, text);
+        }
+        for output in app.exec.poll() {
+            show_exec_output(app, config, connections, output);
+        }
+    }
+
+    Ok(())
+}
+
+/// Handle something that happened on the connection to one of the networks.
+fn handle_event(
+    app: &mut App,
+    config: &Config,
+    network: usize,
+    connection: &mut Connection,
+    event: NetworkEvent,
+) {
+    match event {
+        NetworkEvent::Message(message) if app.is_ignored(&message) => {}
+        // Our own messages, sent back with echo-message, are only shown
+        NetworkEvent::Message(message)
+            if matches!(message.command, Command::PrivMsg | Command::Notice)
+                && message.nick().eq_ignore_ascii_case(connection.nick()) =>
+        {
+            app.handle_message(network, &message);
+        }
+        NetworkEvent::Message(mut message) => {
+            if config.ctcp_replies {
+                reply_to_ctcp(app, connection, &message);
+            }
+            handle_dcc_request(app, config, connection, network, &message);
+
+            // Let scripts rewrite or hide messages, other than CTCP
+            let mut hidden = false;
+            if matches!(message.command, Command::PrivMsg | Command::Notice)
+                && let [target, text] = &message.params[..]
+                && ctcp::parse(text).is_none()
+            {
+                let name = &app.networks[network].name;
+                let text = app.scripts.on_message(name, message.nick(), target, text);
+                hidden = text.is_empty();
+                message.params[1] = text;
+            }
+            if !hidden {
+                app.handle_message(network, &message);
+            }
+        }
+        NetworkEvent::Join { nick, channel } => {
+            // Fill the new buffer with what was said before we joined
+            if nick.eq_ignore_ascii_case(connection.nick())
+                && connection.has_cap("draft/chathistory")
+                && config.history_backfill > 0
+            {
+                let line = format!("CHATHISTORY LATEST {channel} * {}", config.history_backfill);
+                if let Err(e) = connection.send(&line) {
+                    let text = format!("Failed to send message: {e}");
+                    app.push(network, app::STATUS_BUFFER, LineKind::Error, text);
+                }
+            }
+            let name = &app.networks[network].name;
+            app.scripts.on_join(name, &nick, &channel);
+        }
+        // RPL_WELCOME: the profile's channels are being joined
+        NetworkEvent::Numeric { code: 1, .. } => {
+            for channel in &connection.profile().autojoin {
+                app.open(network, channel);
+            }
+        }
+        NetworkEvent::Nick { .. } | NetworkEvent::Numeric { .. } => {}
+        NetworkEvent::Disconnected => {
+            app.networks[network].connected = false;
+            app.networks[network].registered = false;
+            app.push(
+                network,
+                app::STATUS_BUFFER,
+                LineKind::Error,
+                "Disconnected from the server.".to_string(),
+            );
+        }
+        NetworkEvent::Status(status) => {
+            app.push(network, app::STATUS_BUFFER, LineKind::Info, status);
+        }
+        NetworkEvent::Reconnected => {
+            app.networks[network].connected = true;
+            app.push(
+                network,
+                app::STATUS_BUFFER,
+                LineKind::Info,
+                "Reconnected.".to_string(),
+            );
+        }
+        NetworkEvent::Error(e) => {
+            let text = format!("Failed to send message: {e}");
+            app.push(network, app::STATUS_BUFFER, LineKind::Error, text);
+        }
+    }
+}
+
+/// Let the user know about a message, as the highlight settings and notification rules say.
+fn notify(alert: &Alert) {
+    let Alert {
+        buffer,
+        line,
+        notification,
+        ..
+    } = alert;
+    if notification.bell {
+        let _ = io::stdout()
+            .write_all(b"\x07")
+            .and_then(|_| io::stdout().flush());
+    }
+    let mut children = vec![];
+    if notification.desktop {
+        children.push(if cfg!(target_os = "macos") {
+            let script = format!("display notification {line:?} with title {buffer:?}");
+            process::Command::new("osascript")
+                .args(["-e", &script])
+                .spawn()
+        } else {
+            // The text is from other users, so it mustn't be read as options
+            process::Command::new("notify-send")
+                .args(["--", buffer, line])
+                .spawn()
+        });
+    }
+    for command in &notification.commands {
+        let mut shell = if cfg!(windows) {
+            let mut shell = process::Command::new("cmd");
+            shell.args(["/C", command]);
+            shell
+        } else {
+            let mut shell = process::Command::new("sh");
+            shell.args(["-c", command]);
+            shell
+        };
+        // Passed in the environment, so that the message can't be run as part of the command
+        shell
+            .env("IRC_BUFFER", buffer)
+            .env("IRC_SENDER", &alert.nick)
+            .env("IRC_TEXT", &alert.text)
+            .stdin(process::Stdio::null())
+            .stdout(process::Stdio::null())
+            .stderr(process::Stdio::null());
+        children.push(shell.spawn());
+    }
+    // Reap the processes without waiting for them
+    for mut child in children.into_iter().flatten() {
+        thread::spawn(move || child.wait());
+    }
+}
+
+/// Open a link from the active buffer in the system's browser.
+fn open_url(app: &mut App, number: Option<usize>) {
+    let Some(url) = app.active_buffer().url(number).map(str::to_string) else {
+        let err = match number {
+            Some(number) => format!("There's no link [{number}] here. Use /urls to list them."),
+            None => "There are no links here.".to_string(),
+        };
+        app.push_active(LineKind::Error, err);
+        return;
+    };
+    // Links like www.example.com need a scheme for the browser to know what they are
+    let url = if url.contains("://") {
+        url
+    } else {
+        format!("https://{url}")
+    };
+    let result = if cfg!(target_os = "macos") {
+        process::Command::new("open").arg(&url).spawn()
+    } else if cfg!(windows) {
+        // The empty argument is the window title, so that a quoted link isn't taken for one
+        process::Command::new("cmd")
+            .args(["/C", "start", "", &url])
+            .spawn()
+    } else {
+        process::Command::new("xdg-open").arg(&url).spawn()
+    };
+    match result {
+        Ok(mut child) => {
+            // Reap the process without waiting for it
+            thread::spawn(move || child.wait());
+            app.push_active(LineKind::Info, format!("Opening {url}"));
+        }
+        Err(e) => app.push_active(LineKind::Error, format!("Couldn't open {url}: {e}")),
+    }
+}
+
+/// Write the active buffer, or what was logged for it in a range of time, to an HTML or Markdown
+/// file. Without a path, it's named after the buffer and the time and saved in the download
+/// directory.
+fn export_buffer(
+    app: &App,
+    config: &Config,
+    range: Option<Range>,
+    path: Option<PathBuf>,
+    format: Format,
+) -> Result<String, String> {
+    let buffer = app.active_buffer();
+    let title = app.title(buffer);
+    let logged;
+    let lines = match &range {
+        None => buffer.lines.iter().collect::<Vec<_>>(),
+        Some(range) => {
+            let chat_log = app.networks[buffer.network].chat_log.as_ref().ok_or(
+                "Logging is off, so only the lines in the buffer can be exported. Leave out the \
+                 range to export those.",
+            )?;
+            logged = match chat_log.lines_in(&buffer.name, range) {
+                Ok(lines) => lines,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => vec![],
+                Err(e) => return Err(format!("Couldn't read the log of {title}: {e}")),
+            };
+            logged.iter().collect()
+        }
+    };
+    if lines.is_empty() {
+        return Err("There's nothing to export.".to_string());
+    }
+
+    let path = match path {
+        Some(path) => config::expand_home(&path),
+        None => config.dcc.download_directory().join(format!(
+            "{}-{}.{}",
+            chat_log::file_name(&title),
+            Local::now().format("%Y%m%d-%H%M%S"),
+            format.extension()
+        )),
+    };
+    export::write(&path, format, &title, &lines)
+        .map_err(|e| format!("Couldn't write {}: {e}", path.display()))?;
+    Ok(format!(
+        "Exported {} lines to {}",
+        lines.len(),
+        path.display()
+    ))
+}
+
+/// Show a buffer in a new pane below the focused one.
+fn split(app: &mut App, name: Option<&str>) {
+    let index = match name {
+        Some(name) => match app.lookup_buffer(name) {
+            Some(index) => index,
+            None => {
+                app.push_active(LineKind::Error, format!("There's no buffer {name}."));
+                return;
+            }
+        },
+        None => app.active,
+    };
+    if let Err(err) = app.split(index) {
+        app.push_active(LineKind::Error, err);
+    }
+}
+
+/// List the links kept in the active buffer, oldest first.
+fn list_urls(app: &mut App) {
+    let lines = app
+        .active_buffer()
+        .urls
+        .iter()
+        .map(|(number, url)| format!("[{number}] {url}"))
+        .collect::<Vec<_>>();
+    if lines.is_empty() {
+        app.push_active(LineKind::Info, "There are no links here.".to_string());
+    }
+    for line in lines {
+        app.push_active(LineKind::Info, line);
+    }
+}
+
+/// Keybindings:
+/// - Enter: send the typed line
+/// - Tab: complete a command, channel or nickname
+/// - Up/Down: go through the lines typed in the buffer
+/// - Alt+Left/Right or Ctrl+P/N: switch to the previous/next buffer
+/// - Alt+1 to Alt+9: switch to a buffer by number
+/// - Alt+Up/Down: focus the pane above/below, after splitting the screen with `/split`
+/// - Alt+N/Alt+T: show or hide the nicklist/the topic bar
+/// - Page Up/Down: scroll the buffer
+/// - Ctrl+W: close the buffer, leaving the channel
+/// - Ctrl+C: quit
+///
+/// After pasting several lines, Enter sends them, Tab joins them into one line and Esc drops them.
+///
+/// In the buffer of channels listed by `/list`, typing filters them, Up/Down and Page Up/Down
+/// move the selection, Enter joins the selected channel and Ctrl+S sorts by users or by name.
+/// Scroll with the wheel and click buffers in the status bar, and nicknames and the numbers after
+/// links in the buffer. Clicking a topic puts it in the input to be changed, and clicking in a pane
+/// focuses it.
+fn handle_mouse(app: &mut App, mouse: MouseEvent, targets: &[(Rect, Click)]) {
+    let in_list = app.active_buffer().list.is_some();
+    match mouse.kind {
+        MouseEventKind::ScrollUp if in_list => app.move_list_selection(-SCROLL_LINES),
+        MouseEventKind::ScrollDown if in_list => app.move_list_selection(SCROLL_LINES),
+        MouseEventKind::ScrollUp => app.scroll_up(SCROLL_LINES as usize),
+        MouseEventKind::ScrollDown => app.scroll_down(SCROLL_LINES as usize),
+        MouseEventKind::Down(MouseButton::Left) => {
+            let position = Position::new(mouse.column, mouse.row);
+            let mut clicks = targets
+                .iter()
+                .filter(|(area, _)| area.contains(position))
+                .map(|(_, click)| click);
+            let click = clicks.next();
+            // Whatever was clicked in a pane is about the buffer in it
+            if let Some(Click::Pane(pane)) = clicks.find(|click| matches!(click, Click::Pane(_))) {
+                app.focus_pane(*pane);
+            }
+            match click {
+                Some(Click::Buffer(index)) => app.select(*index),
+                Some(Click::Nick(nick)) => {
+                    app.reset_completion();
+                    app.insert_nick(nick);
                 }
+                Some(Click::Url(number)) => open_url(app, Some(*number)),
+                Some(Click::Pane(pane)) => app.focus_pane(*pane),
+                Some(Click::Topic(channel)) => app.edit_topic(channel),
+                None => {}
+            }
+        }
+        _ => {}
+    }
+}
+
+fn handle_key(app: &mut App, key: KeyEvent, config: &Config, connections: &mut Vec<Connection>) {
+    let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+    let alt = key.modifiers.contains(KeyModifiers::ALT);
+    let in_list = app.active_buffer().list.is_some();
+    let page = app.page_size as isize;
+
+    if app.pending_paste.is_some() {
+        match key.code {
+            KeyCode::Enter => send_paste(app, config, connections),
+            KeyCode::Tab => app.collapse_paste(),
+            KeyCode::Esc => {
+                app.pending_paste = None;
+                app.push_active(LineKind::Info, "Dropped the pasted lines.".to_string());
+            }
+            KeyCode::Char('c') if ctrl => quit(app, connections, "QUIT"),
+            _ => {}
+        }
+        return;
+    }
+
+    if key.code == KeyCode::Tab {
+        app.complete();
+        return;
+    }
+    app.reset_completion();
+
+    match key.code {
+        // Typed commands still work in the list buffer
+        KeyCode::Enter if in_list && !app.input.starts_with('/') => {
+            if let Some(channel) = app.selected_channel() {
+                app.take_input();
+                submit(app, config, connections, &format!("/join {channel}"));
+            }
+        }
+        KeyCode::Enter => {
+            let line = app.take_input();
+            if !line.is_empty() {
+                app.remember_input(&line);
+                submit(app, config, connections, &line);
             }
-            Err(err) => panic!("{err}"),
+        }
+        KeyCode::Char('c') if ctrl => quit(app, connections, "QUIT"),
+        KeyCode::Char('p') if ctrl => app.previous_buffer(),
+        KeyCode::Char('n') if ctrl => app.next_buffer(),
+        KeyCode::Char('n') if alt => app.show_nicklist = !app.show_nicklist,
+        KeyCode::Char('t') if alt => app.show_topic = !app.show_topic,
+        KeyCode::Left if alt => app.previous_buffer(),
+        KeyCode::Right if alt => app.next_buffer(),
+        KeyCode::Up if alt => app.previous_pane(),
+        KeyCode::Down if alt => app.next_pane(),
+        KeyCode::Char(digit @ '1'..='9') if alt => {
+            app.select(digit.to_digit(10).unwrap() as usize - 1)
+        }
+        KeyCode::Char('w') if ctrl => {
+            if let Some(channel) = app.target().filter(|target| target.starts_with(['#', '&'])) {
+                let line = format!("PART {channel}");
+                send(app, &mut connections[app.network()], &line);
+            }
+            app.close_active();
+        }
+        KeyCode::Char('s') if ctrl && in_list => app.toggle_list_sort(),
+        KeyCode::Char('a') if ctrl => app.cursor = 0,
+        KeyCode::Char('e') if ctrl => app.cursor_to_end(),
+        KeyCode::Char('u') if ctrl => {
+            app.take_input();
+        }
+        KeyCode::Char(c) => app.insert_char(c),
+        KeyCode::Backspace => app.delete_before_cursor(),
+        KeyCode::Delete => app.delete_at_cursor(),
+        KeyCode::Left => app.move_cursor(-1),
+        KeyCode::Right => app.move_cursor(1),
+        KeyCode::Up if in_list => app.move_list_selection(-1),
+        KeyCode::Down if in_list => app.move_list_selection(1),
+        KeyCode::PageUp if in_list => app.move_list_selection(-page),
+        KeyCode::PageDown if in_list => app.move_list_selection(page),
+        KeyCode::Up => app.history_previous(),
+        KeyCode::Down => app.history_next(),
+        KeyCode::Home => app.cursor = 0,
+        KeyCode::End => app.cursor_to_end(),
+        KeyCode::PageUp => app.scroll_up(app.page_size),
+        KeyCode::PageDown => app.scroll_down(app.page_size),
+        _ => {}
+    }
+}
+
+/// Interpret a typed line and send it to the server of the buffer being shown.
+fn submit(app: &mut App, config: &Config, connections: &mut Vec<Connection>, line: &str) {
+    let network = app.network();
+    let line = command::expand_alias(line, &config.aliases);
+    // Commands added by scripts
+    if let Some((name, args)) = line
+        .strip_prefix('/')
+        .map(|rest| rest.split_once(' ').unwrap_or((rest, "")))
+        && app.scripts.run_command(name, args.trim_start())
+    {
+        return;
+    }
+
+    let line = match command::parse(&line, app.target()) {
+        Ok(Input::Message(message)) if message.command == Command::Quit => {
+            quit(app, connections, &message.to_string());
+            return;
+        }
+        Ok(Input::Message(mut message)) => {
+            if run_send_hooks(app, network, &mut message) {
+                send_typed(app, &mut connections[network], network, message);
+            }
+            return;
+        }
+        Ok(Input::Raw(raw)) => raw,
+        Ok(Input::Query { nick, message }) => {
+            app.open(network, &nick);
+            let Some(mut message) = message else {
+                return;
+            };
+            if run_send_hooks(app, network, &mut message) {
+                send_typed(app, &mut connections[network], network, message);
+            }
+            return;
+        }
+        Ok(Input::Ignore(mask)) => {
+            ignore(app, mask);
+            return;
+        }
+        Ok(Input::Unignore(mask)) => {
+            unignore(app, &mask);
+            return;
+        }
+        Ok(Input::Search(text)) => {
+            if let Err(err) = app.search(text.as_deref()) {
+                app.push_active(LineKind::Error, err);
+            }
+            return;
+        }
+        Ok(Input::Theme(name)) => {
+            switch_theme(app, config, name.as_deref());
+            return;
+        }
+        Ok(Input::ReclaimNick) => {
+            reclaim_nick(app, &mut connections[network], network);
+            return;
+        }
+        Ok(Input::OpenUrl(number)) => {
+            open_url(app, number);
+            return;
+        }
+        Ok(Input::Urls) => {
+            list_urls(app);
+            return;
+        }
+        Ok(Input::Split(name)) => {
+            split(app, name.as_deref());
+            return;
+        }
+        Ok(Input::Unsplit) => {
+            if let Err(err) = app.unsplit() {
+                app.push_active(LineKind::Error, err);
+            }
+            return;
+        }
+        Ok(Input::Connect(name)) => {
+            connect_to(app, config, connections, &name);
+            return;
+        }
+        Ok(Input::Restore) => {
+            restore_session(app, config, connections);
+            return;
+        }
+        // The server's reply shows the topic, and the input is ready to change it
+        Ok(Input::Topic(channel)) => {
+            app.edit_topic(&channel);
+            format!("TOPIC {channel}")
+        }
+        Ok(Input::Export {
+            range,
+            path,
+            format,
+        }) => {
+            match export_buffer(app, config, range, path, format) {
+                Ok(info) => app.push_active(LineKind::Info, info),
+                Err(err) => app.push_active(LineKind::Error, err),
+            }
+            return;
+        }
+        Ok(Input::Exec { command, send }) => {
+            let buffer = app.active_buffer().name.clone();
+            app.exec.run(&command, network, &buffer, send);
+            return;
+        }
+        Ok(Input::Dcc(command)) => {
+            handle_dcc_command(app, config, &mut connections[network], command);
+            return;
+        }
+        Ok(Input::Script(command)) => {
+            handle_script_command(app, command);
+            return;
+        }
+        Err(err) => {
+            app.push_active(LineKind::Error, err);
+            return;
+        }
+    };
+    send(app, &mut connections[network], &line);
+}
+
+/// Let scripts rewrite a message typed by the user. Returns false if one of them dropped it.
+fn run_send_hooks(app: &mut App, network: usize, message: &mut Message) -> bool {
+    if let (Command::PrivMsg, [target, text]) = (&message.command, &message.params[..])
+        && ctcp::parse(text).is_none()
+    {
+        let name = &app.networks[network].name;
+        let text = app.scripts.on_send(name, target, text);
+        if text.is_empty() {
+            return false;
+        }
+        message.params[1] = text;
+    }
+    true
+}
+
+/// Carry out what scripts asked for. Lines they send go to the given network.
+fn run_script_actions(
+    app: &mut App,
+    config: &Config,
+    connections: &mut Vec<Connection>,
+    network: usize,
+) {
+    for action in app.scripts.take_actions() {
+        match action {
+            Action::Send(line) => {
+                if let Ok(message) = Message::from(&line) {
+                    show_sent(app, &connections[network], network, &message);
+                }
+                send(app, &mut connections[network], &line);
+            }
+            Action::Run(line) => submit(app, config, connections, &line),
+            Action::Echo(text) => app.push_active(LineKind::Info, text),
+            Action::Error(text) => app.push_active(LineKind::Error, text),
+        }
+    }
+}
+
+/// Show what a command run with `/exec` printed in the buffer it was run from. With `-o`, the
+/// first `exec_max_lines` lines that aren't blank are sent there as messages instead, paced by
+/// flood protection like anything else.
+fn show_exec_output(
+    app: &mut App,
+    config: &Config,
+    connections: &mut [Connection],
+    output: exec::Output,
+) {
+    let exec::Output {
+        command,
+        network,
+        buffer,
+        send,
+        lines,
+        status,
+    } = output;
+    let lines = match lines {
+        Ok(lines) => lines,
+        Err(err) => {
+            app.push(network, &buffer, LineKind::Error, err);
+            return;
+        }
+    };
+    let mut lines = lines
+        .into_iter()
+        .filter(|line| !send || !line.trim().is_empty());
+    if send {
+        for text in lines.by_ref().take(config.exec_max_lines) {
+            let mut message = Message::privmsg(&buffer, &text);
+            if run_send_hooks(app, network, &mut message) {
+                send_typed(app, &mut connections[network], network, message);
+            }
+        }
+    }
+    let rest = lines.collect::<Vec<_>>();
+    if send && !rest.is_empty() {
+        let text = format!(
+            "Only sent {} lines. The other {} weren't:",
+            config.exec_max_lines,
+            rest.len()
+        );
+        app.push(network, &buffer, LineKind::Info, text);
+    }
+    for line in rest {
+        app.push(network, &buffer, LineKind::Info, line);
+    }
+    match status {
+        Some(0) => {}
+        Some(code) => {
+            let text = format!("{command:?} exited with code {code}");
+            app.push(network, &buffer, LineKind::Error, text);
+        }
+        None => {
+            let text = format!("{command:?} was stopped by a signal");
+            app.push(network, &buffer, LineKind::Error, text);
+        }
+    }
+}
+
+/// Switch to a theme, or list the themes if none is given.
+fn switch_theme(app: &mut App, config: &Config, name: Option<&str>) {
+    let Some(name) = name else {
+        let names = Theme::names(&config.themes).join(", ");
+        let text = format!("Themes: {names}. Using {}.", app.theme.name);
+        app.push_active(LineKind::Info, text);
+        return;
+    };
+    match Theme::load(name, &config.themes) {
+        Ok(theme) => {
+            app.push_active(
+                LineKind::Info,
+                format!("Switched to the {} theme.", theme.name),
+            );
+            app.theme = theme;
+        }
+        Err(err) => app.push_active(LineKind::Error, err),
+    }
+}
+
+/// Take back the profile's nickname, now or once whoever has it leaves.
+fn reclaim_nick(app: &mut App, connection: &mut Connection, network: usize) {
+    let nick = connection.profile().nick.clone();
+    if connection.nick().eq_ignore_ascii_case(&nick) {
+        app.push_active(LineKind::Info, format!("You're already {nick}."));
+        return;
+    }
+    match connection.reclaim_nick() {
+        Ok(()) => {
+            let text = format!("Taking back {nick}, as soon as it's free if someone has it.");
+            app.push(network, app::STATUS_BUFFER, LineKind::Info, text);
+        }
+        Err(e) => app.push_active(LineKind::Error, format!("Failed to send message: {e}")),
+    }
+}
+
+fn handle_script_command(app: &mut App, command: ScriptCommand) {
+    match command {
+        ScriptCommand::Load(path) => match app.scripts.load(&path) {
+            Ok(name) => app.push_active(LineKind::Info, format!("Loaded {name}.")),
+            Err(err) => app.push_active(LineKind::Error, err),
+        },
+        ScriptCommand::Unload(name) => match app.scripts.unload(&name) {
+            Ok(()) => app.push_active(LineKind::Info, format!("Unloaded {name}.")),
+            Err(err) => app.push_active(LineKind::Error, err),
+        },
+        ScriptCommand::List => {
+            let lines = app
+                .scripts
+                .list()
+                .iter()
+                .map(|(name, path)| format!("{name} ({})", path.display()))
+                .collect::<Vec<_>>();
+            if lines.is_empty() {
+                app.push_active(LineKind::Info, "No scripts are loaded.".to_string());
+            }
+            for line in lines {
+                app.push_active(LineKind::Info, line);
+            }
+        }
+    }
+}
+
+/// Leave every network and quit.
+fn quit(app: &mut App, connections: &mut [Connection], line: &str) {
+    // Ahead of anything still queued, which is dropped when the client closes
+    for connection in connections {
+        if let Err(err) = connection.send_now(line) {
+            app.push_active(LineKind::Error, format!("Failed to send message: {err}"));
+        }
+    }
+    app.should_quit = true;
+}
+
+/// Add a mask to the ignore list and save it in the config file, or show the list if no mask is
+/// given. A nickname ignores everyone using it.
+fn ignore(app: &mut App, mask: Option<String>) {
+    let Some(mask) = mask else {
+        let text = if app.ignored.is_empty() {
+            "Nobody is ignored.".to_string()
+        } else {
+            format!("Ignored: {}", app.ignored.join(", "))
         };
+        app.push_active(LineKind::Info, text);
+        return;
+    };
+
+    let mask = mask::normalize(&mask);
+    if app.ignored.iter().any(|m| m.eq_ignore_ascii_case(&mask)) {
+        app.push_active(LineKind::Error, format!("{mask} is already ignored."));
+        return;
+    }
+    app.ignored.push(mask.clone());
+    app.push_active(LineKind::Info, format!("Ignoring {mask}."));
+    save_ignore_list(app);
+}
 
-        // Convert response to a `str` and print it out
-        // TODO: Figure out a way to avoid this mess
-        let response_str = str::from_utf8(&response)
-            .expect("Server sent an invalid UTF-8 message.")
-            .replace('\0', "");
-        let response_str = response_str.trim_end();
-
-        print!("\r"); // Clear the current line; TODO: this needs some work
-        println!("<Server> {:?}", response_str);
-        print!("> ");
-        io::stdout().flush().expect("Failed to flush stdout.");
+fn unignore(app: &mut App, mask: &str) {
+    let mask = mask::normalize(mask);
+    let before = app.ignored.len();
+    app.ignored.retain(|m| !m.eq_ignore_ascii_case(&mask));
+    if app.ignored.len() == before {
+        app.push_active(LineKind::Error, format!("{mask} isn't ignored."));
+        return;
+    }
+    app.push_active(LineKind::Info, format!("No longer ignoring {mask}."));
+    save_ignore_list(app);
+}
+
+fn save_ignore_list(app: &mut App) {
+    let Some(path) = Config::path() else {
+        return;
+    };
+    if let Err(e) = Config::save_ignore_list(&path, &app.ignored) {
+        let text = format!("Couldn't save the ignore list to {}: {e}", path.display());
+        app.push_active(LineKind::Error, text);
+    }
+}
+
+fn send(app: &mut App, connection: &mut Connection, line: &str) {
+    if let Err(err) = connection.send(line) {
+        app.push_active(LineKind::Error, format!("Failed to send message: {err}"));
+    }
+}
+
+/// Send a message typed by the user, split into several if it's too long for one, and show it.
+fn send_typed(app: &mut App, connection: &mut Connection, network: usize, message: Message) {
+    let nick = app.networks[network].nick.clone();
+    for message in command::split_message(message, &nick) {
+        show_sent(app, connection, network, &message);
+        send(app, connection, &message.to_string());
+    }
+}
+
+/// Show what a message we're sending does, like adding our text to the buffer, unless the server
+/// sends our messages back with echo-message. Those are shown when they arrive instead.
+fn show_sent(app: &mut App, connection: &Connection, network: usize, message: &Message) {
+    if message.command == Command::PrivMsg && connection.has_cap("echo-message") {
+        return;
+    }
+    app.handle_sent(network, message);
+}
+
+/// Send the pasted lines to the active target, each as a message of its own. Lines starting with
+/// `/` are sent as text rather than run as commands.
+fn send_paste(app: &mut App, config: &Config, connections: &mut Vec<Connection>) {
+    let Some(lines) = app.pending_paste.take() else {
+        return;
+    };
+    if app.target().is_none() {
+        let text = "You aren't talking to anyone. Use /join or /query first.";
+        app.push_active(LineKind::Error, text.to_string());
+        return;
+    }
+    for line in lines {
+        if line.starts_with('/') {
+            submit(app, config, connections, &format!("/{line}"));
+        } else {
+            submit(app, config, connections, &line);
+        }
     }
 }
 
@@ -0,0 +1,129 @@
+use shared::{
+    casemap::IrcString,
+    mode::{ModeArgs, ModeChange},
+};
+use std::collections::BTreeMap;
+
+/// Prefixes shown in front of nicknames in a channel, highest first, along with the channel modes
+/// that give them: owner (~), admin (&), operator (@), half-operator (%) and voice (+)
+const PREFIXES: [(char, char); 5] = [('~', 'q'), ('&', 'a'), ('@', 'o'), ('%', 'h'), ('+', 'v')];
+
+/// Channel modes other than the prefixes that take a parameter when set, and when unset. Keys
+/// are given when unsetting them, but limits aren't.
+const MODES_WITH_PARAMETER: &str = "beIk";
+const MODES_WITH_PARAMETER_WHEN_SET: &str = "l";
+
+/// Which channel modes take an argument, to parse a MODE with
+pub fn mode_args() -> ModeArgs {
+    let mut always = PREFIXES.iter().map(|(_, mode)| *mode).collect::<String>();
+    always.push_str(MODES_WITH_PARAMETER);
+    ModeArgs::new(&always, MODES_WITH_PARAMETER_WHEN_SET)
+}
+
+/// What we know about a user in a channel.
+#[derive(Debug, Clone, Default)]
+pub struct Member {
+    /// Prefixes the user has, highest first, such as `@+` for an operator with voice. Servers
+    /// only send the highest one unless the multi-prefix capability is enabled.
+    pub prefixes: String,
+    /// Whether they're away, which the server tells us with the away-notify capability
+    pub away: bool,
+}
+
+impl Member {
+    /// The highest prefix, shown in front of the nickname
+    pub fn prefix(&self) -> Option<char> {
+        self.prefixes.chars().next()
+    }
+
+    /// Position of the highest prefix in [`PREFIXES`], with users without one last
+    pub fn rank(&self) -> usize {
+        self.prefix()
+            .and_then(|prefix| PREFIXES.iter().position(|(p, _)| *p == prefix))
+            .unwrap_or(PREFIXES.len())
+    }
+
+    fn set_prefix(&mut self, prefix: char, add: bool) {
+        self.prefixes.retain(|p| p != prefix);
+        if add {
+            self.prefixes.push(prefix);
+        }
+        // Keep them in order, highest first
+        let mut prefixes = self.prefixes.chars().collect::<Vec<_>>();
+        prefixes.sort_by_key(|p| PREFIXES.iter().position(|(other, _)| other == p));
+        self.prefixes = prefixes.into_iter().collect();
+    }
+}
+
+/// The users in a channel by nickname, which ignores case, as far as we know.
+#[derive(Debug, Default)]
+pub struct Members(BTreeMap<IrcString, Member>);
+
+impl Members {
+    /// Add a user seen joining or talking, if they aren't known yet.
+    pub fn add(&mut self, nick: &str) {
+        self.0.entry(IrcString::from(nick)).or_default();
+    }
+
+    /// Add a user listed in RPL_NAMREPLY, where the nickname starts with their prefixes, like
+    /// `@alice`.
+    pub fn add_name(&mut self, name: &str) {
+        let nick = name.trim_start_matches(|c| PREFIXES.iter().any(|(p, _)| *p == c));
+        self.add(nick);
+        let member = self.get_mut(nick).unwrap();
+        member.prefixes.clear();
+        for prefix in name[..name.len() - nick.len()].chars() {
+            member.set_prefix(prefix, true);
+        }
+    }
+
+    /// Remove a user, returning what we knew about them if they were there.
+    pub fn remove(&mut self, nick: &str) -> Option<Member> {
+        self.0.remove(&IrcString::from(nick))
+    }
+
+    pub fn contains(&self, nick: &str) -> bool {
+        self.0.contains_key(&IrcString::from(nick))
+    }
+
+    pub fn get_mut(&mut self, nick: &str) -> Option<&mut Member> {
+        self.0.get_mut(&IrcString::from(nick))
+    }
+
+    /// Carry a user over to their new nickname.
+    pub fn rename(&mut self, old: &str, new: &str) {
+        let member = self.remove(old).unwrap_or_default();
+        self.0.insert(IrcString::from(new), member);
+    }
+
+    pub fn nicks(&self) -> impl Iterator<Item = &str> {
+        self.0.keys().map(IrcString::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// The users grouped by their highest prefix, highest first, and by nickname within each
+    pub fn sorted(&self) -> Vec<(&str, &Member)> {
+        // The map is already in order of nickname, so a stable sort by rank keeps it within each
+        let mut members = self.0.iter().collect::<Vec<_>>();
+        members.sort_by_key(|(_, member)| member.rank());
+        members
+            .into_iter()
+            .map(|(nick, member)| (nick.as_str(), member))
+            .collect()
+    }
+
+    /// Apply the prefixes given and taken away by a channel MODE, e.g. `+ov-v alice bob carol`.
+    pub fn apply_modes(&mut self, changes: &[ModeChange]) {
+        for change in changes {
+            if let Some((prefix, _)) = PREFIXES.iter().find(|(_, m)| *m == change.mode)
+                && let Some(nick) = &change.arg
+                && let Some(member) = self.get_mut(nick)
+            {
+                member.set_prefix(*prefix, change.adding);
+            }
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use serde::Deserialize;
+use shared::codec::Decoding;
+use std::env;
+
+/// How to connect and register with one server.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    pub host: String,
+    /// Defaults to 6697 with TLS and 6667 without
+    pub port: Option<u16>,
+    pub tls: bool,
+    /// SHA-256 fingerprint of the server's certificate, in hex. If set, this certificate is
+    /// trusted instead of checking it against the usual certificate authorities, which allows
+    /// self-signed certificates.
+    pub tls_fingerprint: Option<String>,
+    pub nick: String,
+    /// Nicknames to try in turn if the nickname is taken while registering. After these, an
+    /// underscore is added to the end.
+    pub alt_nicks: Vec<String>,
+    /// Defaults to the nickname
+    pub username: Option<String>,
+    /// Defaults to the nickname
+    pub realname: Option<String>,
+    /// Channels to join once registered
+    pub autojoin: Vec<String>,
+    /// Server password, sent with PASS before registering
+    pub password: Option<String>,
+    pub sasl: Option<SaslConfig>,
+    /// Connect through a proxy instead of directly
+    pub proxy: Option<ProxyConfig>,
+    /// Pace the lines sent, a few at once and then about one a second, so that pasting or
+    /// scripts don't get the connection killed for flooding
+    pub flood_protection: bool,
+    /// How lines from the server that aren't UTF-8 are read
+    pub decoding: Decoding,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SaslConfig {
+    pub username: String,
+    pub password: String,
+}
+
+/// A SOCKS5 or HTTP proxy to connect to a server through. The proxy looks up the server's host
+/// name, so that it isn't revealed to the local DNS server (which matters with Tor).
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProxyConfig {
+    #[serde(rename = "type")]
+    pub kind: ProxyKind,
+    pub host: String,
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxyKind {
+    Socks5,
+    /// A proxy that supports the HTTP CONNECT method
+    Http,
+}
+
+impl Profile {
+    /// Defaults to 6697 with TLS and 6667 without
+    pub fn port(&self) -> u16 {
+        self.port.unwrap_or(if self.tls { 6697 } else { 6667 })
+    }
+
+    /// `host:port` to connect to
+    pub fn address(&self) -> String {
+        format!("{}:{}", self.host, self.port())
+    }
+
+    pub fn username(&self) -> &str {
+        self.username.as_deref().unwrap_or(&self.nick)
+    }
+
+    pub fn realname(&self) -> &str {
+        self.realname.as_deref().unwrap_or(&self.nick)
+    }
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Profile {
+            host: "127.0.0.1".to_string(),
+            port: None,
+            tls: false,
+            tls_fingerprint: None,
+            // Use the name of the user running the client
+            nick: env::var("USER")
+                .or_else(|_| env::var("USERNAME"))
+                .unwrap_or_else(|_| "guest".to_string()),
+            alt_nicks: vec![],
+            username: None,
+            realname: None,
+            autojoin: vec![],
+            password: None,
+            sasl: None,
+            proxy: None,
+            flood_protection: true,
+            // Lines that aren't UTF-8 are most likely from before it was common
+            decoding: Decoding::Latin1,
+        }
+    }
+}
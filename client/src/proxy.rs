@@ -0,0 +1,163 @@
+use crate::profile::{ProxyConfig, ProxyKind};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::{IpAddr, TcpStream},
+    time::Duration,
+};
+
+/// How long to wait for the proxy to answer each step of opening a connection
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Open a connection to `host:port` through a proxy. The host name is passed to the proxy as it
+/// is, so that the proxy looks it up.
+pub fn connect(proxy: &ProxyConfig, host: &str, port: u16) -> io::Result<TcpStream> {
+    let mut stream = TcpStream::connect((proxy.host.as_str(), proxy.port))?;
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    match proxy.kind {
+        ProxyKind::Socks5 => socks5(&mut stream, proxy, host, port)?,
+        ProxyKind::Http => http_connect(&mut stream, proxy, host, port)?,
+    }
+    stream.set_read_timeout(None)?;
+    Ok(stream)
+}
+
+/// Ask a SOCKS5 proxy (RFC 1928) to connect, authenticating with a username and password
+/// (RFC 1929) if the proxy asks for them.
+fn socks5(stream: &mut TcpStream, proxy: &ProxyConfig, host: &str, port: u16) -> io::Result<()> {
+    const VERSION: u8 = 5;
+    const NO_AUTHENTICATION: u8 = 0;
+    const USERNAME_PASSWORD: u8 = 2;
+    const NO_ACCEPTABLE_METHOD: u8 = 0xff;
+    const CONNECT: u8 = 1;
+
+    // Offer the authentication methods we can use
+    let credentials = proxy.username.as_deref().zip(proxy.password.as_deref());
+    let methods = match credentials {
+        Some(_) => vec![NO_AUTHENTICATION, USERNAME_PASSWORD],
+        None => vec![NO_AUTHENTICATION],
+    };
+    stream.write_all(&[&[VERSION, methods.len() as u8], &methods[..]].concat())?;
+    let [_, method] = read_bytes::<2>(stream)?;
+    match (method, credentials) {
+        (NO_AUTHENTICATION, _) => {}
+        (USERNAME_PASSWORD, Some((username, password))) => {
+            if username.len() > 255 || password.len() > 255 {
+                return Err(proxy_error("The proxy username and password are too long"));
+            }
+            let mut request = vec![1, username.len() as u8];
+            request.extend(username.as_bytes());
+            request.push(password.len() as u8);
+            request.extend(password.as_bytes());
+            stream.write_all(&request)?;
+            if read_bytes::<2>(stream)?[1] != 0 {
+                return Err(proxy_error("The proxy rejected the username and password"));
+            }
+        }
+        (NO_ACCEPTABLE_METHOD, None) => {
+            return Err(proxy_error("The proxy requires a username and password"));
+        }
+        _ => {
+            return Err(proxy_error(
+                "The proxy doesn't accept any supported authentication",
+            ));
+        }
+    }
+
+    // The address is an IP address or a host name for the proxy to look up
+    let mut request = vec![VERSION, CONNECT, 0];
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            request.push(1);
+            request.extend(ip.octets());
+        }
+        Ok(IpAddr::V6(ip)) => {
+            request.push(4);
+            request.extend(ip.octets());
+        }
+        Err(_) if host.len() <= 255 => {
+            request.extend([3, host.len() as u8]);
+            request.extend(host.as_bytes());
+        }
+        Err(_) => return Err(proxy_error("The host name is too long")),
+    }
+    request.extend(port.to_be_bytes());
+    stream.write_all(&request)?;
+
+    let [_, reply, _, address_type] = read_bytes::<4>(stream)?;
+    if reply != 0 {
+        let reason = match reply {
+            2 => "connection not allowed by the proxy's rules",
+            3 => "network unreachable",
+            4 => "host unreachable",
+            5 => "connection refused",
+            6 => "TTL expired",
+            7 => "command not supported",
+            8 => "address type not supported",
+            _ => "general failure",
+        };
+        return Err(proxy_error(&format!(
+            "The proxy couldn't connect: {reason}"
+        )));
+    }
+    // Skip the address the proxy connected from
+    let length = match address_type {
+        1 => 4,
+        4 => 16,
+        3 => usize::from(read_bytes::<1>(stream)?[0]),
+        _ => return Err(proxy_error("Invalid reply from the proxy")),
+    };
+    stream.read_exact(&mut vec![0; length + 2])?;
+    Ok(())
+}
+
+/// Ask an HTTP proxy to open a tunnel with the CONNECT method.
+fn http_connect(
+    stream: &mut TcpStream,
+    proxy: &ProxyConfig,
+    host: &str,
+    port: u16,
+) -> io::Result<()> {
+    let authority = match host.parse::<IpAddr>() {
+        Ok(IpAddr::V6(ip)) => format!("[{ip}]:{port}"),
+        _ => format!("{host}:{port}"),
+    };
+    let mut request = format!("CONNECT {authority} HTTP/1.1\r\nHost: {authority}\r\n");
+    if let Some(username) = &proxy.username {
+        let credentials = format!(
+            "{username}:{}",
+            proxy.password.as_deref().unwrap_or_default()
+        );
+        let encoded = BASE64_STANDARD.encode(credentials);
+        request.push_str(&format!("Proxy-Authorization: Basic {encoded}\r\n"));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes())?;
+
+    // Read the headers one byte at a time, so nothing the server sends after them is lost
+    let mut response = vec![];
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() > 8192 {
+            return Err(proxy_error("The proxy's reply is too long"));
+        }
+        response.push(read_bytes::<1>(stream)?[0]);
+    }
+    let response = String::from_utf8_lossy(&response);
+    let status_line = response.lines().next().unwrap_or_default();
+    match status_line.split(' ').nth(1) {
+        Some(status) if status.starts_with('2') => Ok(()),
+        _ => Err(proxy_error(&format!(
+            "The proxy couldn't connect: {status_line}"
+        ))),
+    }
+}
+
+fn read_bytes<const N: usize>(stream: &mut TcpStream) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    stream.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn proxy_error(text: &str) -> io::Error {
+    io::Error::new(ErrorKind::ConnectionRefused, text)
+}
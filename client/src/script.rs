@@ -0,0 +1,352 @@
+use crate::config;
+use rhai::{AST, CallFnOptions, Dynamic, Engine, FnPtr, FuncArgs, Scope};
+use std::{
+    cell::RefCell,
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+/// Most operations a script can run in one call, so that a runaway loop can't freeze the client
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+/// Something a script asked the client to do.
+#[derive(Debug)]
+pub enum Action {
+    /// Send a raw IRC line to the network the script was called for
+    Send(String),
+    /// Run a line as if the user typed it
+    Run(String),
+    /// Show a line in the buffer being shown
+    Echo(String),
+    Error(String),
+}
+
+/// What the functions available to scripts ask for, collected while a script runs.
+#[derive(Debug)]
+enum Request {
+    Action(Action),
+    Timer {
+        interval: Duration,
+        repeat: bool,
+        callback: FnPtr,
+    },
+    Command {
+        name: String,
+        callback: FnPtr,
+    },
+}
+
+#[derive(Debug)]
+struct Script {
+    /// The file name without its extension
+    name: String,
+    path: PathBuf,
+    ast: AST,
+    /// Variables set by the script when it was loaded, which its functions can read
+    scope: Scope<'static>,
+    timers: Vec<Timer>,
+    /// Commands added with `add_command`
+    commands: Vec<(String, FnPtr)>,
+}
+
+#[derive(Debug)]
+struct Timer {
+    interval: Duration,
+    repeat: bool,
+    callback: FnPtr,
+    due: Instant,
+}
+
+/// Rhai scripts that extend the client. A script is run once when it's loaded, and can define
+/// these functions to be called when something happens:
+///
+/// - `on_message(network, nick, target, text)`: a message or notice arrived. Returning a string
+///   shows it instead of the text, and an empty string hides the message.
+/// - `on_send(network, target, text)`: the user typed a message. Returning a string sends it
+///   instead, and an empty string sends nothing.
+/// - `on_join(network, nick, channel)`: someone joined a channel, the user included.
+///
+/// Scripts can call `send(line)` to send a raw IRC line, `say(target, text)` to send a message,
+/// `run(line)` to run a command as if it was typed and `print(text)` to show a line. Timers are
+/// started with `every(seconds, callback)` and `after(seconds, callback)`, and
+/// `add_command(name, callback)` adds a `/name` command, whose callback gets the arguments as a
+/// string:
+///
+/// ```rhai
+/// fn on_message(network, nick, target, text) {
+///     if text == "!ping" {
+///         say(if target.starts_with("#") { target } else { nick }, "pong");
+///     }
+/// }
+///
+/// add_command("shrug", |args| run(`/me ${args} ¯\_(ツ)_/¯`));
+/// ```
+#[derive(Debug)]
+pub struct Scripts {
+    engine: Engine,
+    scripts: Vec<Script>,
+    requests: Rc<RefCell<Vec<Request>>>,
+    /// Actions waiting to be carried out
+    actions: Vec<Action>,
+}
+
+impl Scripts {
+    pub fn new() -> Self {
+        let requests = Rc::new(RefCell::new(vec![]));
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+
+        let pending = requests.clone();
+        engine.on_print(move |text| {
+            let action = Action::Echo(text.to_string());
+            pending.borrow_mut().push(Request::Action(action));
+        });
+        let pending = requests.clone();
+        engine.register_fn("send", move |line: &str| {
+            let action = Action::Send(line.to_string());
+            pending.borrow_mut().push(Request::Action(action));
+        });
+        let pending = requests.clone();
+        engine.register_fn("say", move |target: &str, text: &str| {
+            let action = Action::Send(format!("PRIVMSG {target} :{text}"));
+            pending.borrow_mut().push(Request::Action(action));
+        });
+        let pending = requests.clone();
+        engine.register_fn("run", move |line: &str| {
+            let action = Action::Run(line.to_string());
+            pending.borrow_mut().push(Request::Action(action));
+        });
+        for (name, repeat) in [("every", true), ("after", false)] {
+            let pending = requests.clone();
+            engine.register_fn(name, move |seconds: i64, callback: FnPtr| {
+                pending.borrow_mut().push(Request::Timer {
+                    interval: Duration::from_secs(seconds.max(1) as u64),
+                    repeat,
+                    callback,
+                });
+            });
+        }
+        let pending = requests.clone();
+        engine.register_fn("add_command", move |name: &str, callback: FnPtr| {
+            pending.borrow_mut().push(Request::Command {
+                name: name.trim_start_matches('/').to_lowercase(),
+                callback,
+            });
+        });
+
+        Scripts {
+            engine,
+            scripts: vec![],
+            requests,
+            actions: vec![],
+        }
+    }
+
+    /// Where scripts given by name alone are loaded from
+    pub fn directory() -> PathBuf {
+        dirs::config_dir()
+            .unwrap_or_default()
+            .join("irc_rs")
+            .join("scripts")
+    }
+
+    /// Load and run a script, replacing the one with the same name if it's already loaded, and
+    /// return its name. A name alone, like `greeter`, loads `greeter.rhai` from
+    /// [`Scripts::directory`].
+    pub fn load(&mut self, path: &Path) -> Result<String, String> {
+        let path = config::expand_home(path);
+        let path = if path.components().count() == 1 && !path.exists() {
+            Self::directory().join(path).with_extension("rhai")
+        } else {
+            path
+        };
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .ok_or_else(|| format!("{} isn't a file.", path.display()))?;
+        let ast = self
+            .engine
+            .compile_file(path.clone())
+            .map_err(|e| format!("Couldn't load {}: {e}", path.display()))?;
+
+        let _ = self.unload(&name);
+        let mut scope = Scope::new();
+        let result = self.engine.run_ast_with_scope(&mut scope, &ast);
+        self.scripts.push(Script {
+            name: name.clone(),
+            path,
+            ast,
+            scope,
+            timers: vec![],
+            commands: vec![],
+        });
+        self.collect(self.scripts.len() - 1);
+        if let Err(e) = result {
+            self.scripts.pop();
+            return Err(format!("Error in {name}: {e}"));
+        }
+        Ok(name)
+    }
+
+    pub fn unload(&mut self, name: &str) -> Result<(), String> {
+        let index = self
+            .scripts
+            .iter()
+            .position(|script| script.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("No script named {name} is loaded."))?;
+        self.scripts.remove(index);
+        Ok(())
+    }
+
+    /// Names and paths of the loaded scripts
+    pub fn list(&self) -> Vec<(&str, &Path)> {
+        self.scripts
+            .iter()
+            .map(|script| (script.name.as_str(), script.path.as_path()))
+            .collect()
+    }
+
+    /// Take the actions the scripts asked for since the last call.
+    pub fn take_actions(&mut self) -> Vec<Action> {
+        std::mem::take(&mut self.actions)
+    }
+
+    /// See `on_message` in [`Scripts`].
+    pub fn on_message(&mut self, network: &str, nick: &str, target: &str, text: &str) -> String {
+        let (network, nick, target) = (network.to_string(), nick.to_string(), target.to_string());
+        self.filter("on_message", text, |text| {
+            (network.clone(), nick.clone(), target.clone(), text)
+        })
+    }
+
+    /// See `on_send` in [`Scripts`].
+    pub fn on_send(&mut self, network: &str, target: &str, text: &str) -> String {
+        let (network, target) = (network.to_string(), target.to_string());
+        self.filter("on_send", text, |text| {
+            (network.clone(), target.clone(), text)
+        })
+    }
+
+    pub fn on_join(&mut self, network: &str, nick: &str, channel: &str) {
+        for index in 0..self.scripts.len() {
+            let args = (network.to_string(), nick.to_string(), channel.to_string());
+            self.call(index, "on_join", args);
+        }
+    }
+
+    /// Run a command added by a script, returning false if no script added one by that name.
+    pub fn run_command(&mut self, name: &str, args: &str) -> bool {
+        let name = name.to_lowercase();
+        let Some((index, callback)) = self.scripts.iter().enumerate().find_map(|(i, script)| {
+            let command = script.commands.iter().find(|(command, _)| *command == name);
+            command.map(|(_, callback)| (i, callback.clone()))
+        }) else {
+            return false;
+        };
+        let result =
+            callback.call::<Dynamic>(&self.engine, &self.scripts[index].ast, (args.to_string(),));
+        self.finish(index, result.map(|_| ()));
+        true
+    }
+
+    /// Run the callbacks of timers that are due.
+    pub fn tick(&mut self) {
+        let now = Instant::now();
+        for index in 0..self.scripts.len() {
+            let mut due = vec![];
+            self.scripts[index].timers.retain_mut(|timer| {
+                if timer.due > now {
+                    return true;
+                }
+                due.push(timer.callback.clone());
+                timer.due = now + timer.interval;
+                timer.repeat
+            });
+
+            for callback in due {
+                let result = callback.call::<Dynamic>(&self.engine, &self.scripts[index].ast, ());
+                self.finish(index, result.map(|_| ()));
+            }
+        }
+    }
+
+    /// Pass text through a hook of every script that has it, each getting what the previous one
+    /// returned.
+    fn filter<A: FuncArgs>(
+        &mut self,
+        function: &str,
+        text: &str,
+        args: impl Fn(String) -> A,
+    ) -> String {
+        let mut text = text.to_string();
+        for index in 0..self.scripts.len() {
+            if let Some(result) = self.call(index, function, args(text.clone()))
+                && let Ok(replacement) = result.into_string()
+            {
+                text = replacement;
+            }
+        }
+        text
+    }
+
+    /// Call a function of a script if it has one.
+    fn call(&mut self, index: usize, function: &str, args: impl FuncArgs) -> Option<Dynamic> {
+        let script = &mut self.scripts[index];
+        if !script.ast.iter_functions().any(|f| f.name == function) {
+            return None;
+        }
+        let options = CallFnOptions::new().eval_ast(false);
+        let result = self.engine.call_fn_with_options::<Dynamic>(
+            options,
+            &mut script.scope,
+            &script.ast,
+            function,
+            args,
+        );
+        match result {
+            Ok(value) => {
+                self.finish(index, Ok(()));
+                Some(value)
+            }
+            Err(e) => {
+                self.finish(index, Err(e));
+                None
+            }
+        }
+    }
+
+    /// Report a script's error, if any, and take in what it asked for while running.
+    fn finish(&mut self, index: usize, result: Result<(), Box<rhai::EvalAltResult>>) {
+        if let Err(e) = result {
+            let text = format!("Error in {}: {e}", self.scripts[index].name);
+            self.actions.push(Action::Error(text));
+        }
+        self.collect(index);
+    }
+
+    /// Sort out what a script asked for: actions to carry out, and timers and commands that
+    /// belong to it.
+    fn collect(&mut self, index: usize) {
+        let script = &mut self.scripts[index];
+        for request in self.requests.borrow_mut().drain(..) {
+            match request {
+                Request::Action(action) => self.actions.push(action),
+                Request::Timer {
+                    interval,
+                    repeat,
+                    callback,
+                } => script.timers.push(Timer {
+                    interval,
+                    repeat,
+                    callback,
+                    due: Instant::now() + interval,
+                }),
+                Request::Command { name, callback } => {
+                    script.commands.retain(|(command, _)| *command != name);
+                    script.commands.push((name, callback));
+                }
+            }
+        }
+    }
+}
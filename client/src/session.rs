@@ -0,0 +1,452 @@
+use crate::{
+    connection::{Event, Lag},
+    message::{Command, Message},
+    profile::Profile,
+};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use shared::isupport::ISupport;
+use std::time::{Duration, Instant};
+
+/// How often to PING the server to measure the lag
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long to wait for a PONG before showing the connection as stalled
+const STALLED_AFTER: Duration = Duration::from_secs(10);
+
+/// Capabilities requested from servers that offer them. SASL is requested as well when the profile
+/// has credentials.
+const WANTED_CAPS: &[&str] = &[
+    "away-notify",
+    "batch",
+    "draft/chathistory",
+    "echo-message",
+    "message-tags",
+    "multi-prefix",
+    "server-time",
+];
+
+/// What keeps a connection going, apart from reading and writing: registering, negotiating
+/// capabilities, answering PINGs, measuring the lag, authenticating with SASL, joining the
+/// profile's channels and rejoining channels after reconnecting. Shared by the blocking and async
+/// connections, which send the lines it returns.
+#[derive(Debug)]
+pub(crate) struct Session {
+    pub profile: Profile,
+    /// Our nickname, which may have changed since registering
+    nick: String,
+    /// Channels we're in, which are joined again after reconnecting
+    channels: Vec<String>,
+    /// Whether the server welcomed us on the current connection
+    registered: bool,
+    /// How many of the profile's alternate nicknames were tried while registering
+    alt_nicks_tried: usize,
+    /// Whether to take back the profile's nickname when whoever has it leaves or changes it
+    reclaim_nick: bool,
+    /// When to send the next PING, once registered
+    next_ping: Option<Instant>,
+    /// When the PING waiting for a PONG was sent
+    ping_sent: Option<Instant>,
+    /// Round-trip time of the last PING
+    lag: Option<Duration>,
+    /// Capabilities offered by the server so far, while it's listing them
+    offered_caps: Vec<String>,
+    /// Capabilities the server acknowledged
+    caps: Vec<String>,
+    /// What the server said it supports after welcoming us
+    isupport: ISupport,
+}
+
+impl Session {
+    pub fn new(profile: Profile) -> Self {
+        Session {
+            nick: profile.nick.clone(),
+            profile,
+            channels: vec![],
+            registered: false,
+            alt_nicks_tried: 0,
+            reclaim_nick: false,
+            next_ping: None,
+            ping_sent: None,
+            lag: None,
+            offered_caps: vec![],
+            caps: vec![],
+            isupport: ISupport::default(),
+        }
+    }
+
+    pub fn nick(&self) -> &str {
+        &self.nick
+    }
+
+    /// Whether the server acknowledged a capability we requested
+    pub fn has_cap(&self, cap: &str) -> bool {
+        self.caps.iter().any(|c| c.eq_ignore_ascii_case(cap))
+    }
+
+    pub fn isupport(&self) -> &ISupport {
+        &self.isupport
+    }
+
+    pub fn lag(&self) -> Lag {
+        match (self.ping_sent, self.lag) {
+            (Some(sent), _) if sent.elapsed() >= STALLED_AFTER => Lag::Stalled(sent.elapsed()),
+            (_, Some(lag)) => Lag::Measured(lag),
+            _ => Lag::Unknown,
+        }
+    }
+
+    /// Lines that register with the server on a new connection, asking for the capabilities it
+    /// offers first. Servers without capabilities reply that CAP is unknown and carry on.
+    pub fn register(&mut self) -> Vec<String> {
+        self.next_ping = None;
+        self.ping_sent = None;
+        self.lag = None;
+        self.offered_caps.clear();
+        self.caps.clear();
+        self.isupport.clear();
+        self.registered = false;
+        self.alt_nicks_tried = 0;
+
+        let mut lines = vec![];
+        if let Some(password) = &self.profile.password {
+            lines.push(format!("PASS {password}"));
+        }
+        lines.push("CAP LS 302".to_string());
+        lines.push(format!("NICK {}", self.nick));
+        lines.push(format!(
+            "USER {} 0 * :{}",
+            self.profile.username(),
+            self.profile.realname()
+        ));
+        lines
+    }
+
+    /// The NICK to take back the profile's nickname, which is tried again whenever whoever has it
+    /// leaves or changes it, until we have it or change our nickname to another.
+    pub fn reclaim_nick(&mut self) -> String {
+        self.reclaim_nick = true;
+        format!("NICK {}", self.profile.nick)
+    }
+
+    /// A PING to measure the lag if it's time for one, and no PING is waiting for a PONG.
+    pub fn ping_if_due(&mut self) -> Option<String> {
+        let now = Instant::now();
+        if self.ping_sent.is_some() || self.next_ping.is_none_or(|due| due > now) {
+            return None;
+        }
+        self.ping_sent = Some(now);
+        self.next_ping = Some(now + PING_INTERVAL);
+        Some("PING :lag".to_string())
+    }
+
+    /// Take in a message from the server, returning the lines to answer it with.
+    pub fn handle(&mut self, message: &Message) -> Vec<String> {
+        let param = |i: usize| message.params.get(i).map_or("", String::as_str);
+        let from_me = message.nick().eq_ignore_ascii_case(&self.nick);
+        match &message.command {
+            Command::Ping => return vec![format!("PONG :{}", param(0))],
+            // Servers don't all echo the token, so any PONG answers the PING that's waiting
+            Command::Pong => {
+                if let Some(sent) = self.ping_sent.take() {
+                    self.lag = Some(sent.elapsed());
+                }
+            }
+            // RPL_ISUPPORT
+            Command::Numeric(5) => self.isupport.add(message),
+            // CAP * LS * :sasl=PLAIN batch, where the `*` means more are coming
+            Command::Cap if param(1) == "LS" => {
+                let (more, caps) = match param(2) {
+                    "*" => (true, param(3)),
+                    caps => (false, caps),
+                };
+                // Values such as the SASL mechanisms aren't needed
+                let caps = caps
+                    .split_whitespace()
+                    .map(|cap| match cap.split_once('=') {
+                        Some((name, _)) => name.to_string(),
+                        None => cap.to_string(),
+                    });
+                self.offered_caps.extend(caps);
+                if more {
+                    return vec![];
+                }
+                return vec![match self.caps_to_request() {
+                    Some(caps) => format!("CAP REQ :{caps}"),
+                    None => "CAP END".to_string(),
+                }];
+            }
+            // CAP * ACK :batch sasl
+            Command::Cap if param(1) == "ACK" => {
+                self.caps
+                    .extend(param(2).split_whitespace().map(str::to_string));
+                if self.has_cap("sasl") {
+                    return vec!["AUTHENTICATE PLAIN".to_string()];
+                }
+                return vec!["CAP END".to_string()];
+            }
+            Command::Cap if param(1) == "NAK" => return vec!["CAP END".to_string()],
+            Command::Authenticate if param(0) == "+" => {
+                if let Some(sasl) = &self.profile.sasl {
+                    let credentials = format!("{0}\0{0}\0{1}", sasl.username, sasl.password);
+                    let encoded = BASE64_STANDARD.encode(credentials);
+                    return vec![format!("AUTHENTICATE {encoded}")];
+                }
+            }
+            // RPL_SASLSUCCESS, or one of the failures (ERR_SASLFAIL and so on)
+            Command::Numeric(902..=908) => return vec!["CAP END".to_string()],
+            // ERR_NICKNAMEINUSE while registering: try the next alternate nickname, or else add
+            // an underscore, and take the nickname back once it's free
+            Command::Numeric(433) if !self.registered => {
+                self.nick = match self.profile.alt_nicks.get(self.alt_nicks_tried) {
+                    Some(nick) => nick.clone(),
+                    None => format!("{}_", self.nick),
+                };
+                self.alt_nicks_tried += 1;
+                self.reclaim_nick = true;
+                return vec![format!("NICK {}", self.nick)];
+            }
+            // RPL_WELCOME, which is addressed to the nickname we got
+            Command::Numeric(1) => {
+                self.registered = true;
+                self.nick = param(0).to_string();
+                self.next_ping = Some(Instant::now());
+
+                // The profile's channels, plus the ones we were in before reconnecting
+                let mut channels = self.profile.autojoin.clone();
+                for channel in std::mem::take(&mut self.channels) {
+                    if !channels.iter().any(|c| c.eq_ignore_ascii_case(&channel)) {
+                        channels.push(channel);
+                    }
+                }
+                return channels
+                    .iter()
+                    .map(|channel| format!("JOIN {channel}"))
+                    .collect();
+            }
+            Command::Nick if from_me => {
+                self.nick = param(0).to_string();
+                self.reclaim_nick = false;
+            }
+            Command::Nick | Command::Quit
+                if self.reclaim_nick && message.nick().eq_ignore_ascii_case(&self.profile.nick) =>
+            {
+                return vec![format!("NICK {}", self.profile.nick)];
+            }
+            Command::Join
+                if from_me
+                    && !self
+                        .channels
+                        .iter()
+                        .any(|c| c.eq_ignore_ascii_case(param(0))) =>
+            {
+                self.channels.push(param(0).to_string());
+            }
+            Command::Part if from_me => {
+                self.channels.retain(|c| !c.eq_ignore_ascii_case(param(0)));
+            }
+            Command::Kick if param(1).eq_ignore_ascii_case(&self.nick) => {
+                self.channels.retain(|c| !c.eq_ignore_ascii_case(param(0)));
+            }
+            _ => {}
+        }
+        vec![]
+    }
+
+    /// The offered capabilities that we want, separated by spaces
+    fn caps_to_request(&self) -> Option<String> {
+        let wanted = WANTED_CAPS
+            .iter()
+            .copied()
+            .chain(self.profile.sasl.as_ref().map(|_| "sasl"));
+        let caps = wanted
+            .filter(|cap| self.offered_caps.iter().any(|c| c == cap))
+            .collect::<Vec<_>>();
+        if caps.is_empty() {
+            return None;
+        }
+        Some(caps.join(" "))
+    }
+}
+
+/// The events for a message: [`Event::Message`], followed by a more specific event if it has one.
+pub(crate) fn events_for(message: Message) -> Vec<Event> {
+    let param = |i: usize| message.params.get(i).cloned().unwrap_or_default();
+    let specific = match message.command {
+        Command::Join => Some(Event::Join {
+            nick: message.nick().to_string(),
+            channel: param(0),
+        }),
+        Command::Nick => Some(Event::Nick {
+            old: message.nick().to_string(),
+            new: param(0),
+        }),
+        Command::Numeric(code) => Some(Event::Numeric {
+            code,
+            params: message.params.get(1..).unwrap_or_default().to_vec(),
+        }),
+        _ => None,
+    };
+    let mut events = vec![Event::Message(message)];
+    events.extend(specific);
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profile::SaslConfig;
+
+    fn profile() -> Profile {
+        Profile {
+            nick: "rustbot".to_string(),
+            ..Profile::default()
+        }
+    }
+
+    fn with_sasl() -> Profile {
+        Profile {
+            sasl: Some(SaslConfig {
+                username: "rustbot".to_string(),
+                password: "hunter2".to_string(),
+            }),
+            ..profile()
+        }
+    }
+
+    /// Lines from the server, each with the lines the session should answer it with
+    type Steps<'a> = &'a [(&'a str, &'a [&'a str])];
+
+    /// Register as on a new connection, then feed the session each line from the server in turn,
+    /// checking the lines it answers with
+    fn exchange(session: &mut Session, steps: Steps) {
+        session.register();
+        for (line, expected) in steps {
+            let replies = session.handle(&Message::from(line).unwrap());
+            assert_eq!(replies, *expected, "answering {line:?}");
+        }
+    }
+
+    #[test]
+    fn sasl_is_requested_and_logged_in_with() {
+        let mut session = Session::new(with_sasl());
+        exchange(
+            &mut session,
+            &[
+                (":irc CAP * LS * :sasl=PLAIN,EXTERNAL batch", &[]),
+                (
+                    ":irc CAP * LS :server-time unknown-cap",
+                    &["CAP REQ :batch server-time sasl"],
+                ),
+                (
+                    ":irc CAP * ACK :batch server-time sasl",
+                    &["AUTHENTICATE PLAIN"],
+                ),
+                (
+                    "AUTHENTICATE +",
+                    &["AUTHENTICATE cnVzdGJvdABydXN0Ym90AGh1bnRlcjI="],
+                ),
+                (":irc 900 rustbot rustbot!rb@host rustbot :Logged in", &[]),
+                (
+                    ":irc 903 rustbot :SASL authentication successful",
+                    &["CAP END"],
+                ),
+            ],
+        );
+        assert!(session.has_cap("SASL"));
+        assert!(!session.has_cap("unknown-cap"));
+    }
+
+    #[test]
+    fn capability_negotiation_ends() {
+        let cases: &[(Profile, Steps)] = &[
+            // Nothing we want
+            (profile(), &[(":irc CAP * LS :unknown-cap", &["CAP END"])]),
+            // SASL isn't requested without credentials
+            (profile(), &[(":irc CAP * LS :sasl", &["CAP END"])]),
+            (
+                profile(),
+                &[
+                    (":irc CAP * LS :batch", &["CAP REQ :batch"]),
+                    (":irc CAP * ACK :batch", &["CAP END"]),
+                ],
+            ),
+            (
+                with_sasl(),
+                &[
+                    (":irc CAP * LS :batch sasl", &["CAP REQ :batch sasl"]),
+                    (":irc CAP * NAK :batch sasl", &["CAP END"]),
+                ],
+            ),
+            (
+                with_sasl(),
+                &[
+                    (":irc CAP * LS :sasl", &["CAP REQ :sasl"]),
+                    (":irc CAP * ACK :sasl", &["AUTHENTICATE PLAIN"]),
+                    (
+                        "AUTHENTICATE +",
+                        &["AUTHENTICATE cnVzdGJvdABydXN0Ym90AGh1bnRlcjI="],
+                    ),
+                    (":irc 904 rustbot :SASL authentication failed", &["CAP END"]),
+                ],
+            ),
+        ];
+        for (profile, steps) in cases {
+            exchange(&mut Session::new(profile.clone()), steps);
+        }
+    }
+
+    #[test]
+    fn alternate_nicknames_are_tried_while_registering() {
+        let mut session = Session::new(Profile {
+            alt_nicks: vec!["rustbot2".to_string()],
+            ..profile()
+        });
+        exchange(
+            &mut session,
+            &[
+                (
+                    ":irc 433 * rustbot :Nickname is already in use",
+                    &["NICK rustbot2"],
+                ),
+                (
+                    ":irc 433 * rustbot2 :Nickname is already in use",
+                    &["NICK rustbot2_"],
+                ),
+                (":irc 001 rustbot2_ :Welcome", &[]),
+                // Once registered, it's up to whoever changed the nickname
+                (
+                    ":irc 433 rustbot2_ rustbot3 :Nickname is already in use",
+                    &[],
+                ),
+                // Taken back when whoever has it leaves
+                (":rustbot!rb@host QUIT :bye", &["NICK rustbot"]),
+            ],
+        );
+        assert_eq!(session.nick(), "rustbot2_");
+    }
+
+    #[test]
+    fn channels_are_rejoined_after_reconnecting() {
+        let mut session = Session::new(Profile {
+            autojoin: vec!["#rust".to_string()],
+            ..profile()
+        });
+        exchange(
+            &mut session,
+            &[
+                (":irc 001 rustbot :Welcome", &["JOIN #rust"]),
+                (":rustbot!rb@host JOIN #rust", &[]),
+                (":rustbot!rb@host JOIN #irc_rs", &[]),
+                (":rustbot!rb@host JOIN #left", &[]),
+                (":rustbot!rb@host JOIN #kicked", &[]),
+                (":rustbot!rb@host PART #left", &[]),
+                (":op!op@host KICK #kicked rustbot :bye", &[]),
+            ],
+        );
+        // The profile's channels first, then the others once each
+        exchange(
+            &mut session,
+            &[(":irc 001 rustbot :Welcome", &["JOIN #rust", "JOIN #irc_rs"])],
+        );
+    }
+}
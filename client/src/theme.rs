@@ -0,0 +1,249 @@
+use ratatui::style::{Color, Modifier, Style};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// Names of the themes that come with the client
+pub const BUILT_IN: &[&str] = &["default", "light", "mono"];
+
+/// Colors and attributes of the parts of the interface.
+#[derive(Debug, Clone)]
+pub struct Theme {
+    pub name: String,
+    /// The time in front of each line, and the brackets around senders
+    pub timestamp: Style,
+    /// The user's own nickname as a sender
+    pub own_nick: Style,
+    /// Color other nicknames, each in a color of its own
+    pub nick_colors: bool,
+    pub message: Style,
+    /// Messages that mention the user
+    pub highlight: Style,
+    /// Joins, parts and other events
+    pub event: Style,
+    /// Server replies and client notes
+    pub info: Style,
+    pub error: Style,
+    pub status_bar: Style,
+    /// Buffers with unread messages in the status bar
+    pub activity: Style,
+    /// Buffers with unread highlights in the status bar
+    pub activity_highlight: Style,
+    /// Being disconnected or the connection stalling, in the status bar
+    pub status_alert: Style,
+    /// The prompt in front of the input
+    pub prompt: Style,
+    /// The line found by `/search` and the channel selected in the list buffer
+    pub selection: Style,
+    /// The numbers after links in messages, which `/open` takes
+    pub link: Style,
+    /// The line above a channel's messages with its topic
+    pub topic: Style,
+}
+
+/// A theme of the user's own, from the config file:
+///
+/// ```toml
+/// theme = "mine"
+///
+/// [themes.mine]
+/// base = "light"
+/// own_nick = { fg = "magenta", bold = true }
+/// status_bar = { fg = "black", bg = "#d0d0d0" }
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ThemeConfig {
+    /// The built-in theme it changes, `default` unless given
+    pub base: Option<String>,
+    pub nick_colors: Option<bool>,
+    /// Styles of the parts of the interface, by the names of the fields of [`Theme`]
+    #[serde(flatten)]
+    pub styles: BTreeMap<String, StyleConfig>,
+}
+
+/// Changes to the style of a part of the interface. Colors are names like `red` and
+/// `lightblue`, `#rrggbb` or numbers of the 256 indexed colors, and `reset` is the terminal's
+/// own color.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StyleConfig {
+    pub fg: Option<String>,
+    pub bg: Option<String>,
+    pub bold: Option<bool>,
+    pub dim: Option<bool>,
+    pub italic: Option<bool>,
+    pub underline: Option<bool>,
+    pub reversed: Option<bool>,
+}
+
+impl Theme {
+    /// Find a theme by name, ignoring case, among the built-in ones and the user's own.
+    pub fn load(name: &str, themes: &BTreeMap<String, ThemeConfig>) -> Result<Self, String> {
+        if let Some(theme) = Self::built_in(name) {
+            return Ok(theme);
+        }
+        let (name, config) = themes
+            .iter()
+            .find(|(theme, _)| theme.eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("No theme named {name}."))?;
+
+        let base = config.base.as_deref().unwrap_or("default");
+        let mut theme = Self::built_in(base)
+            .ok_or_else(|| format!("The {name} theme is based on {base}, which isn't built in."))?;
+        theme.name = name.clone();
+        if let Some(nick_colors) = config.nick_colors {
+            theme.nick_colors = nick_colors;
+        }
+        for (part, style) in &config.styles {
+            let target = theme.style_mut(part).ok_or_else(|| {
+                format!(
+                    "The {name} theme has a style for {part}, which isn't a part of the interface."
+                )
+            })?;
+            *target = style
+                .apply(*target)
+                .map_err(|e| format!("The {name} theme's style for {part} is invalid: {e}"))?;
+        }
+        Ok(theme)
+    }
+
+    /// Names of the built-in themes and the user's own
+    pub fn names(themes: &BTreeMap<String, ThemeConfig>) -> Vec<String> {
+        let built_in = BUILT_IN.iter().map(|name| name.to_string());
+        built_in
+            .chain(
+                themes
+                    .keys()
+                    .filter(|name| Self::built_in(name).is_none())
+                    .cloned(),
+            )
+            .collect()
+    }
+
+    fn built_in(name: &str) -> Option<Self> {
+        let plain = Style::default();
+        let bold = plain.add_modifier(Modifier::BOLD);
+        let theme = match name.to_lowercase().as_str() {
+            // For dark terminals
+            "default" => Theme {
+                name: "default".to_string(),
+                timestamp: plain.fg(Color::DarkGray),
+                own_nick: bold,
+                nick_colors: true,
+                message: plain,
+                highlight: bold.fg(Color::Yellow),
+                event: plain.fg(Color::DarkGray),
+                info: plain.fg(Color::Cyan),
+                error: plain.fg(Color::Red),
+                status_bar: plain.bg(Color::Blue).fg(Color::White),
+                activity: bold,
+                activity_highlight: bold.fg(Color::Yellow),
+                status_alert: plain.fg(Color::LightRed),
+                prompt: bold,
+                selection: plain.bg(Color::DarkGray),
+                link: plain.fg(Color::Blue),
+                topic: plain.bg(Color::Blue).fg(Color::White),
+            },
+            // For light terminals, where yellow and cyan are hard to read
+            "light" => Theme {
+                name: "light".to_string(),
+                timestamp: plain.fg(Color::Gray),
+                own_nick: bold,
+                nick_colors: true,
+                message: plain,
+                highlight: bold.fg(Color::Red),
+                event: plain.fg(Color::Gray),
+                info: plain.fg(Color::Blue),
+                error: plain.fg(Color::Red),
+                status_bar: plain.bg(Color::Gray).fg(Color::Black),
+                activity: bold,
+                activity_highlight: bold.fg(Color::Red),
+                status_alert: bold.fg(Color::Red),
+                prompt: bold,
+                selection: plain.bg(Color::Gray),
+                link: plain.fg(Color::Blue),
+                topic: plain.bg(Color::Gray).fg(Color::Black),
+            },
+            // Attributes only, for terminals without colors or users who'd rather not have them
+            "mono" => Theme {
+                name: "mono".to_string(),
+                timestamp: plain.add_modifier(Modifier::DIM),
+                own_nick: bold,
+                nick_colors: false,
+                message: plain,
+                highlight: bold,
+                event: plain.add_modifier(Modifier::DIM),
+                info: plain,
+                error: bold.add_modifier(Modifier::UNDERLINED),
+                status_bar: plain.add_modifier(Modifier::REVERSED),
+                activity: bold,
+                activity_highlight: bold.add_modifier(Modifier::UNDERLINED),
+                status_alert: bold.add_modifier(Modifier::UNDERLINED),
+                prompt: bold,
+                selection: plain.add_modifier(Modifier::REVERSED),
+                link: plain.add_modifier(Modifier::DIM),
+                topic: plain.add_modifier(Modifier::REVERSED),
+            },
+            _ => return None,
+        };
+        Some(theme)
+    }
+
+    fn style_mut(&mut self, part: &str) -> Option<&mut Style> {
+        Some(match part {
+            "timestamp" => &mut self.timestamp,
+            "own_nick" => &mut self.own_nick,
+            "message" => &mut self.message,
+            "highlight" => &mut self.highlight,
+            "event" => &mut self.event,
+            "info" => &mut self.info,
+            "error" => &mut self.error,
+            "status_bar" => &mut self.status_bar,
+            "activity" => &mut self.activity,
+            "activity_highlight" => &mut self.activity_highlight,
+            "status_alert" => &mut self.status_alert,
+            "prompt" => &mut self.prompt,
+            "selection" => &mut self.selection,
+            "link" => &mut self.link,
+            "topic" => &mut self.topic,
+            _ => return None,
+        })
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::built_in("default").unwrap()
+    }
+}
+
+impl StyleConfig {
+    /// Make these changes to a style.
+    fn apply(&self, mut style: Style) -> Result<Style, String> {
+        let color = |name: &str| {
+            name.parse::<Color>()
+                .map_err(|_| format!("{name} isn't a color"))
+        };
+        if let Some(fg) = &self.fg {
+            style = style.fg(color(fg)?);
+        }
+        if let Some(bg) = &self.bg {
+            style = style.bg(color(bg)?);
+        }
+        let modifiers = [
+            (self.bold, Modifier::BOLD),
+            (self.dim, Modifier::DIM),
+            (self.italic, Modifier::ITALIC),
+            (self.underline, Modifier::UNDERLINED),
+            (self.reversed, Modifier::REVERSED),
+        ];
+        for (setting, modifier) in modifiers {
+            style = match setting {
+                Some(true) => style.add_modifier(modifier),
+                Some(false) => style.remove_modifier(modifier),
+                None => style,
+            };
+        }
+        Ok(style)
+    }
+}
@@ -0,0 +1,110 @@
+use std::time::{Duration, Instant};
+
+/// Lines that can be sent at once before the rest are paced
+const BURST: f64 = 5.0;
+
+/// Lines sent per second once the burst is used up
+const RATE: f64 = 1.0;
+
+/// Paces the lines the user sends, so that pasting or a script sending a lot doesn't get them
+/// disconnected for flooding. Works like a bucket of [`BURST`] tokens that refills at [`RATE`]
+/// tokens a second, where sending a line takes one.
+#[derive(Debug)]
+pub(crate) struct Throttle {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Throttle {
+    pub fn new() -> Self {
+        Self::starting_at(Instant::now())
+    }
+
+    fn starting_at(now: Instant) -> Self {
+        Throttle {
+            tokens: BURST,
+            updated: now,
+        }
+    }
+
+    /// Take a token if there's one, which means a line can be sent now.
+    pub fn try_take(&mut self) -> bool {
+        self.try_take_at(Instant::now())
+    }
+
+    fn try_take_at(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+
+    /// How long until the next line can be sent
+    pub fn wait(&mut self) -> Duration {
+        self.wait_at(Instant::now())
+    }
+
+    fn wait_at(&mut self, now: Instant) -> Duration {
+        self.refill(now);
+        Duration::from_secs_f64((1.0 - self.tokens).max(0.0) / RATE)
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * RATE).min(BURST);
+        self.updated = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_burst_of_lines_is_sent_at_once() {
+        let start = Instant::now();
+        let mut throttle = Throttle::starting_at(start);
+        for _ in 0..5 {
+            assert_eq!(throttle.wait_at(start), Duration::ZERO);
+            assert!(throttle.try_take_at(start));
+        }
+        assert!(!throttle.try_take_at(start));
+    }
+
+    #[test]
+    fn lines_after_the_burst_are_paced() {
+        let start = Instant::now();
+        let mut throttle = Throttle::starting_at(start);
+        for _ in 0..5 {
+            throttle.try_take_at(start);
+        }
+        assert_eq!(throttle.wait_at(start), Duration::from_secs(1));
+
+        let later = start + Duration::from_millis(400);
+        assert!(!throttle.try_take_at(later));
+        assert_eq!(throttle.wait_at(later), Duration::from_millis(600));
+
+        let next = start + Duration::from_secs(1);
+        assert!(throttle.try_take_at(next));
+        assert!(!throttle.try_take_at(next));
+        assert_eq!(throttle.wait_at(next), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn the_burst_refills_up_to_its_size() {
+        let start = Instant::now();
+        let mut throttle = Throttle::starting_at(start);
+        for _ in 0..5 {
+            throttle.try_take_at(start);
+        }
+
+        // A long pause only earns the burst back, not more
+        let later = start + Duration::from_secs(60);
+        for _ in 0..5 {
+            assert!(throttle.try_take_at(later));
+        }
+        assert!(!throttle.try_take_at(later));
+    }
+}
@@ -0,0 +1,216 @@
+use crate::{profile::Profile, proxy};
+use ring::digest::{SHA256, digest};
+use rustls::{
+    ClientConfig, ClientConnection, DigitallySignedStruct, RootCertStore, SignatureScheme,
+    StreamOwned,
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{self, CryptoProvider},
+    pki_types::{CertificateDer, ServerName, UnixTime},
+};
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::{Shutdown, SocketAddr, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+/// How long the reading thread holds on to a TLS stream before letting a writer use it
+const TLS_READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+type TlsStream = StreamOwned<ClientConnection, TcpStream>;
+
+/// A plain or TLS connection to a server, split into halves for reading and writing so they can
+/// be used from different threads.
+pub struct Transport {
+    pub reader: Box<dyn Read + Send>,
+    pub writer: Box<dyn Write + Send>,
+    /// Handle to the underlying socket for closing it
+    socket: TcpStream,
+}
+
+impl Transport {
+    /// Connect to the profile's server, through its proxy if it has one, using TLS if the
+    /// profile asks for it.
+    pub fn connect(profile: &Profile) -> io::Result<Self> {
+        let socket = open_socket(profile)?;
+
+        if !profile.tls {
+            return Ok(Transport {
+                reader: Box::new(socket.try_clone()?),
+                writer: Box::new(socket.try_clone()?),
+                socket,
+            });
+        }
+
+        let server_name = ServerName::try_from(profile.host.clone())
+            .map_err(|e| io::Error::new(ErrorKind::InvalidInput, e))?;
+        let mut connection =
+            ClientConnection::new(tls_config(profile)?, server_name).map_err(io::Error::other)?;
+
+        // Handshake now so that certificate problems are reported as connection errors
+        let mut tcp = socket.try_clone()?;
+        while connection.is_handshaking() {
+            connection.complete_io(&mut tcp)?;
+        }
+
+        // Reads give up regularly so that writes get a turn with the stream
+        socket.set_read_timeout(Some(TLS_READ_TIMEOUT))?;
+        let stream = Arc::new(Mutex::new(StreamOwned::new(connection, tcp)));
+        Ok(Transport {
+            reader: Box::new(TlsReader(stream.clone())),
+            writer: Box::new(TlsWriter(stream)),
+            socket,
+        })
+    }
+
+    /// Our end of the connection
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Close the connection, which also ends the reading thread.
+    pub fn shutdown(&self) {
+        let _ = self.socket.shutdown(Shutdown::Both);
+    }
+}
+
+/// Open a TCP connection to the profile's server, through its proxy if it has one.
+pub(crate) fn open_socket(profile: &Profile) -> io::Result<TcpStream> {
+    match &profile.proxy {
+        Some(config) => proxy::connect(config, &profile.host, profile.port()),
+        None => TcpStream::connect(profile.address()),
+    }
+}
+
+/// Certificates are checked against the Mozilla root certificates, unless the profile pins the
+/// server's certificate by its fingerprint.
+pub(crate) fn tls_config(profile: &Profile) -> io::Result<Arc<ClientConfig>> {
+    let provider = Arc::new(crypto::ring::default_provider());
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(io::Error::other)?;
+
+    let config = match &profile.tls_fingerprint {
+        Some(fingerprint) => builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(PinnedCertificate {
+                fingerprint: parse_fingerprint(fingerprint)?,
+                provider,
+            }))
+            .with_no_client_auth(),
+        None => {
+            let roots = RootCertStore {
+                roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+            };
+            builder.with_root_certificates(roots).with_no_client_auth()
+        }
+    };
+    Ok(Arc::new(config))
+}
+
+/// Parse a SHA-256 fingerprint written in hex, with or without colons.
+fn parse_fingerprint(fingerprint: &str) -> io::Result<Vec<u8>> {
+    let hex = fingerprint.replace(':', "");
+    let invalid = || io::Error::new(ErrorKind::InvalidInput, "Invalid TLS fingerprint");
+    if hex.len() != 64 {
+        return Err(invalid());
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| invalid()))
+        .collect()
+}
+
+/// Accepts exactly one certificate, identified by the SHA-256 hash of its DER encoding, for
+/// servers with self-signed certificates.
+#[derive(Debug)]
+struct PinnedCertificate {
+    fingerprint: Vec<u8>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedCertificate {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if digest(&SHA256, end_entity).as_ref() == self.fingerprint.as_slice() {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::General(
+                "Server certificate doesn't match the pinned fingerprint".to_string(),
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+/// Reading half of a TLS stream. The stream is shared with the writing half, so reads time out
+/// and retry to release it now and then.
+struct TlsReader(Arc<Mutex<TlsStream>>);
+
+impl Read for TlsReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.0.lock().unwrap().read(buf) {
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                result => return result,
+            }
+            // Give a waiting writer the chance to take the lock
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+struct TlsWriter(Arc<Mutex<TlsStream>>);
+
+impl Write for TlsWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut stream = self.0.lock().unwrap();
+        stream.write_all(buf)?;
+        stream.flush()?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.lock().unwrap().flush()
+    }
+}
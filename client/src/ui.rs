@@ -0,0 +1,655 @@
+use crate::{
+    app::{App, Buffer, LineKind},
+    channel_list::{ChannelList, ListSort},
+    dcc::State,
+    formatting,
+    members::Members,
+};
+use client::Lag;
+use ratatui::{
+    Frame,
+    layout::{Constraint, Layout, Position, Rect},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, Paragraph},
+};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// What clicking somewhere on the screen does
+#[derive(Debug, Clone)]
+pub enum Click {
+    /// Switch to the buffer with this index
+    Buffer(usize),
+    /// Put the nickname in the input
+    Nick(String),
+    /// Open the link with this number in the active buffer
+    Url(usize),
+    /// Focus the pane with this index, anywhere in it
+    Pane(usize),
+    /// Put the channel's topic in the input to be changed
+    Topic(String),
+}
+
+/// Draw the panes, the status bar and the input line, returning the areas that can be clicked.
+pub fn draw(frame: &mut Frame, app: &App) -> Vec<(Rect, Click)> {
+    let [panes, status, input] = Layout::vertical([
+        Constraint::Min(1),
+        Constraint::Length(1),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    let mut targets = vec![];
+    let areas = Layout::vertical(vec![Constraint::Fill(1); app.panes.len()]).split(panes);
+    for (pane, area) in areas.iter().enumerate() {
+        targets.extend(draw_pane(frame, app, pane, *area));
+    }
+    // After the rest, so that clicking something in a pane does more than focus it
+    targets.extend(
+        areas
+            .iter()
+            .enumerate()
+            .map(|(pane, area)| (*area, Click::Pane(pane))),
+    );
+    targets.extend(draw_status(frame, app, status));
+    draw_input(frame, app, input);
+    targets
+}
+
+/// A buffer shown in a pane. When there are several panes, each has a line on top with the
+/// buffer's number and name, which stands out in the focused one:
+///
+/// ```text
+/// 2:#rust ───────────────────────
+/// ```
+///
+/// Channels have their topic above them and their users beside them, unless those are hidden.
+fn draw_pane(frame: &mut Frame, app: &App, pane: usize, area: Rect) -> Vec<(Rect, Click)> {
+    let index = app.panes[pane];
+    let buffer = &app.buffers[index];
+    let messages = if app.panes.len() > 1 {
+        let style = if pane == app.pane {
+            app.theme.prompt
+        } else {
+            app.theme.timestamp
+        };
+        let block = Block::new()
+            .borders(Borders::TOP)
+            .border_style(style)
+            .title(format!(" {}:{} ", index + 1, app.title(buffer)));
+        let inner = block.inner(area);
+        frame.render_widget(block, area);
+        inner
+    } else {
+        area
+    };
+
+    let mut targets = vec![];
+    let messages = if app.show_topic && buffer.name.starts_with(['#', '&']) {
+        let [topic, messages] =
+            Layout::vertical([Constraint::Length(1), Constraint::Min(1)]).areas(messages);
+        draw_topic(frame, app, buffer, topic);
+        targets.push((topic, Click::Topic(buffer.name.clone())));
+        messages
+    } else {
+        messages
+    };
+    let messages = if app.show_nicklist && buffer.name.starts_with(['#', '&']) {
+        let [messages, nicklist] = Layout::horizontal([
+            Constraint::Min(1),
+            Constraint::Length(nicklist_width(&buffer.members)),
+        ])
+        .areas(messages);
+        targets.extend(draw_nicklist(frame, app, &buffer.members, nicklist));
+        messages
+    } else {
+        messages
+    };
+    match &buffer.list {
+        Some(list) => draw_channel_list(frame, app, list, messages),
+        None => targets.extend(draw_messages(frame, app, buffer, messages)),
+    }
+    targets
+}
+
+/// A channel's topic and who set it, which can be clicked to change it:
+///
+/// ```text
+/// Rust discussion | https://rust-lang.org (set by alice)
+/// ```
+fn draw_topic(frame: &mut Frame, app: &App, buffer: &Buffer, area: Rect) {
+    let style = app.theme.topic;
+    let mut spans = if buffer.topic.is_empty() {
+        vec![Span::styled("No topic", style.add_modifier(Modifier::DIM))]
+    } else if app.strip_formatting {
+        vec![Span::styled(formatting::strip(&buffer.topic), style)]
+    } else {
+        formatting::parse(&buffer.topic, style)
+    };
+    if let Some(setter) = buffer
+        .topic_set_by
+        .as_ref()
+        .filter(|_| !buffer.topic.is_empty())
+    {
+        spans.push(Span::styled(
+            format!(" (set by {setter})"),
+            style.add_modifier(Modifier::DIM),
+        ));
+    }
+    frame.render_widget(Paragraph::new(Line::from(spans)).style(style), area);
+}
+
+/// The nicklist is as wide as the longest nickname, up to this many columns
+const NICKLIST_MAX_WIDTH: usize = 16;
+
+/// Width of the nicklist, with a column for the prefix and one for the border
+fn nicklist_width(members: &Members) -> u16 {
+    let longest = members.nicks().map(|nick| nick.width()).max().unwrap_or(0);
+    let header = format!("{} users", members.len()).width();
+    (longest + 1).max(header).min(NICKLIST_MAX_WIDTH) as u16 + 1
+}
+
+/// The users of a channel grouped by prefix, operators first, with a blank row between groups:
+///
+/// ```text
+/// │4 users
+/// │@alice
+/// │
+/// │+bob
+/// │
+/// │ carol
+/// │ dave
+/// ```
+///
+/// Away users are dimmed, and the nicknames can be clicked.
+fn draw_nicklist(
+    frame: &mut Frame,
+    app: &App,
+    members: &Members,
+    area: Rect,
+) -> Vec<(Rect, Click)> {
+    let block = Block::new()
+        .borders(Borders::LEFT)
+        .border_style(app.theme.timestamp);
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    let height = usize::from(inner.height);
+    let mut rows = vec![Line::styled(
+        format!("{} users", members.len()),
+        app.theme.info,
+    )];
+    let mut targets = vec![];
+    let sorted = members.sorted();
+    let mut rank = sorted.first().map(|(_, member)| member.rank());
+    for (i, (nick, member)) in sorted.iter().enumerate() {
+        if rank != Some(member.rank()) {
+            rank = Some(member.rank());
+            rows.push(Line::default());
+        }
+        // Leave room to say how many more there are
+        if rows.len() + 1 >= height && i + 1 < sorted.len() {
+            let more = format!("{} more", sorted.len() - i);
+            rows.push(Line::styled(more, app.theme.timestamp));
+            break;
+        }
+        let mut style = nick_color(app, nick);
+        if member.away {
+            style = style.add_modifier(Modifier::DIM);
+        }
+        let prefix = member.prefix().unwrap_or(' ').to_string();
+        let rect = Rect::new(
+            inner.x + 1,
+            inner.y + rows.len() as u16,
+            nick.width() as u16,
+            1,
+        );
+        targets.push((rect.intersection(inner), Click::Nick(nick.to_string())));
+        rows.push(Line::from(vec![
+            Span::styled(prefix, app.theme.timestamp),
+            Span::styled(nick.to_string(), style),
+        ]));
+    }
+    frame.render_widget(Paragraph::new(rows), inner);
+    targets
+}
+
+/// Width of the `HH:MM:SS  ` column
+const TIME_WIDTH: usize = 10;
+
+/// Senders are right-aligned in a column this wide, unless they're longer
+const SENDER_WIDTH: usize = 12;
+
+/// Colors that nicknames are picked from
+const NICK_COLORS: [Color; 12] = [
+    Color::Cyan,
+    Color::Green,
+    Color::Yellow,
+    Color::Magenta,
+    Color::Blue,
+    Color::Red,
+    Color::LightCyan,
+    Color::LightGreen,
+    Color::LightYellow,
+    Color::LightMagenta,
+    Color::LightBlue,
+    Color::LightRed,
+];
+
+/// Lines are laid out in columns, with long messages wrapped to stay in theirs:
+///
+/// ```text
+/// 18:30:00       <alice> a message long enough that it
+///                        wraps onto a second line
+/// 18:30:05               --> bob joined
+/// ```
+///
+/// The senders' nicknames and the numbers after links can be clicked.
+fn draw_messages(frame: &mut Frame, app: &App, buffer: &Buffer, area: Rect) -> Vec<(Rect, Click)> {
+    let width = usize::from(area.width);
+    let height = usize::from(area.height);
+
+    // Lay out lines from the newest one shown until the area is full, noting what can be clicked
+    // on each row
+    let end = buffer.lines.len().saturating_sub(buffer.scroll);
+    let mut rows = vec![];
+    let mut clickable = vec![];
+    for (index, line) in buffer.lines.range(..end).enumerate().rev() {
+        if rows.len() >= height {
+            break;
+        }
+        let mut line_rows = layout_line(app, line, width);
+        // Mark the line found by `/search`
+        if buffer
+            .search
+            .as_ref()
+            .is_some_and(|(_, found)| *found == index)
+        {
+            for row in &mut line_rows {
+                row.style = app.theme.selection;
+            }
+        }
+        let mut line_clickable = line_rows
+            .iter()
+            .map(|row| link_numbers(app, line, row))
+            .collect::<Vec<_>>();
+        if let Some((column, nick)) = sender_nick(line) {
+            line_clickable[0].push((column, nick.width(), Click::Nick(nick.to_string())));
+        }
+        line_rows.extend(rows);
+        rows = line_rows;
+        line_clickable.extend(clickable);
+        clickable = line_clickable;
+    }
+
+    // Keep the newest line at the bottom, skipping the top of the oldest if it doesn't fit
+    let skipped = rows.len().saturating_sub(height);
+    let rows = rows.split_off(skipped);
+    let mut area = area;
+    let padding = area.height.saturating_sub(rows.len() as u16);
+    area.y += padding;
+    area.height -= padding;
+    frame.render_widget(Paragraph::new(rows), area);
+
+    clickable
+        .into_iter()
+        .skip(skipped)
+        .enumerate()
+        .flat_map(|(row, targets)| {
+            targets.into_iter().map(move |(column, width, click)| {
+                let rect = Rect::new(area.x + column as u16, area.y + row as u16, width as u16, 1);
+                (rect.intersection(area), click)
+            })
+        })
+        .collect()
+}
+
+/// Where the numbers after a line's links are on one of its rows, with their widths.
+fn link_numbers(app: &App, line: &crate::app::Line, row: &Line) -> Vec<(usize, usize, Click)> {
+    let mut column = 0;
+    let mut numbers = vec![];
+    for span in &row.spans {
+        let width = span.width();
+        if span.style == app.theme.link
+            && let Some(number) = span
+                .content
+                .strip_prefix('[')
+                .and_then(|rest| rest.strip_suffix(']'))
+                .and_then(|number| number.parse().ok())
+            && line.urls.contains(&number)
+        {
+            numbers.push((column, width, Click::Url(number)));
+        }
+        column += width;
+    }
+    numbers
+}
+
+/// Where the nickname of a line's sender starts on its first row, and the nickname.
+fn sender_nick(line: &crate::app::Line) -> Option<(usize, &str)> {
+    let sender = line.sender.as_deref()?;
+    let nick = line.nick()?;
+    let (before, _) = sender.split_once(nick)?;
+    let padding = SENDER_WIDTH.saturating_sub(sender.width());
+    Some((TIME_WIDTH + padding + before.width(), nick))
+}
+
+/// Channels listed by `/list`, under a line saying how they're shown:
+///
+/// ```text
+/// 1234 channels, sorted by users (Ctrl+S to sort by name). Enter joins the selected one.
+/// #rust                  1024  The Rust programming language
+/// #irc_rs                  12  Terminal IRC client and server
+/// ```
+fn draw_channel_list(frame: &mut Frame, app: &App, list: &ChannelList, area: Rect) {
+    let filter = app.list_filter();
+    let shown = list.shown(filter);
+    let selected = list.selected_index(filter);
+
+    let count = if filter.is_empty() {
+        format!("{} channels", list.len())
+    } else {
+        format!(
+            "{} of {} channels match \"{filter}\"",
+            shown.len(),
+            list.len()
+        )
+    };
+    let loading = if list.complete { "" } else { " so far" };
+    let sort = match list.sort {
+        ListSort::Users => "sorted by users (Ctrl+S to sort by name)",
+        ListSort::Name => "sorted by name (Ctrl+S to sort by users)",
+    };
+    let header = format!("{count}{loading}, {sort}. Enter joins the selected one.");
+    let mut rows = vec![Line::styled(header, app.theme.info)];
+
+    // Scroll to keep the selected channel in view
+    let height = usize::from(area.height).saturating_sub(1);
+    let first = (selected + 1).saturating_sub(height);
+    let name_width = shown
+        .iter()
+        .map(|entry| entry.channel.width())
+        .max()
+        .unwrap_or_default()
+        .min(30);
+    for (index, entry) in shown.iter().enumerate().skip(first).take(height) {
+        let padding = " ".repeat(name_width.saturating_sub(entry.channel.width()));
+        let text = format!(
+            "{}{padding}  {:>6}  {}",
+            entry.channel,
+            entry.users,
+            formatting::strip(&entry.topic)
+        );
+        let style = if index == selected {
+            app.theme.selection
+        } else {
+            app.theme.message
+        };
+        rows.push(Line::styled(text, style));
+    }
+    frame.render_widget(Paragraph::new(rows), area);
+}
+
+/// Split a line into the rows it takes up on screen.
+fn layout_line(app: &App, line: &crate::app::Line, width: usize) -> Vec<Line<'static>> {
+    let theme = &app.theme;
+    let style = match line.kind {
+        LineKind::Message => theme.message,
+        LineKind::Highlight => theme.highlight,
+        LineKind::Event => theme.event,
+        LineKind::Info => theme.info,
+        LineKind::Error => theme.error,
+    };
+
+    let mut first = vec![Span::styled(
+        line.time.format("%H:%M:%S  ").to_string(),
+        theme.timestamp,
+    )];
+    let sender = line.sender.as_deref().unwrap_or_default();
+    let sender_width = sender.width();
+    first.push(Span::raw(
+        " ".repeat(SENDER_WIDTH.saturating_sub(sender_width)),
+    ));
+    if let (Some(sender), Some(nick)) = (&line.sender, line.nick()) {
+        // Color only the nickname, not the brackets around it
+        let nick_style = nick_color(app, nick);
+        let (before, after) = sender.split_once(nick).unwrap_or_default();
+        first.push(Span::styled(before.to_string(), theme.timestamp));
+        first.push(Span::styled(nick.to_string(), nick_style));
+        first.push(Span::styled(after.to_string(), theme.timestamp));
+    }
+    first.push(Span::raw(" "));
+
+    let indent = TIME_WIDTH + SENDER_WIDTH.max(sender_width) + 1;
+    let mut text = if app.strip_formatting {
+        vec![Span::styled(formatting::strip(&line.text), style)]
+    } else {
+        formatting::parse(&line.text, style)
+    };
+    // Number the links so that they can be opened
+    for number in &line.urls {
+        text.push(Span::styled(" ", style));
+        text.push(Span::styled(format!("[{number}]"), theme.link));
+    }
+    let mut rows = wrap(text, width.saturating_sub(indent).max(10)).into_iter();
+
+    first.extend(rows.next().unwrap_or_default());
+    let mut lines = vec![Line::from(first)];
+    for row in rows {
+        let mut spans = vec![Span::raw(" ".repeat(indent))];
+        spans.extend(row);
+        lines.push(Line::from(spans));
+    }
+    lines
+}
+
+/// How a nickname is shown: our own stands out, and others have colors of their own if the theme
+/// colors them.
+fn nick_color(app: &App, nick: &str) -> Style {
+    if nick.eq_ignore_ascii_case(app.nick()) {
+        app.theme.own_nick
+    } else if app.theme.nick_colors {
+        nick_style(nick)
+    } else {
+        app.theme.message
+    }
+}
+
+/// The same nickname always gets the same color.
+pub fn nick_style(nick: &str) -> Style {
+    // FNV-1a, which unlike the standard library's hasher is the same in every run
+    let hash = nick
+        .to_lowercase()
+        .bytes()
+        .fold(0x811c9dc5_u32, |hash, byte| {
+            (hash ^ u32::from(byte)).wrapping_mul(0x01000193)
+        });
+    Style::default().fg(NICK_COLORS[hash as usize % NICK_COLORS.len()])
+}
+
+/// Break styled text into rows at most `width` columns wide, between words where possible.
+/// Characters are kept together with the accents and other marks combined with them, and wide
+/// characters like CJK and most emoji take up two columns.
+fn wrap(spans: Vec<Span<'static>>, width: usize) -> Vec<Vec<Span<'static>>> {
+    let graphemes = spans
+        .iter()
+        .flat_map(|span| {
+            let graphemes = span.content.graphemes(true);
+            graphemes.map(move |grapheme| (grapheme, span.style))
+        })
+        .collect::<Vec<_>>();
+
+    let mut rows = vec![];
+    let mut start = 0;
+    loop {
+        // As much as fits, but at least one character so that a row is never empty
+        let mut end = start;
+        let mut used = 0;
+        while let Some((grapheme, _)) = graphemes.get(end) {
+            if used + grapheme.width() > width && end > start {
+                break;
+            }
+            used += grapheme.width();
+            end += 1;
+        }
+        if end == graphemes.len() {
+            rows.push(group(&graphemes[start..]));
+            return rows;
+        }
+
+        // Break after the last space that fits, or in the middle of a word that's too long
+        let end = graphemes[start..end]
+            .iter()
+            .rposition(|(grapheme, _)| *grapheme == " ")
+            .filter(|&space| space > 0)
+            .map_or(end, |space| start + space + 1);
+        rows.push(group(&graphemes[start..end]));
+        start = end;
+    }
+}
+
+/// Join characters back into spans of the same style.
+fn group(graphemes: &[(&str, Style)]) -> Vec<Span<'static>> {
+    let mut spans: Vec<Span> = vec![];
+    for &(grapheme, style) in graphemes {
+        match spans.last_mut() {
+            Some(span) if span.style == style => span.content.to_mut().push_str(grapheme),
+            _ => spans.push(Span::styled(grapheme.to_string(), style)),
+        }
+    }
+    spans
+}
+
+/// Status bar, e.g. `[alice(+iw)] [oper] [away] [2:#rust] [Act: 1:bob(3) 4:#irc(5, 2!)]
+/// [DCC: notes.txt 42%] [queue: 4] [lag: 0.12s]`. The buffers with activity can be clicked.
+fn draw_status(frame: &mut Frame, app: &App, area: Rect) -> Vec<(Rect, Click)> {
+    let theme = &app.theme;
+    let network = &app.networks[app.network()];
+    let mut spans = vec![];
+    if network.user_modes.is_empty() {
+        spans.push(Span::raw(format!("[{}] ", app.nick())));
+    } else {
+        spans.push(Span::raw(format!(
+            "[{}(+{})] ",
+            app.nick(),
+            network.user_modes
+        )));
+    }
+    if network.is_oper() {
+        spans.push(Span::styled("[oper] ", theme.activity_highlight));
+    }
+    if network.away {
+        spans.push(Span::styled("[away] ", theme.activity));
+    }
+    spans.push(Span::raw(format!(
+        "[{}:{}] ",
+        app.active + 1,
+        app.title(app.active_buffer())
+    )));
+
+    // Buffers with unread messages, in yellow with how many mention the user if any do
+    let activity = app
+        .buffers
+        .iter()
+        .enumerate()
+        .filter(|(_, buffer)| buffer.unread > 0)
+        .collect::<Vec<_>>();
+    let mut targets = vec![];
+    if !activity.is_empty() {
+        spans.push(Span::styled("[Act:", theme.activity));
+        for (i, buffer) in activity {
+            let (style, count) = if buffer.highlights > 0 {
+                let count = format!("{}, {}!", buffer.unread, buffer.highlights);
+                (theme.activity_highlight, count)
+            } else {
+                (theme.activity, buffer.unread.to_string())
+            };
+            let entry = format!(" {}:{}({count})", i + 1, app.title(buffer));
+            let column = spans.iter().map(Span::width).sum::<usize>();
+            let rect = Rect::new(area.x + column as u16, area.y, entry.width() as u16, 1);
+            targets.push((rect.intersection(area), Click::Buffer(i)));
+            spans.push(Span::styled(entry, style));
+        }
+        spans.push(Span::styled("] ", theme.activity));
+    }
+    for transfer in &app.dcc.transfers {
+        if transfer.state == State::Transferring {
+            spans.push(Span::raw(format!(
+                "[DCC: {} {}%] ",
+                transfer.file_name,
+                transfer.percent()
+            )));
+        }
+    }
+    // Viewing history rather than the newest lines
+    let buffer = app.active_buffer();
+    if buffer.scroll > 0 {
+        let text = match &buffer.search {
+            Some((query, _)) => format!("[search: {query}, {} more] ", buffer.scroll),
+            None => format!("[-- {} more --] ", buffer.scroll),
+        };
+        spans.push(Span::styled(
+            text,
+            Style::default().add_modifier(Modifier::BOLD),
+        ));
+    }
+    // Lines held back by flood protection
+    if network.queued > 0 {
+        spans.push(Span::raw(format!("[queue: {}] ", network.queued)));
+    }
+    if !network.connected {
+        spans.push(Span::styled("[disconnected]", theme.status_alert));
+    } else {
+        match network.lag {
+            Lag::Unknown => {}
+            Lag::Measured(lag) => {
+                spans.push(Span::raw(format!("[lag: {:.2}s]", lag.as_secs_f64())));
+            }
+            Lag::Stalled(waited) => spans.push(Span::styled(
+                format!("[lag: {}s, stalled]", waited.as_secs()),
+                theme.status_alert,
+            )),
+        }
+    }
+
+    frame.render_widget(
+        Paragraph::new(Line::from(spans)).style(theme.status_bar),
+        area,
+    );
+    targets
+}
+
+fn draw_input(frame: &mut Frame, app: &App, area: Rect) {
+    let prompt = if let Some(lines) = &app.pending_paste {
+        format!("[paste: {} lines] ", lines.len())
+    } else if app.active_buffer().list.is_some() {
+        "[filter] ".to_string()
+    } else {
+        format!("[{}] ", app.target().unwrap_or(app.nick()))
+    };
+    let prompt_width = prompt.width();
+
+    // Scroll the input horizontally to keep the cursor visible, skipping whole characters
+    let available = usize::from(area.width).saturating_sub(prompt_width + 1);
+    let before_cursor = app.input.chars().take(app.cursor).collect::<String>();
+    let mut cursor_column = before_cursor.width();
+    let mut skip = 0;
+    for grapheme in before_cursor.graphemes(true) {
+        if cursor_column <= available {
+            break;
+        }
+        cursor_column -= grapheme.width();
+        skip += grapheme.len();
+    }
+
+    let line = Line::from(vec![
+        Span::styled(prompt, app.theme.prompt),
+        Span::raw(app.input[skip..].to_string()),
+    ]);
+    frame.render_widget(Paragraph::new(line), area);
+    frame.set_cursor_position(Position::new(
+        area.x + (prompt_width + cursor_column) as u16,
+        area.y,
+    ));
+}
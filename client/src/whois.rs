@@ -0,0 +1,131 @@
+use chrono::{DateTime, Local};
+
+/// The replies to a WHOIS, collected until RPL_ENDOFWHOIS to be shown together.
+#[derive(Debug, Default)]
+pub struct Whois {
+    pub nick: String,
+    pub user: String,
+    pub host: String,
+    pub realname: String,
+    pub server: String,
+    /// Usually the server's description
+    pub server_info: String,
+    pub channels: Vec<String>,
+    /// Seconds since they last spoke
+    pub idle: Option<u64>,
+    /// When they connected, as a Unix timestamp
+    pub signon: Option<i64>,
+    /// The services account they're logged in to
+    pub account: Option<String>,
+    pub away: Option<String>,
+    /// Replies that are only text, such as being an operator or connected with TLS
+    pub other: Vec<String>,
+}
+
+/// Replies to WHOIS that aren't collected into fields, but are shown as they are: RPL_WHOISCERTFP,
+/// RPL_WHOISREGNICK, RPL_WHOISHELPOP, RPL_WHOISOPERATOR, RPL_WHOISSPECIAL, RPL_WHOISBOT,
+/// RPL_WHOISACTUALLY, RPL_WHOISHOST, RPL_WHOISMODES and RPL_WHOISSECURE.
+const TEXT_REPLIES: &[u16] = &[276, 307, 310, 313, 320, 335, 338, 378, 379, 671];
+
+impl Whois {
+    /// Start collecting the replies about a nickname, at RPL_WHOISUSER.
+    pub fn new(nick: &str) -> Self {
+        Whois {
+            nick: nick.to_string(),
+            ..Whois::default()
+        }
+    }
+
+    /// Take in a reply about the nickname, without our own nickname in front. Returns whether it
+    /// was part of the WHOIS.
+    pub fn add(&mut self, code: u16, params: &[String]) -> bool {
+        let param = |i: usize| params.get(i).cloned().unwrap_or_default();
+        match code {
+            // RPL_WHOISUSER: `nick user host * :realname`
+            311 => {
+                self.user = param(1);
+                self.host = param(2);
+                self.realname = param(4);
+            }
+            // RPL_WHOISSERVER: `nick server :info`
+            312 => {
+                self.server = param(1);
+                self.server_info = param(2);
+            }
+            // RPL_WHOISIDLE: `nick idle signon :seconds idle, signon time`
+            317 => {
+                self.idle = param(1).parse().ok();
+                self.signon = param(2).parse().ok();
+            }
+            // RPL_WHOISCHANNELS, which may come in several parts
+            319 => self
+                .channels
+                .extend(param(1).split_whitespace().map(str::to_string)),
+            // RPL_WHOISACCOUNT: `nick account :is logged in as`
+            330 => self.account = Some(param(1)),
+            // RPL_AWAY
+            301 => self.away = Some(param(1)),
+            _ if TEXT_REPLIES.contains(&code) => {
+                let text = params.get(1..).unwrap_or_default().join(" ");
+                self.other.push(text);
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// The lines to show, with the nickname and address first and the rest lined up below.
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec![format!(
+            "{} ({}@{}): {}",
+            self.nick, self.user, self.host, self.realname
+        )];
+        let mut field = |name: &str, value: String| lines.push(format!("  {name:<9} {value}"));
+        if !self.server.is_empty() {
+            field("server:", format!("{} ({})", self.server, self.server_info));
+        }
+        if let Some(account) = &self.account {
+            field("account:", account.clone());
+        }
+        if !self.channels.is_empty() {
+            field("channels:", self.channels.join(" "));
+        }
+        match (self.idle, self.signon) {
+            (Some(idle), Some(signon)) => {
+                let signon = DateTime::from_timestamp(signon, 0).map_or(String::new(), |time| {
+                    let time = time.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S");
+                    format!(", connected since {time}")
+                });
+                field("idle:", format!("{}{signon}", format_seconds(idle)));
+            }
+            (Some(idle), None) => field("idle:", format_seconds(idle)),
+            _ => {}
+        }
+        if let Some(away) = &self.away {
+            field("away:", away.clone());
+        }
+        for text in &self.other {
+            lines.push(format!("  {text}"));
+        }
+        lines
+    }
+}
+
+/// A length of time like `2d 3h 5m 10s`, leaving out the larger units that are zero.
+fn format_seconds(seconds: u64) -> String {
+    let units = [
+        (seconds / 86400, "d"),
+        ((seconds / 3600) % 24, "h"),
+        ((seconds / 60) % 60, "m"),
+        (seconds % 60, "s"),
+    ];
+    let start = units
+        .iter()
+        .position(|(amount, _)| *amount > 0)
+        .unwrap_or(units.len() - 1);
+    units[start..]
+        .iter()
+        .map(|(amount, unit)| format!("{amount}{unit}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
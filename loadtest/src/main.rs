@@ -0,0 +1,334 @@
+mod scenario;
+
+use scenario::Scenario;
+use shared::{
+    codec::IrcCodec,
+    message::{Command, Message, ToIrc},
+};
+use std::{
+    env,
+    io::{self, ErrorKind, Write},
+    net::TcpStream,
+    path::Path,
+    process,
+    sync::{
+        Arc, Barrier,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Text that messages sent by the load test start with, followed by when they were sent
+const MARKER: &str = "loadtest";
+
+/// How long to wait for the server to answer while connecting
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often the threads reading from connections check whether the test is over
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+fn main() {
+    let Some(path) = env::args().nth(1) else {
+        eprintln!("Usage: loadtest <scenario.toml>");
+        process::exit(2);
+    };
+    let scenario = Scenario::load(Path::new(&path)).unwrap_or_else(|e| {
+        eprintln!("Couldn't load scenario {path}: {e}");
+        process::exit(1);
+    });
+    let scenario = Arc::new(scenario);
+
+    println!(
+        "Connecting {} clients to {}...",
+        scenario.connections, scenario.address
+    );
+    // Every connection waits here once it's joined its channels, or failed to, and the main
+    // thread goes on once all of them have
+    let ready = Arc::new(Barrier::new(scenario.connections + 1));
+    let stop = Arc::new(AtomicBool::new(false));
+    let sent = Arc::new(AtomicU64::new(0));
+    // Messages carry the time they were sent relative to this
+    let epoch = Instant::now();
+
+    let handles = (0..scenario.connections)
+        .map(|number| {
+            let client = Client {
+                number,
+                scenario: scenario.clone(),
+                ready: ready.clone(),
+                stop: stop.clone(),
+                sent: sent.clone(),
+                epoch,
+            };
+            thread::spawn(move || client.run())
+        })
+        .collect::<Vec<_>>();
+
+    let connecting = Instant::now();
+    ready.wait();
+    println!("Connected in {:.2?}.", connecting.elapsed());
+
+    println!(
+        "Sending {} message(s) per second for {} second(s)...",
+        scenario.messages_per_second, scenario.duration
+    );
+    thread::sleep(Duration::from_secs(scenario.duration + scenario.drain));
+    stop.store(true, Ordering::Relaxed);
+
+    let mut report = Report::default();
+    for handle in handles {
+        match handle.join().expect("A connection thread panicked.") {
+            Ok(outcome) => {
+                report.connected += 1;
+                report.latencies.extend(outcome.latencies);
+            }
+            Err(e) => report.errors.push(e.to_string()),
+        }
+    }
+    report.sent = sent.load(Ordering::Relaxed);
+    report.expected = report.sent * expected_recipients(&scenario);
+    report.print(&scenario);
+}
+
+/// One simulated user
+struct Client {
+    number: usize,
+    scenario: Arc<Scenario>,
+    ready: Arc<Barrier>,
+    stop: Arc<AtomicBool>,
+    sent: Arc<AtomicU64>,
+    epoch: Instant,
+}
+
+/// What a client saw
+struct Outcome {
+    /// How long each message from another client took to arrive
+    latencies: Vec<Duration>,
+}
+
+impl Client {
+    fn run(self) -> io::Result<Outcome> {
+        let connection = self.connect();
+        self.ready.wait();
+        let mut connection = connection?;
+
+        let reader = connection.stream.try_clone()?;
+        let receiving = {
+            let stop = self.stop.clone();
+            let epoch = self.epoch;
+            thread::spawn(move || receive(reader, connection.codec, stop, epoch))
+        };
+
+        // Start at different times so the messages are spread out evenly
+        let interval = self.scenario.interval();
+        let channels = self.scenario.channels_of(self.number);
+        let started = Instant::now();
+        let mut next =
+            started + interval.mul_f64(self.number as f64 / self.scenario.connections as f64);
+        let end = started + Duration::from_secs(self.scenario.duration);
+        let mut count = 0;
+        while next < end {
+            thread::sleep(next.saturating_duration_since(Instant::now()));
+            let channel = channels[count % channels.len()];
+            let text = self.message_text();
+            send(&mut connection.stream, &Message::privmsg(channel, &text))?;
+            self.sent.fetch_add(1, Ordering::Relaxed);
+            count += 1;
+            next += interval;
+        }
+
+        let latencies = receiving.join().expect("A receiving thread panicked.");
+        let _ = send(
+            &mut connection.stream,
+            &Message::new(None, Command::Quit, &[]),
+        );
+        Ok(Outcome { latencies })
+    }
+
+    /// Connect, register and join the channels
+    fn connect(&self) -> io::Result<Connection> {
+        let stream = TcpStream::connect(&self.scenario.address)?;
+        stream.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+        // Send each message right away, so the time it takes is the server's
+        stream.set_nodelay(true)?;
+        let mut connection = Connection {
+            stream,
+            codec: IrcCodec::new(),
+        };
+
+        let nick = format!("{}{}", self.scenario.nick_prefix, self.number);
+        send(
+            &mut connection.stream,
+            &Message::new(None, Command::Nick, &[&nick]),
+        )?;
+        let user = Message::new(None, Command::User, &[&nick, "0", "*", &nick]);
+        send(&mut connection.stream, &user)?;
+        connection.wait_for(|message| match message.command {
+            Command::Numeric(1) => Ok(true),
+            Command::Numeric(433) => Err(io::Error::other(format!("{nick} is in use"))),
+            _ => Ok(false),
+        })?;
+
+        for channel in self.scenario.channels_of(self.number) {
+            send(&mut connection.stream, &Message::join(channel))?;
+        }
+        // The server doesn't tell us we joined, but it handles messages in order, so the
+        // channels have been joined once it answers a PING sent after them
+        let ping = Message::new(None, Command::Ping, &[MARKER]);
+        send(&mut connection.stream, &ping)?;
+        connection.wait_for(|message| Ok(message.command == Command::Pong))?;
+        Ok(connection)
+    }
+
+    fn message_text(&self) -> String {
+        let sent = self.epoch.elapsed().as_micros();
+        let mut text = format!("{MARKER} {sent} ");
+        while text.len() < self.scenario.message_length {
+            text.push('x');
+        }
+        text
+    }
+}
+
+struct Connection {
+    stream: TcpStream,
+    codec: IrcCodec,
+}
+
+impl Connection {
+    /// Read messages until one is what's being waited for, answering PINGs along the way
+    fn wait_for(
+        &mut self,
+        mut is_done: impl FnMut(&Message) -> io::Result<bool>,
+    ) -> io::Result<()> {
+        loop {
+            while let Some(decoded) = self.codec.next_message() {
+                let Ok(message) = decoded else {
+                    continue;
+                };
+                if is_done(&message)? {
+                    return Ok(());
+                }
+                answer_ping(&mut self.stream, &message)?;
+            }
+            if self.codec.read_from(&mut self.stream)? == 0 {
+                return Err(ErrorKind::UnexpectedEof.into());
+            }
+        }
+    }
+}
+
+/// Read messages until the test is over, timing the ones from other clients
+fn receive(
+    mut stream: TcpStream,
+    mut codec: IrcCodec,
+    stop: Arc<AtomicBool>,
+    epoch: Instant,
+) -> Vec<Duration> {
+    let mut latencies = vec![];
+    let _ = stream.set_read_timeout(Some(POLL_INTERVAL));
+    while !stop.load(Ordering::Relaxed) {
+        match codec.read_from(&mut stream) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => continue,
+            Err(_) => break,
+        }
+        while let Some(decoded) = codec.next_message() {
+            let Ok(message) = decoded else {
+                continue;
+            };
+            if let Some(latency) = latency(&message, epoch) {
+                latencies.push(latency);
+            }
+            let _ = answer_ping(&mut stream, &message);
+        }
+    }
+    latencies
+}
+
+/// How long a message from another client took to get here
+fn latency(message: &Message, epoch: Instant) -> Option<Duration> {
+    if message.command != Command::PrivMsg {
+        return None;
+    }
+    let mut words = message.params.get(1)?.split(' ');
+    if words.next() != Some(MARKER) {
+        return None;
+    }
+    let sent = Duration::from_micros(words.next()?.parse().ok()?);
+    Some(epoch.elapsed().saturating_sub(sent))
+}
+
+fn answer_ping(stream: &mut TcpStream, message: &Message) -> io::Result<()> {
+    if message.command == Command::Ping {
+        let token = message.params.first().map_or("", String::as_str);
+        send(stream, &Message::pong(token))?;
+    }
+    Ok(())
+}
+
+fn send(stream: &mut TcpStream, message: &Message) -> io::Result<()> {
+    let line = message
+        .to_irc()
+        .map_err(|e| io::Error::new(ErrorKind::InvalidInput, e))?;
+    stream.write_all(line.as_bytes())
+}
+
+/// How many clients a message should reach on average, which is everyone else in the channel
+fn expected_recipients(scenario: &Scenario) -> u64 {
+    let memberships = scenario.connections
+        * scenario
+            .channels_per_connection
+            .clamp(1, scenario.channels.len());
+    let members_per_channel = memberships as f64 / scenario.channels.len() as f64;
+    (members_per_channel - 1.0).max(0.0).round() as u64
+}
+
+#[derive(Default)]
+struct Report {
+    connected: usize,
+    errors: Vec<String>,
+    sent: u64,
+    /// How many deliveries there should have been, from how many members each channel has
+    expected: u64,
+    latencies: Vec<Duration>,
+}
+
+impl Report {
+    fn print(mut self, scenario: &Scenario) {
+        println!();
+        println!(
+            "Connections: {} of {} ({} failed)",
+            self.connected,
+            scenario.connections,
+            self.errors.len()
+        );
+        for error in self.errors.iter().take(5) {
+            println!("  {error}");
+        }
+        println!("Messages sent: {}", self.sent);
+        println!(
+            "Messages delivered: {} of about {} expected",
+            self.latencies.len(),
+            self.expected
+        );
+
+        if self.latencies.is_empty() {
+            return;
+        }
+        self.latencies.sort();
+        println!("Delivery latency:");
+        for percentile in [50.0, 90.0, 99.0, 99.9] {
+            println!("  p{percentile:<5} {:.2?}", self.percentile(percentile));
+        }
+        println!("  max    {:.2?}", self.latencies.last().unwrap());
+    }
+
+    /// The latency that this percent of deliveries were at most, from the sorted latencies
+    fn percentile(&self, percent: f64) -> Duration {
+        let index = (percent / 100.0 * (self.latencies.len() - 1) as f64).round() as usize;
+        self.latencies[index]
+    }
+}
@@ -0,0 +1,79 @@
+use serde::Deserialize;
+use std::{fs, path::Path, time::Duration};
+
+/// What to do to the server, loaded from a TOML file such as:
+///
+/// ```toml
+/// address = "127.0.0.1:6667"
+/// connections = 200
+/// channels = ["#load-1", "#load-2", "#load-3"]
+/// channels_per_connection = 2
+/// messages_per_second = 500.0
+/// duration = 30
+/// message_length = 80
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Scenario {
+    /// The server to connect to
+    pub address: String,
+    /// How many clients to connect at once
+    pub connections: usize,
+    /// Nicknames are this followed by the number of the connection
+    pub nick_prefix: String,
+    /// Channels the clients join, taking turns so each has about as many members
+    pub channels: Vec<String>,
+    /// How many of the channels each client joins
+    pub channels_per_connection: usize,
+    /// Messages sent to the channels per second, by all clients together
+    pub messages_per_second: f64,
+    /// Seconds to send messages for
+    pub duration: u64,
+    /// Length of the text of each message, which is padded to it
+    pub message_length: usize,
+    /// Seconds to wait for messages still on their way once sending stops
+    pub drain: u64,
+}
+
+impl Default for Scenario {
+    fn default() -> Self {
+        Scenario {
+            address: "127.0.0.1:6667".to_string(),
+            connections: 50,
+            nick_prefix: "load".to_string(),
+            channels: vec!["#load".to_string()],
+            channels_per_connection: 1,
+            messages_per_second: 100.0,
+            duration: 10,
+            message_length: 80,
+            drain: 2,
+        }
+    }
+}
+
+impl Scenario {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let scenario: Scenario = toml::from_str(&fs::read_to_string(path)?)?;
+        if scenario.connections == 0 || scenario.channels.is_empty() {
+            return Err("A scenario needs at least one connection and one channel.".into());
+        }
+        if scenario.messages_per_second <= 0.0 {
+            return Err("messages_per_second must be more than 0.".into());
+        }
+        Ok(scenario)
+    }
+
+    /// The channels a connection joins
+    pub fn channels_of(&self, connection: usize) -> Vec<&str> {
+        let count = self.channels_per_connection.clamp(1, self.channels.len());
+        (0..count)
+            .map(|i| self.channels[(connection + i) % self.channels.len()].as_str())
+            .collect()
+    }
+
+    /// How long each connection waits between its messages, so that together they send
+    /// `messages_per_second`
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs_f64(self.connections as f64 / self.messages_per_second)
+    }
+}
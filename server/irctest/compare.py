@@ -0,0 +1,60 @@
+"""Compare the tests that passed in an irctest run (a JUnit XML file) with passing.txt.
+
+Exits with an error if a test listed in passing.txt ran and didn't pass, since that's a
+regression, or if passing.txt doesn't list any tests yet. With update set to true, the tests
+that ran are rewritten in passing.txt with how they did this time instead.
+"""
+
+import sys
+import xml.etree.ElementTree as ET
+
+
+def run_tests(results_path):
+    """Maps the ID of each test that ran to whether it passed."""
+    results = {}
+    for case in ET.parse(results_path).iter("testcase"):
+        test = f"{case.get('classname')}::{case.get('name')}"
+        results[test] = not any(child.tag in ("failure", "error", "skipped") for child in case)
+    return results
+
+
+def read_passing(passing_path):
+    with open(passing_path) as f:
+        lines = (line.strip() for line in f)
+        return {line for line in lines if line and not line.startswith("#")}
+
+
+def main():
+    results_path, passing_path, update = sys.argv[1:4]
+    results = run_tests(results_path)
+    passed = {test for test, ok in results.items() if ok}
+    expected = read_passing(passing_path)
+
+    if update == "true":
+        # Tests left out of this run, e.g. with -k, keep their entries
+        recorded = {test for test in expected if test not in results} | passed
+        with open(passing_path, "w") as f:
+            f.write("# irctest cases irc_rs passes, kept up to date with run.sh --update\n")
+            f.writelines(f"{test}\n" for test in sorted(recorded))
+        print(f"Recorded {len(recorded)} passing tests.")
+        return
+
+    if not expected:
+        print(f"{passing_path} doesn't list any tests, so there's nothing to compare with.")
+        print("Record the tests that pass now with --update.")
+        sys.exit(1)
+
+    regressions = sorted(test for test in expected if test in results and not results[test])
+    newly_passing = sorted(passed - expected)
+    for test in newly_passing:
+        print(f"Now passing: {test}")
+    for test in regressions:
+        print(f"No longer passing: {test}")
+    print(f"{len(passed)} passed, {len(regressions)} regressions, {len(newly_passing)} new.")
+    if newly_passing:
+        print("Run with --update to add the new ones to passing.txt.")
+    sys.exit(1 if regressions else 0)
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,92 @@
+"""irctest controller for the irc_rs server.
+
+irctest (https://github.com/progval/irctest) imports this module by name, so run it with this
+directory on PYTHONPATH and `--controller irc_rs`. The server binary is found through the
+IRC_RS_SERVER environment variable, or as `server` on PATH. See run.sh.
+"""
+
+import os
+import shutil
+from typing import TYPE_CHECKING, Optional, Set, Type
+
+from irctest.basecontrollers import (
+    BaseServerController,
+    DirectoryBasedController,
+    NotImplementedByController,
+)
+
+if TYPE_CHECKING:
+    from irctest.cases import BaseServerTestCase
+
+# The account irctest's SASL tests log into. Accounts are only read from the config when the
+# server starts, so it's written there up front.
+TEST_ACCOUNT = ("jilles", "sesame")
+
+TEMPLATE_CONFIG = """
+hostname = "{hostname}"
+port = {port}
+
+[[opers]]
+name = "operuser"
+password = "operpassword"
+
+[[accounts]]
+name = "{account_name}"
+password = "{account_password}"
+"""
+
+
+class IrcRsController(BaseServerController, DirectoryBasedController):
+    software_name = "irc_rs"
+    supported_sasl_mechanisms: Set[str] = {"PLAIN"}
+    supports_sts = False
+
+    def run(
+        self,
+        hostname: str,
+        port: int,
+        *,
+        password: Optional[str],
+        ssl: bool,
+        run_services: bool,
+        faketime: Optional[str],
+    ) -> None:
+        if password is not None:
+            raise NotImplementedByController("PASS command")
+        if ssl:
+            raise NotImplementedByController("TLS")
+        if run_services:
+            raise NotImplementedByController("Services")
+        if faketime is not None:
+            raise NotImplementedByController("faketime")
+        assert self.proc is None
+        self.hostname = hostname
+        self.port = port
+        self.create_config()
+        with self.open_file("server.toml") as fd:
+            fd.write(
+                TEMPLATE_CONFIG.format(
+                    hostname=hostname,
+                    port=port,
+                    account_name=TEST_ACCOUNT[0],
+                    account_password=TEST_ACCOUNT[1],
+                )
+            )
+
+        binary = os.environ.get("IRC_RS_SERVER") or shutil.which("server")
+        assert binary, "Set IRC_RS_SERVER to the path of the server binary"
+        assert self.directory
+        self.proc = self.execute([binary, os.path.join(self.directory, "server.toml")])
+
+    def registerUser(
+        self,
+        case: "BaseServerTestCase",
+        username: str,
+        password: Optional[str] = None,
+    ) -> None:
+        if (username, password) != TEST_ACCOUNT:
+            raise NotImplementedByController("Accounts other than the one in the config")
+
+
+def get_irctest_controller_class() -> Type[IrcRsController]:
+    return IrcRsController
@@ -0,0 +1,29 @@
+#!/bin/sh
+# Run irctest's server tests against irc_rs and compare what passes with passing.txt.
+#
+#   IRCTEST_DIR=~/irctest server/irctest/run.sh            # fail if a test in passing.txt fails
+#   IRCTEST_DIR=~/irctest server/irctest/run.sh --update   # record what passes now
+#
+# Extra arguments go to pytest, e.g. `-k Join` to run some of the tests.
+set -eu
+
+here=$(cd "$(dirname "$0")" && pwd)
+: "${IRCTEST_DIR:?Set IRCTEST_DIR to a checkout of https://github.com/progval/irctest}"
+
+update=false
+if [ "${1:-}" = "--update" ]; then
+    update=true
+    shift
+fi
+
+cargo build --bin server --manifest-path "$here/../../Cargo.toml"
+export IRC_RS_SERVER="$here/../../target/debug/server"
+
+results=$(mktemp)
+trap 'rm -f "$results"' EXIT
+# Failing tests are expected, so only the comparison below decides the outcome
+(cd "$IRCTEST_DIR" && PYTHONPATH="$here${PYTHONPATH:+:$PYTHONPATH}" \
+    python3 -m pytest --controller irc_rs -m 'not services' --junit-xml="$results" \
+    irctest/server_tests/ "$@") || true
+
+python3 "$here/compare.py" "$results" "$here/passing.txt" "$update"
@@ -0,0 +1,92 @@
+use chrono::{DateTime, Utc};
+use std::{
+    collections::VecDeque,
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    sync::Mutex,
+};
+use tracing::{error, info};
+
+/// Number of entries kept in memory for `STATS a`.
+const RECENT_ENTRIES: usize = 50;
+
+/// A privileged action taken by an IRC operator.
+#[derive(Debug, Clone)]
+pub struct AuditEntry {
+    pub time: DateTime<Utc>,
+    /// Prefix of the operator who took the action
+    pub oper: String,
+    pub action: String,
+    pub target: String,
+    pub reason: String,
+}
+
+/// Append-only record of operator actions. Entries are written to a file (if one is configured) as
+/// tab-separated lines, and the most recent ones are kept in memory so they can be viewed with
+/// `STATS a`.
+#[derive(Debug)]
+pub struct AuditLog {
+    file: Option<Mutex<File>>,
+    recent: Mutex<VecDeque<AuditEntry>>,
+}
+
+impl AuditLog {
+    pub fn open(path: Option<&Path>) -> io::Result<Self> {
+        let file = match path {
+            Some(path) => Some(Mutex::new(
+                OpenOptions::new().create(true).append(true).open(path)?,
+            )),
+            None => None,
+        };
+
+        Ok(AuditLog {
+            file,
+            recent: Mutex::new(VecDeque::with_capacity(RECENT_ENTRIES)),
+        })
+    }
+
+    pub fn record(&self, oper: &str, action: &str, target: &str, reason: &str) {
+        let entry = AuditEntry {
+            time: Utc::now(),
+            oper: oper.to_string(),
+            action: action.to_string(),
+            target: target.to_string(),
+            reason: reason.to_string(),
+        };
+
+        info!(
+            oper = entry.oper,
+            action = entry.action,
+            target = entry.target,
+            reason = entry.reason,
+            "Operator action"
+        );
+
+        if let Some(file) = &self.file {
+            let line = format!(
+                "{}\t{}\t{}\t{}\t{}\n",
+                entry.time.to_rfc3339(),
+                entry.oper,
+                entry.action,
+                entry.target,
+                entry.reason
+            );
+            // Failing to write the audit log shouldn't stop the action from happening
+            if let Err(e) = file.lock().unwrap().write_all(line.as_bytes()) {
+                error!("Failed to write to audit log: {e}");
+            }
+        }
+
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() == RECENT_ENTRIES {
+            recent.pop_front();
+        }
+        recent.push_back(entry);
+    }
+
+    /// Return the most recent entries, oldest first.
+    pub fn recent(&self) -> Vec<AuditEntry> {
+        self.recent.lock().unwrap().iter().cloned().collect()
+    }
+}
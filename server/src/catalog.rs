@@ -0,0 +1,186 @@
+//! The texts the server sends, which can be replaced from the config file to reword or translate
+//! them. Numeric replies' texts are keyed by their number, like `"401"`, and the server's other
+//! texts by what they're about, like `"quieted"`. Values go in texts in place of their names in
+//! braces, like `{reason}` in `Nickname is reserved: {reason}`.
+
+use crate::config::{LanguageTexts, TextsConfig};
+use shared::{
+    message::ReplyCode,
+    replies::{ReplyTexts, fill},
+};
+use std::collections::HashMap;
+
+/// A text the server sends, with the key it's replaced by and the English it's sent in otherwise
+#[derive(Debug)]
+pub struct Text {
+    pub key: &'static str,
+    pub default: &'static str,
+}
+
+const fn text(key: &'static str, default: &'static str) -> Text {
+    Text { key, default }
+}
+
+pub const CONNECTIONS_REFUSED: Text = text(
+    "connections_refused",
+    "The server is not accepting new connections right now. Try again later.",
+);
+pub const DISCONNECTED: Text = text("disconnected", "User disconnected.");
+pub const SENDQ_EXCEEDED: Text = text("sendq_exceeded", "SendQ exceeded.");
+pub const SASL_FAILURES_EXCEEDED: Text =
+    text("sasl_failures_exceeded", "Too many failed SASL logins.");
+pub const NICK_RESERVED: Text = text("nick_reserved", "Nickname is reserved: {reason}");
+/// The server info in WHOIS replies
+pub const SERVER_INFO: Text = text("server_info", "An irc_rs server");
+/// Sent to users with `+W` when they're WHOISed
+pub const WHOIS_NOTICE: Text = text("whois_notice", "*** {prefix} did a /WHOIS on you.");
+
+// Server notices, which operators subscribe to with `+s`
+pub const SNOTICE_CONNECT: Text = text("snotice_connect", "*** Client connecting: {prefix}");
+pub const SNOTICE_EXIT: Text = text("snotice_exit", "*** Client exiting: {prefix}");
+pub const SNOTICE_LINE_TOO_LONG: Text = text(
+    "snotice_line_too_long",
+    "*** Flood: {prefix} sent a line that is too long",
+);
+pub const SNOTICE_SENDQ_EXCEEDED: Text = text(
+    "snotice_sendq_exceeded",
+    "*** Flood: {prefix} was disconnected for exceeding their SendQ",
+);
+pub const SNOTICE_OPER: Text = text("snotice_oper", "*** {prefix} is now an operator ({name})");
+pub const SNOTICE_OPER_FAILED: Text = text(
+    "snotice_oper_failed",
+    "*** Failed OPER attempt by {prefix} ({name})",
+);
+
+pub const LOCKDOWN_PRIVATE_MESSAGES: Text = text(
+    "lockdown_private_messages",
+    "The server is in lockdown. Private messages are disabled.",
+);
+pub const LOCKDOWN_MODERATED: Text = text(
+    "lockdown_moderated",
+    "The server is in lockdown. Only operators can speak.",
+);
+pub const LOCKDOWN_NEW_CHANNELS: Text = text(
+    "lockdown_new_channels",
+    "The server is in lockdown. New channels cannot be created.",
+);
+pub const NOT_IN_CHANNEL: Text = text("not_in_channel", "You are not in that channel.");
+pub const QUIETED: Text = text("quieted", "You are quieted in that channel.");
+pub const SLOW_MODE: Text = text(
+    "slow_mode",
+    "{channel} is in slow mode. Wait {seconds} more second(s) before sending another message.",
+);
+
+pub const SHUNNED: Text = text("shunned", "{nick} has been shunned.");
+pub const UNSHUNNED: Text = text("unshunned", "{nick} is no longer shunned.");
+pub const SEARCH_DISABLED: Text = text(
+    "search_disabled",
+    "Chat logging is disabled, so there is nothing to search.",
+);
+pub const SEARCH_END: Text = text("search_end", "End of search, {count} result(s).");
+pub const SEARCH_FAILED: Text = text("search_failed", "Search failed: {error}");
+pub const DEFCON_STATUS: Text = text("defcon_status", "The server is at DEFCON {level}.");
+pub const DEFCON_REVERTING: Text = text(
+    "defcon_reverting",
+    "The server is at DEFCON {level}, reverting in {seconds} second(s).",
+);
+pub const DEFCON_USAGE: Text = text("defcon_usage", "Usage: DEFCON <1-5> [seconds]");
+pub const DEFCON_SET: Text = text("defcon_set", "{nick} set the server to DEFCON {level}.");
+pub const DEFCON_SET_FOR: Text = text(
+    "defcon_set_for",
+    "{nick} set the server to DEFCON {level} for {seconds} second(s).",
+);
+pub const QLINE_REASON: Text = text("qline_reason", "Reserved by an operator");
+pub const QLINE_ADDED: Text = text("qline_added", "{mask} is now reserved.");
+pub const QLINE_EXISTS: Text = text("qline_exists", "{mask} is already reserved.");
+pub const QLINE_REMOVED: Text = text("qline_removed", "{mask} is no longer reserved.");
+pub const QLINE_MISSING: Text = text("qline_missing", "{mask} is not reserved.");
+pub const STATS_CONNECTIONS: Text = text(
+    "stats_connections",
+    "Highest connection count: {peak} ({startup_peak} since startup), {total} connections \
+     received",
+);
+
+/// Every text other than numeric replies', so that keys in the config can be checked
+const TEXTS: &[&Text] = &[
+    &CONNECTIONS_REFUSED,
+    &DISCONNECTED,
+    &SENDQ_EXCEEDED,
+    &SASL_FAILURES_EXCEEDED,
+    &NICK_RESERVED,
+    &SERVER_INFO,
+    &WHOIS_NOTICE,
+    &SNOTICE_CONNECT,
+    &SNOTICE_EXIT,
+    &SNOTICE_LINE_TOO_LONG,
+    &SNOTICE_SENDQ_EXCEEDED,
+    &SNOTICE_OPER,
+    &SNOTICE_OPER_FAILED,
+    &LOCKDOWN_PRIVATE_MESSAGES,
+    &LOCKDOWN_MODERATED,
+    &LOCKDOWN_NEW_CHANNELS,
+    &NOT_IN_CHANNEL,
+    &QUIETED,
+    &SLOW_MODE,
+    &SHUNNED,
+    &UNSHUNNED,
+    &SEARCH_DISABLED,
+    &SEARCH_END,
+    &SEARCH_FAILED,
+    &DEFCON_STATUS,
+    &DEFCON_REVERTING,
+    &DEFCON_USAGE,
+    &DEFCON_SET,
+    &DEFCON_SET_FOR,
+    &QLINE_REASON,
+    &QLINE_ADDED,
+    &QLINE_EXISTS,
+    &QLINE_REMOVED,
+    &QLINE_MISSING,
+    &STATS_CONNECTIONS,
+];
+
+/// Whether a key names a text, either a numeric like `"401"` or one of the server's own
+pub fn is_key(key: &str) -> bool {
+    (key.len() == 3 && key.bytes().all(|byte| byte.is_ascii_digit()))
+        || TEXTS.iter().any(|text| text.key == key)
+}
+
+/// The texts from the config that replace the server's own
+#[derive(Debug, Default)]
+pub struct Catalog {
+    texts: HashMap<String, String>,
+}
+
+impl Catalog {
+    /// The chosen language's texts, with the ones set for every language over them
+    pub fn new(config: &TextsConfig) -> Self {
+        let mut texts = HashMap::new();
+        if let Some(LanguageTexts::Texts(language)) = config
+            .language
+            .as_ref()
+            .and_then(|language| config.languages.get(language))
+        {
+            texts.extend(language.clone());
+        }
+        texts.extend(config.overrides.clone());
+        Catalog { texts }
+    }
+
+    /// A text, with values put in place of their names
+    pub fn text(&self, text: &Text, values: &[(&str, &str)]) -> String {
+        let template = self
+            .texts
+            .get(text.key)
+            .map_or(text.default, String::as_str);
+        fill(template, values)
+    }
+}
+
+impl ReplyTexts for Catalog {
+    fn get(&self, code: ReplyCode) -> Option<&str> {
+        self.texts
+            .get(&format!("{:03}", code as u16))
+            .map(String::as_str)
+    }
+}
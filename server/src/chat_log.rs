@@ -0,0 +1,359 @@
+use crate::{clock::Clock, config::ChatLogConfig};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use shared::{casemap::IrcCasemap, mask};
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use tracing::{debug, error};
+
+/// One line of a chat log.
+#[derive(Debug, Serialize)]
+pub struct ChatLogEntry<'a> {
+    pub time: DateTime<Utc>,
+    /// Channel name, or the two nicknames of a private conversation
+    pub target: &'a str,
+    pub nick: &'a str,
+    /// PRIVMSG, NOTICE, JOIN, PART, KICK or QUIT
+    pub command: &'a str,
+    pub text: &'a str,
+}
+
+/// A chat log entry read back from disk by a search.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchivedMessage {
+    pub time: DateTime<Utc>,
+    pub target: String,
+    pub nick: String,
+    pub command: String,
+    pub text: String,
+}
+
+/// Most results a single search returns
+const MAX_SEARCH_RESULTS: usize = 1000;
+
+/// Filters for searching the chat logs. Every filter that is set must match.
+#[derive(Debug)]
+pub struct SearchQuery {
+    /// Channel or conversation to search, or all of them if not set
+    pub target: Option<String>,
+    /// Mask that the sender's nickname must match
+    pub from: Option<String>,
+    pub after: Option<DateTime<Utc>>,
+    pub before: Option<DateTime<Utc>>,
+    /// Text that the message must contain, ignoring case
+    pub text: Option<String>,
+    /// Only the most recent `limit` matches are returned
+    pub limit: usize,
+}
+
+impl Default for SearchQuery {
+    fn default() -> Self {
+        SearchQuery {
+            target: None,
+            from: None,
+            after: None,
+            before: None,
+            text: None,
+            limit: 100,
+        }
+    }
+}
+
+impl SearchQuery {
+    /// Build a query from `key=value` pairs. The keys are `target`, `from`, `after`, `before`,
+    /// `text` and `limit`. Times are either RFC 3339 timestamps or `YYYY-MM-DD` dates.
+    pub fn from_pairs<'a>(
+        pairs: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) -> Result<Self, String> {
+        let mut query = SearchQuery::default();
+        for (key, value) in pairs {
+            match key {
+                "target" => query.target = Some(value.to_string()).filter(|t| t != "*"),
+                "from" => query.from = Some(value.to_string()),
+                "after" => query.after = Some(parse_time(value)?),
+                "before" => query.before = Some(parse_time(value)?),
+                "text" => query.text = Some(value.to_lowercase()),
+                "limit" => {
+                    query.limit = value
+                        .parse::<usize>()
+                        .map_err(|_| format!("Invalid limit: {value}"))?
+                        .min(MAX_SEARCH_RESULTS)
+                }
+                _ => return Err(format!("Unknown search filter: {key}")),
+            }
+        }
+        Ok(query)
+    }
+
+    fn matches(&self, message: &ArchivedMessage) -> bool {
+        self.from
+            .as_ref()
+            .is_none_or(|from| mask::matches(from, &message.nick))
+            && self.after.is_none_or(|after| message.time >= after)
+            && self.before.is_none_or(|before| message.time < before)
+            && self
+                .text
+                .as_ref()
+                .is_none_or(|text| message.text.to_lowercase().contains(text))
+    }
+
+    /// Whether a day's log file could contain matches
+    fn includes_day(&self, date: NaiveDate) -> bool {
+        self.after.is_none_or(|after| date >= after.date_naive())
+            && self.before.is_none_or(|before| date <= before.date_naive())
+    }
+}
+
+fn parse_time(value: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.to_utc());
+    }
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map(|date| date.and_time(Default::default()).and_utc())
+        .map_err(|_| format!("Invalid time: {value}"))
+}
+
+/// Writes channel and private conversations to disk, one directory per target and one file per
+/// day. Files older than the configured retention are deleted when a new day's file is started.
+#[derive(Debug)]
+pub struct ChatLog {
+    config: ChatLogConfig,
+    /// Currently open file of each target and the day it belongs to
+    files: Mutex<HashMap<String, (NaiveDate, File)>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl ChatLog {
+    pub fn new(config: ChatLogConfig, clock: Arc<dyn Clock>) -> Self {
+        ChatLog {
+            config,
+            files: Mutex::new(HashMap::new()),
+            clock,
+        }
+    }
+
+    pub fn config(&self) -> &ChatLogConfig {
+        &self.config
+    }
+
+    /// Log an event in a channel, if the channel is configured to be logged.
+    pub fn log_channel(&self, channel: &str, nick: &str, command: &str, text: &str) {
+        if !self
+            .config
+            .channels
+            .iter()
+            .any(|pattern| mask::matches(pattern, channel))
+        {
+            return;
+        }
+
+        self.write(channel, nick, command, text);
+    }
+
+    /// Log a private message, if private messages are configured to be logged. Both directions of
+    /// a conversation end up in the same file.
+    pub fn log_private(&self, from: &str, to: &str, command: &str, text: &str) {
+        if !self.config.private_messages {
+            return;
+        }
+
+        // Nicknames are compared the way the server compares them, where `[` and `{` are the same
+        let casemap = IrcCasemap::default();
+        let mut nicks = [casemap.to_lowercase(from), casemap.to_lowercase(to)];
+        nicks.sort();
+        self.write(
+            &format!("query-{}-{}", nicks[0], nicks[1]),
+            from,
+            command,
+            text,
+        );
+    }
+
+    fn write(&self, target: &str, nick: &str, command: &str, text: &str) {
+        let entry = ChatLogEntry {
+            time: self.clock.utc_now(),
+            target,
+            nick,
+            command,
+            text,
+        };
+
+        let line = match self.config.format {
+            ChatLogFormat::Text => format_text(&entry),
+            ChatLogFormat::Jsonl => match serde_json::to_string(&entry) {
+                Ok(json) => json,
+                Err(e) => {
+                    error!("Failed to serialize chat log entry: {e}");
+                    return;
+                }
+            },
+        };
+
+        if let Err(e) = self.append(target, entry.time.date_naive(), &line) {
+            error!(target, "Failed to write chat log: {e}");
+        }
+    }
+
+    fn append(&self, target: &str, date: NaiveDate, line: &str) -> io::Result<()> {
+        let mut files = self.files.lock().unwrap();
+
+        // Start a new file when the day changes
+        if files.get(target).is_none_or(|(day, _)| *day != date) {
+            let directory = self.target_directory(target);
+            fs::create_dir_all(&directory)?;
+            let path = directory.join(format!("{date}.{}", self.config.format.extension()));
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            files.insert(target.to_string(), (date, file));
+            self.remove_expired(&directory, date);
+        }
+
+        let (_, file) = files.get_mut(target).unwrap();
+        writeln!(file, "{line}")
+    }
+
+    /// Delete a target's log files that are older than the retention period.
+    fn remove_expired(&self, directory: &PathBuf, today: NaiveDate) {
+        let Some(retention_days) = self.config.retention_days else {
+            return;
+        };
+        let Ok(entries) = fs::read_dir(directory) else {
+            return;
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let date = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| NaiveDate::parse_from_str(stem, "%Y-%m-%d").ok());
+            if let Some(date) = date
+                && (today - date).num_days() > i64::from(retention_days)
+            {
+                debug!(?path, "Removing expired chat log");
+                if let Err(e) = fs::remove_file(&path) {
+                    error!(?path, "Failed to remove expired chat log: {e}");
+                }
+            }
+        }
+    }
+
+    /// Find logged messages matching a query, oldest first. Only logs written in the JSONL format
+    /// can be searched.
+    pub fn search(&self, query: &SearchQuery) -> io::Result<Vec<ArchivedMessage>> {
+        if self.config.format != ChatLogFormat::Jsonl {
+            return Err(io::Error::new(
+                ErrorKind::Unsupported,
+                "Searching requires the jsonl chat log format.",
+            ));
+        }
+
+        let directories = match &query.target {
+            Some(target) => vec![self.target_directory(target)],
+            None => match fs::read_dir(&self.config.directory) {
+                Ok(entries) => entries
+                    .flatten()
+                    .map(|entry| entry.path())
+                    .filter(|path| path.is_dir())
+                    .collect(),
+                Err(e) if e.kind() == ErrorKind::NotFound => vec![],
+                Err(e) => return Err(e),
+            },
+        };
+
+        let mut results = vec![];
+        for directory in directories {
+            search_directory(&directory, query, &mut results)?;
+        }
+
+        // Keep the most recent matches
+        results.sort_by_key(|message| message.time);
+        let excess = results.len().saturating_sub(query.limit);
+        results.drain(..excess);
+        Ok(results)
+    }
+
+    /// Directory holding a target's log files. Path separators are replaced since channel names
+    /// may contain them.
+    pub fn target_directory(&self, target: &str) -> PathBuf {
+        self.config.directory.join(target.replace(['/', '\\'], "_"))
+    }
+}
+
+fn search_directory(
+    directory: &Path,
+    query: &SearchQuery,
+    results: &mut Vec<ArchivedMessage>,
+) -> io::Result<()> {
+    let entries = match fs::read_dir(directory) {
+        Ok(entries) => entries,
+        // Nothing has been logged for this target
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path
+            .extension()
+            .is_none_or(|extension| extension != "jsonl")
+        {
+            continue;
+        }
+        let date = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| NaiveDate::parse_from_str(stem, "%Y-%m-%d").ok());
+        if date.is_none_or(|date| !query.includes_day(date)) {
+            continue;
+        }
+
+        for line in fs::read_to_string(&path)?.lines() {
+            match serde_json::from_str::<ArchivedMessage>(line) {
+                Ok(message) if query.matches(&message) => results.push(message),
+                Ok(_) => {}
+                Err(e) => debug!(?path, "Skipping unreadable chat log line: {e}"),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Format an entry as a line in the style of classic IRC client logs.
+fn format_text(entry: &ChatLogEntry) -> String {
+    let time = entry.time.format("%H:%M:%S");
+    let (nick, text) = (entry.nick, entry.text);
+    match entry.command {
+        "PRIVMSG" => format!("[{time}] <{nick}> {text}"),
+        "NOTICE" => format!("[{time}] -{nick}- {text}"),
+        "JOIN" => format!("[{time}] --> {nick} joined {}", entry.target),
+        "PART" => format!("[{time}] <-- {nick} left {}", entry.target),
+        "QUIT" => format!("[{time}] <-- {nick} quit ({text})"),
+        "KICK" => format!("[{time}] <-- {nick} kicked {text}"),
+        command => format!("[{time}] {command} {nick} {text}"),
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChatLogFormat {
+    /// `[12:34:56] <nick> message` lines
+    #[default]
+    Text,
+    /// One JSON object per line
+    Jsonl,
+}
+
+impl ChatLogFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            ChatLogFormat::Text => "log",
+            ChatLogFormat::Jsonl => "jsonl",
+        }
+    }
+}
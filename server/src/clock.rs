@@ -0,0 +1,64 @@
+use chrono::{DateTime, Utc};
+use std::{fmt, time::Instant};
+
+#[cfg(test)]
+use std::{sync::Mutex, time::Duration};
+
+/// Where the server gets the time from, so that tests can move it forward instead of waiting.
+/// Slow mode, DEFCON lockdowns that expire and chat log retention all go by it.
+pub trait Clock: Send + Sync + fmt::Debug {
+    /// For measuring how long until something is allowed or expires
+    fn now(&self) -> Instant;
+
+    /// For dates and times written down, like the day a chat log file is for
+    fn utc_now(&self) -> DateTime<Utc>;
+}
+
+/// The real time
+#[derive(Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn utc_now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A clock that stands still until it's advanced
+#[cfg(test)]
+#[derive(Debug)]
+pub struct ManualClock {
+    start: Instant,
+    start_utc: DateTime<Utc>,
+    elapsed: Mutex<Duration>,
+}
+
+#[cfg(test)]
+impl ManualClock {
+    pub fn new() -> Self {
+        ManualClock {
+            start: Instant::now(),
+            start_utc: Utc::now(),
+            elapsed: Mutex::new(Duration::ZERO),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.elapsed.lock().unwrap() += duration;
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.start + *self.elapsed.lock().unwrap()
+    }
+
+    fn utc_now(&self) -> DateTime<Utc> {
+        self.start_utc + *self.elapsed.lock().unwrap()
+    }
+}
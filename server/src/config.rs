@@ -0,0 +1,417 @@
+use crate::{catalog, chat_log::ChatLogFormat};
+use serde::Deserialize;
+use shared::{
+    codec::Decoding,
+    message::{MAX_LENGTH, MAX_TAGS_LENGTH},
+};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Server settings, loaded from a TOML file such as:
+///
+/// ```toml
+/// hostname = "127.0.0.1"
+/// port = 6667
+/// audit_log = "audit.log"
+/// log_format = "json"
+/// metrics_address = "127.0.0.1:9100"
+/// stats_file = "stats.json"
+/// decoding = "latin1"
+/// whois_notices = "everyone"
+///
+/// [[opers]]
+/// name = "admin"
+/// password = "hunter2"
+///
+/// [[accounts]]
+/// name = "alice"
+/// password = "correct horse"
+///
+/// [chat_log]
+/// directory = "logs"
+/// channels = ["#public-*"]
+///
+/// [[reserved_nicks]]
+/// mask = "*Serv"
+/// reason = "Reserved for services"
+///
+/// [plugins]
+/// filtered_words = ["heck"]
+/// filter_action = "star"
+///
+/// [plugins.matrix]
+/// homeserver = "http://localhost:8008"
+/// server_name = "example.org"
+/// listen = "127.0.0.1:9090"
+/// as_token = "change-me"
+/// hs_token = "change-me-too"
+/// rooms = [{ channel = "#rust", room = "!abcdefg:example.org" }]
+///
+/// [plugins.discord]
+/// channels = [{ channel = "#rust", webhook = "https://discord.com/api/webhooks/1/abc" }]
+///
+/// [plugins.event_stream]
+/// listen = "127.0.0.1:8080"
+/// channels = ["#rust"]
+///
+/// [limits]
+/// channels = 20
+/// sendq = 262144
+/// away_length = 200
+/// sasl_failures = 3
+///
+/// [texts]
+/// language = "fr"
+/// languages = { fr = "texts/fr.toml" }
+/// overrides = { "401" = "No one here goes by that name" }
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Address to listen on, also used as the prefix of server replies
+    pub hostname: String,
+    pub port: u16,
+    /// File that privileged operator actions are appended to
+    pub audit_log: Option<PathBuf>,
+    /// Format of the server's diagnostic output. Which events are shown is controlled by the
+    /// `RUST_LOG` environment variable.
+    pub log_format: LogFormat,
+    /// Address to serve Prometheus metrics on at `/metrics`. Disabled if not set.
+    pub metrics_address: Option<String>,
+    /// File that the all-time peak connection count is kept in across restarts
+    pub stats_file: Option<PathBuf>,
+    /// Logging of channel and private conversations to disk. Disabled if not set.
+    pub chat_log: Option<ChatLogConfig>,
+    /// Credentials accepted by the OPER command
+    pub opers: Vec<OperConfig>,
+    /// Accounts users can log into with SASL PLAIN while connecting
+    pub accounts: Vec<AccountConfig>,
+    /// Nickname masks (Q-lines) that only operators may use
+    pub reserved_nicks: Vec<ReservedNick>,
+    /// Who may set user mode `+W` to be told when they're WHOISed
+    pub whois_notices: WhoisNotices,
+    /// How lines from clients that aren't UTF-8 are read
+    pub decoding: Decoding,
+    /// How much each connection may take up
+    pub limits: Limits,
+    /// Settings of the optional behaviors in [`crate::plugins`]
+    pub plugins: PluginsConfig,
+    /// Replacements for the texts the server sends, see [`crate::catalog`]
+    pub texts: TextsConfig,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human-readable lines
+    #[default]
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChatLogConfig {
+    /// Directory that log files are written under, in one subdirectory per channel or conversation
+    pub directory: PathBuf,
+    #[serde(default)]
+    pub format: ChatLogFormat,
+    /// Masks of the channels to log, e.g. `["*"]` for every channel
+    #[serde(default)]
+    pub channels: Vec<String>,
+    /// Whether private messages between users are logged too
+    #[serde(default)]
+    pub private_messages: bool,
+    /// Delete log files older than this many days. Logs are kept forever if not set.
+    pub retention_days: Option<u32>,
+    /// Bearer token required by the `/search` HTTP endpoint, which is disabled if not set
+    pub search_token: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WhoisNotices {
+    Everyone,
+    #[default]
+    Opers,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OperConfig {
+    pub name: String,
+    pub password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AccountConfig {
+    pub name: String,
+    pub password: String,
+}
+
+/// Bounds on what's kept for each connection, so that no client can make the server hold on to
+/// an unbounded amount for it
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Limits {
+    /// Longest line a client may send, tags included. Longer ones are answered with
+    /// ERR_INPUTTOOLONG and skipped, so no more than this is buffered while reading a line.
+    pub line_length: usize,
+    /// Most bytes that may be waiting to be sent to a client. Clients that fall this far behind
+    /// are sent an ERROR and disconnected.
+    pub sendq: usize,
+    /// Longest away message kept. Longer ones are cut short.
+    pub away_length: usize,
+    /// Most channels a user may be in at once. Operators may join any number.
+    pub channels: usize,
+    /// How many SASL logins a connection may fail before it's sent an ERROR and closed, so that
+    /// passwords can't be guessed at will
+    pub sasl_failures: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            line_length: MAX_TAGS_LENGTH + MAX_LENGTH,
+            sendq: 256 * 1024,
+            away_length: 200,
+            channels: 20,
+            sasl_failures: 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct TextsConfig {
+    /// Which of `languages` to send texts in. The server's own English texts are sent if not set.
+    pub language: Option<String>,
+    /// Texts by key for each language
+    pub languages: HashMap<String, LanguageTexts>,
+    /// Texts by key that are sent whatever the language
+    pub overrides: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum LanguageTexts {
+    Texts(HashMap<String, String>),
+    /// A TOML file of texts by key, which is read when the config is loaded
+    File(PathBuf),
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct PluginsConfig {
+    /// Words that aren't allowed in messages, ignoring ASCII case. The filter is off if there
+    /// are none.
+    pub filtered_words: Vec<String>,
+    /// What's done with messages that have them
+    pub filter_action: FilterAction,
+    /// Bridge to Matrix rooms. Disabled if not set.
+    pub matrix: Option<MatrixConfig>,
+    /// Relay to Discord channels. Disabled if not set.
+    pub discord: Option<DiscordConfig>,
+    /// Stream of public channels' messages over HTTP. Disabled if not set.
+    pub event_stream: Option<EventStreamConfig>,
+}
+
+/// A Matrix application service, which the homeserver is told about with a registration file
+/// holding the same tokens, a `url` pointing at `listen`, and a user namespace that covers
+/// `user_prefix`
+#[derive(Debug, Clone, Deserialize)]
+pub struct MatrixConfig {
+    /// Where the homeserver's client-server API is, e.g. `https://matrix.example.org`
+    pub homeserver: String,
+    /// The server name in Matrix IDs on the homeserver, e.g. `example.org`
+    pub server_name: String,
+    /// Address to take in events from the homeserver on
+    pub listen: String,
+    /// Token the bridge sends to the homeserver (`as_token` in the registration file)
+    pub as_token: String,
+    /// Token the homeserver sends to the bridge (`hs_token` in the registration file)
+    pub hs_token: String,
+    /// Start of the localparts of the Matrix users that stand in for IRC users, which is
+    /// followed by their nickname
+    #[serde(default = "MatrixConfig::default_user_prefix")]
+    pub user_prefix: String,
+    /// Added to the nicknames that Matrix users show up with on IRC
+    #[serde(default = "MatrixConfig::default_nick_suffix")]
+    pub nick_suffix: String,
+    /// Which channels are bridged to which rooms
+    pub rooms: Vec<BridgedRoom>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BridgedRoom {
+    pub channel: String,
+    /// The room's ID, e.g. `!abcdefg:example.org`
+    pub room: String,
+}
+
+/// Channels whose messages are posted to Discord through webhooks, and optionally a Discord bot
+/// whose channels are read back into them
+#[derive(Debug, Clone, Deserialize)]
+pub struct DiscordConfig {
+    /// Token of a bot that can read the relayed Discord channels. Without one, messages only go
+    /// from IRC to Discord.
+    pub bot_token: Option<String>,
+    /// Nickname that messages from Discord are sent to IRC with
+    #[serde(default = "DiscordConfig::default_nick")]
+    pub nick: String,
+    /// How many seconds to wait between checking Discord channels for new messages
+    #[serde(default = "DiscordConfig::default_poll_interval")]
+    pub poll_interval: u64,
+    pub channels: Vec<RelayedChannel>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RelayedChannel {
+    pub channel: String,
+    /// URL of the webhook that the channel's messages are posted to
+    pub webhook: String,
+    /// ID of the Discord channel that's read back into the channel, if there's a bot to do it
+    pub discord_channel: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterAction {
+    /// The words are starred out
+    #[default]
+    Star,
+    /// The message isn't delivered
+    Block,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReservedNick {
+    pub mask: String,
+    #[serde(default = "ReservedNick::default_reason")]
+    pub reason: String,
+}
+
+impl Config {
+    /// Read and parse a config file.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let text = fs::read_to_string(path)?;
+        let mut config: Config = toml::from_str(&text)?;
+        config.texts.load()?;
+        Ok(config)
+    }
+
+    /// Check a name and password against the configured server operators.
+    pub fn check_oper(&self, name: &str, password: &str) -> bool {
+        self.opers
+            .iter()
+            .any(|oper| oper.name == name && oper.password == password)
+    }
+
+    pub fn check_account(&self, name: &str, password: &str) -> bool {
+        self.accounts
+            .iter()
+            .any(|account| account.name == name && account.password == password)
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            hostname: "127.0.0.1".to_string(),
+            port: 6667, // Default for IRC
+            audit_log: None,
+            log_format: LogFormat::Text,
+            metrics_address: None,
+            stats_file: None,
+            chat_log: None,
+            opers: vec![],
+            accounts: vec![],
+            reserved_nicks: vec![],
+            whois_notices: WhoisNotices::default(),
+            decoding: Decoding::default(),
+            limits: Limits::default(),
+            plugins: PluginsConfig::default(),
+            texts: TextsConfig::default(),
+        }
+    }
+}
+
+impl MatrixConfig {
+    fn default_user_prefix() -> String {
+        "irc_".to_string()
+    }
+
+    fn default_nick_suffix() -> String {
+        "[m]".to_string()
+    }
+}
+
+/// An HTTP endpoint that streams what's said in public channels as Server-Sent Events, for
+/// websites to show without connecting to IRC
+#[derive(Debug, Clone, Deserialize)]
+pub struct EventStreamConfig {
+    /// Address to serve `GET /events` on
+    pub listen: String,
+    /// Masks of the channels that are streamed, e.g. `["#public-*"]`
+    pub channels: Vec<String>,
+    /// Most clients that may be streamed to at once
+    #[serde(default = "EventStreamConfig::default_max_clients")]
+    pub max_clients: usize,
+}
+
+impl TextsConfig {
+    /// Read the languages' files and check that every key names a text
+    fn load(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        for (language, texts) in &mut self.languages {
+            if let LanguageTexts::File(path) = texts {
+                let text = fs::read_to_string(&*path)
+                    .map_err(|e| format!("couldn't read texts for {language}: {e}"))?;
+                *texts = LanguageTexts::Texts(toml::from_str(&text)?);
+            }
+        }
+        if let Some(language) = &self.language
+            && !self.languages.contains_key(language)
+        {
+            return Err(format!("no texts for language {language}").into());
+        }
+
+        let keys = self
+            .languages
+            .values()
+            .flat_map(|texts| match texts {
+                LanguageTexts::Texts(texts) => texts.keys().collect(),
+                LanguageTexts::File(_) => vec![],
+            })
+            .chain(self.overrides.keys());
+        for key in keys {
+            if !catalog::is_key(key) {
+                return Err(format!("unknown text {key}").into());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl DiscordConfig {
+    fn default_nick() -> String {
+        "Discord".to_string()
+    }
+
+    fn default_poll_interval() -> u64 {
+        5
+    }
+}
+
+impl EventStreamConfig {
+    fn default_max_clients() -> usize {
+        100
+    }
+}
+
+impl ReservedNick {
+    fn default_reason() -> String {
+        "Reserved by the server".to_string()
+    }
+}
@@ -0,0 +1,80 @@
+use crate::clock::Clock;
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Level the server runs at when no lockdown is active.
+pub const NORMAL: u8 = 5;
+
+/// Emergency lockdown level, set by operators with the DEFCON command during spam attacks. Each
+/// level adds restrictions on top of the ones above it:
+///
+/// - 5: Normal operation
+/// - 4: No new channels can be created
+/// - 3: No new connections are accepted
+/// - 2: Only IRC operators and channel operators can speak in channels
+/// - 1: Only IRC operators can send private messages
+///
+/// Operators are never restricted. A lockdown can be given a duration after which the server
+/// automatically reverts to normal operation.
+#[derive(Debug)]
+pub struct Defcon {
+    state: Mutex<(u8, Option<Instant>)>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Defcon {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        Defcon {
+            state: Mutex::new((NORMAL, None)),
+            clock,
+        }
+    }
+
+    /// Return the current level, reverting to normal if the lockdown has expired.
+    pub fn level(&self) -> u8 {
+        let mut state = self.state.lock().unwrap();
+        if let (_, Some(expires)) = *state
+            && self.clock.now() >= expires
+        {
+            *state = (NORMAL, None);
+        }
+        state.0
+    }
+
+    /// Return how long until the current lockdown reverts, if it has a timer.
+    pub fn remaining(&self) -> Option<Duration> {
+        let level = self.level();
+        let (_, expires) = *self.state.lock().unwrap();
+        if level == NORMAL {
+            return None;
+        }
+        expires.map(|expires| expires.saturating_duration_since(self.clock.now()))
+    }
+
+    /// Change the level. A duration makes the server revert to normal once it has passed.
+    pub fn set(&self, level: u8, duration: Option<Duration>) {
+        let level = level.clamp(1, NORMAL);
+        let expires = duration
+            .filter(|_| level != NORMAL)
+            .map(|d| self.clock.now() + d);
+        *self.state.lock().unwrap() = (level, expires);
+    }
+
+    pub fn allows_new_channels(&self) -> bool {
+        self.level() > 4
+    }
+
+    pub fn allows_new_connections(&self) -> bool {
+        self.level() > 3
+    }
+
+    pub fn allows_channel_speech(&self) -> bool {
+        self.level() > 2
+    }
+
+    pub fn allows_private_speech(&self) -> bool {
+        self.level() > 1
+    }
+}
@@ -0,0 +1,127 @@
+//! Points where optional behaviors, like filters, statistics and bridges, can watch what happens
+//! on the server and step in. They're written as [`Hook`]s in modules of their own (see
+//! [`crate::plugins`]) and registered on the server state before it starts taking connections.
+//!
+//! Hooks are called without anything in the server state locked, so they're free to look users
+//! and channels up or send messages themselves.
+
+use crate::{server::ServerState, user::Channel};
+use shared::message::Message;
+use std::{fmt, sync::Arc};
+use uuid::Uuid;
+
+/// What a hook decides should happen to a message
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verdict {
+    /// Carry on with the message as it is
+    Continue,
+    /// Carry on with this message instead
+    Rewrite(Message),
+    /// Drop the message, as if it had never been sent
+    Block,
+}
+
+/// Something that's told about events on the server. Every method does nothing by default, so
+/// a hook only implements the ones it cares about.
+pub trait Hook: Send + Sync + fmt::Debug {
+    /// The server is about to start taking connections. Hooks that do things on their own, like
+    /// bridges taking in messages from elsewhere, can hold on to the state from here.
+    fn on_start(&self, _state: &Arc<ServerState>) {}
+
+    /// A user finished registering and was welcomed
+    fn on_register(&self, _state: &ServerState, _user_id: Uuid) {}
+
+    /// A user sent a message, which is about to be handled. Messages from users who haven't
+    /// registered yet only get here if they're part of registering.
+    fn on_message(&self, _state: &ServerState, _user_id: Uuid, _message: &Message) -> Verdict {
+        Verdict::Continue
+    }
+
+    /// A user joined a channel, after the other members were told
+    fn on_join(&self, _state: &ServerState, _user_id: Uuid, _channel: &Arc<Channel>) {}
+
+    /// A user left a channel, whether by parting it, being kicked, joining another or
+    /// disconnecting. They're still in the user table either way.
+    fn on_part(&self, _state: &ServerState, _user_id: Uuid, _channel: &Arc<Channel>) {}
+
+    /// A PRIVMSG or NOTICE from a user is about to be delivered to one of its targets, with the
+    /// sender's prefix and only that target
+    fn pre_send(&self, _state: &ServerState, _user_id: Uuid, _message: &Message) -> Verdict {
+        Verdict::Continue
+    }
+}
+
+/// The hooks registered on the server, which are called in the order they were registered in
+#[derive(Debug, Default)]
+pub struct Hooks(Vec<Box<dyn Hook>>);
+
+impl Hooks {
+    pub fn register(&mut self, hook: impl Hook + 'static) {
+        self.0.push(Box::new(hook));
+    }
+
+    pub fn on_start(&self, state: &Arc<ServerState>) {
+        for hook in &self.0 {
+            hook.on_start(state);
+        }
+    }
+
+    pub fn on_register(&self, state: &ServerState, user_id: Uuid) {
+        for hook in &self.0 {
+            hook.on_register(state, user_id);
+        }
+    }
+
+    /// Let each hook rewrite or block a message from a user. Returns what's left of it to handle.
+    pub fn on_message(
+        &self,
+        state: &ServerState,
+        user_id: Uuid,
+        message: Message,
+    ) -> Option<Message> {
+        self.judge(message, |hook, message| {
+            hook.on_message(state, user_id, message)
+        })
+    }
+
+    pub fn on_join(&self, state: &ServerState, user_id: Uuid, channel: &Arc<Channel>) {
+        for hook in &self.0 {
+            hook.on_join(state, user_id, channel);
+        }
+    }
+
+    pub fn on_part(&self, state: &ServerState, user_id: Uuid, channel: &Arc<Channel>) {
+        for hook in &self.0 {
+            hook.on_part(state, user_id, channel);
+        }
+    }
+
+    /// Let each hook rewrite or block a message before it's delivered. Returns what's left of it
+    /// to send.
+    pub fn pre_send(
+        &self,
+        state: &ServerState,
+        user_id: Uuid,
+        message: Message,
+    ) -> Option<Message> {
+        self.judge(message, |hook, message| {
+            hook.pre_send(state, user_id, message)
+        })
+    }
+
+    /// Pass a message through each hook in turn, stopping once one blocks it
+    fn judge(
+        &self,
+        mut message: Message,
+        verdict: impl Fn(&dyn Hook, &Message) -> Verdict,
+    ) -> Option<Message> {
+        for hook in &self.0 {
+            match verdict(hook.as_ref(), &message) {
+                Verdict::Continue => {}
+                Verdict::Rewrite(rewritten) => message = rewritten,
+                Verdict::Block => return None,
+            }
+        }
+        Some(message)
+    }
+}
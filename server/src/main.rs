@@ -1,32 +1,81 @@
-mod message;
+mod audit;
+mod catalog;
+mod chat_log;
+mod clock;
+mod config;
+mod defcon;
+mod hooks;
+mod metrics;
+mod plugins;
+mod sasl;
 mod server;
+mod snomask;
+mod stats;
 mod user;
 
-use dashmap::DashMap;
-use std::{net::TcpListener, sync::Arc, thread};
-use user::{Channel, User};
-use uuid::Uuid;
+use audit::AuditLog;
+use clock::SystemClock;
+use config::{Config, LogFormat};
+use server::ServerState;
+use std::{env, net::TcpListener, path::Path, process, sync::Arc, thread};
+use tracing::{error, info};
+use tracing_subscriber::EnvFilter;
 
 fn main() {
-    let port = 6667; // Default for IRC
-    let hostname = format!("127.0.0.1:{port}"); // TODO: Allow for custom port
-    let listener = TcpListener::bind(&hostname).expect(&format!("Couldn't bind to {}.", &hostname));
-    println!("Listening on {}.", &hostname);
+    // The config file can be given as the first argument. Otherwise, use `server.toml` in the
+    // working directory if there is one.
+    let config = match env::args().nth(1) {
+        Some(path) => Config::load(Path::new(&path)).unwrap_or_else(|e| {
+            eprintln!("Couldn't load config file {path}: {e}");
+            process::exit(1);
+        }),
+        None if Path::new("server.toml").exists() => Config::load(Path::new("server.toml"))
+            .unwrap_or_else(|e| {
+                eprintln!("Couldn't load config file server.toml: {e}");
+                process::exit(1);
+            }),
+        None => Config::default(),
+    };
 
-    let users = Arc::new(DashMap::<Uuid, User>::new());
-    let channels = Arc::new(DashMap::<String, Arc<Channel>>::new());
+    // Show info-level events by default, unless RUST_LOG says otherwise
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    match config.log_format {
+        LogFormat::Text => tracing_subscriber::fmt().with_env_filter(filter).init(),
+        LogFormat::Json => tracing_subscriber::fmt()
+            .json()
+            .with_env_filter(filter)
+            .init(),
+    }
+
+    let address = format!("{}:{}", config.hostname, config.port);
+    let listener =
+        TcpListener::bind(&address).unwrap_or_else(|_| panic!("Couldn't bind to {}.", &address));
+    info!("Listening on {}.", &address);
+
+    let audit_log = AuditLog::open(config.audit_log.as_deref()).unwrap_or_else(|e| {
+        error!("Couldn't open audit log: {e}");
+        process::exit(1);
+    });
+
+    let mut state = ServerState::new(config, audit_log, Arc::new(SystemClock));
+    plugins::register(&mut state.hooks, &state.config);
+    let state = Arc::new(state);
+    state.hooks.on_start(&state);
+
+    if let Some(metrics_address) = &state.config.metrics_address {
+        metrics::serve(metrics_address, state.clone());
+    }
 
     for stream in listener.incoming() {
         let stream = match stream {
             Ok(s) => s,
             Err(e) => {
-                eprintln!("Failed to accept connection: {e}");
+                error!("Failed to accept connection: {e}");
                 continue;
             }
         };
-        let users = users.clone();
-        let channels = channels.clone();
+        let state = state.clone();
 
-        thread::spawn(move || server::handle_connection(stream, users, channels, "127.0.0.1"));
+        thread::spawn(move || server::handle_connection(stream, state));
     }
 }
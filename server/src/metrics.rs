@@ -0,0 +1,341 @@
+use crate::{chat_log::SearchQuery, server::ServerState};
+use dashmap::DashMap;
+use std::{
+    fmt::Write as _,
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        Arc, LazyLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    thread,
+    time::Duration,
+};
+use tracing::{error, info, warn};
+
+/// Counters updated throughout the server. They live in a global so that low-level helpers (like
+/// the ones writing to sockets) can update them without having the server state passed in.
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::default);
+
+/// Upper bounds (in seconds) of the handler latency histogram buckets.
+const LATENCY_BUCKETS: [f64; 10] = [
+    0.000_1, 0.000_5, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0,
+];
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    pub bytes_in: AtomicU64,
+    pub bytes_out: AtomicU64,
+    /// Bytes waiting in send queues to be written to clients
+    pub queued_bytes: AtomicU64,
+    /// Clients disconnected for falling too far behind on what was sent to them
+    pub sendq_exceeded: AtomicU64,
+    /// Lines skipped for being longer than the limit
+    pub lines_too_long: AtomicU64,
+    /// Time taken to handle each command, keyed by command name
+    handler_latency: DashMap<String, Histogram>,
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    count: AtomicU64,
+    sum_nanos: AtomicU64,
+}
+
+impl Metrics {
+    /// Record that a command was handled and how long it took.
+    pub fn observe_command(&self, command: &str, elapsed: Duration) {
+        let histogram = self.handler_latency.entry(command.to_string()).or_default();
+        let seconds = elapsed.as_secs_f64();
+        for (bucket, bound) in histogram.buckets.iter().zip(LATENCY_BUCKETS) {
+            if seconds <= bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        histogram.count.fetch_add(1, Ordering::Relaxed);
+        histogram
+            .sum_nanos
+            .fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Render all metrics in the Prometheus text exposition format.
+    pub fn render(&self, state: &ServerState) -> String {
+        let registered = state.users.iter().filter(|u| u.is_registered).count();
+        let mut out = String::new();
+
+        let gauges = [
+            (
+                "irc_connected_users",
+                "Users currently connected.",
+                state.users.len() as u64,
+            ),
+            (
+                "irc_registered_users",
+                "Connected users that completed registration.",
+                registered as u64,
+            ),
+            (
+                "irc_channels",
+                "Channels that currently have members.",
+                state.channel_count() as u64,
+            ),
+            (
+                "irc_start_time_seconds",
+                "Unix time the server started at.",
+                state.stats.started_at.timestamp() as u64,
+            ),
+            (
+                "irc_uptime_seconds",
+                "Seconds since the server started.",
+                state.stats.uptime().as_secs(),
+            ),
+            (
+                "irc_peak_connections",
+                "Most simultaneous connections since startup.",
+                state.stats.peak_connections(),
+            ),
+            (
+                "irc_peak_connections_all_time",
+                "Most simultaneous connections ever recorded.",
+                state.stats.all_time_peak().connections,
+            ),
+            (
+                "irc_queued_bytes",
+                "Bytes waiting in send queues to be written to clients.",
+                self.queued_bytes.load(Ordering::Relaxed),
+            ),
+        ];
+        for (name, help, value) in gauges {
+            let _ = writeln!(
+                out,
+                "# HELP {name} {help}\n# TYPE {name} gauge\n{name} {value}"
+            );
+        }
+
+        let counters = [
+            (
+                "irc_connections_total",
+                "Connections accepted since startup.",
+                state.stats.total_connections(),
+            ),
+            (
+                "irc_received_bytes_total",
+                "Bytes read from clients.",
+                self.bytes_in.load(Ordering::Relaxed),
+            ),
+            (
+                "irc_sent_bytes_total",
+                "Bytes written to clients.",
+                self.bytes_out.load(Ordering::Relaxed),
+            ),
+            (
+                "irc_sendq_exceeded_total",
+                "Clients disconnected for exceeding their send queue.",
+                self.sendq_exceeded.load(Ordering::Relaxed),
+            ),
+            (
+                "irc_lines_too_long_total",
+                "Lines from clients skipped for being too long.",
+                self.lines_too_long.load(Ordering::Relaxed),
+            ),
+        ];
+        for (name, help, value) in counters {
+            let _ = writeln!(
+                out,
+                "# HELP {name} {help}\n# TYPE {name} counter\n{name} {value}"
+            );
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP irc_messages_total Messages handled, by command.\n\
+             # TYPE irc_messages_total counter"
+        );
+        for entry in self.handler_latency.iter() {
+            let count = entry.count.load(Ordering::Relaxed);
+            let _ = writeln!(
+                out,
+                "irc_messages_total{{command=\"{}\"}} {count}",
+                escape_label(entry.key())
+            );
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP irc_handler_duration_seconds Time taken to handle a message, by command.\n\
+             # TYPE irc_handler_duration_seconds histogram"
+        );
+        for entry in self.handler_latency.iter() {
+            let command = escape_label(entry.key());
+            for (bucket, bound) in entry.buckets.iter().zip(LATENCY_BUCKETS) {
+                let _ = writeln!(
+                    out,
+                    "irc_handler_duration_seconds_bucket{{command=\"{command}\",le=\"{bound}\"}} {}",
+                    bucket.load(Ordering::Relaxed)
+                );
+            }
+            let count = entry.count.load(Ordering::Relaxed);
+            let sum = entry.sum_nanos.load(Ordering::Relaxed) as f64 / 1e9;
+            let _ = writeln!(
+                out,
+                "irc_handler_duration_seconds_bucket{{command=\"{command}\",le=\"+Inf\"}} {count}\n\
+                 irc_handler_duration_seconds_sum{{command=\"{command}\"}} {sum}\n\
+                 irc_handler_duration_seconds_count{{command=\"{command}\"}} {count}"
+            );
+        }
+
+        out
+    }
+}
+
+/// Escape a label value for the exposition format, in which backslashes, double quotes and line
+/// feeds are written as `\\`, `\"` and `\n`
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Serve `GET /metrics` (and `GET /search` for the chat logs, if enabled) over plain HTTP on its
+/// own thread.
+pub fn serve(address: &str, state: Arc<ServerState>) {
+    let listener = match TcpListener::bind(address) {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Couldn't bind metrics endpoint to {address}: {e}");
+            return;
+        }
+    };
+    info!("Serving metrics on http://{address}/metrics.");
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    // Each request is answered on a thread of its own, so that a client that's
+                    // slow to send it doesn't hold up the others
+                    let state = state.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = handle_request(stream, &state) {
+                            warn!("Failed to answer metrics request: {e}");
+                        }
+                    });
+                }
+                Err(e) => warn!("Failed to accept metrics connection: {e}"),
+            }
+        }
+    });
+}
+
+fn handle_request(mut stream: TcpStream, state: &ServerState) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+    // Only the request line and the Authorization header matter, e.g. `GET /metrics HTTP/1.1`
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut authorization = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':')
+            && name.eq_ignore_ascii_case("authorization")
+        {
+            authorization = Some(value.trim().to_string());
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = (parts.next(), parts.next().unwrap_or_default());
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    let (status, content_type, body) = match (method, path) {
+        (Some("GET"), "/metrics") => ("200 OK", "text/plain; version=0.0.4", METRICS.render(state)),
+        (Some("GET"), "/search") => search(state, query, authorization.as_deref()),
+        _ => ("404 Not Found", "text/plain", "Not found.\n".to_string()),
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+/// Search the chat logs, with the filters of [`SearchQuery`] given as query string parameters.
+/// Results are returned as a JSON array.
+fn search(
+    state: &ServerState,
+    query: &str,
+    authorization: Option<&str>,
+) -> (&'static str, &'static str, String) {
+    let Some(chat_log) = &state.chat_log else {
+        return ("404 Not Found", "text/plain", "Not found.\n".to_string());
+    };
+    let Some(token) = &chat_log.config().search_token else {
+        return ("404 Not Found", "text/plain", "Not found.\n".to_string());
+    };
+    if authorization.and_then(|value| value.strip_prefix("Bearer ")) != Some(token.as_str()) {
+        return (
+            "401 Unauthorized",
+            "text/plain",
+            "Unauthorized.\n".to_string(),
+        );
+    }
+
+    let pairs = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(key), percent_decode(value))
+        })
+        .collect::<Vec<_>>();
+    let query = match SearchQuery::from_pairs(pairs.iter().map(|(k, v)| (k.as_str(), v.as_str()))) {
+        Ok(query) => query,
+        Err(e) => return ("400 Bad Request", "text/plain", format!("{e}\n")),
+    };
+
+    match chat_log.search(&query) {
+        Ok(results) => (
+            "200 OK",
+            "application/json",
+            serde_json::to_string(&results).unwrap_or_default(),
+        ),
+        Err(e) => {
+            warn!("Failed to search chat logs: {e}");
+            ("500 Internal Server Error", "text/plain", format!("{e}\n"))
+        }
+    }
+}
+
+/// Decode a query string component, e.g. `%23rust+talk` to `#rust talk`.
+pub fn percent_decode(input: &str) -> String {
+    let mut bytes = vec![];
+    let mut rest = input.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+        match byte {
+            b'+' => bytes.push(b' '),
+            b'%' if rest.len() >= 2 => {
+                match std::str::from_utf8(&rest[..2])
+                    .ok()
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                {
+                    Some(decoded) => {
+                        bytes.push(decoded);
+                        rest = &rest[2..];
+                    }
+                    None => bytes.push(byte),
+                }
+            }
+            _ => bytes.push(byte),
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
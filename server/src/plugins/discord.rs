@@ -0,0 +1,293 @@
+//! A relay from IRC channels to Discord channels. Messages and actions in a relayed channel are
+//! posted to a Discord webhook under the sender's nickname. With a bot token, the Discord channel
+//! is also checked for new messages now and then, which are sent to the IRC channel by a user of
+//! the server's own, like `<Discord> <alice> hello`.
+
+use super::deliver;
+use crate::{
+    config::DiscordConfig,
+    hooks::{Hook, Verdict},
+    server::ServerState,
+};
+use serde::Deserialize;
+use serde_json::json;
+use shared::{
+    casemap::IrcString,
+    ctcp,
+    message::{Command, Message},
+    prefix::Prefix,
+};
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        mpsc::{self, Receiver, Sender},
+    },
+    thread,
+    time::Duration,
+};
+use tracing::warn;
+use uuid::Uuid;
+
+const API_URL: &str = "https://discord.com/api/v10";
+
+#[derive(Debug)]
+pub struct DiscordRelay {
+    config: DiscordConfig,
+    /// Webhook URLs by channel
+    webhooks: HashMap<IrcString, String>,
+    /// Posts for the webhooks, which a thread of the relay's own sends so that nothing waits on
+    /// Discord while handling IRC messages
+    posts: Sender<Post>,
+}
+
+#[derive(Debug)]
+struct Post {
+    webhook: String,
+    username: String,
+    content: String,
+}
+
+impl DiscordRelay {
+    pub fn new(config: &DiscordConfig) -> Self {
+        let (posts, receiver) = mpsc::channel();
+        thread::spawn(move || send_posts(receiver));
+
+        DiscordRelay {
+            config: config.clone(),
+            webhooks: config
+                .channels
+                .iter()
+                .map(|relayed| {
+                    (
+                        IrcString::from(relayed.channel.as_str()),
+                        relayed.webhook.clone(),
+                    )
+                })
+                .collect(),
+            posts,
+        }
+    }
+}
+
+impl Hook for DiscordRelay {
+    fn on_start(&self, state: &Arc<ServerState>) {
+        let Some(bot_token) = &self.config.bot_token else {
+            return;
+        };
+        let channels = self
+            .config
+            .channels
+            .iter()
+            .filter_map(|relayed| {
+                let discord_channel = relayed.discord_channel.clone()?;
+                Some(ReadChannel {
+                    channel: relayed.channel.clone(),
+                    discord_channel,
+                    webhook_id: webhook_id(&relayed.webhook).map(str::to_string),
+                    last_message: None,
+                })
+            })
+            .collect();
+        let mut reader = Reader {
+            agent: ureq::Agent::new_with_defaults(),
+            authorization: format!("Bot {bot_token}"),
+            prefix: Prefix::User {
+                nick: self.config.nick.as_str().into(),
+                user: Some("discord".into()),
+                host: Some(state.hostname.as_str().into()),
+            },
+            channels,
+        };
+        let interval = Duration::from_secs(self.config.poll_interval);
+        let state = state.clone();
+        thread::spawn(move || {
+            loop {
+                reader.read(&state);
+                thread::sleep(interval);
+            }
+        });
+    }
+
+    fn pre_send(&self, _state: &ServerState, _user_id: Uuid, message: &Message) -> Verdict {
+        if message.command != Command::PrivMsg {
+            return Verdict::Continue;
+        }
+        let Some(webhook) = message
+            .params
+            .first()
+            .and_then(|target| self.webhooks.get(&IrcString::from(target.as_str())))
+        else {
+            return Verdict::Continue;
+        };
+        let Some(content) = message.params.last().and_then(|text| webhook_content(text)) else {
+            return Verdict::Continue;
+        };
+
+        // The thread only ends if the relay is gone too
+        let _ = self.posts.send(Post {
+            webhook: webhook.clone(),
+            username: message.nick().to_string(),
+            content,
+        });
+        Verdict::Continue
+    }
+}
+
+fn send_posts(posts: Receiver<Post>) {
+    let agent = ureq::Agent::new_with_defaults();
+    for post in posts {
+        let body = json!({
+            "username": post.username,
+            "content": post.content,
+            // Nobody on IRC gets to ping everyone on Discord
+            "allowed_mentions": { "parse": [] },
+        });
+        if let Err(e) = agent
+            .post(&post.webhook)
+            .content_type("application/json")
+            .send(&body.to_string())
+        {
+            warn!("Failed to post to Discord webhook: {e}");
+        }
+    }
+}
+
+/// What's posted to Discord for the text of a PRIVMSG. Actions are put in italics, and other CTCP
+/// messages aren't posted.
+fn webhook_content(text: &str) -> Option<String> {
+    match ctcp::parse(text) {
+        Some(("ACTION", action)) => Some(format!("*{}*", ctcp::dequote(action))),
+        Some(_) => None,
+        None => Some(text.to_string()),
+    }
+}
+
+/// The ID in a webhook URL like `https://discord.com/api/webhooks/<id>/<token>`
+fn webhook_id(url: &str) -> Option<&str> {
+    let (_, rest) = url.split_once("/webhooks/")?;
+    rest.split('/').next()
+}
+
+/// The bot's side of the relay, which reads new messages in Discord channels into IRC channels
+struct Reader {
+    agent: ureq::Agent,
+    authorization: String,
+    prefix: Prefix,
+    channels: Vec<ReadChannel>,
+}
+
+struct ReadChannel {
+    channel: String,
+    discord_channel: String,
+    /// The ID of the channel's webhook, so that what's posted from IRC isn't read back
+    webhook_id: Option<String>,
+    /// ID of the latest message that was read. Nothing's read until this is known, so that
+    /// history from before the server started isn't relayed.
+    last_message: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DiscordMessage {
+    id: String,
+    #[serde(default)]
+    content: String,
+    author: Author,
+    webhook_id: Option<String>,
+    #[serde(default)]
+    attachments: Vec<Attachment>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Author {
+    username: String,
+    global_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Attachment {
+    url: String,
+}
+
+impl Reader {
+    fn read(&mut self, state: &ServerState) {
+        for index in 0..self.channels.len() {
+            if let Err(e) = self.read_channel(index, state) {
+                let discord_channel = &self.channels[index].discord_channel;
+                warn!("Failed to read Discord channel {discord_channel}: {e}");
+            }
+        }
+    }
+
+    fn read_channel(&mut self, index: usize, state: &ServerState) -> Result<(), ureq::Error> {
+        let read = &self.channels[index];
+        let url = format!("{API_URL}/channels/{}/messages", read.discord_channel);
+        let request = self
+            .agent
+            .get(&url)
+            .header("Authorization", &self.authorization);
+        let request = match read.last_message {
+            Some(last_message) => request
+                .query("after", last_message.to_string())
+                .query("limit", "100"),
+            None => request.query("limit", "1"),
+        };
+        let body = request.call()?.body_mut().read_to_string()?;
+        let mut messages: Vec<DiscordMessage> =
+            serde_json::from_str(&body).map_err(|e| ureq::Error::Other(Box::new(e)))?;
+        messages.sort_by_key(|message| message.id.parse::<u64>().unwrap_or_default());
+
+        let relay = read.last_message.is_some();
+        let read = &mut self.channels[index];
+        for message in messages {
+            read.last_message = message.id.parse().ok().or(read.last_message);
+            if !relay || (message.webhook_id.is_some() && message.webhook_id == read.webhook_id) {
+                continue;
+            }
+
+            let author = message
+                .author
+                .global_name
+                .as_deref()
+                .unwrap_or(&message.author.username);
+            let lines = message
+                .content
+                .lines()
+                .filter(|line| !line.is_empty())
+                .chain(
+                    message
+                        .attachments
+                        .iter()
+                        .map(|attachment| attachment.url.as_str()),
+                );
+            for line in lines {
+                let text = format!("<{author}> {line}");
+                let irc_message =
+                    Message::privmsg(&read.channel, &text).with_prefix(self.prefix.clone());
+                deliver(state, &read.channel, &irc_message, &text);
+            }
+        }
+        // An empty channel has nothing to start after
+        read.last_message.get_or_insert(0);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn actions_are_posted_in_italics() {
+        assert_eq!(webhook_content("hello").as_deref(), Some("hello"));
+        assert_eq!(
+            webhook_content("\x01ACTION waves\x01").as_deref(),
+            Some("*waves*")
+        );
+        assert_eq!(webhook_content("\x01VERSION\x01"), None);
+        assert_eq!(
+            webhook_id("https://discord.com/api/webhooks/123/abc"),
+            Some("123")
+        );
+    }
+}
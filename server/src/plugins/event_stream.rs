@@ -0,0 +1,321 @@
+//! A read-only view of public channels over HTTP. `GET /events` streams what's said in them as
+//! Server-Sent Events, one JSON object per message, join or part, e.g.
+//!
+//! ```text
+//! data: {"time":"2024-05-01T12:00:00Z","type":"join","channel":"#rust","nick":"alice"}
+//! ```
+//!
+//! Messages have a `type` of `privmsg`, `notice` or `action`, and their `text` too.
+//! `GET /events?channel=%23rust` streams just one of the channels.
+
+use crate::{
+    config::EventStreamConfig,
+    hooks::{Hook, Verdict},
+    metrics::percent_decode,
+    server::ServerState,
+    user::Channel,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use shared::{
+    casemap::IrcString,
+    ctcp, mask,
+    message::{Command, Message},
+};
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        Arc, Mutex,
+        mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError},
+    },
+    thread,
+    time::Duration,
+};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+/// How long a stream may go quiet before a comment is sent, which keeps proxies from closing it
+/// and finds out about clients that have gone away
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Most events that may be waiting for a client. Clients that fall this far behind are dropped.
+const MAX_QUEUED_EVENTS: usize = 256;
+
+#[derive(Debug)]
+pub struct EventStream {
+    config: EventStreamConfig,
+    subscribers: Arc<Mutex<Vec<Subscriber>>>,
+}
+
+#[derive(Debug)]
+struct Subscriber {
+    /// The only channel the client wants, if it asked for one
+    channel: Option<IrcString>,
+    events: SyncSender<Arc<str>>,
+}
+
+#[derive(Debug, Serialize)]
+struct Event<'a> {
+    time: DateTime<Utc>,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    channel: &'a str,
+    nick: &'a str,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    text: &'a str,
+}
+
+impl EventStream {
+    pub fn new(config: &EventStreamConfig) -> Self {
+        EventStream {
+            config: config.clone(),
+            subscribers: Arc::default(),
+        }
+    }
+
+    fn is_streamed(&self, channel: &str) -> bool {
+        Channel::is_channel_name(channel)
+            && self
+                .config
+                .channels
+                .iter()
+                .any(|pattern| mask::matches(pattern, channel))
+    }
+
+    /// Send an event to everyone streaming its channel
+    fn publish(&self, kind: &'static str, channel: &str, nick: &str, text: &str) {
+        if !self.is_streamed(channel) {
+            return;
+        }
+        let event = Event {
+            time: Utc::now(),
+            kind,
+            channel,
+            nick,
+            text,
+        };
+        let Ok(event) = serde_json::to_string(&event) else {
+            return;
+        };
+        let event = Arc::<str>::from(event);
+
+        let channel = IrcString::from(channel);
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.retain(|subscriber| {
+            if subscriber
+                .channel
+                .as_ref()
+                .is_some_and(|wanted| *wanted != channel)
+            {
+                return true;
+            }
+            match subscriber.events.try_send(event.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    warn!("Dropping event stream client that fell behind.");
+                    false
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            }
+        });
+    }
+
+    fn publish_membership(
+        &self,
+        state: &ServerState,
+        kind: &'static str,
+        user_id: Uuid,
+        channel: &Channel,
+    ) {
+        let nick = state
+            .users
+            .get(&user_id)
+            .and_then(|user| user.nickname().cloned());
+        if let Some(nick) = nick {
+            self.publish(kind, &channel.name, &nick, "");
+        }
+    }
+}
+
+impl Hook for EventStream {
+    fn on_start(&self, _state: &Arc<ServerState>) {
+        let listener = match TcpListener::bind(&self.config.listen) {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!("Couldn't bind event stream to {}: {e}", self.config.listen);
+                return;
+            }
+        };
+        info!(
+            "Streaming channel events on http://{}/events.",
+            self.config.listen
+        );
+
+        let subscribers = self.subscribers.clone();
+        let max_clients = self.config.max_clients;
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let subscribers = subscribers.clone();
+                        // Each client is streamed to from a thread of its own
+                        thread::spawn(move || {
+                            if let Err(e) = handle_request(stream, &subscribers, max_clients) {
+                                warn!("Failed to stream events: {e}");
+                            }
+                        });
+                    }
+                    Err(e) => warn!("Failed to accept event stream connection: {e}"),
+                }
+            }
+        });
+    }
+
+    fn on_join(&self, state: &ServerState, user_id: Uuid, channel: &Arc<Channel>) {
+        self.publish_membership(state, "join", user_id, channel);
+    }
+
+    fn on_part(&self, state: &ServerState, user_id: Uuid, channel: &Arc<Channel>) {
+        self.publish_membership(state, "part", user_id, channel);
+    }
+
+    fn pre_send(&self, _state: &ServerState, _user_id: Uuid, message: &Message) -> Verdict {
+        let (Some(target), Some(text)) = (message.params.first(), message.params.last()) else {
+            return Verdict::Continue;
+        };
+        let (kind, text) = match (&message.command, ctcp::parse(text)) {
+            (Command::PrivMsg, Some(("ACTION", action))) => ("action", ctcp::dequote(action)),
+            (_, Some(_)) => return Verdict::Continue,
+            (Command::PrivMsg, None) => ("privmsg", text.into()),
+            (Command::Notice, None) => ("notice", text.into()),
+            _ => return Verdict::Continue,
+        };
+        self.publish(kind, target, message.nick(), &text);
+        Verdict::Continue
+    }
+}
+
+fn handle_request(
+    mut stream: TcpStream,
+    subscribers: &Mutex<Vec<Subscriber>>,
+    max_clients: usize,
+) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    stream.set_write_timeout(Some(KEEPALIVE_INTERVAL))?;
+
+    // Only the request line matters, e.g. `GET /events?channel=%23rust HTTP/1.1`
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = (parts.next(), parts.next().unwrap_or_default());
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    if method != Some("GET") || path != "/events" {
+        return write!(
+            stream,
+            "HTTP/1.1 404 Not Found\r\nContent-Type: text/plain\r\n\
+             Content-Length: 11\r\nConnection: close\r\n\r\nNot found.\n"
+        );
+    }
+    let channel = query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("channel="))
+        .map(|channel| IrcString::from(percent_decode(channel)));
+
+    let events = {
+        let mut subscribers = subscribers.lock().unwrap();
+        if subscribers.len() >= max_clients {
+            None
+        } else {
+            let (sender, events) = mpsc::sync_channel(MAX_QUEUED_EVENTS);
+            subscribers.push(Subscriber {
+                channel,
+                events: sender,
+            });
+            Some(events)
+        }
+    };
+    let Some(events) = events else {
+        return write!(
+            stream,
+            "HTTP/1.1 503 Service Unavailable\r\nContent-Type: text/plain\r\n\
+             Content-Length: 18\r\nConnection: close\r\n\r\nToo many clients.\n"
+        );
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\
+         Access-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n"
+    )?;
+    stream.flush()?;
+    stream_events(stream, events)
+}
+
+/// Write events to a client until it goes away, which drops its receiver and so unsubscribes it
+fn stream_events(mut stream: TcpStream, events: Receiver<Arc<str>>) -> std::io::Result<()> {
+    loop {
+        match events.recv_timeout(KEEPALIVE_INTERVAL) {
+            Ok(event) => write!(stream, "data: {event}\n\n")?,
+            Err(RecvTimeoutError::Timeout) => write!(stream, ": keepalive\n\n")?,
+            // Dropped for falling behind
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+        stream.flush()?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{audit::AuditLog, clock::ManualClock, config::Config};
+    use shared::prefix::Prefix;
+
+    #[test]
+    fn messages_in_streamed_channels_are_published() {
+        let stream = EventStream::new(&EventStreamConfig {
+            listen: "127.0.0.1:0".to_string(),
+            channels: vec!["#public-*".to_string()],
+            max_clients: 1,
+        });
+        let (sender, events) = mpsc::sync_channel(MAX_QUEUED_EVENTS);
+        stream.subscribers.lock().unwrap().push(Subscriber {
+            channel: None,
+            events: sender,
+        });
+
+        let alice = Prefix::User {
+            nick: "alice".into(),
+            user: None,
+            host: None,
+        };
+        let state = ServerState::new(
+            Config::default(),
+            AuditLog::open(None).unwrap(),
+            Arc::new(ManualClock::new()),
+        );
+        for (target, text) in [
+            ("#public-rust", "\x01ACTION waves\x01"),
+            ("#private", "secret"),
+            ("bob", "psst"),
+        ] {
+            let message = Message::privmsg(target, text).with_prefix(alice.clone());
+            stream.pre_send(&state, Uuid::nil(), &message);
+        }
+
+        let event: serde_json::Value = serde_json::from_str(&events.try_recv().unwrap()).unwrap();
+        assert_eq!(event["type"], "action");
+        assert_eq!(event["channel"], "#public-rust");
+        assert_eq!(event["nick"], "alice");
+        assert_eq!(event["text"], "waves");
+        assert!(events.try_recv().is_err());
+    }
+}
@@ -0,0 +1,576 @@
+//! A bridge between IRC channels and Matrix rooms, as a Matrix application service. IRC users who
+//! speak in a bridged channel are stood in for by Matrix users of the bridge's own, like
+//! `@irc_alice:example.org`. Matrix users show up in the channel with a prefix like
+//! `alice[m]!alice@matrix.org`, although they aren't users of the server.
+//!
+//! Channels don't have topics on this server, so only messages, joins and parts are bridged.
+
+use super::deliver;
+use crate::{
+    config::MatrixConfig,
+    hooks::{Hook, Verdict},
+    server::ServerState,
+    user::Channel,
+};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use shared::{
+    casemap::{IrcCasemap, IrcString},
+    ctcp,
+    message::{Command, Message},
+    prefix::Prefix,
+};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        Arc,
+        mpsc::{self, Receiver, Sender},
+    },
+    thread,
+    time::Duration,
+};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+/// Largest transaction the homeserver may send at once
+const MAX_TRANSACTION_LENGTH: usize = 16 * 1024 * 1024;
+
+/// How many transaction IDs are remembered, so that ones the homeserver sends again after not
+/// hearing back are only relayed once
+const REMEMBERED_TRANSACTIONS: usize = 64;
+
+#[derive(Debug)]
+pub struct MatrixBridge {
+    config: MatrixConfig,
+    /// Room IDs by channel
+    rooms: HashMap<IrcString, String>,
+    /// Requests for the homeserver, which a thread of the bridge's own makes so that nothing
+    /// waits on the homeserver while handling IRC messages
+    requests: Sender<Request>,
+}
+
+#[derive(Debug)]
+enum Request {
+    Join {
+        nick: String,
+        room: String,
+    },
+    Leave {
+        nick: String,
+        room: String,
+    },
+    Send {
+        nick: String,
+        room: String,
+        msgtype: &'static str,
+        body: String,
+    },
+}
+
+impl MatrixBridge {
+    pub fn new(config: &MatrixConfig) -> Self {
+        let (requests, receiver) = mpsc::channel();
+        let homeserver = Homeserver {
+            agent: ureq::Agent::new_with_defaults(),
+            config: config.clone(),
+            registered: HashSet::new(),
+            joined: HashSet::new(),
+            next_transaction: 0,
+        };
+        thread::spawn(move || homeserver.run(receiver));
+
+        MatrixBridge {
+            config: config.clone(),
+            rooms: config
+                .rooms
+                .iter()
+                .map(|bridged| {
+                    (
+                        IrcString::from(bridged.channel.as_str()),
+                        bridged.room.clone(),
+                    )
+                })
+                .collect(),
+            requests,
+        }
+    }
+
+    fn request(&self, request: Request) {
+        // The thread only ends if the bridge is gone too
+        let _ = self.requests.send(request);
+    }
+}
+
+impl Hook for MatrixBridge {
+    fn on_start(&self, state: &Arc<ServerState>) {
+        let listener = match TcpListener::bind(&self.config.listen) {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!("Couldn't bind Matrix bridge to {}: {e}", self.config.listen);
+                return;
+            }
+        };
+        info!("Taking in Matrix events on {}.", self.config.listen);
+
+        let mut events = Events {
+            config: self.config.clone(),
+            channels: self
+                .config
+                .rooms
+                .iter()
+                .map(|bridged| (bridged.room.clone(), bridged.channel.clone()))
+                .collect(),
+            seen: VecDeque::new(),
+        };
+        let state = state.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        if let Err(e) = events.handle_request(stream, &state) {
+                            warn!("Failed to answer Matrix homeserver: {e}");
+                        }
+                    }
+                    Err(e) => warn!("Failed to accept Matrix homeserver connection: {e}"),
+                }
+            }
+        });
+    }
+
+    fn on_join(&self, state: &ServerState, user_id: Uuid, channel: &Arc<Channel>) {
+        if let Some(room) = self.rooms.get(&channel.name)
+            && let Some(nick) = nickname(state, user_id)
+        {
+            let room = room.clone();
+            self.request(Request::Join { nick, room });
+        }
+    }
+
+    fn on_part(&self, state: &ServerState, user_id: Uuid, channel: &Arc<Channel>) {
+        if let Some(room) = self.rooms.get(&channel.name)
+            && let Some(nick) = nickname(state, user_id)
+        {
+            let room = room.clone();
+            self.request(Request::Leave { nick, room });
+        }
+    }
+
+    fn pre_send(&self, _state: &ServerState, _user_id: Uuid, message: &Message) -> Verdict {
+        let Some(room) = message
+            .params
+            .first()
+            .and_then(|target| self.rooms.get(&IrcString::from(target.as_str())))
+        else {
+            return Verdict::Continue;
+        };
+        let (nick, Some(text)) = (message.nick(), message.params.last()) else {
+            return Verdict::Continue;
+        };
+
+        let (msgtype, body) = match ctcp::parse(text) {
+            Some(("ACTION", action)) => ("m.emote", ctcp::dequote(action).into_owned()),
+            Some(_) => return Verdict::Continue,
+            None if message.command == Command::Notice => ("m.notice", text.clone()),
+            None => ("m.text", text.clone()),
+        };
+        self.request(Request::Send {
+            nick: nick.to_string(),
+            room: room.clone(),
+            msgtype,
+            body,
+        });
+        Verdict::Continue
+    }
+}
+
+fn nickname(state: &ServerState, user_id: Uuid) -> Option<String> {
+    let user = state.users.get(&user_id)?;
+    user.nickname().map(|nickname| nickname.to_string())
+}
+
+/// The bridge's side of the client-server API, which makes requests as the Matrix users standing
+/// in for IRC users
+struct Homeserver {
+    agent: ureq::Agent,
+    config: MatrixConfig,
+    /// Matrix users that have been registered
+    registered: HashSet<String>,
+    /// Matrix users and the rooms they've joined
+    joined: HashSet<(String, String)>,
+    next_transaction: u64,
+}
+
+impl Homeserver {
+    fn run(mut self, requests: Receiver<Request>) {
+        for request in requests {
+            if let Err(e) = self.handle(request) {
+                warn!("Matrix homeserver request failed: {e}");
+            }
+        }
+    }
+
+    fn handle(&mut self, request: Request) -> Result<(), ureq::Error> {
+        match request {
+            Request::Join { nick, room } => self.join(&nick, &room),
+            Request::Leave { nick, room } => {
+                let user_id = matrix_user_id(&self.config, &nick);
+                if self.joined.remove(&(user_id.clone(), room.clone())) {
+                    let path = format!("/rooms/{}/leave", percent_encode(&room));
+                    self.post(&path, &user_id, &json!({}))?;
+                }
+                Ok(())
+            }
+            Request::Send {
+                nick,
+                room,
+                msgtype,
+                body,
+            } => {
+                self.join(&nick, &room)?;
+                let user_id = matrix_user_id(&self.config, &nick);
+                self.next_transaction += 1;
+                let path = format!(
+                    "/rooms/{}/send/m.room.message/irc_rs-{}-{}",
+                    percent_encode(&room),
+                    std::process::id(),
+                    self.next_transaction
+                );
+                let content = json!({ "msgtype": msgtype, "body": body });
+                self.put(&path, &user_id, &content)
+            }
+        }
+    }
+
+    /// Join the room as the Matrix user standing in for an IRC user, registering them first if
+    /// they're new
+    fn join(&mut self, nick: &str, room: &str) -> Result<(), ureq::Error> {
+        let user_id = matrix_user_id(&self.config, nick);
+        if !self.registered.contains(&user_id) {
+            let localpart = user_id[1..].split(':').next().unwrap_or_default();
+            let registration = json!({
+                "type": "m.login.application_service",
+                "username": localpart,
+            });
+            match self.post("/register", &user_id, &registration) {
+                Ok(()) => {
+                    let path = format!("/profile/{}/displayname", percent_encode(&user_id));
+                    self.put(&path, &user_id, &json!({ "displayname": nick }))?;
+                }
+                // M_USER_IN_USE, from having been registered before
+                Err(ureq::Error::StatusCode(400)) => {}
+                Err(e) => return Err(e),
+            }
+            self.registered.insert(user_id.clone());
+        }
+
+        let key = (user_id, room.to_string());
+        if !self.joined.contains(&key) {
+            let path = format!("/rooms/{}/join", percent_encode(room));
+            self.post(&path, &key.0, &json!({}))?;
+            self.joined.insert(key);
+        }
+        Ok(())
+    }
+
+    fn post(&self, path: &str, user_id: &str, body: &Value) -> Result<(), ureq::Error> {
+        self.agent
+            .post(self.url(path))
+            .header("Authorization", &format!("Bearer {}", self.config.as_token))
+            .query("user_id", user_id)
+            .content_type("application/json")
+            .send(&body.to_string())?;
+        Ok(())
+    }
+
+    fn put(&self, path: &str, user_id: &str, body: &Value) -> Result<(), ureq::Error> {
+        self.agent
+            .put(self.url(path))
+            .header("Authorization", &format!("Bearer {}", self.config.as_token))
+            .query("user_id", user_id)
+            .content_type("application/json")
+            .send(&body.to_string())?;
+        Ok(())
+    }
+
+    fn url(&self, path: &str) -> String {
+        let homeserver = self.config.homeserver.trim_end_matches('/');
+        format!("{homeserver}/_matrix/client/v3{path}")
+    }
+}
+
+/// Events pushed by the homeserver, which are relayed to the bridged channels
+struct Events {
+    config: MatrixConfig,
+    /// Channels by room ID
+    channels: HashMap<String, String>,
+    /// IDs of the latest transactions
+    seen: VecDeque<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Transaction {
+    #[serde(default)]
+    events: Vec<Event>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Event {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    room_id: String,
+    sender: String,
+    #[serde(default)]
+    content: Value,
+    #[serde(default)]
+    unsigned: Value,
+}
+
+impl Events {
+    /// Answer a request from the homeserver. Only transactions of events are taken in.
+    fn handle_request(
+        &mut self,
+        mut stream: TcpStream,
+        state: &ServerState,
+    ) -> std::io::Result<()> {
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+        let mut reader = BufReader::new(&stream);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        let mut authorization = None;
+        let mut content_length = 0;
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("authorization") {
+                    authorization = Some(value.trim().to_string());
+                } else if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap_or(0);
+                }
+            }
+        }
+
+        let mut parts = request_line.split_whitespace();
+        let (method, target) = (parts.next(), parts.next().unwrap_or_default());
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        // Older homeservers send the token in the query string instead
+        let token = authorization
+            .as_deref()
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .or_else(|| {
+                query
+                    .split('&')
+                    .find_map(|pair| pair.strip_prefix("access_token="))
+            });
+
+        let (status, body) = if token.is_none() {
+            (
+                "401 Unauthorized",
+                error_body("M_UNAUTHORIZED", "No token given."),
+            )
+        } else if token != Some(self.config.hs_token.as_str()) {
+            ("403 Forbidden", error_body("M_FORBIDDEN", "Wrong token."))
+        } else if let (Some("PUT"), Some(transaction_id)) =
+            (method, path.strip_prefix("/_matrix/app/v1/transactions/"))
+        {
+            if content_length > MAX_TRANSACTION_LENGTH {
+                (
+                    "413 Payload Too Large",
+                    error_body("M_TOO_LARGE", "Too many events."),
+                )
+            } else {
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body)?;
+                match serde_json::from_slice::<Transaction>(&body) {
+                    Ok(transaction) => {
+                        self.handle_transaction(transaction_id, transaction, state);
+                        ("200 OK", "{}".to_string())
+                    }
+                    Err(e) => ("400 Bad Request", error_body("M_BAD_JSON", &e.to_string())),
+                }
+            }
+        } else {
+            (
+                "404 Not Found",
+                error_body("M_UNRECOGNIZED", "Unrecognized request."),
+            )
+        };
+
+        write!(
+            stream,
+            "HTTP/1.1 {status}\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )
+    }
+
+    fn handle_transaction(&mut self, id: &str, transaction: Transaction, state: &ServerState) {
+        if self.seen.iter().any(|seen| seen == id) {
+            return;
+        }
+        if self.seen.len() == REMEMBERED_TRANSACTIONS {
+            self.seen.pop_front();
+        }
+        self.seen.push_back(id.to_string());
+
+        for event in transaction.events {
+            self.handle_event(event, state);
+        }
+    }
+
+    fn handle_event(&self, event: Event, state: &ServerState) {
+        let Some(channel) = self.channels.get(&event.room_id) else {
+            return;
+        };
+        // What the bridge sent itself comes back as events too
+        if is_bridged_user(&self.config, &event.sender) {
+            return;
+        }
+        let prefix = irc_prefix(&self.config, &event.sender);
+
+        match event.kind.as_str() {
+            "m.room.message" => {
+                let body = event.content["body"].as_str().unwrap_or_default();
+                let msgtype = event.content["msgtype"].as_str().unwrap_or_default();
+                for line in body.lines().filter(|line| !line.is_empty()) {
+                    let message = match msgtype {
+                        "m.emote" => Message::privmsg(channel, &ctcp::encode("ACTION", line)),
+                        "m.notice" => Message::notice(channel, line),
+                        _ => Message::privmsg(channel, line),
+                    };
+                    deliver(state, channel, &message.with_prefix(prefix.clone()), line);
+                }
+            }
+            "m.room.member" => {
+                let membership = event.content["membership"].as_str();
+                // Changes of name or avatar come as a membership that's the same as before
+                if membership == event.unsigned["prev_content"]["membership"].as_str() {
+                    return;
+                }
+                let message = match membership {
+                    Some("join") => Message::join(channel),
+                    Some("leave" | "ban") => Message::part(channel, None),
+                    _ => return,
+                };
+                deliver(state, channel, &message.with_prefix(prefix), "");
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The Matrix user standing in for an IRC user, e.g. `@irc_alice:example.org`. Nicknames are
+/// lowercased, and characters that can't be in a Matrix ID are written as `=` and their bytes in
+/// hex.
+fn matrix_user_id(config: &MatrixConfig, nick: &str) -> String {
+    let mut localpart = config.user_prefix.clone();
+    for byte in IrcCasemap::default().to_lowercase(nick).bytes() {
+        match byte {
+            b'a'..=b'z' | b'0'..=b'9' | b'.' | b'_' | b'-' => localpart.push(byte as char),
+            _ => localpart.push_str(&format!("={byte:02x}")),
+        }
+    }
+    format!("@{localpart}:{}", config.server_name)
+}
+
+/// Whether a Matrix user is one of the bridge's own
+fn is_bridged_user(config: &MatrixConfig, user_id: &str) -> bool {
+    let (localpart, server_name) = split_user_id(user_id);
+    localpart.starts_with(&config.user_prefix) && server_name == config.server_name
+}
+
+/// How a Matrix user shows up on IRC, e.g. `alice[m]!alice@matrix.org` for `@alice:matrix.org`
+fn irc_prefix(config: &MatrixConfig, user_id: &str) -> Prefix {
+    let (localpart, server_name) = split_user_id(user_id);
+    let name = localpart
+        .chars()
+        .map(|c| match c {
+            'a'..='z'
+            | 'A'..='Z'
+            | '0'..='9'
+            | '['
+            | ']'
+            | '\\'
+            | '`'
+            | '_'
+            | '^'
+            | '{'
+            | '|'
+            | '}'
+            | '-' => c,
+            _ => '_',
+        })
+        .collect::<String>();
+    Prefix::User {
+        nick: format!("{name}{}", config.nick_suffix).into(),
+        user: Some(name.into()),
+        host: Some(server_name.into()),
+    }
+}
+
+/// The localpart and server name of a user ID like `@alice:matrix.org`
+fn split_user_id(user_id: &str) -> (&str, &str) {
+    let user_id = user_id.strip_prefix('@').unwrap_or(user_id);
+    user_id.split_once(':').unwrap_or((user_id, ""))
+}
+
+fn error_body(code: &str, error: &str) -> String {
+    json!({ "errcode": code, "error": error }).to_string()
+}
+
+/// Encode a room or user ID to go in a URL path
+fn percent_encode(text: &str) -> String {
+    let mut encoded = String::new();
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> MatrixConfig {
+        toml::from_str(
+            r#"
+            homeserver = "http://localhost:8008"
+            server_name = "example.org"
+            listen = "127.0.0.1:0"
+            as_token = "as"
+            hs_token = "hs"
+            rooms = []
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn irc_users_are_stood_in_for_by_escaped_matrix_users() {
+        let config = config();
+        assert_eq!(matrix_user_id(&config, "Alice"), "@irc_alice:example.org");
+        assert_eq!(
+            matrix_user_id(&config, "[away]"),
+            "@irc_=7baway=7d:example.org"
+        );
+        assert!(is_bridged_user(&config, &matrix_user_id(&config, "x|y")));
+        assert!(!is_bridged_user(&config, "@irc_alice:matrix.org"));
+    }
+
+    #[test]
+    fn matrix_users_show_up_with_a_suffixed_nickname() {
+        let prefix = irc_prefix(&config(), "@bob.smith:matrix.org");
+        assert_eq!(prefix.to_string(), "bob_smith[m]!bob_smith@matrix.org");
+    }
+}
@@ -0,0 +1,55 @@
+//! Optional behaviors built on [`crate::hooks`]. Each one is registered here when its config
+//! turns it on.
+
+mod discord;
+mod event_stream;
+mod matrix;
+mod word_filter;
+
+use crate::{
+    config::Config,
+    hooks::Hooks,
+    server::{ServerState, send_to_channel},
+};
+use discord::DiscordRelay;
+use event_stream::EventStream;
+use matrix::MatrixBridge;
+use shared::{casemap::IrcString, message::Message};
+use tracing::warn;
+use uuid::Uuid;
+use word_filter::WordFilter;
+
+pub fn register(hooks: &mut Hooks, config: &Config) {
+    let plugins = &config.plugins;
+    if !plugins.filtered_words.is_empty() {
+        hooks.register(WordFilter::new(
+            &plugins.filtered_words,
+            plugins.filter_action,
+        ));
+    }
+    // Bridges come after the filter, so that what it keeps off IRC isn't sent elsewhere either
+    if let Some(matrix) = &plugins.matrix {
+        hooks.register(MatrixBridge::new(matrix));
+    }
+    if let Some(discord) = &plugins.discord {
+        hooks.register(DiscordRelay::new(discord));
+    }
+    if let Some(event_stream) = &plugins.event_stream {
+        hooks.register(EventStream::new(event_stream));
+    }
+}
+
+/// Send a message from outside the server to everyone in a channel, if anyone's in it
+fn deliver(state: &ServerState, channel_name: &str, message: &Message, text: &str) {
+    let Some(channel) = state
+        .channels
+        .get(&IrcString::from(channel_name))
+        .map(|channel| channel.clone())
+    else {
+        return;
+    };
+    if let Err(e) = send_to_channel(message, &state.users, &channel, Uuid::nil()) {
+        warn!("Failed to relay a message to {channel_name}: {e}");
+    }
+    state.log_channel(&channel.name, message, text);
+}
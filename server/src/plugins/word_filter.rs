@@ -0,0 +1,58 @@
+use crate::{
+    config::FilterAction,
+    hooks::{Hook, Verdict},
+    server::ServerState,
+};
+use shared::message::Message;
+use uuid::Uuid;
+
+/// Keeps words out of messages before they're delivered, by starring them out (e.g. `what the
+/// heck` as `what the ****`) or not delivering the message at all
+#[derive(Debug)]
+pub struct WordFilter {
+    /// In ASCII lowercase
+    words: Vec<String>,
+    action: FilterAction,
+}
+
+impl WordFilter {
+    pub fn new(words: &[String], action: FilterAction) -> Self {
+        WordFilter {
+            words: words
+                .iter()
+                .filter(|word| !word.is_empty())
+                .map(|word| word.to_ascii_lowercase())
+                .collect(),
+            action,
+        }
+    }
+
+    /// The text with the words starred out, or `None` if it has none of them
+    fn filter(&self, text: &str) -> Option<String> {
+        // Only ASCII is lowercased, so the lowercase text lines up byte for byte with the text
+        let lowercase = text.to_ascii_lowercase();
+        let mut filtered = text.to_string();
+        for word in &self.words {
+            for (start, _) in lowercase.match_indices(word.as_str()) {
+                filtered.replace_range(start..start + word.len(), &"*".repeat(word.len()));
+            }
+        }
+        (filtered != text).then_some(filtered)
+    }
+}
+
+impl Hook for WordFilter {
+    fn pre_send(&self, _state: &ServerState, _user_id: Uuid, message: &Message) -> Verdict {
+        let Some(filtered) = message.params.last().and_then(|text| self.filter(text)) else {
+            return Verdict::Continue;
+        };
+        match self.action {
+            FilterAction::Star => {
+                let mut message = message.clone();
+                *message.params.last_mut().unwrap() = filtered;
+                Verdict::Rewrite(message)
+            }
+            FilterAction::Block => Verdict::Block,
+        }
+    }
+}
@@ -0,0 +1,34 @@
+//! Logging into an account while connecting, with SASL PLAIN. The client turns on the `sasl`
+//! capability with `CAP REQ :sasl`, sends `AUTHENTICATE PLAIN`, and once the server answers
+//! `AUTHENTICATE +`, sends its credentials in base64.
+
+use base64::prelude::*;
+
+/// The only mechanism supported
+pub const MECHANISM: &str = "PLAIN";
+
+/// Most base64 sent in one AUTHENTICATE. Credentials that need more are split over several lines,
+/// which isn't supported.
+pub const MAX_DATA_LENGTH: usize = 400;
+
+/// An account name and password sent with SASL PLAIN
+#[derive(Debug, PartialEq)]
+pub struct Credentials {
+    pub account: String,
+    pub password: String,
+}
+
+/// Read the base64 `authzid\0authcid\0password` of SASL PLAIN. Logging in as an account other than
+/// the one whose password was given isn't supported, so the authzid must be empty or the same.
+pub fn decode_plain(data: &str) -> Option<Credentials> {
+    let decoded = String::from_utf8(BASE64_STANDARD.decode(data).ok()?).ok()?;
+    let mut parts = decoded.split('\0');
+    let (authzid, authcid, password) = (parts.next()?, parts.next()?, parts.next()?);
+    if parts.next().is_some() || (!authzid.is_empty() && authzid != authcid) {
+        return None;
+    }
+    Some(Credentials {
+        account: authcid.to_string(),
+        password: password.to_string(),
+    })
+}
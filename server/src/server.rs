@@ -1,95 +1,306 @@
 use crate::{
-    message::{Command, Message, ReplyCode, Response, ToIrc},
-    user::{Channel, User},
+    audit::AuditLog,
+    catalog::{self, Catalog, Text},
+    chat_log::{ChatLog, SearchQuery},
+    clock::Clock,
+    config::{Config, ReservedNick, WhoisNotices},
+    defcon::{self, Defcon},
+    hooks::Hooks,
+    metrics::METRICS,
+    sasl,
+    snomask::{Snomask, Snomasks},
+    stats::ServerStats,
+    user::{self, Channel, Transport, User, Writer},
+};
+use dashmap::{DashMap, mapref::entry::Entry};
+use shared::{
+    casemap::{IrcCasemap, IrcString},
+    codec::{CodecError, IrcCodec},
+    command::{MissingParams, Target, TypedCommand},
+    mask,
+    message::{Command, Message, ParseError, ToIrc},
+    mode::{ModeArgs, ModeChange},
+    prefix::Prefix,
+    replies::Replies,
 };
-use dashmap::DashMap;
 use std::{
-    io::{Read, Write},
-    net::TcpStream,
+    io::Read,
+    mem,
+    net::{IpAddr, TcpStream},
     str::{self},
-    sync::Arc,
+    sync::{Arc, Mutex, atomic::Ordering},
+    time::{Duration, Instant},
 };
+use tracing::{Span, debug, field, info, info_span, trace, warn};
 use uuid::Uuid;
 
 type UserTable = DashMap<Uuid, User>;
-type ChannelTable = DashMap<String, Arc<Channel>>;
+/// Channels by name, which ignores case
+type ChannelTable = DashMap<IrcString, Arc<Channel>>;
+/// Users by nickname, which ignores case, so they can be found without going through every user
+type NickTable = DashMap<IrcString, Uuid>;
+
+/// Channel modes that take an argument when set and when unset: quiets and operators
+const CHANNEL_MODES_WITH_ARG: &str = "qo";
+/// Channel modes that only take an argument when set: the slow mode's delay
+const CHANNEL_MODES_WITH_ARG_WHEN_SET: &str = "W";
+/// User modes that only take an argument when set: the server notice mask
+const USER_MODES_WITH_ARG_WHEN_SET: &str = "s";
+
+/// Capabilities clients can turn on with CAP REQ
+const CAPABILITIES: [&str; 1] = ["sasl"];
+
+/// Most channels listed in one RPL_WHOISCHANNELS, which keeps it within the line length however
+/// long their names are
+const WHOIS_CHANNELS_PER_LINE: usize = 5;
+
+/// How long a write to a client may go without any of it being sent before the connection is
+/// given up on, which is how a client that stopped reading is let go of
+const WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// State shared by every connection thread.
+pub struct ServerState {
+    /// Prefix used for replies sent by the server
+    pub hostname: String,
+    pub users: UserTable,
+    /// Kept up to date with the nicknames in `users`
+    pub nicks: NickTable,
+    pub channels: ChannelTable,
+    pub config: Config,
+    pub defcon: Defcon,
+    pub audit_log: AuditLog,
+    /// Nickname masks (Q-lines) that only operators may use
+    pub reserved_nicks: Mutex<Vec<ReservedNick>>,
+    pub chat_log: Option<ChatLog>,
+    pub stats: ServerStats,
+    /// What slow mode, lockdowns and chat logs go by
+    pub clock: Arc<dyn Clock>,
+    /// Optional behaviors, registered before the server starts taking connections
+    pub hooks: Hooks,
+    /// Texts that the config replaces the server's own with
+    pub catalog: Catalog,
+}
+
+impl ServerState {
+    pub fn new(config: Config, audit_log: AuditLog, clock: Arc<dyn Clock>) -> Self {
+        ServerState {
+            hostname: config.hostname.clone(),
+            users: DashMap::new(),
+            nicks: DashMap::new(),
+            channels: DashMap::new(),
+            defcon: Defcon::new(clock.clone()),
+            audit_log,
+            reserved_nicks: Mutex::new(config.reserved_nicks.clone()),
+            chat_log: config
+                .chat_log
+                .clone()
+                .map(|chat_log| ChatLog::new(chat_log, clock.clone())),
+            stats: ServerStats::new(config.stats_file.clone()),
+            catalog: Catalog::new(&config.texts),
+            config,
+            clock,
+            hooks: Hooks::default(),
+        }
+    }
+
+    /// Builds numeric replies from the server, in the texts from the config
+    pub fn replies(&self) -> Replies<'_> {
+        Replies::new(&self.hostname).with_texts(&self.catalog)
+    }
+
+    /// Server text, in the words from the config, with values put in place of their names
+    pub fn text(&self, text: &Text, values: &[(&str, &str)]) -> String {
+        self.catalog.text(text, values)
+    }
+
+    /// How many channels have anyone in them. Channels stay in the table once everyone has left.
+    pub fn channel_count(&self) -> usize {
+        self.channels
+            .iter()
+            .filter(|channel| channel.member_count() > 0)
+            .count()
+    }
+
+    /// Record a message or event in a channel's chat log, if chat logging is enabled.
+    pub fn log_channel(&self, channel: &str, message: &Message, text: &str) {
+        if let Some(chat_log) = &self.chat_log {
+            let nick = message_nick(message);
+            let command = message.command.to_string().to_uppercase();
+            chat_log.log_channel(channel, nick, &command, text);
+        }
+    }
+}
 
-#[derive(PartialEq)]
+#[derive(Debug, PartialEq)]
 enum CommandResponse {
     Continue,
     Quit,
 }
 
-pub fn handle_connection(
-    mut stream: TcpStream,
-    users: Arc<UserTable>,
-    channels: Arc<ChannelTable>,
-    hostname: &str,
-) {
+pub fn handle_connection(stream: TcpStream, state: Arc<ServerState>) {
     let address = stream
-        .local_addr()
+        .peer_addr()
         .expect("Failed to get IP address of client socket.")
         .ip();
+    let writer = stream.try_clone().expect("Failed to clone client socket.");
+    writer
+        .set_write_timeout(Some(WRITE_TIMEOUT))
+        .expect("Failed to set a write timeout on client socket.");
+    handle_stream(stream, writer, address, state);
+}
+
+/// Serve a client whose lines are read from one stream and whose replies are written to
+/// another, which are the two halves of a socket except in tests
+pub fn handle_stream(
+    mut reader: impl Read,
+    mut writer: impl Transport + 'static,
+    address: IpAddr,
+    state: Arc<ServerState>,
+) {
+    let users = &state.users;
+    let hostname = state.hostname.as_str();
+
+    // Turn away new connections while the server is in lockdown
+    if !state.defcon.allows_new_connections() {
+        let error =
+            Message::error(&state.text(&catalog::CONNECTIONS_REFUSED, &[])).with_prefix(hostname);
+        if let Ok(line) = error.to_irc() {
+            let _ = writer.write_all(line.as_bytes());
+        }
+        info!(%address, "Refused connection during lockdown");
+        return;
+    }
 
-    let user = User::new(address, stream.try_clone().unwrap());
+    let exceeded = state.text(&catalog::SENDQ_EXCEEDED, &[]);
+    let writer = Writer::new(writer, state.config.limits.sendq, &exceeded);
+    let user = User::new(address, writer.clone());
     let user_id = user.id; // Created because value is moved into users table
     users.insert(user_id, user);
-    println!(
-        "New connection from {}. {} active connections.",
-        address,
-        users.len()
-    );
-
-    loop {
-        // Wait for message from client
-        // TODO: Consider creating a buffered reader and using reader.lines() to process the string
-        // that ends with CLRF
-        let mut message_ascii = vec![0; shared::MESSAGE_SIZE];
-        stream
-            .read(&mut message_ascii)
-            .expect("Failed to read message from client.");
-
-        // Convert `message` to a String and print it out
-        let message_str = str::from_utf8(&message_ascii)
-            .expect("Client sent an invalid UTF-8 message.")
-            .replace('\0', "");
-        println!("Raw Message: {:?}", message_str);
-
-        // Extract IRC command from client input
-        let message = match Message::from(&message_str) {
-            Ok(message) => {
-                println!("Parsed Message: {:?}", message);
-                message
-            }
-            Err(err) => {
-                // TODO: Fix reply code
-                let response =
-                    Response::new(hostname, ReplyCode::ERR_UNKNOWNCOMMAND, &[&err.to_string()]);
-                send_to_user(&response, &users, user_id).expect("Failed to send message.");
-                continue;
+    state.stats.connection_opened(users.len());
+
+    // Everything logged while handling this connection is tagged with the user's ID and nickname
+    let span = info_span!("connection", id = %user_id, nick = field::Empty);
+    let _span = span.enter();
+    info!(%address, active_connections = users.len(), "New connection");
+
+    // Reads can end partway through a line or hold several, so lines are put back together here
+    let mut codec = IrcCodec::new()
+        .with_decoding(state.config.decoding)
+        .with_max_line_length(state.config.limits.line_length);
+    'connection: loop {
+        let bytes = match codec.read_from(&mut reader) {
+            Ok(0) => break, // Client closed the connection
+            Ok(bytes) => bytes,
+            Err(e) => {
+                info!("Failed to read from connection: {e}");
+                break;
             }
         };
+        METRICS.bytes_in.fetch_add(bytes as u64, Ordering::Relaxed);
+        trace!(bytes, "Received bytes");
+
+        while let Some(decoded) = codec.next_message() {
+            let message = match decoded {
+                Ok(message) => {
+                    // As JSON, so that it stays structured in the JSON log format. Only serialized
+                    // when debug logging is on.
+                    debug!(
+                        parsed = %serde_json::to_string(&message).unwrap_or_default(),
+                        "Parsed message"
+                    );
+                    message
+                }
+                Err(err) => {
+                    let nick = reply_nick(users, user_id);
+                    let result = match err {
+                        CodecError::Parse(ParseError::TooLong) => {
+                            METRICS.lines_too_long.fetch_add(1, Ordering::Relaxed);
+                            let response = state.replies().input_too_long(&nick);
+                            let prefix = user_prefix(users, user_id);
+                            let text =
+                                state.text(&catalog::SNOTICE_LINE_TOO_LONG, &[("prefix", &prefix)]);
+                            send_to_user(&response, users, user_id).and_then(|()| {
+                                send_to_snomask(Snomask::Floods, &text, users, hostname)
+                            })
+                        }
+                        // There's no numeric for a malformed line, so say what's wrong with it
+                        _ => {
+                            let notice =
+                                Message::notice(&nick, &err.to_string()).with_prefix(hostname);
+                            send_to_user(&notice, users, user_id)
+                        }
+                    };
+                    result.expect("Failed to send message.");
+                    continue;
+                }
+            };
+
+            // Unknown commands are counted together, so that clients can't add labels at will
+            let command = match &message.command {
+                Command::Unknown(_) => "UNKNOWN".to_string(),
+                command => command.to_string().to_uppercase(),
+            };
+            let started = Instant::now();
+            let result = handle_message(message, &state, user_id);
+            METRICS.observe_command(&command, started.elapsed());
+
+            match result {
+                Ok(CommandResponse::Quit) => break 'connection,
+                Ok(CommandResponse::Continue) => {}
+                Err(e) => warn!("Error handling message: {e}"),
+            }
+        }
+    }
+
+    info!("Connection closed");
 
-        match handle_message(message, &users, &channels, user_id, hostname) {
-            Ok(CommandResponse::Quit) => break,
-            Ok(CommandResponse::Continue) => {}
-            Err(e) => eprintln!("Error handling message: {e}"),
+    let is_registered = users.get(&user_id).is_some_and(|user| user.is_registered);
+    if is_registered {
+        let prefix = user_prefix(users, user_id);
+        let mut notices = vec![(Snomask::Connects, &catalog::SNOTICE_EXIT)];
+        if writer.is_exceeded() {
+            notices.push((Snomask::Floods, &catalog::SNOTICE_SENDQ_EXCEEDED));
         }
+        for (snomask, text) in notices {
+            let text = state.text(text, &[("prefix", &prefix)]);
+            let _ = send_to_snomask(snomask, &text, users, hostname);
+        }
+    }
+
+    // Take the user out of their channels while hooks can still look them up, and then remove
+    // them from the tables
+    let channels = users
+        .get_mut(&user_id)
+        .map(|mut user| mem::take(&mut user.channels))
+        .unwrap_or_default();
+    for channel in channels {
+        channel.remove_member(user_id);
+        state.hooks.on_part(&state, user_id, &channel);
+    }
+    if let Some((_, user)) = users.remove(&user_id)
+        && let Some(nickname) = user.nickname()
+    {
+        state
+            .nicks
+            .remove_if(&IrcString::from(nickname.clone()), |_, id| *id == user_id);
     }
 
-    // Remove user from the table
-    users.remove(&user_id);
+    // Let whatever was sent before the connection ended go out first
+    writer.close();
 }
 
 fn handle_message<'a>(
     mut message: Message,
-    users: &'a UserTable,
-    channels: &'a ChannelTable,
+    state: &'a ServerState,
     user_id: Uuid,
-    server_prefix: &str,
 ) -> Result<CommandResponse, Box<dyn std::error::Error + 'a>> {
+    let users = &state.users;
+    let channels = &state.channels;
+    let server_prefix = state.hostname.as_str();
+    let replies = state.replies();
+
     // Check if the user is registered
-    let is_registered = {
+    let (is_registered, is_shunned, nick) = {
         // Get a reference to the user in the table
         let user = users.get(&user_id).unwrap();
 
@@ -97,49 +308,60 @@ fn handle_message<'a>(
         // users
         message.prefix = user.prefix();
 
-        // Return it
-        user.is_registered
+        // Return it, along with the nickname replies to the user start with
+        let nick = user.nickname().cloned().unwrap_or_else(|| "*".into());
+        (user.is_registered, user.is_shunned, nick)
     };
 
+    // Shunned users stay connected, but everything except keepalives and QUIT is silently dropped
+    if is_shunned
+        && !matches!(
+            message.command,
+            Command::Ping | Command::Pong | Command::Quit
+        )
+    {
+        return Ok(CommandResponse::Continue);
+    }
+
     // In order for a user to become registered, the client has to send a NICK message with a valid
     // nickname and a USER message with their username. If all checks pass, they will receieve a
     // welcome message.
 
-    // Only allow USER, NICK, and QUIT commands if user is not registered
+    // Only allow USER, NICK, QUIT and logging in with SASL if user is not registered
     if !is_registered
         && !matches!(
             message.command,
-            Command::User | Command::Nick | Command::Quit
+            Command::User | Command::Nick | Command::Quit | Command::Cap | Command::Authenticate
         )
     {
-        let response = Response::new(
-            server_prefix,
-            ReplyCode::ERR_NOTREGISTERED,
-            &["You have not registered."],
-        );
-        send_to_user(&response, &users, user_id)?;
+        send_to_user(&replies.not_registered(&nick), users, user_id)?;
         return Ok(CommandResponse::Continue);
     }
 
-    // Perform command associated with message
-    match message.command {
-        Command::User => {
-            // Example: USER guest 0 * :Ronnie Reagan
-
-            // We will only parse the first argument (username) and ignore the rest
-            let username = match message.params.get(0) {
-                Some(name) => name.clone(),
-                None => {
-                    let response = Response::new(
-                        server_prefix,
-                        ReplyCode::ERR_NONICKNAMEGIVEN,
-                        &["No nickname was given."],
-                    );
-                    send_to_user(&response, &users, user_id)?;
+    // Hooks may rewrite or drop what users send before it's handled
+    let Some(message) = state.hooks.on_message(state, user_id, message) else {
+        return Ok(CommandResponse::Continue);
+    };
 
-                    return Ok(CommandResponse::Continue);
-                }
+    let command = match message.typed() {
+        Ok(command) => command,
+        Err(MissingParams(command)) => {
+            let response = match command {
+                Command::Nick => replies.no_nickname_given(&nick),
+                Command::PrivMsg => replies.no_recipient(&nick, "PRIVMSG"),
+                // NOTICE must never trigger an automatic reply
+                Command::Notice => return Ok(CommandResponse::Continue),
+                command => replies.need_more_params(&nick, &String::from(command)),
             };
+            send_to_user(&response, users, user_id)?;
+            return Ok(CommandResponse::Continue);
+        }
+    };
+
+    // Perform command associated with message
+    match command {
+        TypedCommand::User { username, realname } => {
+            // Example: USER guest 0 * :Ronnie Reagan
 
             // Check if user is already registered
             let is_registered = users
@@ -149,13 +371,7 @@ fn handle_message<'a>(
 
             // If the user is already registered, ignore the request and send ERR_ALREADYREGISTERED
             if is_registered {
-                let response = Response::new(
-                    server_prefix,
-                    ReplyCode::ERR_ALREADYREGISTRED,
-                    &["Cannot send USER message since the client is already registered."],
-                );
-
-                send_to_user(&response, &users, user_id)?;
+                send_to_user(&replies.already_registered(&nick), users, user_id)?;
                 return Ok(CommandResponse::Continue);
             }
 
@@ -163,400 +379,893 @@ fn handle_message<'a>(
             users
                 .get_mut(&user_id)
                 .ok_or("Unable to find user in table with given ID.")?
-                .username = Some(username);
+                .set_username(&username);
+            users
+                .get_mut(&user_id)
+                .ok_or("Unable to find user in table with given ID.")?
+                .realname = realname.unwrap_or_default();
         }
-        Command::Nick => {
+        TypedCommand::Nick { nick: nickname } => {
             // Example: NICK Wiz
 
-            // Get the first parameter in the message
-            let nickname = match message.params.get(0) {
-                Some(name) => name.clone(),
-                None => {
-                    let response = Response::new(
-                        server_prefix,
-                        ReplyCode::ERR_NONICKNAMEGIVEN,
-                        &["No nickname was given."],
-                    );
+            // Reserved nicknames can only be taken by operators
+            let reservation = state
+                .reserved_nicks
+                .lock()
+                .unwrap()
+                .iter()
+                .find(|reserved| mask::matches(&reserved.mask, &nickname))
+                .cloned();
+            if let Some(reserved) = reservation
+                && !is_operator(users, user_id)?
+            {
+                let response = replies.erroneous_nickname(
+                    &nick,
+                    &nickname,
+                    &state.text(&catalog::NICK_RESERVED, &[("reason", &reserved.reason)]),
+                );
+                send_to_user(&response, users, user_id)?;
+                return Ok(CommandResponse::Continue);
+            }
 
-                    send_to_user(&response, &users, user_id)?;
-                    return Ok(CommandResponse::Continue);
+            // Claim the nickname in the index, unless someone else has it. Users may change the
+            // case of their own. The index and the user share the name.
+            let nickname = IrcString::from(nickname);
+            let claimed = match state.nicks.entry(nickname.clone()) {
+                Entry::Occupied(entry) => *entry.get() == user_id,
+                Entry::Vacant(entry) => {
+                    entry.insert(user_id);
+                    true
                 }
             };
-
-            if nickname_in_use(&nickname, &users) {
-                let response = Response::new(
-                    server_prefix,
-                    ReplyCode::ERR_NICKNAMEINUSE,
-                    &["Nickname is already in use."],
-                );
-
-                send_to_user(&response, &users, user_id)?;
+            if !claimed {
+                send_to_user(&replies.nickname_in_use(&nick, &nickname), users, user_id)?;
                 return Ok(CommandResponse::Continue);
             }
 
             // Update nickname and get registration status
-            let is_registered = {
+            let (old_nickname, is_registered) = {
                 let mut user = users
                     .get_mut(&user_id)
                     .ok_or("Unable to find user in table with given ID.")?;
-                user.nickname = Some(nickname);
-                user.is_registered
+                Span::current().record("nick", field::display(&nickname));
+                let old_nickname = user.set_nickname(nickname.as_shared().clone());
+                (old_nickname, user.is_registered)
             }; // RefMut dropped here
 
+            // Free the old nickname, unless it's the new one in another case
+            if let Some(old_nickname) = old_nickname
+                && !IrcCasemap::default().eq_ignore_case(&old_nickname, &nickname)
+            {
+                state
+                    .nicks
+                    .remove_if(&IrcString::from(old_nickname), |_, id| *id == user_id);
+            }
+
             // Only broadcast NICK message if user is registered
             if is_registered {
-                broadcast_to_all(&message, &users)?;
+                broadcast_to_all(&message, users)?;
             }
         }
-        Command::Away => {
-            // Toggle away status and prepare response
-            let is_away = {
-                let mut user = users.get_mut(&user_id).unwrap();
-                user.is_away = !user.is_away;
-                user.is_away
-            }; // RefMut dropped here
+        TypedCommand::Away {
+            message: away_message,
+        } => {
+            // Example: AWAY :Gone to lunch
+            //          AWAY
+            let is_away = away_message.is_some();
+            let max_length = state.config.limits.away_length;
+            users.get_mut(&user_id).unwrap().away_message =
+                away_message.map(|text| text[..text.floor_char_boundary(max_length)].to_string());
 
             let response = if is_away {
-                Response::new(
-                    server_prefix,
-                    ReplyCode::RPL_NOWAWAY,
-                    &["You are now away."],
-                )
+                replies.now_away(&nick)
             } else {
-                Response::new(
-                    server_prefix,
-                    ReplyCode::RPL_UNAWAY,
-                    &["You are no longer away."],
-                )
+                replies.unaway(&nick)
             };
 
-            send_to_user(&response, &users, user_id)?;
+            send_to_user(&response, users, user_id)?;
         }
-        Command::PrivMsg => {
-            // TODO: Do not allow messaging channels if user has not joined it
+        TypedCommand::PrivMsg { targets, text } | TypedCommand::Notice { targets, text } => {
             // Example: PRIVMSG user :Hello there!
-            //          PRIVMSG #channel :Hello there!
-            if message.params.len() != 2 {
-                let response = Response::new(
-                    server_prefix,
-                    ReplyCode::ERR_NORECIPIENT,
-                    &["No recipient for the message was given."],
-                );
-                send_to_user(&response, &users, user_id)?;
-                return Ok(CommandResponse::Continue);
+            //          PRIVMSG #channel,alice :Hello there!
+            //
+            // NOTICE is delivered the same way, but must never trigger an automatic reply, so
+            // errors are silently dropped instead of being sent back
+            let is_notice = matches!(message.command, Command::Notice);
+            let sender_is_operator = is_operator(users, user_id)?;
+            if !is_notice {
+                users
+                    .get_mut(&user_id)
+                    .ok_or("Unable to find user in table with given ID.")?
+                    .last_active = state.clock.now();
             }
 
-            let recipient = message.params.get(0).unwrap().clone();
-
-            // It's not a channel
-            if !recipient.starts_with("#") {
-                if let Some(nickname_id) = get_nickname_id(&recipient, &users) {
-                    let is_away = users
-                        .get(&nickname_id)
-                        .ok_or("Unable to find user in table with given ID")?
-                        .is_away;
-                    if is_away {
-                        let response = Response::new(
-                            server_prefix,
-                            ReplyCode::RPL_AWAY,
-                            &[&recipient, "The recipient is marked as away."],
-                        );
-                        send_to_user(&response, &users, user_id)?;
+            for target in targets {
+                // Each recipient sees the message as sent to them alone
+                let recipient = target.name();
+                let message = Message {
+                    prefix: message.prefix.clone(),
+                    ..Message::builder()
+                        .command(message.command.clone())
+                        .param(recipient)
+                        .trailing(&text)
+                        .build()
+                };
+
+                // It's not a channel
+                if let Target::Nick(_) = target {
+                    if !state.defcon.allows_private_speech() && !sender_is_operator {
+                        if !is_notice {
+                            let response = replies.cannot_send_to_chan(
+                                &nick,
+                                recipient,
+                                &state.text(&catalog::LOCKDOWN_PRIVATE_MESSAGES, &[]),
+                            );
+                            send_to_user(&response, users, user_id)?;
+                        }
+                        continue;
                     }
 
-                    send_to_user(&message, &users, nickname_id)?;
+                    if let Some(nickname_id) = get_nickname_id(recipient, &state.nicks) {
+                        let away_message = users
+                            .get(&nickname_id)
+                            .ok_or("Unable to find user in table with given ID")?
+                            .away_message
+                            .clone();
+                        if let Some(away_message) = away_message
+                            && !is_notice
+                        {
+                            let response = replies.away(&nick, recipient, &away_message);
+                            send_to_user(&response, users, user_id)?;
+                        }
+
+                        let Some(message) = state.hooks.pre_send(state, user_id, message) else {
+                            continue;
+                        };
+                        send_to_user(&message, users, nickname_id)?;
+                        if let Some(chat_log) = &state.chat_log {
+                            let command = message.command.to_string().to_uppercase();
+                            chat_log.log_private(
+                                message_nick(&message),
+                                recipient,
+                                &command,
+                                message_text(&message),
+                            );
+                        }
+                    } else if !is_notice {
+                        send_to_user(&replies.no_such_nick(&nick, recipient), users, user_id)?;
+                    }
                 } else {
-                    let response = Response::new(
-                        server_prefix,
-                        ReplyCode::ERR_NOSUCHNICK,
-                        &["The given nick was not found."],
-                    );
-                    send_to_user(&response, &users, user_id)?;
-                }
-            } else {
-                let channel = match channels.get(&recipient) {
-                    Some(c) => c,
-                    None => {
-                        let response = Response::new(
-                            server_prefix,
-                            ReplyCode::ERR_NOSUCHCHANNEL,
-                            &["The given channel was not found."],
-                        );
-                        send_to_user(&response, &users, user_id)?;
-                        return Ok(CommandResponse::Continue);
+                    let channel = match channels.get(&IrcString::from(recipient)) {
+                        Some(c) => c.clone(),
+                        None => {
+                            if !is_notice {
+                                let response = replies.no_such_channel(&nick, recipient);
+                                send_to_user(&response, users, user_id)?;
+                            }
+                            continue;
+                        }
+                    };
+
+                    let in_channel = users
+                        .get(&user_id)
+                        .ok_or("Unable to find user in table with given ID.")?
+                        .is_in(&channel);
+
+                    if !in_channel {
+                        if !is_notice {
+                            let response = replies.cannot_send_to_chan(
+                                &nick,
+                                recipient,
+                                &state.text(&catalog::NOT_IN_CHANNEL, &[]),
+                            );
+                            send_to_user(&response, users, user_id)?;
+                        }
+                        continue;
                     }
-                };
 
-                let in_channel = users
-                    .get(&user_id)
-                    .ok_or("Unable to find user in table with given ID.")?
-                    .channel
-                    .as_ref()
-                    .map_or(false, |c| c.name == recipient);
-
-                if !in_channel {
-                    let response = Response::new(
-                        server_prefix,
-                        ReplyCode::ERR_CANNOTSENDTOCHAN,
-                        &["You are not in that channel."],
-                    );
-                    send_to_user(&response, &users, user_id)?;
-                    return Ok(CommandResponse::Continue);
-                }
+                    let locked_down = !state.defcon.allows_channel_speech()
+                        && !sender_is_operator
+                        && !channel.is_operator(user_id);
+                    if locked_down {
+                        if !is_notice {
+                            let response = replies.cannot_send_to_chan(
+                                &nick,
+                                recipient,
+                                &state.text(&catalog::LOCKDOWN_MODERATED, &[]),
+                            );
+                            send_to_user(&response, users, user_id)?;
+                        }
+                        continue;
+                    }
+
+                    // Quieted members stay in the channel but cannot speak in it
+                    let is_quieted = !channel.is_operator(user_id)
+                        && message
+                            .prefix
+                            .as_ref()
+                            .is_some_and(|prefix| channel.is_quieted(prefix));
+                    if is_quieted {
+                        if !is_notice {
+                            let response = replies.cannot_send_to_chan(
+                                &nick,
+                                recipient,
+                                &state.text(&catalog::QUIETED, &[]),
+                            );
+                            send_to_user(&response, users, user_id)?;
+                        }
+                        continue;
+                    }
+
+                    if let Some(wait) = channel.slowmode_wait(user_id, state.clock.now()) {
+                        if !is_notice {
+                            let seconds = wait.as_secs_f64().ceil().to_string();
+                            let text = state.text(
+                                &catalog::SLOW_MODE,
+                                &[("channel", recipient), ("seconds", &seconds)],
+                            );
+                            let notice = Message::notice(&nick, &text).with_prefix(server_prefix);
+                            send_to_user(&notice, users, user_id)?;
+                        }
+                        continue;
+                    }
 
-                send_to_channel(&message, &users, channel.value(), user_id)?;
+                    let Some(message) = state.hooks.pre_send(state, user_id, message) else {
+                        continue;
+                    };
+                    send_to_channel(&message, users, &channel, user_id)?;
+                    // Under the channel's own name, however the sender wrote it
+                    state.log_channel(&channel.name, &message, message_text(&message));
+                }
             }
         }
-        Command::Quit => {
-            let acknowledgement_response = Message::new(
-                Some(server_prefix.to_string()),
-                Command::Error,
-                &["User disconnected."],
-            );
-            send_to_user(&acknowledgement_response, &users, user_id)?;
+        TypedCommand::Quit { reason } => {
+            let acknowledgement_response =
+                Message::error(&state.text(&catalog::DISCONNECTED, &[])).with_prefix(server_prefix);
+            send_to_user(&acknowledgement_response, users, user_id)?;
 
             // If the user is registered, tell everyone else that the user has left.
             // TODO: ONLY broadcast to users in the same channel(s) as the user
-            let is_registered = users
-                .get(&user_id)
-                .ok_or("Unable to find user in table with given ID.")?
-                .is_registered;
+            let (is_registered, channels) = {
+                let user = users
+                    .get(&user_id)
+                    .ok_or("Unable to find user in table with given ID.")?;
+                (user.is_registered, user.channels.clone())
+            };
             if is_registered {
-                broadcast_message(&message, &users, user_id)?;
+                broadcast_message(&message, users, user_id)?;
+            }
+            for channel in channels {
+                state.log_channel(
+                    &channel.name,
+                    &message,
+                    reason.as_deref().unwrap_or_default(),
+                );
             }
 
             return Ok(CommandResponse::Quit);
         }
-        Command::Unknown => {
-            let response = Response::new(
-                server_prefix,
-                ReplyCode::ERR_UNKNOWNCOMMAND,
-                &["Unknown command."],
-            );
-            send_to_user(&response, &users, user_id)?;
-        }
-        Command::Join => {
-            let channel_name = match message.params.get(0) {
-                Some(name) => name.clone(),
-                None => {
-                    let response = Response::new(
-                        server_prefix,
-                        ReplyCode::ERR_NEEDMOREPARAMS,
-                        &["Specify which channel to join."],
+        TypedCommand::Join {
+            channels: channel_names,
+            ..
+        } => {
+            // Example: JOIN #rust,#irc_rs
+            for channel_name in channel_names {
+                let message = Message {
+                    prefix: message.prefix.clone(),
+                    ..Message::join(&channel_name)
+                };
+
+                if !Channel::is_channel_name(&channel_name) {
+                    send_to_user(
+                        &replies.no_such_channel(&nick, &channel_name),
+                        users,
+                        user_id,
+                    )?;
+                    continue;
+                }
+
+                if !Channel::is_valid_name(&channel_name) {
+                    send_to_user(&replies.bad_chan_mask(&nick, &channel_name), users, user_id)?;
+                    continue;
+                }
+
+                if !channels.contains_key(&IrcString::from(channel_name.as_str()))
+                    && !state.defcon.allows_new_channels()
+                    && !is_operator(users, user_id)?
+                {
+                    let response = replies.unavail_resource(
+                        &nick,
+                        &channel_name,
+                        &state.text(&catalog::LOCKDOWN_NEW_CHANNELS, &[]),
                     );
-                    send_to_user(&response, &users, user_id)?;
-                    return Ok(CommandResponse::Continue);
+                    send_to_user(&response, users, user_id)?;
+                    continue;
+                }
+
+                // Joining a channel again does nothing, and operators may join any number
+                let (already_joined, joined_count, is_logged_in) = {
+                    let user = users
+                        .get(&user_id)
+                        .ok_or("Unable to find user in table with given ID.")?;
+                    let already_joined = user.channels.iter().any(|c| c.name == channel_name);
+                    (already_joined, user.channels.len(), user.account.is_some())
+                };
+                if already_joined {
+                    continue;
+                }
+                if joined_count >= state.config.limits.channels && !is_operator(users, user_id)? {
+                    let response = replies.too_many_channels(&nick, &channel_name);
+                    send_to_user(&response, users, user_id)?;
+                    continue;
+                }
+
+                // Registered-only channels turn away users who aren't logged in, except operators
+                let registered_only = channels
+                    .get(&IrcString::from(channel_name.as_str()))
+                    .is_some_and(|channel| channel.modes.lock().unwrap().registered_only);
+                if registered_only && !is_logged_in && !is_operator(users, user_id)? {
+                    let response = replies.need_regged_nick(&nick, &channel_name);
+                    send_to_user(&response, users, user_id)?;
+                    continue;
                 }
-            };
 
-            // Get a reference to the channel if it is in the channels table, otherwise create it
-            let channel = channels
-                .entry(channel_name.clone())
-                .or_insert(Arc::new(Channel::new(&channel_name)))
-                .clone();
+                // Get a reference to the channel if it is in the channels table, otherwise create it
+                // The table and the channel share its name
+                let name = IrcString::from(channel_name.as_str());
+                let channel = channels
+                    .entry(name.clone())
+                    .or_insert_with(|| Arc::new(Channel::new(name)))
+                    .clone();
 
-            // Set the user's channel to the channel from the table
-            users
-                .get_mut(&user_id)
-                .ok_or("Unable to find user in table with given ID.")?
-                .channel = Some(channel.clone());
+                users
+                    .get_mut(&user_id)
+                    .ok_or("Unable to find user in table with given ID.")?
+                    .channels
+                    .push(channel.clone());
+
+                // Whoever joins an empty channel becomes its operator
+                if channel.add_member(user_id) {
+                    channel.set_operator(user_id, true);
+                }
 
-            // Broadcast to all users in the channel
-            send_to_channel(&message, &users, &channel, user_id)?;
+                // Broadcast to all users in the channel
+                send_to_channel(&message, users, &channel, user_id)?;
+                state.log_channel(&channel.name, &message, "");
+                state.hooks.on_join(state, user_id, &channel);
+            }
         }
-        Command::Part => {
-            let channel_name = match message.params.get(0) {
-                Some(name) => name.clone(),
-                None => {
-                    let response = Response::new(
-                        server_prefix,
-                        ReplyCode::ERR_NEEDMOREPARAMS,
-                        &["Specify which channel to leave."],
-                    );
-                    send_to_user(&response, &users, user_id)?;
+        TypedCommand::Part {
+            channels: channel_names,
+            reason,
+        } => {
+            // Example: PART #rust,#irc_rs :Goodbye
+            for channel_name in channel_names {
+                let message = Message {
+                    prefix: message.prefix.clone(),
+                    ..Message::part(&channel_name, reason.as_deref())
+                };
+
+                // Look up channel and check user is actually in it
+                let channel = match channels.get(&IrcString::from(channel_name.as_str())) {
+                    Some(c) => c.clone(),
+                    None => {
+                        let response = replies.no_such_channel(&nick, &channel_name);
+                        send_to_user(&response, users, user_id)?;
+                        continue;
+                    }
+                };
+
+                // Remove user from channel
+                let was_in_channel = users
+                    .get_mut(&user_id)
+                    .ok_or("Unable to find user in table with given ID.")?
+                    .leave(&channel);
+
+                if !was_in_channel {
+                    send_to_user(
+                        &replies.not_on_channel(&nick, &channel_name),
+                        users,
+                        user_id,
+                    )?;
+                    continue;
+                }
+                channel.remove_member(user_id);
+
+                // Broadcast to channel after removing user
+                send_to_channel(&message, users, &channel, user_id)?;
+                state.log_channel(&channel.name, &message, "");
+                state.hooks.on_part(state, user_id, &channel);
+            }
+        }
+        TypedCommand::Kick {
+            channel: channel_name,
+            nicks,
+            reason,
+        } => {
+            // Example: KICK #general bob,carol :Using profanity
+            for target_user in nicks {
+                let message = Message {
+                    prefix: message.prefix.clone(),
+                    ..Message::kick(&channel_name, &target_user, reason.as_deref())
+                };
+
+                // Verify channel exists
+                let channel = match channels.get(&IrcString::from(channel_name.as_str())) {
+                    Some(c) => c.clone(),
+                    None => {
+                        let response = replies.no_such_channel(&nick, &channel_name);
+                        send_to_user(&response, users, user_id)?;
+                        continue;
+                    }
+                };
+
+                // Check if kicker is in the channel
+                let kicker_in_channel = users
+                    .get(&user_id)
+                    .ok_or("Unable to find user in table with given ID.")?
+                    .is_in(&channel);
+
+                if !kicker_in_channel {
+                    send_to_user(
+                        &replies.not_on_channel(&nick, &channel_name),
+                        users,
+                        user_id,
+                    )?;
+                    continue;
+                }
+
+                // Find target user ID
+                let target_id = match get_nickname_id(&target_user, &state.nicks) {
+                    Some(id) => id,
+                    None => {
+                        let response = replies.no_such_nick(&nick, &target_user);
+                        send_to_user(&response, users, user_id)?;
+                        continue;
+                    }
+                };
+
+                // Check target is in the channel
+                let target_in_channel = users
+                    .get(&target_id)
+                    .ok_or("Unable to find target user in table with given ID.")?
+                    .is_in(&channel);
+
+                if !target_in_channel {
+                    let response = replies.user_not_in_channel(&nick, &target_user, &channel_name);
+                    send_to_user(&response, users, user_id)?;
+                    continue;
+                }
+
+                // Broadcast KICK to channel
+                send_to_channel(&message, users, &channel, user_id)?;
+                let text = match reason.as_deref().filter(|reason| !reason.is_empty()) {
+                    Some(reason) => format!("{target_user} ({reason})"),
+                    None => target_user.clone(),
+                };
+                state.log_channel(&channel.name, &message, &text);
+
+                // Remove target from channel
+                users
+                    .get_mut(&target_id)
+                    .ok_or("Unable to find target user in table with given ID.")?
+                    .leave(&channel);
+                channel.remove_member(target_id);
+                state.hooks.on_part(state, target_id, &channel);
+            }
+        }
+        TypedCommand::Ping { .. } => {
+            // Ignore any parameters and send back a PONG message
+            let response = Message::pong(server_prefix).with_prefix(server_prefix);
+            send_to_user(&response, users, user_id)?;
+        }
+        TypedCommand::Mode { target, .. } => {
+            // Example: MODE #announcements +W 30
+            //          MODE alice +p
+            let target = match target {
+                Target::Channel(target) => target,
+                Target::Nick(target) => {
+                    handle_user_mode(&message, state, &target, user_id)?;
                     return Ok(CommandResponse::Continue);
                 }
             };
 
-            // Look up channel and check user is actually in it
-            let channel = match channels.get(&channel_name) {
+            let channel = match channels.get(&IrcString::from(target.as_str())) {
                 Some(c) => c.clone(),
                 None => {
-                    let response = Response::new(
-                        server_prefix,
-                        ReplyCode::ERR_NOSUCHCHANNEL,
-                        &["The given channel was not found."],
-                    );
-                    send_to_user(&response, &users, user_id)?;
+                    send_to_user(&replies.no_such_channel(&nick, &target), users, user_id)?;
                     return Ok(CommandResponse::Continue);
                 }
             };
 
-            let in_channel = users
-                .get(&user_id)
-                .ok_or("Unable to find user in table with given ID.")?
-                .channel
-                .as_ref()
-                .map(|c| c.name == channel_name)
-                .unwrap_or(false);
-
-            if !in_channel {
-                let response = Response::new(
-                    server_prefix,
-                    ReplyCode::ERR_NOTONCHANNEL,
-                    &["You are not in that channel."],
+            handle_channel_mode(&message, state, &channel, user_id)?;
+        }
+        TypedCommand::Oper { name, password } => {
+            // Example: OPER admin hunter2
+            let prefix = message_prefix(&message);
+            if !state.config.check_oper(&name, &password) {
+                state
+                    .audit_log
+                    .record(&prefix, "OPER", &name, "Failed: password incorrect");
+                send_to_user(&replies.passwd_mismatch(&nick), users, user_id)?;
+                let text = state.text(
+                    &catalog::SNOTICE_OPER_FAILED,
+                    &[("prefix", &prefix), ("name", &name)],
                 );
-                send_to_user(&response, &users, user_id)?;
+                send_to_snomask(Snomask::Opers, &text, users, server_prefix)?;
                 return Ok(CommandResponse::Continue);
             }
 
-            // Remove user from channel
             users
                 .get_mut(&user_id)
                 .ok_or("Unable to find user in table with given ID.")?
-                .channel = None;
+                .is_operator = true;
+            state.audit_log.record(&prefix, "OPER", &name, "");
 
-            // Broadcast to channel after removing user
-            send_to_channel(&message, &users, &channel, user_id)?;
+            send_to_user(&replies.youre_oper(&nick), users, user_id)?;
+            let text = state.text(
+                &catalog::SNOTICE_OPER,
+                &[("prefix", &prefix), ("name", &name)],
+            );
+            send_to_snomask(Snomask::Opers, &text, users, server_prefix)?;
         }
-        Command::Kick => {
-            // Example: KICK #general bob :Using profanity
-            let channel_name = match message.params.get(0) {
-                Some(name) => name.clone(),
-                None => {
-                    let response = Response::new(
-                        server_prefix,
-                        ReplyCode::ERR_NEEDMOREPARAMS,
-                        &["Specify a channel and user to kick."],
+        TypedCommand::Whois { nick: target } => send_whois(state, user_id, &target)?,
+        TypedCommand::Cap { subcommand, args } => handle_cap(state, user_id, &subcommand, &args)?,
+        TypedCommand::Authenticate { data } => {
+            handle_authenticate(state, user_id, &data)?;
+            let failures = users.get(&user_id).map_or(0, |user| user.sasl_failures);
+            if failures > 0 && failures >= state.config.limits.sasl_failures {
+                let error = Message::error(&state.text(&catalog::SASL_FAILURES_EXCEEDED, &[]))
+                    .with_prefix(server_prefix);
+                send_to_user(&error, users, user_id)?;
+                return Ok(CommandResponse::Quit);
+            }
+        }
+        // Commands without typed parameters, and ones the server doesn't support
+        TypedCommand::Topic { .. } | TypedCommand::Other => {
+            match message.command {
+                Command::Lusers => send_lusers(state, user_id)?,
+                Command::List => {
+                    // Send one RPL_LIST per channel, then RPL_LISTEND
+                    for channel in channels.iter() {
+                        let user_count = channel.member_count();
+                        let response = replies.list(&nick, &channel.name, user_count, "");
+                        send_to_user(&response, users, user_id)?;
+                    }
+
+                    // At the end, send RPL_LISTEND
+                    send_to_user(&replies.list_end(&nick), users, user_id)?;
+                }
+                Command::Shun | Command::Unshun => {
+                    // Example: SHUN spambot :Flooding #help
+                    //          UNSHUN spambot
+                    let shun = matches!(message.command, Command::Shun);
+
+                    if !is_operator(users, user_id)? {
+                        send_to_user(&replies.no_privileges(&nick), users, user_id)?;
+                        return Ok(CommandResponse::Continue);
+                    }
+
+                    let nickname = match message.params.first() {
+                        Some(name) => name.clone(),
+                        None => {
+                            let command = String::from(message.command.clone());
+                            send_to_user(
+                                &replies.need_more_params(&nick, &command),
+                                users,
+                                user_id,
+                            )?;
+                            return Ok(CommandResponse::Continue);
+                        }
+                    };
+
+                    let target_id = match get_nickname_id(&nickname, &state.nicks) {
+                        Some(id) => id,
+                        None => {
+                            send_to_user(&replies.no_such_nick(&nick, &nickname), users, user_id)?;
+                            return Ok(CommandResponse::Continue);
+                        }
+                    };
+
+                    users
+                        .get_mut(&target_id)
+                        .ok_or("Unable to find target user in table with given ID.")?
+                        .is_shunned = shun;
+                    state.audit_log.record(
+                        &message_prefix(&message),
+                        if shun { "SHUN" } else { "UNSHUN" },
+                        &nickname,
+                        message.params.get(1).map_or("", String::as_str),
                     );
-                    send_to_user(&response, &users, user_id)?;
-                    return Ok(CommandResponse::Continue);
+
+                    // The target is not told about the shun, only the operator gets a confirmation
+                    let text = if shun {
+                        &catalog::SHUNNED
+                    } else {
+                        &catalog::UNSHUNNED
+                    };
+                    let text = state.text(text, &[("nick", &nickname)]);
+                    let notice = Message::notice(&nick, &text).with_prefix(server_prefix);
+                    send_to_user(&notice, users, user_id)?;
                 }
-            };
+                Command::Search => {
+                    // Example: SEARCH #rust from=alice after=2024-01-01 :text=borrow checker
+                    //          SEARCH * limit=10
+                    if !is_operator(users, user_id)? {
+                        send_to_user(&replies.no_privileges(&nick), users, user_id)?;
+                        return Ok(CommandResponse::Continue);
+                    }
 
-            let target_user = match message.params.get(1) {
-                Some(user) => user.clone(),
-                None => {
-                    let response = Response::new(
-                        server_prefix,
-                        ReplyCode::ERR_NEEDMOREPARAMS,
-                        &["Specify a user to kick."],
+                    let Some(target) = message.params.first() else {
+                        send_to_user(&replies.need_more_params(&nick, "SEARCH"), users, user_id)?;
+                        return Ok(CommandResponse::Continue);
+                    };
+
+                    let notify = |text: &str| {
+                        let notice = Message::notice(&nick, text).with_prefix(server_prefix);
+                        send_to_user(&notice, users, user_id)
+                    };
+
+                    let Some(chat_log) = &state.chat_log else {
+                        notify(&state.text(&catalog::SEARCH_DISABLED, &[]))?;
+                        return Ok(CommandResponse::Continue);
+                    };
+
+                    // Filters are given as key=value parameters
+                    let pairs = message.params[1..]
+                        .iter()
+                        .map(|param| param.split_once('=').unwrap_or((param, "")));
+                    // Channels are logged under their own names, which may be in another case
+                    let target = channels
+                        .get(&IrcString::from(target.as_str()))
+                        .map_or_else(|| target.clone(), |channel| channel.name.to_string());
+                    let query = SearchQuery::from_pairs(
+                        [("target", target.as_str())].into_iter().chain(pairs),
                     );
-                    send_to_user(&response, &users, user_id)?;
-                    return Ok(CommandResponse::Continue);
+                    let query = match query {
+                        Ok(query) => query,
+                        Err(e) => {
+                            notify(&e)?;
+                            return Ok(CommandResponse::Continue);
+                        }
+                    };
+
+                    state.audit_log.record(
+                        &message_prefix(&message),
+                        "SEARCH",
+                        &target,
+                        &message.params[1..].join(" "),
+                    );
+
+                    match chat_log.search(&query) {
+                        Ok(results) => {
+                            for result in &results {
+                                let time = result.time.format("%Y-%m-%d %H:%M:%S");
+                                let (target, nick, text) =
+                                    (&result.target, &result.nick, &result.text);
+                                notify(&match result.command.as_str() {
+                                    "PRIVMSG" => format!("[{time}] {target} <{nick}> {text}"),
+                                    "NOTICE" => format!("[{time}] {target} -{nick}- {text}"),
+                                    command => format!("[{time}] {target} {nick} {command} {text}"),
+                                })?;
+                            }
+                            let count = results.len().to_string();
+                            notify(&state.text(&catalog::SEARCH_END, &[("count", &count)]))?;
+                        }
+                        Err(e) => {
+                            warn!("Failed to search chat logs: {e}");
+                            let error = e.to_string();
+                            notify(&state.text(&catalog::SEARCH_FAILED, &[("error", &error)]))?;
+                        }
+                    }
                 }
-            };
+                Command::Defcon => {
+                    // Example: DEFCON 3 600
+                    //          DEFCON
+                    if !is_operator(users, user_id)? {
+                        send_to_user(&replies.no_privileges(&nick), users, user_id)?;
+                        return Ok(CommandResponse::Continue);
+                    }
 
-            // Verify channel exists
-            let channel = match channels.get(&channel_name) {
-                Some(c) => c.clone(),
-                None => {
-                    let response = Response::new(
-                        server_prefix,
-                        ReplyCode::ERR_NOSUCHCHANNEL,
-                        &["The given channel was not found."],
+                    // Without parameters, report the current level
+                    let Some(level) = message.params.first() else {
+                        let level = state.defcon.level().to_string();
+                        let status = match state.defcon.remaining() {
+                            Some(remaining) => state.text(
+                                &catalog::DEFCON_REVERTING,
+                                &[
+                                    ("level", &level),
+                                    ("seconds", &remaining.as_secs().to_string()),
+                                ],
+                            ),
+                            None => state.text(&catalog::DEFCON_STATUS, &[("level", &level)]),
+                        };
+                        let notice = Message::notice(&nick, &status).with_prefix(server_prefix);
+                        send_to_user(&notice, users, user_id)?;
+                        return Ok(CommandResponse::Continue);
+                    };
+
+                    let level = level
+                        .parse::<u8>()
+                        .ok()
+                        .filter(|l| (1..=defcon::NORMAL).contains(l));
+                    let duration = match message.params.get(1) {
+                        Some(seconds) => seconds
+                            .parse::<u64>()
+                            .ok()
+                            .map(|s| Some(Duration::from_secs(s))),
+                        None => Some(None),
+                    };
+                    let (Some(level), Some(duration)) = (level, duration) else {
+                        let usage = state.text(&catalog::DEFCON_USAGE, &[]);
+                        let notice = Message::notice(&nick, &usage).with_prefix(server_prefix);
+                        send_to_user(&notice, users, user_id)?;
+                        return Ok(CommandResponse::Continue);
+                    };
+
+                    state.defcon.set(level, duration);
+                    warn!(level, ?duration, "DEFCON level changed");
+                    state.audit_log.record(
+                        &message_prefix(&message),
+                        "DEFCON",
+                        &level.to_string(),
+                        &duration.map_or(String::new(), |d| format!("{} second(s)", d.as_secs())),
                     );
-                    send_to_user(&response, &users, user_id)?;
-                    return Ok(CommandResponse::Continue);
+
+                    // Let every operator know the server's state changed
+                    let level_text = level.to_string();
+                    let status = match duration {
+                        Some(duration) if level != defcon::NORMAL => state.text(
+                            &catalog::DEFCON_SET_FOR,
+                            &[
+                                ("nick", &nick),
+                                ("level", &level_text),
+                                ("seconds", &duration.as_secs().to_string()),
+                            ],
+                        ),
+                        _ => state.text(
+                            &catalog::DEFCON_SET,
+                            &[("nick", &nick), ("level", &level_text)],
+                        ),
+                    };
+                    send_to_operators(&status, users, server_prefix)?;
                 }
-            };
+                Command::Qline | Command::Unqline => {
+                    // Example: QLINE *Serv :Reserved for services
+                    //          UNQLINE *Serv
+                    let add = matches!(message.command, Command::Qline);
 
-            // Check if kicker is in the channel
-            let kicker_in_channel = users
-                .get(&user_id)
-                .ok_or("Unable to find user in table with given ID.")?
-                .channel
-                .as_ref()
-                .map_or(false, |c| c.name == channel_name);
-
-            if !kicker_in_channel {
-                let response = Response::new(
-                    server_prefix,
-                    ReplyCode::ERR_NOTONCHANNEL,
-                    &["You are not in that channel."],
-                );
-                send_to_user(&response, &users, user_id)?;
-                return Ok(CommandResponse::Continue);
-            }
+                    if !is_operator(users, user_id)? {
+                        send_to_user(&replies.no_privileges(&nick), users, user_id)?;
+                        return Ok(CommandResponse::Continue);
+                    }
 
-            // Find target user ID
-            let target_id = match get_nickname_id(&target_user, &users) {
-                Some(id) => id,
-                None => {
-                    let response = Response::new(
-                        server_prefix,
-                        ReplyCode::ERR_NOSUCHNICK,
-                        &["The given user was not found."],
-                    );
-                    send_to_user(&response, &users, user_id)?;
-                    return Ok(CommandResponse::Continue);
+                    let Some(mask) = message.params.first().cloned() else {
+                        let command = String::from(message.command.clone());
+                        send_to_user(&replies.need_more_params(&nick, &command), users, user_id)?;
+                        return Ok(CommandResponse::Continue);
+                    };
+                    let reason = message
+                        .params
+                        .get(1)
+                        .cloned()
+                        .unwrap_or_else(|| state.text(&catalog::QLINE_REASON, &[]));
+
+                    let changed = {
+                        let mut reserved_nicks = state.reserved_nicks.lock().unwrap();
+                        let existing = reserved_nicks
+                            .iter()
+                            .position(|reserved| reserved.mask.eq_ignore_ascii_case(&mask));
+                        match (add, existing) {
+                            (true, None) => {
+                                reserved_nicks.push(ReservedNick {
+                                    mask: mask.clone(),
+                                    reason: reason.clone(),
+                                });
+                                true
+                            }
+                            (false, Some(index)) => {
+                                reserved_nicks.remove(index);
+                                true
+                            }
+                            _ => false,
+                        }
+                    };
+
+                    let status = match (add, changed) {
+                        (true, true) => &catalog::QLINE_ADDED,
+                        (true, false) => &catalog::QLINE_EXISTS,
+                        (false, true) => &catalog::QLINE_REMOVED,
+                        (false, false) => &catalog::QLINE_MISSING,
+                    };
+                    let status = state.text(status, &[("mask", &mask)]);
+                    if changed {
+                        state.audit_log.record(
+                            &message_prefix(&message),
+                            if add { "QLINE" } else { "UNQLINE" },
+                            &mask,
+                            if add { &reason } else { "" },
+                        );
+                    }
+
+                    let oper_nickname = users
+                        .get(&user_id)
+                        .ok_or("Unable to find user in table with given ID.")?
+                        .nickname()
+                        .cloned()
+                        .unwrap_or_default();
+                    let notice =
+                        Message::notice(&oper_nickname, &status).with_prefix(server_prefix);
+                    send_to_user(&notice, users, user_id)?;
                 }
-            };
+                Command::Stats => {
+                    // Example: STATS a
+                    let letter = message.params.first().cloned().unwrap_or_default();
 
-            // Check target is in the channel
-            let target_in_channel = users
-                .get(&target_id)
-                .ok_or("Unable to find target user in table with given ID.")?
-                .channel
-                .as_ref()
-                .map_or(false, |c| c.name == channel_name);
-
-            if !target_in_channel {
-                let response = Response::new(
-                    server_prefix,
-                    ReplyCode::ERR_USERNOTINCHANNEL,
-                    &["That user is not in the channel."],
-                );
-                send_to_user(&response, &users, user_id)?;
-                return Ok(CommandResponse::Continue);
-            }
+                    // Anyone may see the uptime, everything else is for operators
+                    if letter != "u" && !is_operator(users, user_id)? {
+                        send_to_user(&replies.no_privileges(&nick), users, user_id)?;
+                        return Ok(CommandResponse::Continue);
+                    }
 
-            // Broadcast KICK to channel
-            send_to_channel(&message, &users, &channel, user_id)?;
+                    match letter.as_str() {
+                        // a: Recent entries of the operator audit log
+                        "a" => {
+                            for entry in state.audit_log.recent() {
+                                let mut line = format!(
+                                    "{} {} {} {}",
+                                    entry.time.format("%Y-%m-%d %H:%M:%S"),
+                                    entry.oper,
+                                    entry.action,
+                                    entry.target
+                                );
+                                if !entry.reason.is_empty() {
+                                    line.push_str(&format!(" ({})", entry.reason));
+                                }
+                                send_to_user(
+                                    &replies.stats_debug(&nick, 'a', &line),
+                                    users,
+                                    user_id,
+                                )?;
+                            }
+                        }
+                        // q: Reserved nicknames
+                        "q" => {
+                            let reserved_nicks = state.reserved_nicks.lock().unwrap().clone();
+                            for reserved in reserved_nicks {
+                                let response =
+                                    replies.stats_qline(&nick, &reserved.mask, &reserved.reason);
+                                send_to_user(&response, users, user_id)?;
+                            }
+                        }
+                        // u: Uptime and connection counts
+                        "u" => {
+                            let response = replies.stats_uptime(&nick, state.stats.uptime());
+                            send_to_user(&response, users, user_id)?;
+
+                            let connections = state.text(
+                                &catalog::STATS_CONNECTIONS,
+                                &[
+                                    ("peak", &state.stats.all_time_peak().connections.to_string()),
+                                    ("startup_peak", &state.stats.peak_connections().to_string()),
+                                    ("total", &state.stats.total_connections().to_string()),
+                                ],
+                            );
+                            send_to_user(&replies.stats_conn(&nick, &connections), users, user_id)?;
+                        }
+                        _ => {}
+                    }
 
-            // Remove target from channel
-            users
-                .get_mut(&target_id)
-                .ok_or("Unable to find target user in table with given ID.")?
-                .channel = None;
-        }
-        Command::List => {
-            // Send one RPL_LIST per channel, then RPL_LISTEND
-            for entry in channels.iter() {
-                let channel = entry.value();
-                let user_count = users
-                    .iter()
-                    .filter(|user| {
-                        user.channel // It really isn't necessary to call value() first as done above
-                            .as_ref()
-                            .map_or(false, |c| c.name == channel.name)
-                    })
-                    .count();
-
-                // Send RPL_LIST for this channel
-                let response = Response::new(
-                    server_prefix,
-                    ReplyCode::RPL_LIST,
-                    &[&channel.name, &user_count.to_string()],
-                );
-                send_to_user(&response, &users, user_id)?;
+                    send_to_user(&replies.end_of_stats(&nick, &letter), users, user_id)?;
+                }
+                Command::Error => {}
+                command => {
+                    let response = replies.unknown_command(&nick, &String::from(command.clone()));
+                    send_to_user(&response, users, user_id)?;
+                }
             }
-
-            // At the end, send RPL_LISTEND
-            let response = Response::new(server_prefix, ReplyCode::RPL_LISTEND, &["End of LIST"]);
-            send_to_user(&response, &users, user_id)?;
-        }
-        Command::Ping => {
-            // Ignore any parameters and send back a PONG message
-            let response = Message::new(
-                Some(server_prefix.to_string()),
-                Command::Pong,
-                &[server_prefix],
-            );
-            send_to_user(&response, &users, user_id)?;
         }
-        Command::Pong | Command::Error => {}
-        _ => send_to_user(&message, &users, user_id)?,
+        TypedCommand::Pong { .. } => {}
     }
 
     // Send welcome message if user has completed registration (has both nick and username)
@@ -564,7 +1273,8 @@ fn handle_message<'a>(
     let user = users
         .get(&user_id)
         .ok_or("Unable to find user in table with given ID.")?;
-    let should_register = !user.is_registered && user.prefix().is_some();
+    let should_register =
+        !user.is_registered && user.prefix().is_some() && !user.is_negotiating_caps;
     let prefix = user.prefix();
     drop(user); // Most drop explicitly here
 
@@ -574,118 +1284,1562 @@ fn handle_message<'a>(
             .get_mut(&user_id)
             .ok_or("Unable to find user in table with given ID.")?;
         user.is_registered = true;
-        let response = Response::new(
-            &prefix,
-            ReplyCode::RPL_WELCOME,
-            &[
-                user.nickname.as_ref().unwrap(),
-                &format!("Welcome to the Internet Relay Network {}", prefix),
-            ],
+        user.signon = state.clock.utc_now();
+        user.last_active = state.clock.now();
+        // The nickname may have only just been chosen, so it's not the one replies used above
+        let nick = user.nickname().unwrap().clone();
+        let writer = user.writer.clone();
+        drop(user);
+        writer.send(&replies.welcome(&nick, &prefix));
+        let tokens = [
+            "CHANTYPES=#&".to_string(),
+            format!("CHANLIMIT=#&:{}", state.config.limits.channels),
+        ];
+        writer.send(&replies.isupport(&nick, &tokens));
+
+        send_lusers(state, user_id)?;
+        state.hooks.on_register(state, user_id);
+        let text = state.text(
+            &catalog::SNOTICE_CONNECT,
+            &[("prefix", &prefix.to_string())],
         );
-        user.stream.write_all(response.to_irc().as_bytes())?;
+        send_to_snomask(Snomask::Connects, &text, users, server_prefix)?;
     }
 
     Ok(CommandResponse::Continue)
 }
 
-/// This mutates the user table by writing with the stream
-pub fn send_to_user<'a, T: ToIrc>(
-    message: &T,
-    users: &'a UserTable,
-    id: Uuid,
+/// Send what WHOIS tells about a user. Operators are also told where the user really connected
+/// from, and see idle time and channels even if the user hides them with `+p`. Users with `+W` are
+/// told who asked.
+fn send_whois<'a>(
+    state: &'a ServerState,
+    user_id: Uuid,
+    target: &str,
 ) -> Result<(), Box<dyn std::error::Error + 'a>> {
-    Ok(users
-        .get_mut(&id)
-        .ok_or("Invalid ID given. User not found in table.")?
-        .stream
-        .write_all(message.to_irc().as_bytes())?)
-}
+    let users = &state.users;
+    let replies = state.replies();
+    let nick = reply_nick(users, user_id);
+    let asker_is_operator = is_operator(users, user_id)?;
+
+    let Some(target_id) = get_nickname_id(target, &state.nicks) else {
+        send_to_user(&replies.no_such_nick(&nick, target), users, user_id)?;
+        send_to_user(&replies.end_of_whois(&nick, target), users, user_id)?;
+        return Ok(());
+    };
 
-/// This mutates the user table by writing with the stream
-pub fn send_to_channel<'a, T: ToIrc>(
-    message: &T,
-    users: &'a UserTable,
+    let asker = user_prefix(users, user_id);
+
+    // Put the replies together first, so that the target isn't locked while they're sent
+    let (responses, notice) = {
+        let user = users
+            .get(&target_id)
+            .ok_or("Unable to find target user in table with given ID.")?;
+        let target = user.nickname().cloned().unwrap_or_else(|| target.into());
+        let username = user.username().cloned().unwrap_or_default();
+        let is_hidden = user.is_private && target_id != user_id && !asker_is_operator;
+
+        let mut responses =
+            vec![replies.whois_user(&nick, &target, &username, &user.hostname, &user.realname)];
+        if !is_hidden {
+            let channels = user
+                .channels
+                .iter()
+                .map(|channel| {
+                    let prefix = if channel.is_operator(target_id) {
+                        "@"
+                    } else {
+                        ""
+                    };
+                    format!("{prefix}{}", channel.name)
+                })
+                .collect::<Vec<_>>();
+            for channels in channels.chunks(WHOIS_CHANNELS_PER_LINE) {
+                responses.push(replies.whois_channels(&nick, &target, channels));
+            }
+        }
+        let info = state.text(&catalog::SERVER_INFO, &[]);
+        responses.push(replies.whois_server(&nick, &target, &state.hostname, &info));
+        if let Some(away_message) = &user.away_message {
+            responses.push(replies.away(&nick, &target, away_message));
+        }
+        if user.is_operator {
+            responses.push(replies.whois_operator(&nick, &target));
+        }
+        if let Some(account) = &user.account {
+            responses.push(replies.whois_account(&nick, &target, account));
+        }
+        if asker_is_operator {
+            responses.push(replies.whois_actually(&nick, &target, &user.hostname));
+        }
+        if !is_hidden {
+            let idle = state
+                .clock
+                .now()
+                .saturating_duration_since(user.last_active);
+            let signon = user.signon.timestamp();
+            responses.push(replies.whois_idle(&nick, &target, idle.as_secs(), signon));
+        }
+        responses.push(replies.end_of_whois(&nick, &target));
+
+        let notice = (user.sees_whois && target_id != user_id).then(|| {
+            let text = state.text(&catalog::WHOIS_NOTICE, &[("prefix", &asker)]);
+            Message::notice(&target, &text).with_prefix(state.hostname.as_str())
+        });
+        (responses, notice)
+    };
+    for response in &responses {
+        send_to_user(response, users, user_id)?;
+    }
+    if let Some(notice) = notice {
+        send_to_user(&notice, users, target_id)?;
+    }
+    Ok(())
+}
+
+/// Answer capability negotiation. Registration waits from the client's first CAP LS or REQ until
+/// its CAP END, so that it can log in with SASL first.
+fn handle_cap<'a>(
+    state: &'a ServerState,
+    user_id: Uuid,
+    subcommand: &str,
+    args: &[String],
+) -> Result<(), Box<dyn std::error::Error + 'a>> {
+    let users = &state.users;
+    let nick = reply_nick(users, user_id);
+    let reply = |subcommand: &str, caps: &str| {
+        Message::builder()
+            .command(Command::Cap)
+            .param(&*nick)
+            .param(subcommand)
+            .trailing(caps)
+            .build()
+            .with_prefix(state.hostname.as_str())
+    };
+
+    let subcommand = subcommand.to_uppercase();
+    if matches!(subcommand.as_str(), "LS" | "REQ") {
+        let mut user = users
+            .get_mut(&user_id)
+            .ok_or("Unable to find user in table with given ID.")?;
+        user.is_negotiating_caps = !user.is_registered;
+    }
+
+    match subcommand.as_str() {
+        "LS" => {
+            // Clients that support version 302 are told the SASL mechanism too
+            let version = args.first().and_then(|version| version.parse::<u32>().ok());
+            let caps = if version.is_some_and(|version| version >= 302) {
+                format!("sasl={}", sasl::MECHANISM)
+            } else {
+                "sasl".to_string()
+            };
+            send_to_user(&reply("LS", &caps), users, user_id)
+        }
+        "LIST" => {
+            let caps = users
+                .get(&user_id)
+                .ok_or("Unable to find user in table with given ID.")?
+                .caps
+                .iter()
+                .copied()
+                .collect::<Vec<_>>()
+                .join(" ");
+            send_to_user(&reply("LIST", &caps), users, user_id)
+        }
+        "REQ" => {
+            // The changes are made together, or not at all if any capability is unknown
+            let requested = args.first().map_or("", String::as_str);
+            let changes = requested
+                .split_whitespace()
+                .map(|cap| {
+                    let (adding, cap) = match cap.strip_prefix('-') {
+                        Some(cap) => (false, cap),
+                        None => (true, cap),
+                    };
+                    CAPABILITIES
+                        .into_iter()
+                        .find(|known| *known == cap)
+                        .map(|cap| (adding, cap))
+                })
+                .collect::<Option<Vec<_>>>()
+                .filter(|changes| !changes.is_empty());
+            let Some(changes) = changes else {
+                return send_to_user(&reply("NAK", requested), users, user_id);
+            };
+
+            {
+                let mut user = users
+                    .get_mut(&user_id)
+                    .ok_or("Unable to find user in table with given ID.")?;
+                for (adding, cap) in changes {
+                    if adding {
+                        user.caps.insert(cap);
+                    } else {
+                        user.caps.remove(cap);
+                    }
+                }
+            }
+            send_to_user(&reply("ACK", requested), users, user_id)
+        }
+        "END" => {
+            let was_authenticating = {
+                let mut user = users
+                    .get_mut(&user_id)
+                    .ok_or("Unable to find user in table with given ID.")?;
+                user.is_negotiating_caps = false;
+                mem::take(&mut user.is_authenticating)
+            };
+            if was_authenticating {
+                send_to_user(&state.replies().sasl_aborted(&nick), users, user_id)?;
+            }
+            Ok(())
+        }
+        _ => {
+            let response = state.replies().invalid_cap_cmd(&nick, &subcommand);
+            send_to_user(&response, users, user_id)
+        }
+    }
+}
+
+/// Log into an account with SASL PLAIN. `AUTHENTICATE PLAIN` starts it, the credentials follow
+/// once the server answers `AUTHENTICATE +`, and `AUTHENTICATE *` gives up.
+fn handle_authenticate<'a>(
+    state: &'a ServerState,
+    user_id: Uuid,
+    data: &str,
+) -> Result<(), Box<dyn std::error::Error + 'a>> {
+    let users = &state.users;
+    let replies = state.replies();
+    let nick = reply_nick(users, user_id);
+
+    let (has_sasl, is_authenticating, is_logged_in, prefix) = {
+        let user = users
+            .get(&user_id)
+            .ok_or("Unable to find user in table with given ID.")?;
+        (
+            user.caps.contains("sasl"),
+            user.is_authenticating,
+            user.account.is_some(),
+            user.prefix(),
+        )
+    };
+    if is_logged_in {
+        return send_to_user(&replies.sasl_already(&nick), users, user_id);
+    }
+    if !has_sasl {
+        return send_to_user(&replies.sasl_fail(&nick), users, user_id);
+    }
+    if data == "*" {
+        users
+            .get_mut(&user_id)
+            .ok_or("Unable to find user in table with given ID.")?
+            .is_authenticating = false;
+        return send_to_user(&replies.sasl_aborted(&nick), users, user_id);
+    }
+
+    if !is_authenticating {
+        if !data.eq_ignore_ascii_case(sasl::MECHANISM) {
+            let mechanisms = [sasl::MECHANISM.to_string()];
+            send_to_user(&replies.sasl_mechs(&nick, &mechanisms), users, user_id)?;
+            return send_to_user(&replies.sasl_fail(&nick), users, user_id);
+        }
+        users
+            .get_mut(&user_id)
+            .ok_or("Unable to find user in table with given ID.")?
+            .is_authenticating = true;
+        let response = Message::builder()
+            .command(Command::Authenticate)
+            .param("+")
+            .build();
+        return send_to_user(&response, users, user_id);
+    }
+
+    users
+        .get_mut(&user_id)
+        .ok_or("Unable to find user in table with given ID.")?
+        .is_authenticating = false;
+    if data.len() >= sasl::MAX_DATA_LENGTH {
+        return send_to_user(&replies.sasl_too_long(&nick), users, user_id);
+    }
+    let credentials = sasl::decode_plain(data).filter(|credentials| {
+        state
+            .config
+            .check_account(&credentials.account, &credentials.password)
+    });
+    let Some(credentials) = credentials else {
+        let failures = {
+            let mut user = users
+                .get_mut(&user_id)
+                .ok_or("Unable to find user in table with given ID.")?;
+            user.sasl_failures += 1;
+            user.sasl_failures
+        };
+        info!(failures, "Failed SASL login");
+        return send_to_user(&replies.sasl_fail(&nick), users, user_id);
+    };
+
+    info!(account = credentials.account, "Logged in with SASL");
+    users
+        .get_mut(&user_id)
+        .ok_or("Unable to find user in table with given ID.")?
+        .account = Some(credentials.account.clone());
+    let prefix = prefix.unwrap_or_else(|| Prefix::User {
+        nick: nick.clone(),
+        user: None,
+        host: None,
+    });
+    send_to_user(
+        &replies.logged_in(&nick, &prefix, &credentials.account),
+        users,
+        user_id,
+    )?;
+    send_to_user(&replies.sasl_success(&nick), users, user_id)
+}
+
+/// Send the LUSERS summary of how many users and channels there are, which is also sent on
+/// registration.
+fn send_lusers<'a>(
+    state: &'a ServerState,
+    user_id: Uuid,
+) -> Result<(), Box<dyn std::error::Error + 'a>> {
+    let users = &state.users;
+    let replies = state.replies();
+    let nick = reply_nick(users, user_id);
+
+    let total = users.len();
+    let registered = users.iter().filter(|user| user.is_registered).count();
+    let operators = users.iter().filter(|user| user.is_operator).count();
+    let peak = state.stats.all_time_peak().connections as usize;
+
+    for response in [
+        replies.luser_client(&nick, registered, 0, 1),
+        replies.luser_op(&nick, operators),
+        replies.luser_unknown(&nick, total - registered),
+        replies.luser_channels(&nick, state.channel_count()),
+        replies.luser_me(&nick, registered, 0),
+        replies.local_users(&nick, total, peak),
+        replies.global_users(&nick, total, peak),
+    ] {
+        send_to_user(&response, users, user_id)?;
+    }
+
+    Ok(())
+}
+
+/// Show or change the modes of a channel. Only channel operators may change modes, although IRC
+/// operators can override this (which is recorded in the audit log).
+///
+/// Supported modes:
+/// - `o <nick>`: Give or take channel operator status
+/// - `q <mask>`: Quiet users matching the mask. `MODE #channel q` lists the current quiets.
+/// - `r`: Only users logged into an account may join
+/// - `W <seconds>`: Slow mode, limiting non-operators to one message every `<seconds>` seconds
+fn handle_channel_mode<'a>(
+    message: &Message,
+    state: &'a ServerState,
     channel: &Arc<Channel>,
-    id_to_exclude: Uuid,
+    user_id: Uuid,
+) -> Result<(), Box<dyn std::error::Error + 'a>> {
+    let users = &state.users;
+    let replies = state.replies();
+    let nick = message_nick(message);
+
+    // Without a mode string, reply with the current modes
+    let mode_string = match message.params.get(1) {
+        Some(modes) => modes.clone(),
+        None => {
+            let response = replies.channel_mode_is(nick, &channel.name, &channel.mode_params());
+            return send_to_user(&response, users, user_id);
+        }
+    };
+
+    // Anyone may view the quiet list
+    if mode_string.trim_start_matches(['+', '-']) == "q" && message.params.len() == 2 {
+        let quiets = channel.modes.lock().unwrap().quiets.clone();
+        for quiet in quiets {
+            send_to_user(
+                &replies.quiet_list(nick, &channel.name, &quiet),
+                users,
+                user_id,
+            )?;
+        }
+
+        let response = replies.end_of_quiet_list(nick, &channel.name);
+        return send_to_user(&response, users, user_id);
+    }
+
+    let is_override = !channel.is_operator(user_id);
+    if is_override && !is_operator(users, user_id)? {
+        let response = replies.chanop_privs_needed(nick, &channel.name);
+        return send_to_user(&response, users, user_id);
+    }
+
+    let mode_args = ModeArgs::new(CHANNEL_MODES_WITH_ARG, CHANNEL_MODES_WITH_ARG_WHEN_SET);
+    let args = message.params.iter().skip(2).map(String::as_str);
+    // Changes that were applied, which are broadcast to the channel afterwards
+    let mut applied = vec![];
+
+    for change in ModeChange::parse(&mode_string, args, &mode_args) {
+        let adding = change.adding;
+        match change.mode {
+            'W' => {
+                let slow = if adding {
+                    match change
+                        .arg
+                        .as_deref()
+                        .and_then(|arg| arg.parse::<u64>().ok())
+                    {
+                        Some(seconds) if seconds > 0 => Some(seconds),
+                        _ => {
+                            send_to_user(&replies.need_more_params(nick, "MODE"), users, user_id)?;
+                            continue;
+                        }
+                    }
+                } else {
+                    None
+                };
+
+                channel.modes.lock().unwrap().slow = slow;
+                applied.push(ModeChange {
+                    arg: slow.map(|seconds| seconds.to_string()),
+                    ..change
+                });
+            }
+            'r' => {
+                let mut modes = channel.modes.lock().unwrap();
+                if modes.registered_only != adding {
+                    modes.registered_only = adding;
+                    applied.push(change);
+                }
+            }
+            'q' => {
+                let Some(quiet) = &change.arg else {
+                    send_to_user(&replies.need_more_params(nick, "MODE"), users, user_id)?;
+                    continue;
+                };
+
+                let quiet = mask::normalize(quiet);
+                let changed = {
+                    let quiets = &mut channel.modes.lock().unwrap().quiets;
+                    let existing = quiets.iter().position(|q| q.eq_ignore_ascii_case(&quiet));
+                    match (adding, existing) {
+                        (true, None) => {
+                            quiets.push(quiet.clone());
+                            true
+                        }
+                        (false, Some(index)) => {
+                            quiets.remove(index);
+                            true
+                        }
+                        _ => false,
+                    }
+                };
+
+                if changed {
+                    applied.push(ModeChange::new(adding, 'q', Some(&quiet)));
+                }
+            }
+            'o' => {
+                let Some(nickname) = &change.arg else {
+                    send_to_user(&replies.need_more_params(nick, "MODE"), users, user_id)?;
+                    continue;
+                };
+
+                let target_in_channel = get_nickname_id(nickname, &state.nicks).and_then(|id| {
+                    users
+                        .get(&id)
+                        .filter(|user| user.is_in(channel))
+                        .map(|_| id)
+                });
+                let Some(target_id) = target_in_channel else {
+                    let response = replies.user_not_in_channel(nick, nickname, &channel.name);
+                    send_to_user(&response, users, user_id)?;
+                    continue;
+                };
+
+                channel.set_operator(target_id, adding);
+                applied.push(change);
+            }
+            mode => {
+                let response = replies.unknown_mode(nick, mode, &channel.name);
+                send_to_user(&response, users, user_id)?;
+            }
+        }
+    }
+
+    if applied.is_empty() {
+        return Ok(());
+    }
+
+    // Tell everyone in the channel (including the sender) which modes changed
+    let mode_message = Message {
+        prefix: message.prefix.clone(),
+        ..Message::builder()
+            .command(Command::Mode)
+            .param(channel.name.as_str())
+            .params(ModeChange::format(&applied))
+            .build()
+    };
+    if is_override {
+        state.audit_log.record(
+            &message_prefix(message),
+            "MODE",
+            &channel.name,
+            &format!("Override: {}", mode_message.params[1..].join(" ")),
+        );
+    }
+    send_to_channel(&mode_message, users, channel, user_id)?;
+    send_to_user(&mode_message, users, user_id)
+}
+
+fn handle_user_mode<'a>(
+    message: &Message,
+    state: &'a ServerState,
+    target: &str,
+    user_id: Uuid,
 ) -> Result<(), Box<dyn std::error::Error + 'a>> {
-    // Ok(users
-    //     .iter_mut()
-    //     .filter(|(_, user)| user.channel == Some(channel.clone()))
-    //     .for_each(|(_, user)| user.stream.write_all(message.to_irc().as_bytes()).unwrap()))
+    let users = &state.users;
+    let replies = state.replies();
+    let nick = message_nick(message);
+
+    // Users can only see and change their own modes
+    if get_nickname_id(target, &state.nicks) != Some(user_id) {
+        return send_to_user(&replies.users_dont_match(nick), users, user_id);
+    }
+    let Some(mode_string) = message.params.get(1) else {
+        let modes = users
+            .get(&user_id)
+            .ok_or("Unable to find user in table with given ID.")?
+            .mode_string();
+        return send_to_user(&replies.umode_is(nick, &modes), users, user_id);
+    };
 
-    for mut entry in users.iter_mut() {
-        let id = *entry.key();
-        let user = entry.value_mut();
-        if id != id_to_exclude && user.channel == Some(channel.clone()) {
-            user.stream.write_all(message.to_irc().as_bytes())?;
+    let whois_notices_allowed = state.config.whois_notices == WhoisNotices::Everyone;
+    let args = message.params.iter().skip(2).map(String::as_str);
+    let mut applied = vec![];
+    let mut unknown = false;
+    // The new server notice mask, if it was changed
+    let mut snomasks = None;
+    {
+        let mut user = users
+            .get_mut(&user_id)
+            .ok_or("Unable to find user in table with given ID.")?;
+        for change in ModeChange::parse(
+            mode_string,
+            args,
+            &ModeArgs::new("", USER_MODES_WITH_ARG_WHEN_SET),
+        ) {
+            let adding = change.adding;
+            let mode = match change.mode {
+                // Only operators get server notices
+                's' if adding && !user.is_operator => continue,
+                's' if adding => {
+                    match &change.arg {
+                        Some(mask) => user.snomasks.apply(mask),
+                        None => user.snomasks = Snomasks::all(),
+                    }
+                    let mask = user.snomasks.to_string();
+                    if !user.snomasks.is_empty() {
+                        applied.push(ModeChange::new(true, 's', Some(&mask)));
+                    } else if change.arg.is_some() {
+                        // Removing every kind of event is the same as unsetting the mode
+                        applied.push(ModeChange::new(false, 's', None));
+                    }
+                    snomasks = Some(mask);
+                    continue;
+                }
+                's' => {
+                    if !user.snomasks.is_empty() {
+                        user.snomasks = Snomasks::default();
+                        applied.push(change);
+                        snomasks = Some(user.snomasks.to_string());
+                    }
+                    continue;
+                }
+                'W' if adding && !whois_notices_allowed && !user.is_operator => continue,
+                'W' => &mut user.sees_whois,
+                'p' => &mut user.is_private,
+                // Operator status comes from OPER, but can be dropped like any other mode
+                'o' if adding => continue,
+                'o' => &mut user.is_operator,
+                _ => {
+                    unknown = true;
+                    continue;
+                }
+            };
+            if *mode != adding {
+                *mode = adding;
+                applied.push(change);
+            }
+        }
+        // Dropping operator status can take away the notices too
+        if !user.is_operator && !whois_notices_allowed && user.sees_whois {
+            user.sees_whois = false;
+            applied.push(ModeChange::new(false, 'W', None));
+        }
+        if !user.is_operator && !user.snomasks.is_empty() {
+            user.snomasks = Snomasks::default();
+            applied.push(ModeChange::new(false, 's', None));
+            snomasks = Some(user.snomasks.to_string());
         }
     }
 
+    if unknown {
+        send_to_user(&replies.umode_unknown_flag(nick), users, user_id)?;
+    }
+    if applied.is_empty() {
+        return Ok(());
+    }
+    let mode_message = Message {
+        prefix: message.prefix.clone(),
+        ..Message::builder()
+            .command(Command::Mode)
+            .param(nick)
+            .params(ModeChange::format(&applied))
+            .build()
+    };
+    send_to_user(&mode_message, users, user_id)?;
+    if let Some(snomasks) = snomasks {
+        send_to_user(&replies.snomask(nick, &snomasks), users, user_id)?;
+    }
     Ok(())
 }
 
-/// This mutates the user table by writing with the stream
+/// Send a message to one user. Like the other helpers below, it copies the user's writer out of
+/// the table first, so the table isn't locked while a slow connection is written to.
+pub fn send_to_user<'a, T: ToIrc>(
+    message: &T,
+    users: &'a UserTable,
+    id: Uuid,
+) -> Result<(), Box<dyn std::error::Error + 'a>> {
+    let writer = users
+        .get(&id)
+        .ok_or("Invalid ID given. User not found in table.")?
+        .writer
+        .clone();
+    writer.send(message);
+    Ok(())
+}
+
+/// Send a message to everyone in a channel except one user, who's usually the sender
+pub fn send_to_channel<'a, T: ToIrc>(
+    message: &T,
+    users: &'a UserTable,
+    channel: &Arc<Channel>,
+    id_to_exclude: Uuid,
+) -> Result<(), Box<dyn std::error::Error + 'a>> {
+    let recipients = writers(users, |id, user| id != id_to_exclude && user.is_in(channel));
+    send_to_writers(message, &recipients)
+}
+
+/// Send a message to everyone except one user
 pub fn broadcast_message<'a, T: ToIrc>(
     message: &T,
     users: &'a UserTable,
     id_to_exclude: Uuid,
 ) -> Result<(), Box<dyn std::error::Error + 'a>> {
-    // Ok(users
-    //     .iter_mut()
-    //     .filter(|(id, _)| **id != id_to_exclude)
-    //     .for_each(|(_, user)| user.stream.write_all(message.to_irc().as_bytes()).unwrap()))
+    let recipients = writers(users, |id, _| id != id_to_exclude);
+    send_to_writers(message, &recipients)
+}
 
-    for mut entry in users.iter_mut() {
-        let id = *entry.key();
-        let user = entry.value_mut();
-        if id != id_to_exclude {
-            user.stream.write_all(message.to_irc().as_bytes())?
-        }
+/// Send a message to everyone
+pub fn broadcast_to_all<'a, T: ToIrc>(
+    message: &T,
+    users: &'a UserTable,
+) -> Result<(), Box<dyn std::error::Error + 'a>> {
+    let recipients = writers(users, |_, _| true);
+    send_to_writers(message, &recipients)
+}
+
+/// Send a server NOTICE to every IRC operator.
+pub fn send_to_operators<'a>(
+    text: &str,
+    users: &'a UserTable,
+    server_prefix: &str,
+) -> Result<(), Box<dyn std::error::Error + 'a>> {
+    let operators = users
+        .iter()
+        .filter(|user| user.is_operator)
+        .map(|user| (user.writer.clone(), user.nickname().cloned()))
+        .collect::<Vec<_>>();
+    for (writer, nickname) in operators {
+        let notice =
+            Message::notice(nickname.as_deref().unwrap_or("*"), text).with_prefix(server_prefix);
+        writer.send(&notice);
     }
 
     Ok(())
 }
 
-/// This mutates the user table by writing with the stream
-pub fn broadcast_to_all<'a, T: ToIrc>(
-    message: &T,
+/// Send a notice to the operators that subscribed to a kind of event with `+s`
+pub fn send_to_snomask<'a>(
+    snomask: Snomask,
+    text: &str,
     users: &'a UserTable,
+    server_prefix: &str,
 ) -> Result<(), Box<dyn std::error::Error + 'a>> {
-    // Ok(users
-    //     .iter_mut()
-    //     .for_each(|mut entry| entry.stream.write_all(message.to_irc().as_bytes()).unwrap()))
+    let operators = users
+        .iter()
+        .filter(|user| user.is_operator && user.snomasks.contains(snomask))
+        .map(|user| (user.writer.clone(), user.nickname().cloned()))
+        .collect::<Vec<_>>();
+    for (writer, nickname) in operators {
+        let notice =
+            Message::notice(nickname.as_deref().unwrap_or("*"), text).with_prefix(server_prefix);
+        writer.send(&notice);
+    }
 
-    for mut entry in users.iter_mut() {
-        let user = entry.value_mut();
-        user.stream.write_all(message.to_irc().as_bytes())?;
+    Ok(())
+}
+
+/// The writers of the users that match, copied out of the table so that none of it is locked
+/// while they're written to
+fn writers(users: &UserTable, mut include: impl FnMut(Uuid, &User) -> bool) -> Vec<Writer> {
+    users
+        .iter()
+        .filter(|entry| include(*entry.key(), entry.value()))
+        .map(|entry| entry.writer.clone())
+        .collect()
+}
+
+/// Write a message out once and send it to each of the writers
+fn send_to_writers<'a, T: ToIrc>(
+    message: &T,
+    writers: &[Writer],
+) -> Result<(), Box<dyn std::error::Error + 'a>> {
+    let Some(line) = user::encode(message) else {
+        return Ok(());
+    };
+    for writer in writers {
+        writer.send_line(&line);
     }
 
     Ok(())
 }
 
-pub fn nickname_in_use(nickname: &str, users: &UserTable) -> bool {
-    for entry in users.iter() {
-        let user = entry.value();
-        if let Some(name) = &user.nickname
-            && name == nickname
-        {
-            return true;
+/// Return the user's nickname, or `*` if they haven't chosen one yet, which numeric replies to
+/// them start with.
+fn reply_nick(users: &UserTable, id: Uuid) -> Arc<str> {
+    users
+        .get(&id)
+        .and_then(|user| user.nickname().cloned())
+        .unwrap_or_else(|| "*".into())
+}
+
+/// Return the user's `nick!user@host`, or just their nickname if they haven't given a username.
+fn user_prefix(users: &UserTable, id: Uuid) -> String {
+    users
+        .get(&id)
+        .and_then(|user| user.prefix())
+        .map(|prefix| prefix.to_string())
+        .unwrap_or_else(|| reply_nick(users, id).to_string())
+}
+
+/// Return the nickname in a message's prefix.
+fn message_nick(message: &Message) -> &str {
+    message.prefix.as_ref().map_or("*", Prefix::name)
+}
+
+/// Return the text of a PRIVMSG or NOTICE, which is its last parameter.
+fn message_text(message: &Message) -> &str {
+    message.params.last().map_or("", String::as_str)
+}
+
+/// Return a message's whole `nick!user@host` prefix, as recorded in the audit log.
+fn message_prefix(message: &Message) -> String {
+    message
+        .prefix
+        .as_ref()
+        .map(Prefix::to_string)
+        .unwrap_or_default()
+}
+
+/// Check whether the user has identified as an IRC operator with the OPER command.
+pub fn is_operator<'a>(
+    users: &'a UserTable,
+    id: Uuid,
+) -> Result<bool, Box<dyn std::error::Error + 'a>> {
+    Ok(users
+        .get(&id)
+        .ok_or("Unable to find user in table with given ID.")?
+        .is_operator)
+}
+
+/// The user with a nickname, ignoring case
+pub fn get_nickname_id(nickname: &str, nicks: &NickTable) -> Option<Uuid> {
+    nicks.get(&IrcString::from(nickname)).map(|id| *id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::ManualClock,
+        config::{AccountConfig, ChatLogConfig, LanguageTexts, OperConfig, TextsConfig},
+        hooks::{Hook, Verdict},
+        plugins,
+    };
+    use std::{
+        collections::HashMap,
+        env, fs,
+        io::{Cursor, Write},
+        mem,
+        net::TcpListener,
+        thread,
+    };
+
+    /// The server's end of an in-memory connection, which the test reads back what was sent to
+    #[derive(Debug, Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(bytes)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
         }
     }
 
-    return false;
-}
+    impl Transport for Output {}
+
+    impl Output {
+        /// The lines sent since the last call, without their CRLF
+        fn lines(&self) -> Vec<String> {
+            let bytes = mem::take(&mut *self.0.lock().unwrap());
+            String::from_utf8(bytes)
+                .unwrap()
+                .lines()
+                .map(str::to_string)
+                .collect()
+        }
+    }
+
+    /// A server without any users, and the clock it goes by
+    fn state() -> (ServerState, Arc<ManualClock>) {
+        let clock = Arc::new(ManualClock::new());
+        let config = Config::default();
+        let state = ServerState::new(config, AuditLog::open(None).unwrap(), clock.clone());
+        (state, clock)
+    }
+
+    /// Handle a line as if the user had sent it, and wait for what it sent to be written
+    fn send(state: &ServerState, user_id: Uuid, line: &str) -> CommandResponse {
+        let response = handle_message(Message::from(line).unwrap(), state, user_id).unwrap();
+        for writer in writers(&state.users, |_, _| true) {
+            writer.flush();
+        }
+        response
+    }
 
-pub fn get_nickname_id(nickname: &str, users: &UserTable) -> Option<Uuid> {
-    for entry in users.iter() {
-        let id = entry.key();
-        let user = entry.value();
-        if let Some(name) = &user.nickname {
-            if name == nickname {
-                return Some(*id);
+    /// Add a user without a socket, who hasn't registered yet
+    fn connect(state: &ServerState) -> (Uuid, Output) {
+        let output = Output::default();
+        let writer = Writer::new(output.clone(), state.config.limits.sendq, "SendQ exceeded.");
+        let user = User::new(IpAddr::from([127, 0, 0, 1]), writer);
+        let user_id = user.id;
+        state.users.insert(user_id, user);
+        (user_id, output)
+    }
+
+    /// Add a user without a socket and register them, skipping the welcome
+    fn register(state: &ServerState, nick: &str) -> (Uuid, Output) {
+        let (user_id, output) = connect(state);
+        send(state, user_id, &format!("NICK {nick}"));
+        send(state, user_id, &format!("USER {nick} 0 * :{nick}"));
+        output.lines();
+        (user_id, output)
+    }
+
+    #[test]
+    fn channel_messages_reach_other_members() {
+        let (state, _) = state();
+        let (alice, alice_output) = register(&state, "alice");
+        let (bob, bob_output) = register(&state, "bob");
+        send(&state, alice, "JOIN #rust");
+        send(&state, bob, "JOIN #rust");
+        assert_eq!(alice_output.lines(), [":bob!bob@127.0.0.1 JOIN #rust"]);
+
+        send(&state, bob, "PRIVMSG #rust :hello, world");
+        assert_eq!(
+            alice_output.lines(),
+            [":bob!bob@127.0.0.1 PRIVMSG #rust :hello, world"]
+        );
+        assert!(bob_output.lines().is_empty());
+    }
+
+    #[test]
+    fn only_operators_set_channel_modes() {
+        let (state, _) = state();
+        let (alice, alice_output) = register(&state, "alice");
+        let (bob, bob_output) = register(&state, "bob");
+        send(&state, alice, "JOIN #rust");
+        send(&state, bob, "JOIN #rust");
+        alice_output.lines();
+
+        send(&state, bob, "MODE #rust +W 10");
+        assert_eq!(
+            bob_output.lines(),
+            [":127.0.0.1 482 bob #rust :You're not channel operator"]
+        );
+        send(&state, alice, "MODE #rust +W 10");
+        assert_eq!(
+            alice_output.lines(),
+            [":alice!alice@127.0.0.1 MODE #rust +W 10"]
+        );
+        assert_eq!(
+            bob_output.lines(),
+            [":alice!alice@127.0.0.1 MODE #rust +W 10"]
+        );
+    }
+
+    #[test]
+    fn registered_only_channels_turn_away_users_who_are_not_logged_in() {
+        let (state, _) = state();
+        let (alice, alice_output) = register(&state, "alice");
+        let (bob, bob_output) = register(&state, "bob");
+        let (carol, _) = register(&state, "carol");
+        send(&state, alice, "JOIN #members");
+        send(&state, alice, "MODE #members +r");
+        send(&state, alice, "MODE #members");
+        assert_eq!(
+            alice_output.lines(),
+            [
+                ":alice!alice@127.0.0.1 MODE #members +r",
+                ":127.0.0.1 324 alice #members +r"
+            ]
+        );
+
+        send(&state, bob, "JOIN #members");
+        assert_eq!(
+            bob_output.lines(),
+            [
+                ":127.0.0.1 477 bob #members :Cannot join channel (+r) - you need to be logged into your account"
+            ]
+        );
+
+        // Once logged in, or as an operator, they're let in
+        state.users.get_mut(&bob).unwrap().account = Some("bob".to_string());
+        state.users.get_mut(&carol).unwrap().is_operator = true;
+        send(&state, bob, "JOIN #members");
+        send(&state, carol, "JOIN #members");
+        assert_eq!(
+            alice_output.lines(),
+            [
+                ":bob!bob@127.0.0.1 JOIN #members",
+                ":carol!carol@127.0.0.1 JOIN #members"
+            ]
+        );
+    }
+
+    #[test]
+    fn changing_nickname_frees_the_old_one() {
+        let (state, _) = state();
+        let (alice, alice_output) = register(&state, "alice");
+        let (_, bob_output) = register(&state, "bob");
+
+        send(&state, alice, "NICK BOB");
+        assert_eq!(
+            alice_output.lines(),
+            [":127.0.0.1 433 alice BOB :Nickname is already in use"]
+        );
+        send(&state, alice, "NICK Alice");
+        send(&state, alice, "NICK carol");
+        bob_output.lines();
+        assert_eq!(get_nickname_id("ALICE", &state.nicks), None);
+        assert_eq!(get_nickname_id("Carol", &state.nicks), Some(alice));
+
+        let (dave, _) = register(&state, "alice");
+        assert_eq!(get_nickname_id("alice", &state.nicks), Some(dave));
+    }
+
+    #[test]
+    fn slow_mode_lets_users_speak_again_after_waiting() {
+        let (state, clock) = state();
+        let (alice, alice_output) = register(&state, "alice");
+        let (bob, bob_output) = register(&state, "bob");
+        send(&state, alice, "JOIN #rust");
+        send(&state, bob, "JOIN #rust");
+        send(&state, alice, "MODE #rust +W 10");
+        alice_output.lines();
+        bob_output.lines();
+
+        send(&state, bob, "PRIVMSG #rust :one");
+        send(&state, bob, "PRIVMSG #rust :two");
+        assert_eq!(
+            alice_output.lines(),
+            [":bob!bob@127.0.0.1 PRIVMSG #rust one"]
+        );
+        assert_eq!(
+            bob_output.lines(),
+            [
+                ":127.0.0.1 NOTICE bob :#rust is in slow mode. Wait 10 more second(s) before \
+              sending another message."
+            ]
+        );
+
+        clock.advance(Duration::from_secs(10));
+        send(&state, bob, "PRIVMSG #rust :three");
+        assert_eq!(
+            alice_output.lines(),
+            [":bob!bob@127.0.0.1 PRIVMSG #rust three"]
+        );
+    }
+
+    #[test]
+    fn conversations_are_logged_under_one_name_whatever_the_case() {
+        let directory = env::temp_dir().join(format!("irc_rs-chat-log-{}", Uuid::new_v4()));
+        let config = Config {
+            chat_log: Some(ChatLogConfig {
+                directory: directory.clone(),
+                format: Default::default(),
+                channels: vec!["*".to_string()],
+                private_messages: true,
+                retention_days: None,
+                search_token: None,
+            }),
+            ..Config::default()
+        };
+        let state = ServerState::new(
+            config,
+            AuditLog::open(None).unwrap(),
+            Arc::new(ManualClock::new()),
+        );
+        let (alice, _) = register(&state, "alice");
+        let (bob, _) = register(&state, "bob[m]");
+        send(&state, alice, "JOIN #Rust");
+        send(&state, bob, "JOIN #rust");
+        send(&state, alice, "PRIVMSG #RUST :hello");
+        send(&state, bob, "PRIVMSG #rust :hi");
+        send(&state, alice, "PRIVMSG BOB{M} :psst");
+        send(&state, bob, "PRIVMSG alice :what");
+
+        let mut names = fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        names.sort();
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(names, ["#Rust", "query-alice-bob{m}"]);
+    }
+
+    #[test]
+    fn list_counts_the_members_of_each_channel() {
+        let (state, _) = state();
+        let (alice, alice_output) = register(&state, "alice");
+        let (bob, _) = register(&state, "bob");
+        send(&state, alice, "JOIN #rust");
+        send(&state, bob, "JOIN #rust");
+        send(&state, bob, "JOIN #rust");
+        send(&state, bob, "JOIN #irc");
+        send(&state, bob, "PART #irc");
+        alice_output.lines();
+
+        send(&state, alice, "LIST");
+        let mut lines = alice_output.lines();
+        lines.sort();
+        assert_eq!(
+            lines,
+            [
+                ":127.0.0.1 322 alice #irc 0 :",
+                ":127.0.0.1 322 alice #rust 2 :",
+                ":127.0.0.1 323 alice :End of LIST"
+            ]
+        );
+        assert_eq!(state.channel_count(), 1);
+    }
+
+    #[test]
+    fn users_can_be_in_several_channels_up_to_the_limit() {
+        let (mut state, _) = state();
+        state.config.limits.channels = 2;
+        let (alice, alice_output) = register(&state, "alice");
+        let (bob, bob_output) = register(&state, "bob");
+        send(&state, alice, "JOIN #rust,#irc,#games");
+        assert_eq!(
+            alice_output.lines(),
+            [":127.0.0.1 405 alice #games :You have joined too many channels"]
+        );
+
+        send(&state, bob, "JOIN #rust,#irc");
+        send(&state, alice, "PRIVMSG #rust :hi there");
+        send(&state, alice, "PRIVMSG #irc :hello again");
+        assert_eq!(
+            bob_output.lines(),
+            [
+                ":alice!alice@127.0.0.1 PRIVMSG #rust :hi there",
+                ":alice!alice@127.0.0.1 PRIVMSG #irc :hello again"
+            ]
+        );
+        send(&state, bob, "WHOIS alice");
+        assert!(
+            bob_output
+                .lines()
+                .contains(&":127.0.0.1 319 bob alice :@#rust @#irc".to_string())
+        );
+
+        // Leaving one channel makes room for another, without leaving the rest
+        alice_output.lines();
+        send(&state, alice, "PART #rust");
+        send(&state, alice, "JOIN #games");
+        send(&state, alice, "PRIVMSG #irc :still here");
+        assert!(alice_output.lines().is_empty());
+        assert_eq!(
+            bob_output.lines(),
+            [
+                ":alice!alice@127.0.0.1 PART #rust",
+                ":alice!alice@127.0.0.1 PRIVMSG #irc :still here"
+            ]
+        );
+
+        // Operators may join any number
+        state.users.get_mut(&bob).unwrap().is_operator = true;
+        send(&state, bob, "JOIN #games");
+        assert!(bob_output.lines().is_empty());
+        assert_eq!(alice_output.lines(), [":bob!bob@127.0.0.1 JOIN #games"]);
+    }
+
+    #[test]
+    fn users_log_in_with_sasl_before_registering() {
+        let (mut state, _) = state();
+        state.config.accounts = vec![AccountConfig {
+            name: "alice".to_string(),
+            password: "hunter2".to_string(),
+        }];
+        let (alice, alice_output) = connect(&state);
+        send(&state, alice, "CAP LS 302");
+        send(&state, alice, "NICK alice");
+        send(&state, alice, "USER alice 0 * :Alice");
+        send(&state, alice, "CAP REQ :sasl");
+        send(&state, alice, "AUTHENTICATE PLAIN");
+        // `\0alice\0hunter2`
+        send(&state, alice, "AUTHENTICATE AGFsaWNlAGh1bnRlcjI=");
+        assert_eq!(
+            alice_output.lines(),
+            [
+                ":127.0.0.1 CAP * LS sasl=PLAIN",
+                ":127.0.0.1 CAP alice ACK sasl",
+                "AUTHENTICATE +",
+                ":127.0.0.1 900 alice alice!alice@127.0.0.1 alice :You are now logged in as alice",
+                ":127.0.0.1 903 alice :SASL authentication successful",
+            ]
+        );
+
+        // Registering waits until the client is done
+        send(&state, alice, "CAP END");
+        assert!(alice_output.lines()[0].starts_with(":127.0.0.1 001 alice "));
+
+        let (bob, bob_output) = register(&state, "bob");
+        send(&state, bob, "WHOIS alice");
+        assert!(
+            bob_output
+                .lines()
+                .contains(&":127.0.0.1 330 bob alice alice :is logged in as".to_string())
+        );
+    }
+
+    #[test]
+    fn sasl_logins_are_refused_without_the_right_password() {
+        let (mut state, _) = state();
+        state.config.accounts = vec![AccountConfig {
+            name: "alice".to_string(),
+            password: "hunter2".to_string(),
+        }];
+        let (alice, alice_output) = register(&state, "alice");
+        send(&state, alice, "AUTHENTICATE PLAIN");
+        send(&state, alice, "CAP REQ :sasl multi-prefix");
+        send(&state, alice, "CAP REQ :sasl");
+        send(&state, alice, "AUTHENTICATE EXTERNAL");
+        send(&state, alice, "AUTHENTICATE PLAIN");
+        // `\0alice\0wrong`
+        send(&state, alice, "AUTHENTICATE AGFsaWNlAHdyb25n");
+        assert_eq!(
+            alice_output.lines(),
+            [
+                ":127.0.0.1 904 alice :SASL authentication failed",
+                ":127.0.0.1 CAP alice NAK :sasl multi-prefix",
+                ":127.0.0.1 CAP alice ACK sasl",
+                ":127.0.0.1 908 alice PLAIN :are available SASL mechanisms",
+                ":127.0.0.1 904 alice :SASL authentication failed",
+                "AUTHENTICATE +",
+                ":127.0.0.1 904 alice :SASL authentication failed",
+            ]
+        );
+        assert_eq!(state.users.get(&alice).unwrap().account, None);
+    }
+
+    #[test]
+    fn connections_are_closed_after_too_many_failed_sasl_logins() {
+        let (mut state, _) = state();
+        state.config.limits.sasl_failures = 2;
+        let (alice, alice_output) = connect(&state);
+        send(&state, alice, "CAP REQ :sasl");
+        send(&state, alice, "AUTHENTICATE PLAIN");
+        // `\0alice\0wrong`
+        send(&state, alice, "AUTHENTICATE AGFsaWNlAHdyb25n");
+        send(&state, alice, "AUTHENTICATE PLAIN");
+        let response = send(&state, alice, "AUTHENTICATE AGFsaWNlAHdyb25n");
+        assert_eq!(response, CommandResponse::Quit);
+        assert_eq!(
+            alice_output.lines(),
+            [
+                ":127.0.0.1 CAP * ACK sasl",
+                "AUTHENTICATE +",
+                ":127.0.0.1 904 * :SASL authentication failed",
+                "AUTHENTICATE +",
+                ":127.0.0.1 904 * :SASL authentication failed",
+                ":127.0.0.1 ERROR :Too many failed SASL logins.",
+            ]
+        );
+    }
+
+    /// A hook that writes down what it's told about, and drops AWAY
+    #[derive(Debug, Clone, Default)]
+    struct Events(Arc<Mutex<Vec<String>>>);
+
+    impl Hook for Events {
+        fn on_register(&self, _: &ServerState, _: Uuid) {
+            self.0.lock().unwrap().push("register".to_string());
+        }
+
+        fn on_message(&self, _: &ServerState, _: Uuid, message: &Message) -> Verdict {
+            match message.command {
+                Command::Away => Verdict::Block,
+                _ => Verdict::Continue,
             }
         }
+
+        fn on_join(&self, _: &ServerState, _: Uuid, channel: &Arc<Channel>) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("join {}", channel.name));
+        }
+
+        fn on_part(&self, _: &ServerState, _: Uuid, channel: &Arc<Channel>) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("part {}", channel.name));
+        }
+    }
+
+    #[test]
+    fn hooks_are_told_about_events_and_can_drop_messages() {
+        let (mut state, _) = state();
+        let events = Events::default();
+        state.hooks.register(events.clone());
+        let (alice, alice_output) = register(&state, "alice");
+        send(&state, alice, "JOIN #rust");
+        send(&state, alice, "JOIN #irc");
+        send(&state, alice, "PART #irc");
+        alice_output.lines();
+
+        send(&state, alice, "AWAY :Gone to lunch");
+        assert!(alice_output.lines().is_empty());
+        assert_eq!(
+            *events.0.lock().unwrap(),
+            ["register", "join #rust", "join #irc", "part #irc"]
+        );
+    }
+
+    #[test]
+    fn filtered_words_are_starred_out() {
+        let (mut state, _) = state();
+        state.config.plugins.filtered_words = vec!["heck".to_string()];
+        plugins::register(&mut state.hooks, &state.config);
+        let (alice, alice_output) = register(&state, "alice");
+        let (bob, _) = register(&state, "bob");
+        send(&state, alice, "JOIN #rust");
+        send(&state, bob, "JOIN #rust");
+        alice_output.lines();
+
+        send(&state, bob, "PRIVMSG #rust :what the HECK");
+        assert_eq!(
+            alice_output.lines(),
+            [":bob!bob@127.0.0.1 PRIVMSG #rust :what the ****"]
+        );
+    }
+
+    #[test]
+    fn away_messages_are_cut_to_the_limit() {
+        let (mut state, _) = state();
+        state.config.limits.away_length = 4;
+        let (alice, _) = register(&state, "alice");
+        let (bob, bob_output) = register(&state, "bob");
+
+        send(&state, alice, "AWAY :Gone to lunch");
+        send(&state, bob, "PRIVMSG alice :hi");
+        assert_eq!(bob_output.lines(), [":127.0.0.1 301 bob alice Gone"]);
+    }
+
+    #[test]
+    fn whois_shows_operators_and_real_host_to_operators() {
+        let (state, clock) = state();
+        let (alice, _) = register(&state, "alice");
+        let (bob, bob_output) = register(&state, "bob");
+        send(&state, alice, "JOIN #rust");
+        state.users.get_mut(&alice).unwrap().is_operator = true;
+        let signon = state.users.get(&alice).unwrap().signon.timestamp();
+        clock.advance(Duration::from_secs(30));
+
+        send(&state, bob, "WHOIS Alice");
+        assert_eq!(
+            bob_output.lines(),
+            [
+                ":127.0.0.1 311 bob alice alice 127.0.0.1 * alice".to_string(),
+                ":127.0.0.1 319 bob alice @#rust".to_string(),
+                ":127.0.0.1 312 bob alice 127.0.0.1 :An irc_rs server".to_string(),
+                ":127.0.0.1 313 bob alice :is an IRC operator".to_string(),
+                format!(":127.0.0.1 317 bob alice 30 {signon} :seconds idle, signon time"),
+                ":127.0.0.1 318 bob alice :End of WHOIS list".to_string(),
+            ]
+        );
+
+        state.users.get_mut(&bob).unwrap().is_operator = true;
+        send(&state, bob, "WHOIS alice");
+        assert!(
+            bob_output
+                .lines()
+                .contains(&":127.0.0.1 338 bob alice 127.0.0.1 :actually using host".to_string())
+        );
+        send(&state, bob, "WHOIS carol");
+        assert_eq!(
+            bob_output.lines(),
+            [
+                ":127.0.0.1 401 bob carol :No such nick/channel",
+                ":127.0.0.1 318 bob carol :End of WHOIS list",
+            ]
+        );
+    }
+
+    #[test]
+    fn whois_shows_operators_the_address_users_connected_from() {
+        // Connections to 127.0.0.2 come from 127.0.0.1, so the two ends' addresses differ
+        let listener = TcpListener::bind("127.0.0.2:0").unwrap();
+        let listener_address = listener.local_addr().unwrap();
+        let config = Config {
+            hostname: "irc.test".to_string(),
+            opers: vec![OperConfig {
+                name: "admin".to_string(),
+                password: "hunter2".to_string(),
+            }],
+            ..Config::default()
+        };
+        let clock = Arc::new(ManualClock::new());
+        let state = Arc::new(ServerState::new(
+            config,
+            AuditLog::open(None).unwrap(),
+            clock,
+        ));
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            handle_connection(stream, state);
+        });
+
+        let mut client = TcpStream::connect(listener_address).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        client
+            .write_all(b"NICK alice\r\nUSER alice 0 * :Alice\r\nOPER admin hunter2\r\n")
+            .unwrap();
+        client.write_all(b"WHOIS alice\r\nQUIT\r\n").unwrap();
+        let mut output = String::new();
+        client.read_to_string(&mut output).unwrap();
+        server.join().unwrap();
+
+        let address = client.local_addr().unwrap().ip();
+        assert!(output.contains(&format!(
+            ":irc.test 338 alice alice {address} :actually using host"
+        )));
     }
 
-    return None;
+    #[test]
+    fn private_users_hide_from_whois_and_can_see_who_asked() {
+        let (state, _) = state();
+        let (alice, alice_output) = register(&state, "alice");
+        let (bob, bob_output) = register(&state, "bob");
+        send(&state, alice, "JOIN #rust");
+
+        // Only operators are told about WHOIS by default
+        send(&state, alice, "MODE alice +Wpx");
+        assert_eq!(
+            alice_output.lines(),
+            [
+                ":127.0.0.1 501 alice :Unknown MODE flag",
+                ":alice!alice@127.0.0.1 MODE alice +p",
+            ]
+        );
+        send(&state, bob, "MODE alice");
+        assert_eq!(
+            bob_output.lines(),
+            [":127.0.0.1 502 bob :Cannot change mode for other users"]
+        );
+
+        state.users.get_mut(&alice).unwrap().is_operator = true;
+        send(&state, alice, "MODE alice +W");
+        send(&state, alice, "MODE alice");
+        assert_eq!(
+            alice_output.lines(),
+            [
+                ":alice!alice@127.0.0.1 MODE alice +W",
+                ":127.0.0.1 221 alice +Wop",
+            ]
+        );
+
+        send(&state, bob, "WHOIS alice");
+        let whois = bob_output.lines();
+        assert!(whois.iter().any(|line| line.contains(" 313 ")));
+        assert!(
+            !whois
+                .iter()
+                .any(|line| line.contains(" 319 ") || line.contains(" 317 "))
+        );
+        assert_eq!(
+            alice_output.lines(),
+            [":127.0.0.1 NOTICE alice :*** bob!bob@127.0.0.1 did a /WHOIS on you."]
+        );
+
+        // Without operator status, the notices go too
+        send(&state, alice, "MODE alice -o");
+        assert_eq!(
+            alice_output.lines(),
+            [":alice!alice@127.0.0.1 MODE alice -oW"]
+        );
+    }
+
+    #[test]
+    fn operators_get_the_server_notices_they_subscribe_to() {
+        let (state, _) = state();
+        let (alice, alice_output) = register(&state, "alice");
+        let (bob, bob_output) = register(&state, "bob");
+
+        send(&state, bob, "MODE bob +s");
+        assert!(bob_output.lines().is_empty());
+
+        state.users.get_mut(&alice).unwrap().is_operator = true;
+        send(&state, alice, "MODE alice +s +cF");
+        assert_eq!(
+            alice_output.lines(),
+            [
+                ":alice!alice@127.0.0.1 MODE alice +s +c",
+                ":127.0.0.1 008 alice +c :Server notice mask",
+            ]
+        );
+        register(&state, "carol");
+        send(&state, bob, "OPER admin hunter2");
+        assert_eq!(
+            alice_output.lines(),
+            [":127.0.0.1 NOTICE alice :*** Client connecting: carol!carol@127.0.0.1"]
+        );
+
+        send(&state, alice, "MODE alice +s -c+o");
+        alice_output.lines();
+        send(&state, bob, "OPER admin hunter2");
+        assert_eq!(
+            alice_output.lines(),
+            [":127.0.0.1 NOTICE alice :*** Failed OPER attempt by bob!bob@127.0.0.1 (admin)"]
+        );
+
+        send(&state, alice, "MODE alice -s");
+        send(&state, alice, "MODE alice");
+        assert_eq!(
+            alice_output.lines(),
+            [
+                ":alice!alice@127.0.0.1 MODE alice -s",
+                ":127.0.0.1 008 alice + :Server notice mask",
+                ":127.0.0.1 221 alice +o",
+            ]
+        );
+    }
+
+    #[test]
+    fn replies_are_sent_in_the_configured_texts() {
+        let (mut state, _) = state();
+        let french = [("401", "Pseudo inconnu"), ("403", "Canal inconnu")];
+        let french = french.map(|(key, text)| (key.to_string(), text.to_string()));
+        state.catalog = Catalog::new(&TextsConfig {
+            language: Some("fr".to_string()),
+            languages: HashMap::from([("fr".to_string(), LanguageTexts::Texts(french.into()))]),
+            overrides: HashMap::from([("403".to_string(), "Pas de canal".to_string())]),
+        });
+        let (alice, alice_output) = register(&state, "alice");
+
+        send(&state, alice, "PRIVMSG bob :hi");
+        send(&state, alice, "PART #rust");
+        assert_eq!(
+            alice_output.lines(),
+            [
+                ":127.0.0.1 401 alice bob :Pseudo inconnu",
+                ":127.0.0.1 403 alice #rust :Pas de canal",
+            ]
+        );
+    }
+
+    #[test]
+    fn clients_that_fall_behind_are_disconnected() {
+        let output = Output::default();
+        let writer = Writer::new(output.clone(), 16, "SendQ exceeded.");
+        writer.send(&Message::privmsg("#rust", "longer than the send queue"));
+        writer.send(&Message::privmsg("#rust", "hi"));
+        writer.close();
+        assert_eq!(output.lines(), ["ERROR :SendQ exceeded."]);
+    }
+
+    #[test]
+    fn lockdowns_expire() {
+        let (state, clock) = state();
+        state.defcon.set(3, Some(Duration::from_secs(60)));
+        clock.advance(Duration::from_secs(59));
+        assert_eq!(state.defcon.level(), 3);
+        assert_eq!(state.defcon.remaining(), Some(Duration::from_secs(1)));
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(state.defcon.level(), defcon::NORMAL);
+    }
+
+    #[test]
+    fn connections_end_after_quit() {
+        let state = Arc::new(state().0);
+        let output = Output::default();
+        let input = Cursor::new("NICK alice\r\nUSER alice 0 * :Alice\r\nQUIT\r\nPING :late\r\n");
+        handle_stream(
+            input,
+            output.clone(),
+            IpAddr::from([127, 0, 0, 1]),
+            state.clone(),
+        );
+
+        let lines = output.lines();
+        assert_eq!(
+            lines.first().map(String::as_str),
+            Some(
+                ":127.0.0.1 001 alice :Welcome to the Internet Relay Network alice!alice@127.0.0.1"
+            )
+        );
+        // Nothing after QUIT is handled, and the user is gone
+        assert_eq!(
+            lines.last().map(String::as_str),
+            Some(":127.0.0.1 ERROR :User disconnected.")
+        );
+        assert!(state.users.is_empty());
+    }
+
+    #[test]
+    fn unknown_commands_share_a_metrics_label() {
+        let state = Arc::new(state().0);
+        let input = Cursor::new("NICK alice\r\nUSER alice 0 * :Alice\r\nBOGUS\r\n");
+        handle_stream(
+            input,
+            Output::default(),
+            IpAddr::from([127, 0, 0, 1]),
+            state.clone(),
+        );
+
+        let metrics = METRICS.render(&state);
+        assert!(metrics.contains("irc_messages_total{command=\"UNKNOWN\"}"));
+        assert!(!metrics.contains("BOGUS"));
+    }
 }
@@ -0,0 +1,84 @@
+//! Server notice masks, which choose the kinds of events an operator is sent notices about. They're
+//! set with user mode `+s` and letters to add or remove, like `MODE alice +s +cf` or
+//! `MODE alice +s -f`.
+
+use std::{collections::BTreeSet, fmt};
+
+/// A kind of event operators can be sent notices about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Snomask {
+    /// Clients finishing registration and disconnecting
+    Connects,
+    /// Clients sending lines that are too long, or falling so far behind that they're disconnected
+    Floods,
+    /// Attempts to become an operator with OPER
+    Opers,
+}
+
+impl Snomask {
+    const ALL: [Snomask; 3] = [Snomask::Connects, Snomask::Floods, Snomask::Opers];
+
+    pub fn letter(self) -> char {
+        match self {
+            Snomask::Connects => 'c',
+            Snomask::Floods => 'f',
+            Snomask::Opers => 'o',
+        }
+    }
+
+    pub fn from_letter(letter: char) -> Option<Self> {
+        Snomask::ALL
+            .into_iter()
+            .find(|snomask| snomask.letter() == letter)
+    }
+}
+
+/// The kinds of events a user is sent notices about. Shown as letters, like `+cf`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Snomasks(BTreeSet<Snomask>);
+
+impl Snomasks {
+    /// Every kind of event, which is what `+s` without a mask subscribes to
+    pub fn all() -> Self {
+        Snomasks(Snomask::ALL.into())
+    }
+
+    pub fn contains(&self, snomask: Snomask) -> bool {
+        self.0.contains(&snomask)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Add and remove kinds of events with a change like `+cf-o`. Letters before any sign are
+    /// added, and ones that aren't known are ignored.
+    pub fn apply(&mut self, change: &str) {
+        let mut adding = true;
+        for letter in change.chars() {
+            match letter {
+                '+' => adding = true,
+                '-' => adding = false,
+                letter => {
+                    if let Some(snomask) = Snomask::from_letter(letter) {
+                        if adding {
+                            self.0.insert(snomask);
+                        } else {
+                            self.0.remove(&snomask);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Snomasks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "+")?;
+        for snomask in &self.0 {
+            write!(f, "{}", snomask.letter())?;
+        }
+        Ok(())
+    }
+}
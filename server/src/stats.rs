@@ -0,0 +1,103 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::PathBuf,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+use tracing::warn;
+
+/// Usage statistics shown by LUSERS, STATS u and the metrics endpoint.
+#[derive(Debug)]
+pub struct ServerStats {
+    pub started_at: DateTime<Utc>,
+    started: Instant,
+    /// Connections accepted since startup
+    total_connections: AtomicU64,
+    /// Most simultaneous connections since startup
+    peak_connections: AtomicU64,
+    /// Most simultaneous connections ever, kept across restarts if a stats file is configured
+    all_time_peak: Mutex<PeakRecord>,
+    file: Option<PathBuf>,
+}
+
+/// Contents of the stats file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PeakRecord {
+    pub connections: u64,
+    pub time: Option<DateTime<Utc>>,
+}
+
+impl ServerStats {
+    /// Start tracking statistics, loading the historical peak from `file` if it exists.
+    pub fn new(file: Option<PathBuf>) -> Self {
+        let all_time_peak = file
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|text| match serde_json::from_str(&text) {
+                Ok(record) => Some(record),
+                Err(e) => {
+                    warn!("Ignoring unreadable stats file: {e}");
+                    None
+                }
+            })
+            .unwrap_or_default();
+
+        ServerStats {
+            started_at: Utc::now(),
+            started: Instant::now(),
+            total_connections: AtomicU64::new(0),
+            peak_connections: AtomicU64::new(0),
+            all_time_peak: Mutex::new(all_time_peak),
+            file,
+        }
+    }
+
+    /// Record a new connection, given how many connections are open now that it was accepted.
+    pub fn connection_opened(&self, current: usize) {
+        let current = current as u64;
+        self.total_connections.fetch_add(1, Ordering::Relaxed);
+        self.peak_connections.fetch_max(current, Ordering::Relaxed);
+
+        let mut all_time_peak = self.all_time_peak.lock().unwrap();
+        if current > all_time_peak.connections {
+            *all_time_peak = PeakRecord {
+                connections: current,
+                time: Some(Utc::now()),
+            };
+            self.save(&all_time_peak);
+        }
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    pub fn total_connections(&self) -> u64 {
+        self.total_connections.load(Ordering::Relaxed)
+    }
+
+    pub fn peak_connections(&self) -> u64 {
+        self.peak_connections.load(Ordering::Relaxed)
+    }
+
+    pub fn all_time_peak(&self) -> PeakRecord {
+        self.all_time_peak.lock().unwrap().clone()
+    }
+
+    fn save(&self, record: &PeakRecord) {
+        let Some(path) = &self.file else {
+            return;
+        };
+        let result = serde_json::to_string(record)
+            .map_err(|e| e.to_string())
+            .and_then(|json| fs::write(path, json).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            warn!("Failed to save stats file: {e}");
+        }
+    }
+}
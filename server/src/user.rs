@@ -1,56 +1,519 @@
 use std::{
-    net::{IpAddr, TcpStream},
-    sync::Arc,
+    collections::{HashMap, HashSet, VecDeque},
+    fmt,
+    io::Write,
+    net::{IpAddr, Shutdown, TcpStream},
+    sync::{Arc, Condvar, Mutex, atomic::Ordering},
+    thread,
+    time::{Duration, Instant},
 };
 
+use chrono::{DateTime, Utc};
+use tracing::{debug, warn};
 use uuid::Uuid;
 
+use crate::{metrics::METRICS, snomask::Snomasks};
+use shared::{
+    casemap::IrcString,
+    mask,
+    message::{Message, ToIrc},
+    prefix::Prefix,
+};
+
 #[derive(Debug)]
 pub struct User {
     pub id: Uuid,
-    pub nickname: Option<String>,
-    pub username: Option<String>,
-    pub hostname: String,
-    pub channel: Option<Arc<Channel>>,
+    nickname: Option<Arc<str>>,
+    username: Option<Arc<str>>,
+    pub hostname: Arc<str>,
+    /// Kept up to date as the nickname and username are set, so that it can be put on every
+    /// message from the user without copying any of them
+    prefix: Option<Prefix>,
+    /// The real name given with USER
+    pub realname: String,
+    /// The channels the user is in, in the order they joined them
+    pub channels: Vec<Arc<Channel>>,
     pub is_registered: bool,
-    pub is_away: bool,
-    pub stream: TcpStream,
+    /// When the user finished registering
+    pub signon: DateTime<Utc>,
+    /// When the user last sent a PRIVMSG, or registered if they haven't, which WHOIS counts how
+    /// long they've been idle from
+    pub last_active: Instant,
+    /// What the user said they're doing while away, cut to the configured length
+    pub away_message: Option<String>,
+    /// Identified as an IRC operator with the OPER command
+    pub is_operator: bool,
+    /// User mode `+W`, to be told when someone WHOISes them
+    pub sees_whois: bool,
+    /// User mode `+p`, which hides how long they've been idle and what channel they're in from
+    /// WHOIS, except to themselves and operators
+    pub is_private: bool,
+    /// User mode `+s`, the kinds of events an operator is sent server notices about
+    pub snomasks: Snomasks,
+    /// Restricted by an operator so that only PING, PONG and QUIT are processed
+    pub is_shunned: bool,
+    /// Capabilities the client turned on with CAP REQ
+    pub caps: HashSet<&'static str>,
+    /// Registration waits while the client negotiates capabilities, until it sends CAP END
+    pub is_negotiating_caps: bool,
+    /// Sent `AUTHENTICATE PLAIN`, so its credentials come next
+    pub is_authenticating: bool,
+    /// The account the user logged into with SASL
+    pub account: Option<String>,
+    /// SASL logins that failed on this connection, which is closed after too many
+    pub sasl_failures: usize,
+    pub writer: Writer,
+}
+
+/// The writing half of a user's connection. Lines sent to it wait in a queue that a thread of the
+/// connection's own writes out, so a client that's slow to read only holds up itself. It's
+/// cloned out of the user table before sending, so that nothing in the table stays locked while
+/// sending either.
+#[derive(Debug, Clone)]
+pub struct Writer(Arc<Handle>);
+
+/// Shared by the clones of a writer, and closes the queue once the last of them is dropped
+#[derive(Debug)]
+struct Handle(Arc<SendQueue>);
+
+#[derive(Debug)]
+struct SendQueue {
+    state: Mutex<QueueState>,
+    changed: Condvar,
+    /// Most bytes that may be waiting before the client is disconnected
+    max_bytes: usize,
+    /// The ERROR the client is disconnected with
+    exceeded: Option<Line>,
+}
+
+#[derive(Debug, Default)]
+struct QueueState {
+    lines: VecDeque<Line>,
+    /// Bytes in `lines`
+    bytes: usize,
+    /// Whether the thread is writing a line it took from the queue
+    writing: bool,
+    /// Whether no more lines are taken, so that the thread ends once it's written the rest
+    closed: bool,
+    /// Whether the thread has ended and the connection is closed
+    finished: bool,
+    /// Whether the client was disconnected for falling too far behind
+    exceeded: bool,
+}
+
+/// A message written out as the line to send, which can be shared by every user it's sent to
+pub type Line = Arc<[u8]>;
+
+/// Write out a message to send. One that can't be sent as it is is dropped with a warning.
+pub fn encode<T: ToIrc>(message: &T) -> Option<Line> {
+    match message.to_irc() {
+        Ok(line) => Some(Line::from(line.into_bytes())),
+        Err(e) => {
+            warn!("Dropped a message that can't be sent: {e}");
+            None
+        }
+    }
+}
+
+impl Writer {
+    /// Start writing to a connection. Once more than `max_bytes` are waiting to be written, the
+    /// client is sent an ERROR with the text and disconnected.
+    pub fn new(transport: impl Transport + 'static, max_bytes: usize, exceeded: &str) -> Self {
+        let queue = Arc::new(SendQueue {
+            state: Mutex::new(QueueState::default()),
+            changed: Condvar::new(),
+            max_bytes,
+            exceeded: encode(&Message::error(exceeded)),
+        });
+        let thread_queue = queue.clone();
+        thread::spawn(move || thread_queue.write_to(Box::new(transport)));
+        Writer(Arc::new(Handle(queue)))
+    }
+
+    /// Queue a message to be written to the connection. One that can't be sent as it is, such as
+    /// one with a space in a parameter before the last, is dropped.
+    pub fn send<T: ToIrc>(&self, message: &T) {
+        if let Some(line) = encode(message) {
+            self.send_line(&line);
+        }
+    }
+
+    /// Queue a message that was already written out with [`encode`], which is how a message
+    /// going to many users is only written out once. Lines sent after the connection is closed
+    /// are dropped.
+    pub fn send_line(&self, line: &Line) {
+        let queue = &self.0.0;
+        let mut state = queue.state.lock().unwrap();
+        if state.closed {
+            return;
+        }
+
+        if state.bytes + line.len() > queue.max_bytes {
+            warn!(
+                queued = state.bytes,
+                "Send queue exceeded, closing connection"
+            );
+            METRICS.sendq_exceeded.fetch_add(1, Ordering::Relaxed);
+            state.clear();
+            state.closed = true;
+            state.exceeded = true;
+            if let Some(error) = &queue.exceeded {
+                state.push(error.clone());
+            }
+        } else {
+            state.push(line.clone());
+        }
+        queue.changed.notify_all();
+    }
+
+    /// Whether the client was disconnected for having more than `max_bytes` waiting
+    pub fn is_exceeded(&self) -> bool {
+        self.0.0.state.lock().unwrap().exceeded
+    }
+
+    /// Stop taking lines and wait until the ones already queued are written and the connection
+    /// is closed
+    pub fn close(&self) {
+        let queue = &self.0.0;
+        queue.close();
+        let state = queue.state.lock().unwrap();
+        let _finished = queue.changed.wait_while(state, |state| !state.finished);
+    }
+
+    /// Wait until everything queued so far is written
+    #[cfg(test)]
+    pub fn flush(&self) {
+        let queue = &self.0.0;
+        let state = queue.state.lock().unwrap();
+        let _flushed = queue.changed.wait_while(state, |state| {
+            !state.finished && (state.writing || !state.lines.is_empty())
+        });
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        self.0.close();
+    }
+}
+
+impl SendQueue {
+    /// Write queued lines until the queue is closed and empty, or the connection fails
+    fn write_to(&self, mut transport: Box<dyn Transport>) {
+        loop {
+            let line = {
+                let state = self.state.lock().unwrap();
+                let mut state = self
+                    .changed
+                    .wait_while(state, |state| state.lines.is_empty() && !state.closed)
+                    .unwrap();
+                let Some(line) = state.pop() else {
+                    break;
+                };
+                state.writing = true;
+                line
+            };
+
+            let result = transport.write_all(&line);
+            self.state.lock().unwrap().writing = false;
+            self.changed.notify_all();
+            if let Err(e) = result {
+                debug!("Failed to write to connection: {e}");
+                break;
+            }
+            METRICS
+                .bytes_out
+                .fetch_add(line.len() as u64, Ordering::Relaxed);
+        }
+
+        // Ends the reads from the connection too, if the client is still sending
+        transport.close();
+        let mut state = self.state.lock().unwrap();
+        state.clear();
+        state.closed = true;
+        state.finished = true;
+        self.changed.notify_all();
+    }
+
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.changed.notify_all();
+    }
+}
+
+impl QueueState {
+    fn push(&mut self, line: Line) {
+        self.bytes += line.len();
+        METRICS
+            .queued_bytes
+            .fetch_add(line.len() as u64, Ordering::Relaxed);
+        self.lines.push_back(line);
+    }
+
+    fn pop(&mut self) -> Option<Line> {
+        let line = self.lines.pop_front()?;
+        self.bytes -= line.len();
+        METRICS
+            .queued_bytes
+            .fetch_sub(line.len() as u64, Ordering::Relaxed);
+        Some(line)
+    }
+
+    fn clear(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
+
+/// Where a user's messages are written, which is their socket except in tests
+pub trait Transport: Write + Send + fmt::Debug {
+    /// End the connection, once everything for it is written or writing to it failed
+    fn close(&mut self) {}
+}
+
+impl Transport for TcpStream {
+    fn close(&mut self) {
+        let _ = self.shutdown(Shutdown::Both);
+    }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug)]
 pub struct Channel {
     pub id: Uuid,
-    pub name: String,
+    /// The name as it was first joined, which is compared ignoring case
+    pub name: IrcString,
+    pub modes: Mutex<ChannelModes>,
+    /// Kept alongside each user's `channels`, so that the channel can be counted without going
+    /// through every user
+    members: Mutex<HashSet<Uuid>>,
+    operators: Mutex<HashSet<Uuid>>,
+    last_message: Mutex<HashMap<Uuid, Instant>>,
+}
+
+/// Modes that can be set on a channel with the MODE command.
+#[derive(Debug, Default, Clone)]
+pub struct ChannelModes {
+    /// Minimum number of seconds a non-operator has to wait between messages (`+W <seconds>`)
+    pub slow: Option<u64>,
+    /// Masks of users who may stay in the channel but not speak in it (`+q <mask>`)
+    pub quiets: Vec<String>,
+    /// Only users logged into an account may join (`+r`)
+    pub registered_only: bool,
 }
 
 impl User {
-    pub fn new(hostname: IpAddr, writer: TcpStream) -> Self {
+    pub fn new(hostname: IpAddr, writer: Writer) -> Self {
         User {
             id: Uuid::new_v4(),
             nickname: None,
             username: None,
-            hostname: hostname.to_string(),
-            channel: None,
+            hostname: hostname.to_string().into(),
+            prefix: None,
+            realname: String::new(),
+            channels: vec![],
             is_registered: false,
-            is_away: false,
-            stream: writer,
+            signon: Utc::now(),
+            last_active: Instant::now(),
+            away_message: None,
+            is_operator: false,
+            is_shunned: false,
+            sees_whois: false,
+            is_private: false,
+            snomasks: Snomasks::default(),
+            caps: HashSet::new(),
+            is_negotiating_caps: false,
+            is_authenticating: false,
+            account: None,
+            sasl_failures: 0,
+            writer,
+        }
+    }
+
+    /// Return the user's modes for RPL_UMODEIS, e.g. `+Wo`.
+    pub fn mode_string(&self) -> String {
+        let mut modes = String::from("+");
+        for (mode, set) in [
+            ('W', self.sees_whois),
+            ('o', self.is_operator),
+            ('p', self.is_private),
+            ('s', !self.snomasks.is_empty()),
+        ] {
+            if set {
+                modes.push(mode);
+            }
         }
+        modes
+    }
+
+    pub fn is_in(&self, channel: &Channel) -> bool {
+        self.channels.iter().any(|joined| **joined == *channel)
+    }
+
+    /// Take the user out of a channel, returning whether they were in it
+    pub fn leave(&mut self, channel: &Channel) -> bool {
+        let count = self.channels.len();
+        self.channels.retain(|joined| **joined != *channel);
+        self.channels.len() != count
+    }
+
+    pub fn nickname(&self) -> Option<&Arc<str>> {
+        self.nickname.as_ref()
+    }
+
+    /// Change the user's nickname, returning the old one
+    pub fn set_nickname(&mut self, nickname: Arc<str>) -> Option<Arc<str>> {
+        let old_nickname = self.nickname.replace(nickname);
+        self.update_prefix();
+        old_nickname
+    }
+
+    pub fn username(&self) -> Option<&Arc<str>> {
+        self.username.as_ref()
+    }
+
+    pub fn set_username(&mut self, username: &str) {
+        self.username = Some(username.into());
+        self.update_prefix();
+    }
+
+    /// The user's `nick!user@host`, once they've given both a nickname and a username
+    pub fn prefix(&self) -> Option<Prefix> {
+        self.prefix.clone()
     }
 
-    pub fn prefix(&self) -> Option<String> {
-        if let (Some(nickname), Some(username)) = (&self.nickname, &self.username) {
-            Some(format!("{}!{}@{}", nickname, username, self.hostname))
+    fn update_prefix(&mut self) {
+        self.prefix = if let (Some(nickname), Some(username)) = (&self.nickname, &self.username) {
+            Some(Prefix::User {
+                nick: nickname.clone(),
+                user: Some(username.clone()),
+                host: Some(self.hostname.clone()),
+            })
         } else {
             None
-        }
+        };
     }
 }
 
+/// Longest allowed channel name, including the prefix (RFC 2812 section 1.3).
+const MAX_CHANNEL_NAME_LENGTH: usize = 50;
+
 impl Channel {
-    pub fn new(name: &str) -> Channel {
+    pub fn new(name: IrcString) -> Channel {
         Channel {
             id: Uuid::new_v4(),
-            name: name.to_string(),
+            name,
+            modes: Mutex::new(ChannelModes::default()),
+            members: Mutex::new(HashSet::new()),
+            operators: Mutex::new(HashSet::new()),
+            last_message: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Check whether a message target refers to a channel rather than a nickname. Channels start
+    /// with `#` (network-wide) or `&` (local to this server). Since this server doesn't link to
+    /// others, both kinds currently behave the same.
+    pub fn is_channel_name(name: &str) -> bool {
+        name.starts_with(['#', '&'])
+    }
+
+    /// Check whether a channel name is allowed per RFC 2812: it must have a channel prefix, be at
+    /// most 50 characters long, and not contain spaces, commas, colons, control-G (BEL), NUL or
+    /// line endings.
+    pub fn is_valid_name(name: &str) -> bool {
+        Channel::is_channel_name(name)
+            && name.len() > 1
+            && name.len() <= MAX_CHANNEL_NAME_LENGTH
+            && !name.contains([' ', ',', '\x07', '\0', '\r', '\n', ':'])
+    }
+
+    pub fn is_operator(&self, id: Uuid) -> bool {
+        self.operators.lock().unwrap().contains(&id)
+    }
+
+    pub fn set_operator(&self, id: Uuid, is_operator: bool) {
+        let mut operators = self.operators.lock().unwrap();
+        if is_operator {
+            operators.insert(id);
+        } else {
+            operators.remove(&id);
+        }
+    }
+
+    /// Record that a user joined the channel. Returns whether it was empty before, so that they
+    /// can be made its operator.
+    pub fn add_member(&self, id: Uuid) -> bool {
+        let mut members = self.members.lock().unwrap();
+        let was_empty = members.is_empty();
+        members.insert(id);
+        was_empty
+    }
+
+    pub fn member_count(&self) -> usize {
+        self.members.lock().unwrap().len()
+    }
+
+    /// Forget a user that is leaving the channel, along with any per-member state kept for them.
+    pub fn remove_member(&self, id: Uuid) {
+        self.members.lock().unwrap().remove(&id);
+        self.operators.lock().unwrap().remove(&id);
+        self.last_message.lock().unwrap().remove(&id);
+    }
+
+    /// Check whether the user is allowed to speak under the channel's slow mode. If they are, the
+    /// time of the message is recorded and `None` is returned. Otherwise, return how much longer
+    /// they have to wait. Channel operators are never throttled.
+    pub fn slowmode_wait(&self, id: Uuid, now: Instant) -> Option<Duration> {
+        let slow = self.modes.lock().unwrap().slow?;
+        if self.is_operator(id) {
+            return None;
         }
+
+        let interval = Duration::from_secs(slow);
+        let mut last_message = self.last_message.lock().unwrap();
+        if let Some(last) = last_message.get(&id) {
+            let elapsed = now.duration_since(*last);
+            if elapsed < interval {
+                return Some(interval - elapsed);
+            }
+        }
+
+        last_message.insert(id, now);
+        None
+    }
+
+    /// Check whether a user's `nick!user@host` prefix matches any of the channel's quiet masks.
+    pub fn is_quieted(&self, prefix: &Prefix) -> bool {
+        let prefix = prefix.to_string();
+        self.modes
+            .lock()
+            .unwrap()
+            .quiets
+            .iter()
+            .any(|quiet| mask::matches(quiet, &prefix))
+    }
+
+    /// Return the channel's modes as parameters for RPL_CHANNELMODEIS, e.g. `["+W", "5"]`.
+    pub fn mode_params(&self) -> Vec<String> {
+        let modes = self.modes.lock().unwrap();
+        let mut flags = String::from("+");
+        let mut args = vec![];
+
+        if modes.registered_only {
+            flags.push('r');
+        }
+        if let Some(slow) = modes.slow {
+            flags.push('W');
+            args.push(slow.to_string());
+        }
+
+        let mut params = vec![flags];
+        params.extend(args);
+        params
+    }
+}
+
+// Channels are shared through `Arc`s, so two handles refer to the same channel if their IDs match
+impl PartialEq for Channel {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
     }
 }
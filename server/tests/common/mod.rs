@@ -0,0 +1,173 @@
+//! Runs the server binary on a free port and connects scripted clients to it, which check the
+//! exact lines the server sends them.
+
+#![allow(dead_code)]
+
+use std::{
+    env, fs,
+    io::{self, BufRead, BufReader, ErrorKind, Write},
+    net::{TcpListener, TcpStream},
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How long to wait for a line the server should send
+const RECV_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait before deciding the server isn't sending anything more
+const QUIET_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// The address the server replies from, which is also the host of every user
+pub const HOST: &str = "127.0.0.1";
+
+/// A server of its own for one test, stopped when dropped
+pub struct TestServer {
+    child: Child,
+    config: PathBuf,
+    port: u16,
+}
+
+impl TestServer {
+    /// Start a server with the default config, apart from the port
+    pub fn start() -> Self {
+        TestServer::with_config("")
+    }
+
+    /// Start a server with extra TOML settings, like `[[opers]]`
+    pub fn with_config(extra: &str) -> Self {
+        // Ask the OS for a port nothing is using and give it to the server
+        let port = TcpListener::bind((HOST, 0))
+            .and_then(|listener| listener.local_addr())
+            .expect("Couldn't find a free port.")
+            .port();
+        let config =
+            env::temp_dir().join(format!("irc_rs-test-{}-{port}.toml", std::process::id()));
+        fs::write(
+            &config,
+            format!("hostname = \"{HOST}\"\nport = {port}\n{extra}\n"),
+        )
+        .expect("Couldn't write the server config.");
+
+        let child = Command::new(env!("CARGO_BIN_EXE_server"))
+            .arg(&config)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("Couldn't start the server.");
+        let server = TestServer {
+            child,
+            config,
+            port,
+        };
+
+        // Wait for it to listen
+        let start = Instant::now();
+        let mut probe = loop {
+            if let Ok(probe) = TcpStream::connect((HOST, port)) {
+                break probe;
+            }
+            assert!(
+                start.elapsed() < RECV_TIMEOUT,
+                "The server didn't start listening."
+            );
+            thread::sleep(Duration::from_millis(20));
+        };
+        // Quit and wait for the server to close the connection, which it does once it has
+        // forgotten the user, so that tests don't see it in the user counts
+        probe.set_read_timeout(Some(RECV_TIMEOUT)).unwrap();
+        let _ = probe.write_all(b"QUIT\r\n");
+        let _ = io::copy(&mut probe, &mut io::sink());
+        server
+    }
+
+    /// Connect without registering
+    pub fn connect(&self) -> TestClient {
+        let stream =
+            TcpStream::connect((HOST, self.port)).expect("Couldn't connect to the server.");
+        stream.set_read_timeout(Some(RECV_TIMEOUT)).unwrap();
+        TestClient {
+            reader: BufReader::new(stream.try_clone().unwrap()),
+            writer: stream,
+        }
+    }
+
+    /// Connect and register as a nickname, skipping the welcome
+    pub fn register(&self, nick: &str) -> TestClient {
+        let mut client = self.connect();
+        client.send(&format!("NICK {nick}"));
+        client.send(&format!("USER {nick} 0 * :{nick}"));
+        // The welcome ends with RPL_GLOBALUSERS
+        while !client.recv().starts_with(&format!(":{HOST} 266 ")) {}
+        client
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = fs::remove_file(&self.config);
+    }
+}
+
+/// One connection to the server, sending and expecting lines without their CRLF
+pub struct TestClient {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl TestClient {
+    pub fn send(&mut self, line: &str) {
+        self.writer
+            .write_all(format!("{line}\r\n").as_bytes())
+            .expect("Couldn't send to the server.");
+    }
+
+    /// The next line, failing the test if none comes in time
+    pub fn recv(&mut self) -> String {
+        let mut line = String::new();
+        match self.reader.read_line(&mut line) {
+            Ok(0) => panic!("The server closed the connection."),
+            Ok(_) => line.trim_end_matches(['\r', '\n']).to_string(),
+            Err(e) => panic!("No line from the server: {e}"),
+        }
+    }
+
+    /// Check that the next lines are exactly these, in order
+    pub fn expect(&mut self, lines: &[&str]) {
+        for expected in lines {
+            assert_eq!(self.recv(), *expected);
+        }
+    }
+
+    /// Check that the server isn't sending anything more for now
+    pub fn expect_nothing(&mut self) {
+        let stream = self.reader.get_ref();
+        stream.set_read_timeout(Some(QUIET_TIMEOUT)).unwrap();
+        let mut line = String::new();
+        let result = self.reader.read_line(&mut line);
+        self.reader
+            .get_ref()
+            .set_read_timeout(Some(RECV_TIMEOUT))
+            .unwrap();
+        match result {
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Ok(0) => panic!("The server closed the connection."),
+            Ok(_) => panic!("Unexpected line from the server: {}", line.trim_end()),
+            Err(e) => panic!("Couldn't read from the server: {e}"),
+        }
+    }
+
+    /// Check that the server closes the connection once it's sent what's left
+    pub fn expect_closed(&mut self) {
+        let mut line = String::new();
+        match self.reader.read_line(&mut line) {
+            Ok(0) => {}
+            Ok(_) => panic!("Unexpected line from the server: {}", line.trim_end()),
+            Err(e) if e.kind() == ErrorKind::ConnectionReset => {}
+            Err(e) => panic!("The server didn't close the connection: {e}"),
+        }
+    }
+}
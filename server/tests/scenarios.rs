@@ -0,0 +1,105 @@
+//! Clients registering, talking in channels, kicking and quitting, checked against the exact lines
+//! the server sends each of them.
+
+mod common;
+
+use common::TestServer;
+
+#[test]
+fn registering_sends_the_welcome() {
+    let server = TestServer::start();
+    let mut alice = server.connect();
+    alice.send("NICK alice");
+    alice.send("USER alice 0 * :Alice");
+    alice.expect(&[
+        ":127.0.0.1 001 alice :Welcome to the Internet Relay Network alice!alice@127.0.0.1",
+        ":127.0.0.1 005 alice CHANTYPES=#& CHANLIMIT=#&:20 :are supported by this server",
+        ":127.0.0.1 251 alice :There are 1 users and 0 invisible on 1 servers",
+        ":127.0.0.1 252 alice 0 :operator(s) online",
+        ":127.0.0.1 253 alice 0 :unknown connection(s)",
+        ":127.0.0.1 254 alice 0 :channels formed",
+        ":127.0.0.1 255 alice :I have 1 clients and 0 servers",
+        ":127.0.0.1 265 alice 1 1 :Current local users 1, max 1",
+        ":127.0.0.1 266 alice 1 1 :Current global users 1, max 1",
+    ]);
+    alice.expect_nothing();
+}
+
+#[test]
+fn nicknames_in_use_are_refused() {
+    let server = TestServer::start();
+    let _alice = server.register("alice");
+    let mut other = server.connect();
+    other.send("NICK Alice");
+    other.expect(&[":127.0.0.1 433 * Alice :Nickname is already in use"]);
+}
+
+#[test]
+fn channel_messages_reach_the_other_members() {
+    let server = TestServer::start();
+    let mut alice = server.register("alice");
+    let mut bob = server.register("bob");
+    let mut carol = server.register("carol");
+
+    alice.send("JOIN #rust");
+    alice.expect_nothing();
+    bob.send("JOIN #rust");
+    alice.expect(&[":bob!bob@127.0.0.1 JOIN #rust"]);
+
+    bob.send("PRIVMSG #rust :hello, world");
+    alice.expect(&[":bob!bob@127.0.0.1 PRIVMSG #rust :hello, world"]);
+    // Not back to the sender, nor to anyone outside the channel
+    bob.expect_nothing();
+    carol.expect_nothing();
+}
+
+#[test]
+fn private_messages_reach_only_their_target() {
+    let server = TestServer::start();
+    let mut alice = server.register("alice");
+    let mut bob = server.register("bob");
+    let mut carol = server.register("carol");
+
+    alice.send("PRIVMSG bob :psst");
+    bob.expect(&[":alice!alice@127.0.0.1 PRIVMSG bob psst"]);
+    alice.send("PRIVMSG dave :anyone?");
+    alice.expect(&[":127.0.0.1 401 alice dave :No such nick/channel"]);
+    carol.expect_nothing();
+}
+
+#[test]
+fn kicked_users_leave_the_channel() {
+    let server = TestServer::start();
+    let mut alice = server.register("alice");
+    let mut bob = server.register("bob");
+    alice.send("JOIN #rust");
+    alice.expect_nothing();
+    bob.send("JOIN #rust");
+    alice.expect(&[":bob!bob@127.0.0.1 JOIN #rust"]);
+
+    alice.send("KICK #rust bob :bye");
+    bob.expect(&[":alice!alice@127.0.0.1 KICK #rust bob bye"]);
+    alice.expect_nothing();
+
+    alice.send("PRIVMSG #rust :still here?");
+    bob.expect_nothing();
+}
+
+#[test]
+fn quitting_closes_the_connection() {
+    let server = TestServer::start();
+    let mut alice = server.register("alice");
+    let mut bob = server.register("bob");
+    alice.send("JOIN #rust");
+    alice.expect_nothing();
+    bob.send("JOIN #rust");
+    alice.expect(&[":bob!bob@127.0.0.1 JOIN #rust"]);
+
+    bob.send("QUIT :done");
+    bob.expect(&[":127.0.0.1 ERROR :User disconnected."]);
+    bob.expect_closed();
+    alice.expect(&[":bob!bob@127.0.0.1 QUIT done"]);
+
+    // The nickname is free again
+    let _bob = server.register("bob");
+}
@@ -0,0 +1,51 @@
+//! How many messages can be parsed and written a second, for lines like the ones servers and
+//! clients see most. Run with `cargo bench -p shared`.
+
+use criterion::{BenchmarkId, Criterion, Throughput, black_box, criterion_group, criterion_main};
+use shared::message::{Message, ToIrc};
+
+const LINES: [(&str, &str); 3] = [
+    (
+        "privmsg",
+        ":alice!alice@example.com PRIVMSG #rust :hello world",
+    ),
+    (
+        "tagged",
+        "@time=2024-05-01T18:30:00.000Z;msgid=63b1f9c2-7a2d;account=alice;batch=1;\
+         +draft/reply=5e2c;+example.com/emoji=\\:party\\:\\s\\\\o/ \
+         :alice!~alice@user/alice/host.example.com PRIVMSG #rust :did anyone try the new \
+         release? the changelog says parsing got faster",
+    ),
+    (
+        "15 params",
+        ":irc.example.com 005 alice AWAYLEN=200 CASEMAPPING=rfc1459 CHANLIMIT=#&:100 \
+         CHANMODES=beI,k,l,imnpst CHANNELLEN=64 CHANTYPES=#& KICKLEN=255 MODES=4 NETWORK=Example \
+         MAXLIST=beI:100 NICKLEN=30 PREFIX=(ov)@+ TOPICLEN=390 :are supported by this server",
+    ),
+];
+
+fn parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    group.throughput(Throughput::Elements(1));
+    for (name, line) in LINES {
+        group.bench_with_input(BenchmarkId::from_parameter(name), line, |b, line| {
+            b.iter(|| Message::from(black_box(line)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn write(c: &mut Criterion) {
+    let mut group = c.benchmark_group("write");
+    group.throughput(Throughput::Elements(1));
+    for (name, line) in LINES {
+        let message = Message::from(line).unwrap();
+        group.bench_with_input(BenchmarkId::from_parameter(name), &message, |b, message| {
+            b.iter(|| black_box(message).to_irc().unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, parse, write);
+criterion_main!(benches);
@@ -0,0 +1,23 @@
+//! Feeds arbitrary lines into `Message::from`, tags and all. Run with
+//! `cargo +nightly fuzz run parse_message` from `shared`.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use shared::message::Message;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(line) = std::str::from_utf8(data) else {
+        return;
+    };
+    let Ok(message) = Message::from(line) else {
+        return;
+    };
+    // Anything that parses has to be written back as a line that parses the same
+    let written = message.to_string();
+    assert!(
+        message.validate().is_ok(),
+        "{line:?} was written as {written:?}"
+    );
+    assert_eq!(Message::from(&written).as_ref(), Ok(&message), "{line:?}");
+});
@@ -0,0 +1,204 @@
+//! Comparing nicknames and channel names the way IRC servers do. Besides ASCII letters, most
+//! servers treat `[]\~` as the uppercase forms of `{}|^`, since they were in the Scandinavian
+//! character sets IRC started out with. Which rules a server uses is in the `CASEMAPPING` it
+//! advertises.
+
+use alloc::{string::String, sync::Arc};
+use core::{
+    cmp::Ordering,
+    fmt::{Display, Formatter},
+    hash::{Hash, Hasher},
+    ops::Deref,
+};
+
+/// The rules for which characters are the same letter in a different case
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IrcCasemap {
+    /// Only `A-Z` and `a-z`
+    Ascii,
+    /// ASCII letters, along with `[]\~` and `{}|^`. The default when a server doesn't say.
+    #[default]
+    Rfc1459,
+    /// ASCII letters, along with `[]\` and `{}|`
+    StrictRfc1459,
+}
+
+impl IrcCasemap {
+    /// The casemapping with the name a server advertises, like `rfc1459`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "ascii" => Some(IrcCasemap::Ascii),
+            "rfc1459" => Some(IrcCasemap::Rfc1459),
+            "strict-rfc1459" => Some(IrcCasemap::StrictRfc1459),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            IrcCasemap::Ascii => "ascii",
+            IrcCasemap::Rfc1459 => "rfc1459",
+            IrcCasemap::StrictRfc1459 => "strict-rfc1459",
+        }
+    }
+
+    /// The lowercase form of a character. Letters outside ASCII are left alone, as servers do.
+    pub fn to_lowercase_char(self, c: char) -> char {
+        match (self, c) {
+            (_, 'A'..='Z') => c.to_ascii_lowercase(),
+            (IrcCasemap::Rfc1459 | IrcCasemap::StrictRfc1459, '[') => '{',
+            (IrcCasemap::Rfc1459 | IrcCasemap::StrictRfc1459, ']') => '}',
+            (IrcCasemap::Rfc1459 | IrcCasemap::StrictRfc1459, '\\') => '|',
+            (IrcCasemap::Rfc1459, '~') => '^',
+            _ => c,
+        }
+    }
+
+    /// The lowercase form of a nickname or channel name, which is the same for every way of
+    /// writing it
+    pub fn to_lowercase(self, text: &str) -> String {
+        text.chars().map(|c| self.to_lowercase_char(c)).collect()
+    }
+
+    /// Whether two names are the same, ignoring case
+    pub fn eq_ignore_case(self, a: &str, b: &str) -> bool {
+        a.len() == b.len()
+            && a.chars()
+                .map(|c| self.to_lowercase_char(c))
+                .eq(b.chars().map(|c| self.to_lowercase_char(c)))
+    }
+
+    /// Compare two names as if they were lowercase
+    pub fn cmp_ignore_case(self, a: &str, b: &str) -> Ordering {
+        a.chars()
+            .map(|c| self.to_lowercase_char(c))
+            .cmp(b.chars().map(|c| self.to_lowercase_char(c)))
+    }
+}
+
+/// A nickname or channel name that's equal to, hashed and sorted like the same name in another
+/// case, so that it can be the key of a map. It keeps the case it was written in, shared so that
+/// copies of the name don't each need their own.
+///
+/// Names should only be compared with ones under the same casemapping, since the rules of the one
+/// on the left are used.
+#[derive(Debug, Clone)]
+pub struct IrcString {
+    text: Arc<str>,
+    casemap: IrcCasemap,
+}
+
+impl IrcString {
+    pub fn new(text: impl Into<Arc<str>>, casemap: IrcCasemap) -> Self {
+        IrcString {
+            text: text.into(),
+            casemap,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    pub fn casemap(&self) -> IrcCasemap {
+        self.casemap
+    }
+
+    /// The name, as the text it shares with every copy of it
+    pub fn as_shared(&self) -> &Arc<str> {
+        &self.text
+    }
+
+    pub fn into_string(self) -> String {
+        String::from(&*self.text)
+    }
+}
+
+/// A name under the default [`IrcCasemap::Rfc1459`] casemapping
+impl From<&str> for IrcString {
+    fn from(text: &str) -> Self {
+        IrcString::new(text, IrcCasemap::default())
+    }
+}
+
+/// A name under the default [`IrcCasemap::Rfc1459`] casemapping
+impl From<String> for IrcString {
+    fn from(text: String) -> Self {
+        IrcString::new(text, IrcCasemap::default())
+    }
+}
+
+/// A name under the default [`IrcCasemap::Rfc1459`] casemapping
+impl From<Arc<str>> for IrcString {
+    fn from(text: Arc<str>) -> Self {
+        IrcString::new(text, IrcCasemap::default())
+    }
+}
+
+impl PartialEq for IrcString {
+    fn eq(&self, other: &Self) -> bool {
+        self.casemap.eq_ignore_case(&self.text, &other.text)
+    }
+}
+
+impl Eq for IrcString {}
+
+impl PartialEq<str> for IrcString {
+    fn eq(&self, other: &str) -> bool {
+        self.casemap.eq_ignore_case(&self.text, other)
+    }
+}
+
+impl PartialEq<&str> for IrcString {
+    fn eq(&self, other: &&str) -> bool {
+        self.casemap.eq_ignore_case(&self.text, other)
+    }
+}
+
+impl PartialEq<String> for IrcString {
+    fn eq(&self, other: &String) -> bool {
+        self.casemap.eq_ignore_case(&self.text, other)
+    }
+}
+
+impl Hash for IrcString {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for c in self.text.chars() {
+            self.casemap.to_lowercase_char(c).hash(state);
+        }
+        // Like `str`, so that names next to each other in a tuple hash differently
+        state.write_u8(0xff);
+    }
+}
+
+impl PartialOrd for IrcString {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for IrcString {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.casemap.cmp_ignore_case(&self.text, &other.text)
+    }
+}
+
+impl Deref for IrcString {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.text
+    }
+}
+
+impl AsRef<str> for IrcString {
+    fn as_ref(&self) -> &str {
+        &self.text
+    }
+}
+
+impl Display for IrcString {
+    fn fmt(&self, f: &mut Formatter) -> core::fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
@@ -0,0 +1,372 @@
+//! Turning the bytes read from a connection into messages, without doing the reading. Bytes can
+//! arrive in any size of chunk, so a line may be split across reads or several may come in one.
+//!
+//! ```
+//! # use shared::{codec::IrcCodec, message::Command};
+//! let mut codec = IrcCodec::new();
+//! codec.feed(b"PING :irc.exa");
+//! assert!(codec.next_message().is_none());
+//! codec.feed(b"mple.com\r\nPRIVMSG #rust :hi\r\n");
+//! assert_eq!(codec.next_message().unwrap().unwrap().command, Command::Ping);
+//! assert_eq!(codec.next_message().unwrap().unwrap().command, Command::PrivMsg);
+//! assert!(codec.next_message().is_none());
+//! ```
+
+use crate::message::{MAX_LENGTH, MAX_TAGS_LENGTH, Message, ParseError, ToIrc};
+use alloc::{string::String, vec, vec::Vec};
+use thiserror::Error;
+
+/// How much room is made at the end of the buffer for each read
+#[cfg(feature = "std")]
+const READ_SIZE: usize = 4096;
+
+/// How lines that aren't valid UTF-8 are read
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum Decoding {
+    /// Rejected with [`CodecError::InvalidUtf8`]
+    #[default]
+    Strict,
+    /// Read with U+FFFD in place of the bytes that aren't UTF-8
+    Lossy,
+    /// Read as Latin-1, which older clients and servers send, with every byte as the character
+    /// with the same code
+    Latin1,
+}
+
+/// Why a line couldn't be decoded
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum CodecError {
+    /// Not UTF-8, with [`Decoding::Strict`]
+    #[error("The message is not valid UTF-8.")]
+    InvalidUtf8,
+    /// Decoded, but not a valid message. Lines that go on past the longest a message can be
+    /// are [`ParseError::TooLong`].
+    #[error(transparent)]
+    Parse(#[from] ParseError),
+}
+
+/// Splits bytes into lines and parses them. Blank lines are skipped, and lines can end in CRLF
+/// or LF alone.
+#[derive(Debug)]
+pub struct IrcCodec {
+    /// Bytes not yet made into messages, which are the first `filled`. The rest is room for the
+    /// next read, which stays initialized so that it isn't zeroed again for every read.
+    buffer: Vec<u8>,
+    filled: usize,
+    /// How far into the buffer there's no line ending, so it isn't searched again
+    searched: usize,
+    /// Whether the rest of a line that was too long is being skipped
+    discarding: bool,
+    max_line_length: usize,
+    decoding: Decoding,
+}
+
+impl Default for IrcCodec {
+    fn default() -> Self {
+        IrcCodec {
+            buffer: vec![],
+            filled: 0,
+            searched: 0,
+            discarding: false,
+            max_line_length: MAX_TAGS_LENGTH + MAX_LENGTH,
+            decoding: Decoding::default(),
+        }
+    }
+}
+
+impl IrcCodec {
+    pub fn new() -> Self {
+        IrcCodec::default()
+    }
+
+    /// Read lines that aren't UTF-8 some other way than failing with
+    /// [`CodecError::InvalidUtf8`]
+    pub fn with_decoding(mut self, decoding: Decoding) -> Self {
+        self.decoding = decoding;
+        self
+    }
+
+    /// Treat lines longer than this as [`ParseError::TooLong`] instead of the longest a message
+    /// with tags can be. No more than this and one read is buffered while waiting for a line
+    /// ending.
+    pub fn with_max_line_length(mut self, max_line_length: usize) -> Self {
+        self.max_line_length = max_line_length;
+        self
+    }
+
+    /// Add bytes read from the connection
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.buffer.truncate(self.filled);
+        self.buffer.extend_from_slice(bytes);
+        self.filled = self.buffer.len();
+    }
+
+    /// Read once from the connection straight into the buffer, which is kept between reads and
+    /// only grows when a line doesn't fit. Returns how many bytes were read, which is 0 once the
+    /// connection is closed.
+    #[cfg(feature = "std")]
+    pub fn read_from<R: std::io::Read + ?Sized>(
+        &mut self,
+        reader: &mut R,
+    ) -> std::io::Result<usize> {
+        if self.filled == self.buffer.len() {
+            self.buffer.resize(self.filled + READ_SIZE, 0);
+        }
+        let read = reader.read(&mut self.buffer[self.filled..])?;
+        self.filled += read;
+        Ok(read)
+    }
+
+    /// The next whole line as a message, or `None` until more bytes are fed. A line that's too
+    /// long gives one error, and the rest of it up to the next line ending is skipped.
+    pub fn next_message(&mut self) -> Option<Result<Message, CodecError>> {
+        loop {
+            let Some(end) = self.buffer[self.searched..self.filled]
+                .iter()
+                .position(|&b| b == b'\n')
+                .map(|i| self.searched + i)
+            else {
+                self.searched = self.filled;
+                if !self.discarding && self.filled > self.max_line_length {
+                    self.discarding = true;
+                    self.clear();
+                    return Some(Err(ParseError::TooLong.into()));
+                }
+                if self.discarding {
+                    self.clear();
+                }
+                return None;
+            };
+
+            // Decoded where it is in the buffer, and then removed from it
+            let result = match (self.discarding, end + 1 > self.max_line_length) {
+                (true, _) => None,
+                (false, true) => Some(Err(ParseError::TooLong.into())),
+                (false, false) => Some(self.decode_line(&self.buffer[..=end])),
+            };
+            self.buffer.copy_within(end + 1..self.filled, 0);
+            self.filled -= end + 1;
+            self.searched = 0;
+            self.discarding = false;
+            match result {
+                None | Some(Err(CodecError::Parse(ParseError::EmptyInput))) => continue,
+                result => return result,
+            }
+        }
+    }
+
+    /// The line to send for a message, which fails if it can't be sent as it is
+    pub fn encode<T: ToIrc>(&self, message: &T) -> Result<String, ParseError> {
+        message.to_irc()
+    }
+
+    fn clear(&mut self) {
+        self.filled = 0;
+        self.searched = 0;
+    }
+
+    fn decode_line(&self, line: &[u8]) -> Result<Message, CodecError> {
+        let message = match (core::str::from_utf8(line), self.decoding) {
+            (Ok(text), _) => Message::from(text),
+            (Err(_), Decoding::Strict) => return Err(CodecError::InvalidUtf8),
+            (Err(_), Decoding::Lossy) => Message::from(&String::from_utf8_lossy(line)),
+            (Err(_), Decoding::Latin1) => {
+                Message::from(&line.iter().map(|&b| b as char).collect::<String>())
+            }
+        };
+        Ok(message?)
+    }
+}
+
+/// Decodes each line as a result of its own, so that a bad line doesn't end the stream
+#[cfg(feature = "tokio")]
+impl tokio_util::codec::Decoder for IrcCodec {
+    type Item = Result<Message, CodecError>;
+    type Error = std::io::Error;
+
+    fn decode(&mut self, src: &mut bytes::BytesMut) -> std::io::Result<Option<Self::Item>> {
+        self.feed(&src.split());
+        Ok(self.next_message())
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<T: ToIrc> tokio_util::codec::Encoder<T> for IrcCodec {
+    type Error = std::io::Error;
+
+    fn encode(&mut self, message: T, dst: &mut bytes::BytesMut) -> std::io::Result<()> {
+        let line = message
+            .to_irc()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        dst.extend_from_slice(line.as_bytes());
+        Ok(())
+    }
+}
+
+// The tests read with std, which is on by default
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::message::Command;
+
+    /// Gives out its bytes a few at a time, like a slow connection
+    struct Trickle<'a>(&'a [u8]);
+
+    impl std::io::Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = self.0.len().min(buf.len()).min(5);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn reads_reuse_the_buffer() {
+        let mut codec = IrcCodec::new();
+        let mut reader = Trickle(b"PING :a\r\nPRIVMSG #rust :hello there\r\nPONG :b\r\n");
+        let mut commands = vec![];
+        while codec.read_from(&mut reader).unwrap() > 0 {
+            // Room for a read is only made once, and kept
+            assert_eq!(codec.buffer.len(), READ_SIZE);
+            while let Some(message) = codec.next_message() {
+                commands.push(message.unwrap().command);
+            }
+        }
+        assert_eq!(commands, [Command::Ping, Command::PrivMsg, Command::Pong]);
+        assert_eq!(codec.filled, 0);
+    }
+
+    /// The messages the codec has whole lines for
+    fn messages(codec: &mut IrcCodec) -> Vec<Result<Message, CodecError>> {
+        core::iter::from_fn(|| codec.next_message()).collect()
+    }
+
+    fn ok(line: &str) -> Result<Message, CodecError> {
+        Ok(Message::from(line).unwrap())
+    }
+
+    fn too_long() -> Result<Message, CodecError> {
+        Err(ParseError::TooLong.into())
+    }
+
+    #[test]
+    fn the_rest_of_a_line_that_is_too_long_is_skipped() {
+        let mut codec = IrcCodec::new().with_max_line_length(16);
+        codec.feed(b"PRIVMSG #rust :this is");
+        assert_eq!(messages(&mut codec), [too_long()]);
+        // Only one error for the line, however much more of it comes
+        codec.feed(b" a lot longer than it may be");
+        codec.feed(b" and still going");
+        assert_eq!(messages(&mut codec), []);
+        codec.feed(b"\r\nPING :irc\r\n");
+        assert_eq!(messages(&mut codec), [ok("PING :irc")]);
+    }
+
+    #[test]
+    fn a_whole_line_that_is_too_long_is_an_error() {
+        let mut codec = IrcCodec::new().with_max_line_length(16);
+        codec.feed(b"PRIVMSG #rust :too long\r\nPING :irc\r\n");
+        assert_eq!(messages(&mut codec), [too_long(), ok("PING :irc")]);
+    }
+
+    #[test]
+    fn lines_may_be_as_long_as_the_limit_with_their_line_ending() {
+        // 16 bytes with the CRLF
+        let line = b"PRIVMSG #a :hi\r\n";
+        let mut codec = IrcCodec::new().with_max_line_length(line.len());
+        codec.feed(line);
+        assert_eq!(messages(&mut codec), [ok("PRIVMSG #a :hi")]);
+        codec.feed(b"PRIVMSG #a :hi!\r\n");
+        assert_eq!(messages(&mut codec), [too_long()]);
+    }
+
+    #[test]
+    fn lines_may_end_in_lf_alone() {
+        let mut codec = IrcCodec::new();
+        codec.feed(b"PING :a\n\nPRIVMSG #rust :hi there\n");
+        let messages = messages(&mut codec);
+        assert_eq!(messages, [ok("PING :a"), ok("PRIVMSG #rust :hi there")]);
+        assert_eq!(messages[1].as_ref().unwrap().params, ["#rust", "hi there"]);
+    }
+
+    /// What a line with `é` in Latin-1 is read as with a decoding policy
+    fn decode_latin1(decoding: Decoding) -> Vec<Result<Message, CodecError>> {
+        let mut codec = IrcCodec::new().with_decoding(decoding);
+        codec.feed(b"PRIVMSG #a :caf\xe9\r\nPING :still here\r\n");
+        messages(&mut codec)
+    }
+
+    #[test]
+    fn strict_decoding_rejects_lines_that_are_not_utf8() {
+        // The line after it is still read
+        assert_eq!(
+            decode_latin1(Decoding::Strict),
+            [Err(CodecError::InvalidUtf8), ok("PING :still here")]
+        );
+    }
+
+    #[test]
+    fn lossy_decoding_replaces_what_is_not_utf8() {
+        assert_eq!(
+            decode_latin1(Decoding::Lossy),
+            [ok("PRIVMSG #a :caf\u{FFFD}"), ok("PING :still here")]
+        );
+    }
+
+    #[test]
+    fn latin1_decoding_reads_bytes_as_latin1() {
+        assert_eq!(
+            decode_latin1(Decoding::Latin1),
+            [ok("PRIVMSG #a :café"), ok("PING :still here")]
+        );
+        // UTF-8 is still read as UTF-8
+        let mut codec = IrcCodec::new().with_decoding(Decoding::Latin1);
+        codec.feed("PRIVMSG #a :café\r\n".as_bytes());
+        assert_eq!(messages(&mut codec), [ok("PRIVMSG #a :café")]);
+    }
+
+    #[test]
+    #[cfg(feature = "tokio")]
+    fn decoder_gives_each_line_as_it_comes() {
+        use bytes::BytesMut;
+        use tokio_util::codec::Decoder;
+
+        let mut codec = IrcCodec::new();
+        let mut src = BytesMut::from(&b"PING :a\r\n: \r\nPRIV"[..]);
+        assert_eq!(codec.decode(&mut src).unwrap(), Some(ok("PING :a")));
+        // A bad line is an item of its own, and doesn't end the stream
+        assert_eq!(
+            codec.decode(&mut src).unwrap(),
+            Some(Err(ParseError::MissingCommand.into()))
+        );
+        assert_eq!(codec.decode(&mut src).unwrap(), None);
+        src.extend_from_slice(b"MSG #rust :hi there\r\n");
+        assert_eq!(
+            codec.decode(&mut src).unwrap(),
+            Some(ok("PRIVMSG #rust :hi there"))
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "tokio")]
+    fn encoder_writes_lines_it_can_send() {
+        use bytes::BytesMut;
+        use tokio_util::codec::Encoder;
+
+        let mut codec = IrcCodec::new();
+        let mut dst = BytesMut::new();
+        Encoder::encode(&mut codec, Message::privmsg("#rust", "hi there"), &mut dst).unwrap();
+        assert_eq!(&dst[..], b"PRIVMSG #rust :hi there\r\n");
+
+        let quit = Message::privmsg("#rust", "a\r\nQUIT");
+        let error = Encoder::encode(&mut codec, quit, &mut dst).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+        assert_eq!(&dst[..], b"PRIVMSG #rust :hi there\r\n");
+    }
+}
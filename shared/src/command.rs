@@ -0,0 +1,200 @@
+use crate::message::{Command, Message};
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::fmt::{Display, Formatter};
+use thiserror::Error;
+
+/// A message's command with its parameters checked and split up, so that handlers don't have to
+/// index into [`Message::params`]. Decoded with [`Message::typed`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypedCommand {
+    /// `USER guest 0 * :Ronnie Reagan`. Only the username is needed.
+    User {
+        username: String,
+        realname: Option<String>,
+    },
+    Nick {
+        nick: String,
+    },
+    /// `JOIN #rust,#irc_rs key`, where keys go with the channels in order
+    Join {
+        channels: Vec<String>,
+        keys: Vec<String>,
+    },
+    Part {
+        channels: Vec<String>,
+        reason: Option<String>,
+    },
+    /// `KICK #general bob,carol :Using profanity`
+    Kick {
+        channel: String,
+        nicks: Vec<String>,
+        reason: Option<String>,
+    },
+    PrivMsg {
+        targets: Vec<Target>,
+        text: String,
+    },
+    Notice {
+        targets: Vec<Target>,
+        text: String,
+    },
+    /// Asks for the topic without one, and clears it with an empty one
+    Topic {
+        channel: String,
+        topic: Option<String>,
+    },
+    /// `MODE #channel +o alice`, or `MODE #channel` to ask for the modes
+    Mode {
+        target: Target,
+        modes: Option<String>,
+        args: Vec<String>,
+    },
+    Whois {
+        nick: String,
+    },
+    Oper {
+        name: String,
+        password: String,
+    },
+    /// Marks the user away with a message, or back without one
+    Away {
+        message: Option<String>,
+    },
+    Quit {
+        reason: Option<String>,
+    },
+    Ping {
+        token: Option<String>,
+    },
+    Pong {
+        token: Option<String>,
+    },
+    /// `CAP REQ :sasl`, where what follows the subcommand depends on it
+    Cap {
+        subcommand: String,
+        args: Vec<String>,
+    },
+    /// `AUTHENTICATE PLAIN` to start logging in, then the base64 credentials
+    Authenticate {
+        data: String,
+    },
+    /// Any other command, whose parameters are left in the message
+    Other,
+}
+
+/// Who a message or mode is for
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    Channel(String),
+    Nick(String),
+}
+
+/// A message without the parameters its command needs
+#[derive(Debug, Clone, PartialEq, Error)]
+#[error("Not enough parameters for {}", .0.to_string().to_uppercase())]
+pub struct MissingParams(pub Command);
+
+impl Message {
+    /// The command with its parameters, or an error if any that are needed are missing. Extra
+    /// parameters are ignored.
+    pub fn typed(&self) -> Result<TypedCommand, MissingParams> {
+        let required = |i: usize| {
+            self.params
+                .get(i)
+                .filter(|param| !param.is_empty())
+                .cloned()
+                .ok_or_else(|| MissingParams(self.command.clone()))
+        };
+        let optional = |i: usize| self.params.get(i).cloned();
+        let list = |text: String| text.split(',').map(str::to_string).collect::<Vec<_>>();
+        let targets = |text: String| text.split(',').map(Target::parse).collect::<Vec<_>>();
+
+        Ok(match self.command {
+            Command::User => TypedCommand::User {
+                username: required(0)?,
+                realname: optional(3),
+            },
+            Command::Nick => TypedCommand::Nick { nick: required(0)? },
+            Command::Join => TypedCommand::Join {
+                channels: list(required(0)?),
+                keys: optional(1).map(list).unwrap_or_default(),
+            },
+            Command::Part => TypedCommand::Part {
+                channels: list(required(0)?),
+                reason: optional(1),
+            },
+            Command::Kick => TypedCommand::Kick {
+                channel: required(0)?,
+                nicks: list(required(1)?),
+                reason: optional(2),
+            },
+            Command::PrivMsg | Command::Notice => {
+                let targets = targets(required(0)?);
+                // The text can't be left out, but may be empty
+                let text = optional(1).ok_or_else(|| MissingParams(self.command.clone()))?;
+                if self.command == Command::PrivMsg {
+                    TypedCommand::PrivMsg { targets, text }
+                } else {
+                    TypedCommand::Notice { targets, text }
+                }
+            }
+            Command::Topic => TypedCommand::Topic {
+                channel: required(0)?,
+                topic: optional(1),
+            },
+            Command::Mode => TypedCommand::Mode {
+                target: Target::parse(&required(0)?),
+                modes: optional(1),
+                args: self.params.iter().skip(2).cloned().collect(),
+            },
+            Command::Whois => TypedCommand::Whois {
+                // The nickname comes last, after the server to ask if one is given
+                nick: required(self.params.len().saturating_sub(1))?,
+            },
+            Command::Oper => TypedCommand::Oper {
+                name: required(0)?,
+                password: required(1)?,
+            },
+            Command::Away => TypedCommand::Away {
+                message: optional(0).filter(|message| !message.is_empty()),
+            },
+            Command::Quit => TypedCommand::Quit {
+                reason: optional(0),
+            },
+            Command::Ping => TypedCommand::Ping { token: optional(0) },
+            Command::Pong => TypedCommand::Pong { token: optional(0) },
+            Command::Cap => TypedCommand::Cap {
+                subcommand: required(0)?,
+                args: self.params.iter().skip(1).cloned().collect(),
+            },
+            Command::Authenticate => TypedCommand::Authenticate { data: required(0)? },
+            _ => TypedCommand::Other,
+        })
+    }
+}
+
+impl Target {
+    /// A channel if the name starts with `#` or `&`, otherwise a nickname
+    pub fn parse(name: &str) -> Self {
+        if name.starts_with(['#', '&']) {
+            Target::Channel(name.to_string())
+        } else {
+            Target::Nick(name.to_string())
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Target::Channel(name) | Target::Nick(name) => name,
+        }
+    }
+}
+
+impl Display for Target {
+    fn fmt(&self, f: &mut Formatter) -> core::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
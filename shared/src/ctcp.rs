@@ -0,0 +1,194 @@
+//! CTCP, the requests and replies users send each other in the text of a PRIVMSG or NOTICE,
+//! wrapped in `\x01` characters. `/me` is the `ACTION` request, and files are offered with `DCC`.
+//!
+//! ```
+//! # use shared::ctcp;
+//! let text = ctcp::encode("ACTION", "waves");
+//! assert_eq!(text, "\x01ACTION waves\x01");
+//! assert_eq!(ctcp::parse(&text), Some(("ACTION", "waves")));
+//! assert_eq!(ctcp::parse("just talking"), None);
+//! ```
+
+use alloc::{
+    borrow::Cow,
+    format,
+    string::{String, ToString},
+};
+use core::net::{IpAddr, Ipv4Addr};
+
+/// The character around a CTCP request or reply
+pub const DELIMITER: char = '\x01';
+
+/// The character that starts the low-level quoting of characters that can't be in a message
+const QUOTE: char = '\x10';
+
+/// Whether the text of a PRIVMSG or NOTICE is a CTCP request or reply rather than a message
+pub fn is_ctcp(text: &str) -> bool {
+    text.starts_with(DELIMITER)
+}
+
+/// Split a CTCP message into its command and arguments, e.g. `\x01ACTION waves\x01` into
+/// `("ACTION", "waves")`. The closing `\x01` is optional since some clients leave it out. The
+/// arguments are as sent, so [`dequote`] them if they might hold a quoted line break.
+pub fn parse(text: &str) -> Option<(&str, &str)> {
+    let inner = text.strip_prefix(DELIMITER)?;
+    let inner = inner.strip_suffix(DELIMITER).unwrap_or(inner);
+    let (command, args) = inner.split_once(' ').unwrap_or((inner, ""));
+    Some((command, args))
+}
+
+/// Wrap a CTCP command and its arguments to send as the text of a PRIVMSG or NOTICE. Characters
+/// in the arguments that can't be sent are [`quote`]d.
+pub fn encode(command: &str, args: &str) -> String {
+    if args.is_empty() {
+        format!("{DELIMITER}{command}{DELIMITER}")
+    } else {
+        format!("{DELIMITER}{command} {}{DELIMITER}", quote(args))
+    }
+}
+
+/// Quote NUL, CR and LF, which can't be in a message, as `\x10` followed by `0`, `r` or `n`. The
+/// quote character itself is doubled.
+pub fn quote(text: &str) -> Cow<'_, str> {
+    if !text.contains(['\0', '\r', '\n', QUOTE]) {
+        return Cow::Borrowed(text);
+    }
+    let mut quoted = String::with_capacity(text.len() + 2);
+    for c in text.chars() {
+        match c {
+            '\0' => quoted.push_str("\x100"),
+            '\r' => quoted.push_str("\x10r"),
+            '\n' => quoted.push_str("\x10n"),
+            QUOTE => quoted.push_str("\x10\x10"),
+            _ => quoted.push(c),
+        }
+    }
+    Cow::Owned(quoted)
+}
+
+/// Undo [`quote`]. A quote character before anything else is dropped.
+pub fn dequote(text: &str) -> Cow<'_, str> {
+    if !text.contains(QUOTE) {
+        return Cow::Borrowed(text);
+    }
+    let mut dequoted = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != QUOTE {
+            dequoted.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('0') => dequoted.push('\0'),
+            Some('r') => dequoted.push('\r'),
+            Some('n') => dequoted.push('\n'),
+            Some(other) => dequoted.push(other),
+            None => {}
+        }
+    }
+    Cow::Owned(dequoted)
+}
+
+/// A `DCC SEND` request: `DCC SEND <file> <ip> <port> <size> [token]`. The port is 0 for a
+/// passive offer, where the sender asks the receiver to listen.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DccOffer {
+    pub file_name: String,
+    pub ip: IpAddr,
+    pub port: u16,
+    pub size: u64,
+    pub token: Option<String>,
+}
+
+impl DccOffer {
+    /// Parse the arguments of a `DCC` CTCP request, returning `None` for anything but a valid
+    /// `SEND`.
+    pub fn parse(args: &str) -> Option<DccOffer> {
+        let (file_name, rest) = split_file_name(args.strip_prefix("SEND ")?)?;
+        let mut parts = rest.split_whitespace();
+        let ip = parts.next()?;
+        let ip = match ip.parse::<u32>() {
+            Ok(number) => IpAddr::V4(Ipv4Addr::from(number)),
+            Err(_) => ip.parse().ok()?, // Some clients send IPv6 addresses as they are
+        };
+        Some(DccOffer {
+            file_name: file_name.to_string(),
+            ip,
+            port: parts.next()?.parse().ok()?,
+            size: parts.next()?.parse().ok()?,
+            token: parts.next().map(str::to_string),
+        })
+    }
+
+    /// The CTCP text of the offer
+    pub fn encode(&self) -> String {
+        let ip = match self.ip {
+            IpAddr::V4(ip) => u32::from(ip).to_string(),
+            IpAddr::V6(ip) => ip.to_string(),
+        };
+        let file_name = quote_file_name(&self.file_name);
+        let mut args = format!("SEND {file_name} {ip} {} {}", self.port, self.size);
+        if let Some(token) = &self.token {
+            args.push(' ');
+            args.push_str(token);
+        }
+        encode("DCC", &args)
+    }
+}
+
+/// A `DCC RESUME <file> <port> <position> [token]` request, where the receiver of an offer asks
+/// to continue a partial download from a position, or the `DCC ACCEPT` the sender agrees with,
+/// which has the same arguments. The offer is known by its port, or by its token if it's passive.
+/// Some clients send a placeholder like `file.ext` instead of the file name, so it isn't used.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DccResume {
+    pub file_name: String,
+    pub port: u16,
+    pub position: u64,
+    pub token: Option<String>,
+}
+
+impl DccResume {
+    /// Parse the arguments of a `DCC` CTCP request, returning `None` for anything but a valid
+    /// request of the kind given, `RESUME` or `ACCEPT`.
+    pub fn parse(args: &str, kind: &str) -> Option<DccResume> {
+        let rest = args.strip_prefix(kind)?.strip_prefix(' ')?;
+        let (file_name, rest) = split_file_name(rest)?;
+        let mut parts = rest.split_whitespace();
+        Some(DccResume {
+            file_name: file_name.to_string(),
+            port: parts.next()?.parse().ok()?,
+            position: parts.next()?.parse().ok()?,
+            token: parts.next().map(str::to_string),
+        })
+    }
+
+    /// The CTCP text of the request, `RESUME` or `ACCEPT`
+    pub fn encode(&self, kind: &str) -> String {
+        let file_name = quote_file_name(&self.file_name);
+        let mut args = format!("{kind} {file_name} {} {}", self.port, self.position);
+        if let Some(token) = &self.token {
+            args.push(' ');
+            args.push_str(token);
+        }
+        encode("DCC", &args)
+    }
+}
+
+/// Split off the file name at the start of a DCC request's arguments. File names with spaces are
+/// quoted.
+fn split_file_name(args: &str) -> Option<(&str, &str)> {
+    let args = args.trim_start();
+    match args.strip_prefix('"') {
+        Some(quoted) => quoted.split_once('"'),
+        None => args.split_once(' '),
+    }
+}
+
+fn quote_file_name(file_name: &str) -> String {
+    if file_name.contains(' ') {
+        format!("\"{file_name}\"")
+    } else {
+        file_name.to_string()
+    }
+}
@@ -0,0 +1,217 @@
+//! What a server supports, as it says in RPL_ISUPPORT (005) after welcoming a client. Servers send
+//! a few of these with up to 13 tokens each, like `PREFIX=(ov)@+` or `CASEMAPPING=ascii`, and
+//! can send more later to change them.
+//!
+//! ```
+//! # use shared::{casemap::IrcCasemap, isupport::ISupport, message::Message};
+//! let mut isupport = ISupport::default();
+//! isupport.add(&Message::from(
+//!     ":irc.example.com 005 alice CASEMAPPING=ascii NICKLEN=30 PREFIX=(qov)~@+ :are supported",
+//! ).unwrap());
+//! isupport.add_token("CHANLIMIT=#:20,&:");
+//! assert_eq!(isupport.casemapping(), IrcCasemap::Ascii);
+//! assert_eq!(isupport.nicklen(), Some(30));
+//! assert_eq!(isupport.prefix(), vec![('q', '~'), ('o', '@'), ('v', '+')]);
+//! assert_eq!(isupport.chanlimit('#'), Some(20));
+//! assert_eq!(isupport.chanlimit('&'), None);
+//! ```
+
+use crate::{
+    casemap::IrcCasemap,
+    message::{Command, Message},
+};
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+/// The tokens a server sent, by name. Typed accessors give the defaults from the spec for the
+/// ones it didn't send.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ISupport {
+    /// Values unescaped, and empty for tokens without one
+    tokens: BTreeMap<String, String>,
+}
+
+/// The kinds of channel modes in `CHANMODES`, as letters
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChanModes {
+    /// Modes for lists like bans, which always take a parameter (type A)
+    pub list: String,
+    /// Modes that always take a parameter, like the key (type B)
+    pub always_param: String,
+    /// Modes that only take a parameter when set, like the limit (type C)
+    pub param_when_set: String,
+    /// Modes that never take a parameter (type D)
+    pub no_param: String,
+}
+
+impl ISupport {
+    /// Take in the tokens of an RPL_ISUPPORT. Other messages are ignored.
+    pub fn add(&mut self, message: &Message) {
+        if message.command != Command::Numeric(5) {
+            return;
+        }
+        // After the nickname and before the text at the end
+        let end = message.params.len().saturating_sub(1);
+        for token in message.params.get(1..end).unwrap_or_default() {
+            self.add_token(token);
+        }
+    }
+
+    /// Take in one token, like `NICKLEN=30`, or `-NICKLEN` to go back to the default
+    pub fn add_token(&mut self, token: &str) {
+        if let Some(name) = token.strip_prefix('-') {
+            self.tokens.remove(&name.to_ascii_uppercase());
+            return;
+        }
+        let (name, value) = token.split_once('=').unwrap_or((token, ""));
+        if !name.is_empty() {
+            self.tokens
+                .insert(name.to_ascii_uppercase(), unescape_value(value));
+        }
+    }
+
+    /// Forget everything, for a new connection
+    pub fn clear(&mut self) {
+        self.tokens.clear();
+    }
+
+    /// The value of a token, which is empty for tokens without one
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.tokens
+            .get(&name.to_ascii_uppercase())
+            .map(String::as_str)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    fn number(&self, name: &str) -> Option<usize> {
+        self.get(name)?.parse().ok()
+    }
+
+    /// How nicknames and channel names are compared. Defaults to rfc1459, which is also used for
+    /// casemappings that aren't known.
+    pub fn casemapping(&self) -> IrcCasemap {
+        self.get("CASEMAPPING")
+            .and_then(IrcCasemap::from_name)
+            .unwrap_or_default()
+    }
+
+    /// The channel modes that give users a prefix, highest first, as pairs of mode and prefix
+    /// like `('o', '@')`. Defaults to `(ov)@+`.
+    pub fn prefix(&self) -> Vec<(char, char)> {
+        let value = self.get("PREFIX").unwrap_or("(ov)@+");
+        value
+            .strip_prefix('(')
+            .and_then(|value| value.split_once(')'))
+            .map(|(modes, prefixes)| modes.chars().zip(prefixes.chars()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Defaults to `beI,k,l,imnpst`
+    pub fn chanmodes(&self) -> ChanModes {
+        let mut kinds = self
+            .get("CHANMODES")
+            .unwrap_or("beI,k,l,imnpst")
+            .split(',')
+            .map(str::to_string);
+        ChanModes {
+            list: kinds.next().unwrap_or_default(),
+            always_param: kinds.next().unwrap_or_default(),
+            param_when_set: kinds.next().unwrap_or_default(),
+            no_param: kinds.next().unwrap_or_default(),
+        }
+    }
+
+    /// The characters channel names can start with. Defaults to `#&`.
+    pub fn chantypes(&self) -> &str {
+        self.get("CHANTYPES").unwrap_or("#&")
+    }
+
+    /// How many channels starting with a channel type a user may be in at once, or `None` if
+    /// there's no limit. `CHANLIMIT=#&:10` limits users to 10 channels starting with `#` or `&`
+    /// between them.
+    pub fn chanlimit(&self, chan_type: char) -> Option<usize> {
+        self.get("CHANLIMIT")?
+            .split(',')
+            .filter_map(|limit| limit.split_once(':'))
+            .find(|(types, _)| types.contains(chan_type))
+            .and_then(|(_, limit)| limit.parse().ok())
+    }
+
+    /// Whether a name is a channel's rather than a nickname
+    pub fn is_channel(&self, name: &str) -> bool {
+        name.starts_with(|c| self.chantypes().contains(c))
+    }
+
+    /// Prefixes that send a message to only the users in a channel with that prefix or higher,
+    /// like `@#rust` for its operators. Empty if the server doesn't support this.
+    pub fn statusmsg(&self) -> &str {
+        self.get("STATUSMSG").unwrap_or_default()
+    }
+
+    /// The network's name, like `Libera.Chat`
+    pub fn network(&self) -> Option<&str> {
+        self.get("NETWORK").filter(|network| !network.is_empty())
+    }
+
+    pub fn nicklen(&self) -> Option<usize> {
+        self.number("NICKLEN")
+    }
+
+    pub fn channellen(&self) -> Option<usize> {
+        self.number("CHANNELLEN")
+    }
+
+    pub fn topiclen(&self) -> Option<usize> {
+        self.number("TOPICLEN")
+    }
+
+    pub fn kicklen(&self) -> Option<usize> {
+        self.number("KICKLEN")
+    }
+
+    pub fn awaylen(&self) -> Option<usize> {
+        self.number("AWAYLEN")
+    }
+
+    /// How many modes with a parameter can be set in one MODE. Defaults to 3, and has no limit
+    /// if the token has no value.
+    pub fn modes(&self) -> Option<usize> {
+        match self.get("MODES") {
+            Some("") => None,
+            Some(value) => value.parse().ok(),
+            None => Some(3),
+        }
+    }
+}
+
+/// Undo the escaping of a value, in which `\xHH` stands for the byte with that code, like `\x20`
+/// for a space
+fn unescape_value(value: &str) -> String {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+    while let Some((&b, after)) = rest.split_first() {
+        let code = after
+            .strip_prefix(b"x")
+            .and_then(|hex| hex.get(..2))
+            .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| core::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match code {
+            Some(code) if b == b'\\' => {
+                bytes.push(code);
+                rest = &after[3..];
+            }
+            _ => {
+                bytes.push(b);
+                rest = after;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
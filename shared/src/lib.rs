@@ -1,3 +1,25 @@
+//! The IRC protocol, shared by the client and the server. Only `alloc` is needed without the
+//! `std` feature, so parsing and writing messages also works in a browser through wasm32.
+
+#![no_std]
+
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+
+pub mod casemap;
+pub mod codec;
+pub mod command;
+pub mod ctcp;
+pub mod isupport;
+pub mod mask;
+pub mod message;
+pub mod mode;
+pub mod prefix;
+pub mod replies;
+// pub mod user;
+pub const MESSAGE_SIZE: usize = 1024;
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -6,7 +28,3 @@ mod tests {
         assert_eq!(result, 4);
     }
 }
-
-// pub mod message;
-// pub mod user;
-pub const MESSAGE_SIZE: usize = 1024;
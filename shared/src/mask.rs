@@ -0,0 +1,49 @@
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+/// Check whether `text` matches an IRC-style wildcard mask, where `*` matches any sequence of
+/// characters (including none) and `?` matches exactly one character. Matching ignores ASCII case,
+/// since nicknames and hostnames are case-insensitive.
+pub fn matches(mask: &str, text: &str) -> bool {
+    let mask = mask.to_ascii_lowercase().chars().collect::<Vec<_>>();
+    let text = text.to_ascii_lowercase().chars().collect::<Vec<_>>();
+
+    // Position in the mask and text
+    let (mut m, mut t) = (0, 0);
+    // Position of the last `*` in the mask, and the text position it was tried against
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if m < mask.len() && (mask[m] == '?' || mask[m] == text[t]) {
+            m += 1;
+            t += 1;
+        } else if m < mask.len() && mask[m] == '*' {
+            backtrack = Some((m, t));
+            m += 1;
+        } else if let Some((star, star_t)) = backtrack {
+            // Let the last `*` swallow one more character and try again
+            m = star + 1;
+            t = star_t + 1;
+            backtrack = Some((star, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+
+    // Any trailing stars match the empty string
+    mask[m..].iter().all(|&c| c == '*')
+}
+
+/// Expand a partial mask into a full `nick!user@host` mask, so that `troll` quiets everyone using
+/// that nickname and `*@spam.example` quiets a whole host.
+pub fn normalize(mask: &str) -> String {
+    match (mask.contains('!'), mask.contains('@')) {
+        (true, true) => mask.to_string(),
+        (true, false) => format!("{mask}@*"),
+        (false, true) => format!("*!{mask}"),
+        (false, false) => format!("{mask}!*@*"),
+    }
+}
@@ -0,0 +1,867 @@
+//! IRC messages, parsed and written the same way by the client and the server.
+
+#![allow(non_camel_case_types)]
+
+use crate::prefix::Prefix;
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::fmt::{Display, Formatter};
+use thiserror::Error;
+
+/// Longest a message can be in bytes, not counting its tags but counting the CRLF at the end
+pub const MAX_LENGTH: usize = 512;
+
+/// Longest the tags at the start of a message can be in bytes, counting the `@` and the space
+/// after them
+pub const MAX_TAGS_LENGTH: usize = 8191;
+
+/// Most parameters a message can have
+pub const MAX_PARAMS: usize = 15;
+
+/// A message as sent over the connection. With the `serde` feature, messages can also be written
+/// as JSON, with the command as it's sent and the tags as an object:
+///
+/// ```json
+/// {"tags": {"time": "2024-05-01T18:30:00.000Z"}, "prefix": "alice!alice@example.com",
+///  "command": "PRIVMSG", "params": ["#rust", "hello world"]}
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Message {
+    /// IRCv3 message tags, such as `time` with the server-time capability, unescaped
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, with = "tag_map", skip_serializing_if = "Vec::is_empty")
+    )]
+    pub tags: Vec<(String, String)>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub prefix: Option<Prefix>,
+    pub command: Command,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub params: Vec<String>,
+}
+
+/// A numeric reply from the server. In JSON, the code is written by name, like `RPL_WELCOME`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Response {
+    pub prefix: String,
+    pub code: ReplyCode,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub params: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(into = "String", from = "String")
+)]
+pub enum Command {
+    User,
+    Nick,
+    Join,
+    Kick,
+    Part,
+    PrivMsg,
+    Notice,
+    Mode,
+    Topic,
+    Whois,
+    Oper,
+    Shun,
+    Unshun,
+    Defcon,
+    Stats,
+    Qline,
+    Unqline,
+    Search,
+    List,
+    Lusers,
+    Away,
+    Quit,
+    Cap,
+    Authenticate,
+    /// Start or end of a group of related messages, with the batch capability
+    Batch,
+    /// Tags without a message, such as typing notifications, with the message-tags capability
+    TagMsg,
+    Ping,
+    Pong,
+    Error,
+    /// Numeric reply, such as 001 (RPL_WELCOME)
+    Numeric(u16),
+    /// A command that isn't known, in uppercase since commands ignore case
+    Unknown(String),
+}
+
+/// Numeric replies from RFC 2812, and the common ones added since by IRCv3 and servers. Build
+/// replies with [`Replies`](crate::replies::Replies) so they get the parameters they need.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ReplyCode {
+    RPL_WELCOME = 1,
+    RPL_YOURHOST = 2,
+    RPL_CREATED = 3,
+    RPL_MYINFO = 4,
+    /// RPL_BOUNCE in RFC 2812, but used by every server since for what it supports
+    RPL_ISUPPORT = 5,
+    /// The kinds of server notices an operator is sent, as a mask like `+cf`
+    RPL_SNOMASK = 8,
+
+    RPL_TRACELINK = 200,
+    RPL_TRACECONNECTING = 201,
+    RPL_TRACEHANDSHAKE = 202,
+    RPL_TRACEUNKNOWN = 203,
+    RPL_TRACEOPERATOR = 204,
+    RPL_TRACEUSER = 205,
+    RPL_TRACESERVER = 206,
+    RPL_TRACESERVICE = 207,
+    RPL_TRACENEWTYPE = 208,
+    RPL_TRACECLASS = 209,
+    RPL_STATSLINKINFO = 211,
+    RPL_STATSCOMMANDS = 212,
+    RPL_STATSQLINE = 217,
+    RPL_ENDOFSTATS = 219,
+    RPL_UMODEIS = 221,
+    RPL_SERVLIST = 234,
+    RPL_SERVLISTEND = 235,
+    RPL_STATSUPTIME = 242,
+    RPL_STATSOLINE = 243,
+    RPL_STATSDEBUG = 249,
+    RPL_STATSCONN = 250,
+    RPL_LUSERCLIENT = 251,
+    RPL_LUSEROP = 252,
+    RPL_LUSERUNKNOWN = 253,
+    RPL_LUSERCHANNELS = 254,
+    RPL_LUSERME = 255,
+    RPL_ADMINME = 256,
+    RPL_ADMINLOC1 = 257,
+    RPL_ADMINLOC2 = 258,
+    RPL_ADMINEMAIL = 259,
+    RPL_TRACELOG = 261,
+    RPL_TRACEEND = 262,
+    RPL_TRYAGAIN = 263,
+    RPL_LOCALUSERS = 265,
+    RPL_GLOBALUSERS = 266,
+    RPL_WHOISCERTFP = 276,
+
+    RPL_AWAY = 301,
+    RPL_USERHOST = 302,
+    RPL_ISON = 303,
+    RPL_UNAWAY = 305,
+    RPL_NOWAWAY = 306,
+    RPL_WHOISUSER = 311,
+    RPL_WHOISSERVER = 312,
+    RPL_WHOISOPERATOR = 313,
+    RPL_WHOWASUSER = 314,
+    RPL_ENDOFWHO = 315,
+    RPL_WHOISIDLE = 317,
+    RPL_ENDOFWHOIS = 318,
+    RPL_WHOISCHANNELS = 319,
+    RPL_LISTSTART = 321,
+    RPL_LIST = 322,
+    RPL_LISTEND = 323,
+    RPL_CHANNELMODEIS = 324,
+    RPL_UNIQOPIS = 325,
+    RPL_CREATIONTIME = 329,
+    RPL_WHOISACCOUNT = 330,
+    /// The host or IP address a user really connected from, for operators
+    RPL_WHOISACTUALLY = 338,
+    RPL_NOTOPIC = 331,
+    RPL_TOPIC = 332,
+    RPL_TOPICWHOTIME = 333,
+    RPL_INVITING = 341,
+    RPL_SUMMONING = 342,
+    RPL_INVITELIST = 346,
+    RPL_ENDOFINVITELIST = 347,
+    RPL_EXCEPTLIST = 348,
+    RPL_ENDOFEXCEPTLIST = 349,
+    RPL_VERSION = 351,
+    RPL_WHOREPLY = 352,
+    RPL_NAMREPLY = 353,
+    RPL_LINKS = 364,
+    RPL_ENDOFLINKS = 365,
+    RPL_ENDOFNAMES = 366,
+    RPL_BANLIST = 367,
+    RPL_ENDOFBANLIST = 368,
+    RPL_ENDOFWHOWAS = 369,
+    RPL_INFO = 371,
+    RPL_MOTD = 372,
+    RPL_ENDOFINFO = 374,
+    RPL_MOTDSTART = 375,
+    RPL_ENDOFMOTD = 376,
+    RPL_YOUREOPER = 381,
+    RPL_REHASHING = 382,
+    RPL_YOURESERVICE = 383,
+    RPL_TIME = 391,
+    RPL_USERSSTART = 392,
+    RPL_USERS = 393,
+    RPL_ENDOFUSERS = 394,
+    RPL_NOUSERS = 395,
+    RPL_HOSTHIDDEN = 396,
+
+    ERR_NOSUCHNICK = 401,
+    ERR_NOSUCHSERVER = 402,
+    ERR_NOSUCHCHANNEL = 403,
+    ERR_CANNOTSENDTOCHAN = 404,
+    ERR_TOOMANYCHANNELS = 405,
+    ERR_WASNOSUCHNICK = 406,
+    ERR_TOOMANYTARGETS = 407,
+    ERR_NOSUCHSERVICE = 408,
+    ERR_NOORIGIN = 409,
+    ERR_INVALIDCAPCMD = 410,
+    ERR_NORECIPIENT = 411,
+    ERR_NOTEXTTOSEND = 412,
+    ERR_NOTOPLEVEL = 413,
+    ERR_WILDTOPLEVEL = 414,
+    ERR_BADMASK = 415,
+    ERR_INPUTTOOLONG = 417,
+    ERR_UNKNOWNCOMMAND = 421,
+    ERR_NOMOTD = 422,
+    ERR_NOADMININFO = 423,
+    ERR_FILEERROR = 424,
+    ERR_NONICKNAMEGIVEN = 431,
+    ERR_ERRONEUSNICKNAME = 432,
+    ERR_NICKNAMEINUSE = 433,
+    ERR_NICKCOLLISION = 436,
+    ERR_UNAVAILRESOURCE = 437,
+    ERR_USERNOTINCHANNEL = 441,
+    ERR_NOTONCHANNEL = 442,
+    ERR_USERONCHANNEL = 443,
+    ERR_NOLOGIN = 444,
+    ERR_SUMMONDISABLED = 445,
+    ERR_USERSDISABLED = 446,
+    ERR_NOTREGISTERED = 451,
+    ERR_NEEDMOREPARAMS = 461,
+    ERR_ALREADYREGISTRED = 462,
+    ERR_NOPERMFORHOST = 463,
+    ERR_PASSWDMISMATCH = 464,
+    ERR_YOUREBANNEDCREEP = 465,
+    ERR_YOUWILLBEBANNED = 466,
+    ERR_KEYSET = 467,
+    ERR_CHANNELISFULL = 471,
+    ERR_UNKNOWNMODE = 472,
+    ERR_INVITEONLYCHAN = 473,
+    ERR_BANNEDFROMCHAN = 474,
+    ERR_BADCHANNELKEY = 475,
+    ERR_BADCHANMASK = 476,
+    ERR_NOCHANMODES = 477,
+    ERR_BANLISTFULL = 478,
+    ERR_NOPRIVILEGES = 481,
+    ERR_CHANOPRIVSNEEDED = 482,
+    ERR_CANTKILLSERVER = 483,
+    ERR_RESTRICTED = 484,
+    ERR_UNIQOPPRIVSNEEDED = 485,
+    ERR_NOOPERHOST = 491,
+    ERR_UMODEUNKNOWNFLAG = 501,
+    ERR_USERSDONTMATCH = 502,
+
+    RPL_STARTTLS = 670,
+    RPL_WHOISSECURE = 671,
+    ERR_STARTTLS = 691,
+    RPL_QUIETLIST = 728,
+    RPL_ENDOFQUIETLIST = 729,
+    RPL_MONONLINE = 730,
+    RPL_MONOFFLINE = 731,
+    RPL_MONLIST = 732,
+    RPL_ENDOFMONLIST = 733,
+    ERR_MONLISTFULL = 734,
+    RPL_LOGGEDIN = 900,
+    RPL_LOGGEDOUT = 901,
+    ERR_NICKLOCKED = 902,
+    RPL_SASLSUCCESS = 903,
+    ERR_SASLFAIL = 904,
+    ERR_SASLTOOLONG = 905,
+    ERR_SASLABORTED = 906,
+    ERR_SASLALREADY = 907,
+    RPL_SASLMECHS = 908,
+}
+
+impl ReplyCode {
+    /// 477 as servers use it today, for a channel that only users logged into an account may join.
+    /// RFC 2812 gave the number to ERR_NOCHANMODES, which servers no longer send.
+    pub const ERR_NEEDREGGEDNICK: ReplyCode = ReplyCode::ERR_NOCHANMODES;
+}
+
+/// Why a line couldn't be parsed as a message
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum ParseError {
+    /// Nothing but a line ending or spaces, which should be ignored
+    #[error("The message is empty.")]
+    EmptyInput,
+    /// Tags or a prefix without a command after them
+    #[error("The message does not contain a command.")]
+    MissingCommand,
+    /// A NUL, CR or LF inside the message, at a position in bytes
+    #[error("The message contains an illegal character at position {pos}.")]
+    IllegalCharacter { pos: usize },
+    /// Longer than [`MAX_LENGTH`], or with tags longer than [`MAX_TAGS_LENGTH`]
+    #[error("The message is too long.")]
+    TooLong,
+    /// A tag without a name, or with characters that aren't allowed in one
+    #[error("The message has an invalid tag {0:?}.")]
+    BadTag(String),
+    /// A command starting with `@` or `:`, which would be read as tags or a prefix instead
+    #[error("The message has an invalid command {0:?}.")]
+    BadCommand(String),
+    /// More than [`MAX_PARAMS`] parameters
+    #[error("The message has more than {MAX_PARAMS} parameters.")]
+    TooManyParams,
+    /// A parameter before the last that's empty, contains a space or starts with a colon, so
+    /// that it can't be written on its own, at a position counting from 0
+    #[error("The message has an invalid parameter at position {index}.")]
+    BadParam { index: usize },
+}
+
+/// Messages and replies as a line to send, ending in CRLF.
+pub trait ToIrc: ToString {
+    /// Check that the message can be sent. See [`Message::validate`].
+    fn validate(&self) -> Result<(), ParseError>;
+
+    /// The line to send, or why the message can't be sent
+    fn to_irc(&self) -> Result<String, ParseError> {
+        self.validate()?;
+        Ok(format!("{}\r\n", self.to_string()))
+    }
+}
+
+impl Message {
+    /// Parse an IRC message from a line, with or without its line ending. If the command isn't
+    /// known, it's parsed as [`Command::Unknown`] so that it can still be shown or answered.
+    pub fn from(raw: &str) -> Result<Message, ParseError> {
+        // Trim the line ending only, since spaces at the end belong to the last parameter
+        let line = raw.trim_end_matches(['\r', '\n']);
+        if let Some(pos) = line.find(['\0', '\r', '\n']) {
+            return Err(ParseError::IllegalCharacter { pos });
+        }
+        let mut raw = line.trim_start_matches(' ');
+        if raw.is_empty() {
+            return Err(ParseError::EmptyInput);
+        }
+
+        // IRCv3 message tags, e.g. `@time=2024-05-01T18:30:00.000Z;batch=1`
+        let mut tags = vec![];
+        if let Some(text) = raw.strip_prefix('@') {
+            let (word, text) = Message::get_next_word(text);
+            if word.len() + 2 > MAX_TAGS_LENGTH {
+                return Err(ParseError::TooLong);
+            }
+            for tag in word.split(';').filter(|tag| !tag.is_empty()) {
+                let (key, value) = tag.split_once('=').unwrap_or((tag, ""));
+                if !is_valid_tag_key(key) {
+                    return Err(ParseError::BadTag(tag.to_string()));
+                }
+                tags.push((key.to_string(), unescape_tag_value(value)));
+            }
+            raw = text;
+        }
+        if raw.len() + 2 > MAX_LENGTH {
+            return Err(ParseError::TooLong);
+        }
+
+        // There is a prefix
+        let prefix = if raw.starts_with(":") {
+            // Remove colon from the beginning of the string
+            let (_, text) = raw.split_once(":").unwrap();
+            // Cut first part of input out and store it in `prefix`
+            let (prefix, text) = Message::get_next_word(text);
+            // Set raw to input without prefix
+            raw = text;
+            // Return prefix
+            Some(Prefix::parse(prefix))
+        } else {
+            None
+        };
+
+        // Cut command word from string
+        let (command, text) = Message::get_next_word(raw);
+        if command.is_empty() {
+            return Err(ParseError::MissingCommand);
+        }
+        if !is_valid_command(command) {
+            return Err(ParseError::BadCommand(command.to_string()));
+        }
+        // Convert command word to Command enum
+        let command = Command::from(command);
+        // Set raw to input without command
+        raw = text;
+
+        let mut params = vec![];
+        // Add remaining parts of string to parameters
+        while !raw.is_empty() {
+            if raw.starts_with(":") {
+                let (_, param) = raw.split_once(":").unwrap();
+                params.push(param.to_string());
+                break;
+            } else {
+                let (param, text) = Message::get_next_word(raw);
+                params.push(param.to_string());
+                raw = text;
+            }
+        }
+        validate_params(&params)?;
+
+        Ok(Message {
+            tags,
+            prefix,
+            command,
+            params,
+        })
+    }
+
+    pub fn new(prefix: Option<Prefix>, command: Command, params: &[&str]) -> Self {
+        Message {
+            tags: vec![],
+            prefix,
+            command,
+            params: params.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    /// Start a message to fill in one part at a time, e.g.
+    ///
+    /// ```
+    /// # use shared::message::{Command, Message};
+    /// let message = Message::builder()
+    ///     .prefix("alice!alice@example.com")
+    ///     .command(Command::PrivMsg)
+    ///     .param("#rust")
+    ///     .trailing("hello world")
+    ///     .build();
+    /// assert_eq!(message.to_string(), ":alice!alice@example.com PRIVMSG #rust :hello world");
+    /// ```
+    pub fn builder() -> MessageBuilder {
+        MessageBuilder::default()
+    }
+
+    pub fn privmsg(target: &str, text: &str) -> Self {
+        Message::builder()
+            .command(Command::PrivMsg)
+            .param(target)
+            .trailing(text)
+            .build()
+    }
+
+    pub fn notice(target: &str, text: &str) -> Self {
+        Message::builder()
+            .command(Command::Notice)
+            .param(target)
+            .trailing(text)
+            .build()
+    }
+
+    pub fn join(channel: &str) -> Self {
+        Message::builder()
+            .command(Command::Join)
+            .param(channel)
+            .build()
+    }
+
+    pub fn part(channel: &str, reason: Option<&str>) -> Self {
+        let builder = Message::builder().command(Command::Part).param(channel);
+        match reason {
+            Some(reason) => builder.trailing(reason).build(),
+            None => builder.build(),
+        }
+    }
+
+    pub fn kick(channel: &str, nick: &str, reason: Option<&str>) -> Self {
+        let builder = Message::builder()
+            .command(Command::Kick)
+            .param(channel)
+            .param(nick);
+        match reason {
+            Some(reason) => builder.trailing(reason).build(),
+            None => builder.build(),
+        }
+    }
+
+    pub fn pong(token: &str) -> Self {
+        Message::builder()
+            .command(Command::Pong)
+            .param(token)
+            .build()
+    }
+
+    /// The ERROR a server sends before closing the connection
+    pub fn error(text: &str) -> Self {
+        Message::builder()
+            .command(Command::Error)
+            .trailing(text)
+            .build()
+    }
+
+    /// The message as sent by a user or server, e.g.
+    /// `Message::notice("alice", "Hi").with_prefix("irc.example.com")`
+    pub fn with_prefix(mut self, prefix: impl Into<Prefix>) -> Self {
+        self.prefix = Some(prefix.into());
+        self
+    }
+
+    /// The value of a tag, which is empty for tags without one
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Check that the message can be sent as it is and read back the same. It can't have more
+    /// than [`MAX_PARAMS`] parameters, any but the last have to be written on their own, and it
+    /// can't have NUL, CR or LF anywhere but in tags, where they're escaped. The tags and the rest
+    /// have to fit in [`MAX_TAGS_LENGTH`] and [`MAX_LENGTH`].
+    ///
+    /// Messages that are parsed are checked the same way, and ones that fail aren't sent by
+    /// [`ToIrc::to_irc`].
+    pub fn validate(&self) -> Result<(), ParseError> {
+        if let Command::Unknown(command) = &self.command
+            && !is_valid_command(command)
+        {
+            return Err(ParseError::BadCommand(command.clone()));
+        }
+        validate_params(&self.params)?;
+        if let Some((key, _)) = self.tags.iter().find(|(key, _)| !is_valid_tag_key(key)) {
+            return Err(ParseError::BadTag(key.clone()));
+        }
+        validate_line(&self.to_string())
+    }
+
+    pub fn set_prefix(&mut self, prefix: impl Into<Prefix>) {
+        self.prefix = Some(prefix.into());
+    }
+
+    /// The nickname in the prefix, e.g. `alice` in `alice!alice@example.com`. For messages from
+    /// a server, this is the server's name, and it's empty without a prefix.
+    pub fn nick(&self) -> &str {
+        self.prefix.as_ref().map(Prefix::name).unwrap_or_default()
+    }
+
+    /// Return the first subsequence of the string separated by spaces as well as the rest of the
+    /// string. If the string has no spaces, return the input.
+    ///
+    /// This is an adjustment to `str::split_once` that returns the input as well as an empty string
+    /// instead of `None`, and skips repeated spaces so they don't make empty parameters.
+    fn get_next_word(input: &str) -> (&str, &str) {
+        match input.split_once(" ") {
+            Some((word, rest)) => (word, rest.trim_start_matches(' ')),
+            None => (input, ""), // String is done
+        }
+    }
+}
+
+/// Whether a command can be written as the word after the tags and prefix and read back as one
+fn is_valid_command(command: &str) -> bool {
+    !command.is_empty() && !command.contains(' ') && !command.starts_with(['@', ':'])
+}
+
+/// Check the number of parameters, and that all but the last can be written without a colon.
+fn validate_params(params: &[String]) -> Result<(), ParseError> {
+    if params.len() > MAX_PARAMS {
+        return Err(ParseError::TooManyParams);
+    }
+    let middle = params.len().saturating_sub(1);
+    match params[..middle]
+        .iter()
+        .position(|param| param.is_empty() || param.contains(' ') || param.starts_with(':'))
+    {
+        Some(index) => Err(ParseError::BadParam { index }),
+        None => Ok(()),
+    }
+}
+
+/// Check a message as it would be written, without its line ending, for characters that would
+/// end it early and for its length.
+fn validate_line(line: &str) -> Result<(), ParseError> {
+    if let Some(pos) = line.find(['\0', '\r', '\n']) {
+        return Err(ParseError::IllegalCharacter { pos });
+    }
+    let (tags, rest) = match line.strip_prefix('@') {
+        Some(_) => line.split_once(' ').unwrap_or((line, "")),
+        None => ("", line),
+    };
+    // Both counting the space after the tags or the CRLF
+    if tags.len() + 1 > MAX_TAGS_LENGTH || rest.len() + 2 > MAX_LENGTH {
+        return Err(ParseError::TooLong);
+    }
+    Ok(())
+}
+
+/// Whether a tag's name is made of letters, digits and hyphens, optionally with a vendor before a
+/// `/` (like `example.com/foo`) and a `+` in front for tags meant for other clients.
+fn is_valid_tag_key(key: &str) -> bool {
+    let key = key.strip_prefix('+').unwrap_or(key);
+    let name = key.rsplit_once('/').map_or(key, |(_, name)| name);
+    !name.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '/'))
+}
+
+/// Undo the escaping of a tag's value, in which `\:` stands for `;` and `\s` for a space.
+fn unescape_tag_value(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some(':') => unescaped.push(';'),
+            Some('s') => unescaped.push(' '),
+            Some('r') => unescaped.push('\r'),
+            Some('n') => unescaped.push('\n'),
+            // Any other character stands for itself, and a lone backslash at the end is dropped
+            Some(other) => unescaped.push(other),
+            None => {}
+        }
+    }
+    unescaped
+}
+
+/// The escaped form of a tag's value. See [`unescape_tag_value`].
+fn escape_tag_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            ';' => escaped.push_str("\\:"),
+            ' ' => escaped.push_str("\\s"),
+            '\\' => escaped.push_str("\\\\"),
+            '\r' => escaped.push_str("\\r"),
+            '\n' => escaped.push_str("\\n"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+impl From<&str> for Command {
+    fn from(input: &str) -> Self {
+        match input.to_uppercase().as_str() {
+            "USER" => Command::User,
+            "NICK" => Command::Nick,
+            "JOIN" => Command::Join,
+            "KICK" => Command::Kick,
+            "PART" => Command::Part,
+            "PRIVMSG" => Command::PrivMsg,
+            "NOTICE" => Command::Notice,
+            "MODE" => Command::Mode,
+            "TOPIC" => Command::Topic,
+            "WHOIS" => Command::Whois,
+            "OPER" => Command::Oper,
+            "SHUN" => Command::Shun,
+            "UNSHUN" => Command::Unshun,
+            "DEFCON" => Command::Defcon,
+            "STATS" => Command::Stats,
+            "QLINE" => Command::Qline,
+            "UNQLINE" => Command::Unqline,
+            "SEARCH" => Command::Search,
+            "LIST" => Command::List,
+            "LUSERS" => Command::Lusers,
+            "AWAY" => Command::Away,
+            "QUIT" => Command::Quit,
+            "CAP" => Command::Cap,
+            "AUTHENTICATE" => Command::Authenticate,
+            "BATCH" => Command::Batch,
+            "TAGMSG" => Command::TagMsg,
+            "PING" => Command::Ping,
+            "PONG" => Command::Pong,
+            "ERROR" => Command::Error,
+            code if code.len() == 3 && code.bytes().all(|b| b.is_ascii_digit()) => {
+                Command::Numeric(code.parse().unwrap())
+            }
+            code => Command::Unknown(code.to_string()),
+        }
+    }
+}
+
+impl Display for Message {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        if !self.tags.is_empty() {
+            let tags = self
+                .tags
+                .iter()
+                .map(|(key, value)| {
+                    if value.is_empty() {
+                        key.clone()
+                    } else {
+                        format!("{key}={}", escape_tag_value(value))
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(";");
+            write!(f, "@{tags} ")?;
+        }
+        if let Some(prefix) = &self.prefix {
+            write!(f, ":{prefix} ")?;
+        }
+        write!(f, "{}", self.command.to_string().to_uppercase())?;
+        write_params(f, &self.params)
+    }
+}
+
+/// Write parameters after a command, each after a space. The last one gets a colon if it's
+/// empty, contains a space or starts with a colon, since it wouldn't otherwise be read back as
+/// the same parameter. Only the last one can be written like that, so if one before it can't be
+/// written on its own, it becomes the last one along with the rest instead of being split up.
+/// Such messages fail validation, so they're only ever shown like this and not sent.
+fn write_params(f: &mut Formatter<'_>, params: &[String]) -> core::fmt::Result {
+    for (i, param) in params.iter().enumerate() {
+        if param.is_empty() || param.contains(' ') || param.starts_with(':') {
+            return write!(f, " :{}", params[i..].join(" "));
+        }
+        write!(f, " {param}")?;
+    }
+    Ok(())
+}
+
+impl ToIrc for Message {
+    fn validate(&self) -> Result<(), ParseError> {
+        Message::validate(self)
+    }
+}
+
+/// Builds a [`Message`] from its parts. Started with [`Message::builder`].
+#[derive(Debug, Default)]
+pub struct MessageBuilder {
+    tags: Vec<(String, String)>,
+    prefix: Option<Prefix>,
+    command: Option<Command>,
+    params: Vec<String>,
+    trailing: Option<String>,
+}
+
+impl MessageBuilder {
+    /// Add a tag, unescaped. An empty value leaves it without one.
+    pub fn tag(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.tags.push((key.into(), value.into()));
+        self
+    }
+
+    pub fn prefix(mut self, prefix: impl Into<Prefix>) -> Self {
+        self.prefix = Some(prefix.into());
+        self
+    }
+
+    pub fn command(mut self, command: Command) -> Self {
+        self.command = Some(command);
+        self
+    }
+
+    /// Add a parameter after the ones before it. These shouldn't contain spaces.
+    pub fn param(mut self, param: impl Into<String>) -> Self {
+        self.params.push(param.into());
+        self
+    }
+
+    pub fn params<I>(mut self, params: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.params.extend(params.into_iter().map(Into::into));
+        self
+    }
+
+    /// Set the last parameter, which can contain spaces, such as the text of a message. It comes
+    /// after the others however many are added after it.
+    pub fn trailing(mut self, text: impl Into<String>) -> Self {
+        self.trailing = Some(text.into());
+        self
+    }
+
+    /// The message. Panics if no command was given.
+    #[track_caller]
+    pub fn build(self) -> Message {
+        let mut params = self.params;
+        params.extend(self.trailing);
+        Message {
+            tags: self.tags,
+            prefix: self.prefix,
+            command: self.command.expect("A message needs a command"),
+            params,
+        }
+    }
+}
+
+impl Response {
+    pub fn new(prefix: &str, code: ReplyCode, params: &[&str]) -> Self {
+        Response {
+            prefix: prefix.to_string(),
+            code,
+            params: params.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+}
+
+impl From<String> for Command {
+    fn from(input: String) -> Self {
+        Command::from(input.as_str())
+    }
+}
+
+/// The command as it's sent, like `PRIVMSG` or `001`
+impl From<Command> for String {
+    fn from(command: Command) -> Self {
+        command.to_string().to_uppercase()
+    }
+}
+
+impl Display for Command {
+    fn fmt(&self, f: &mut Formatter) -> core::fmt::Result {
+        match self {
+            Command::Numeric(code) => write!(f, "{:03}", code),
+            Command::Unknown(command) => write!(f, "{command}"),
+            _ => write!(f, "{:?}", self),
+        }
+    }
+}
+
+impl Display for Response {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, ":{} {:03}", self.prefix, self.code as u16)?;
+        write_params(f, &self.params)
+    }
+}
+
+impl Response {
+    /// Check that the reply can be sent, the same way as [`Message::validate`]
+    pub fn validate(&self) -> Result<(), ParseError> {
+        validate_params(&self.params)?;
+        validate_line(&self.to_string())
+    }
+}
+
+impl ToIrc for Response {
+    fn validate(&self) -> Result<(), ParseError> {
+        Response::validate(self)
+    }
+}
+
+/// Tags as a JSON object rather than a list of pairs
+#[cfg(feature = "serde")]
+mod tag_map {
+    use alloc::{collections::BTreeMap, string::String, vec::Vec};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        tags: &[(String, String)],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(tags.iter().map(|(key, value)| (key, value)))
+    }
+
+    /// The tags sorted by name, since their order doesn't matter
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<(String, String)>, D::Error> {
+        Ok(BTreeMap::<String, String>::deserialize(deserializer)?
+            .into_iter()
+            .collect())
+    }
+}
@@ -0,0 +1,125 @@
+//! The mode changes in a MODE message, like `+ook-b alice bob secret *!*@spam`. Which modes take
+//! an argument depends on the server, so the parser is told which ones do.
+//!
+//! ```
+//! # use shared::mode::{ModeArgs, ModeChange};
+//! let args = ModeArgs::new("bkov", "l");
+//! let changes = ModeChange::parse("+ook-b", ["alice", "bob", "secret", "*!*@spam"], &args);
+//! assert_eq!(changes[0], ModeChange::new(true, 'o', Some("alice")));
+//! assert_eq!(changes[3], ModeChange::new(false, 'b', Some("*!*@spam")));
+//! assert_eq!(
+//!     ModeChange::format(&changes),
+//!     ["+ook-b", "alice", "bob", "secret", "*!*@spam"],
+//! );
+//! ```
+
+use crate::isupport::ISupport;
+use alloc::{
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+
+/// Setting or unsetting one mode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModeChange {
+    /// Whether the mode is set (`+`) rather than unset (`-`)
+    pub adding: bool,
+    pub mode: char,
+    /// `None` for modes without an argument, and for ones that needed one but it was left out
+    pub arg: Option<String>,
+}
+
+/// Which modes take an argument
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ModeArgs {
+    /// Modes that take one when set and when unset, like bans and operators
+    always: String,
+    /// Modes that take one only when set, like the limit
+    when_set: String,
+}
+
+impl ModeArgs {
+    pub fn new(always: &str, when_set: &str) -> Self {
+        ModeArgs {
+            always: always.to_string(),
+            when_set: when_set.to_string(),
+        }
+    }
+
+    /// The channel modes that take an argument on a server, from its `PREFIX` and `CHANMODES`
+    pub fn from_isupport(isupport: &ISupport) -> Self {
+        let chanmodes = isupport.chanmodes();
+        let mut always = isupport
+            .prefix()
+            .into_iter()
+            .map(|(mode, _)| mode)
+            .collect::<String>();
+        always.push_str(&chanmodes.list);
+        always.push_str(&chanmodes.always_param);
+        ModeArgs {
+            always,
+            when_set: chanmodes.param_when_set,
+        }
+    }
+
+    pub fn takes_arg(&self, mode: char, adding: bool) -> bool {
+        self.always.contains(mode) || (adding && self.when_set.contains(mode))
+    }
+}
+
+impl ModeChange {
+    pub fn new(adding: bool, mode: char, arg: Option<&str>) -> Self {
+        ModeChange {
+            adding,
+            mode,
+            arg: arg.map(str::to_string),
+        }
+    }
+
+    /// Split a mode string and the arguments after it into changes. Modes start out being set
+    /// until a `-`, and arguments left over at the end are ignored.
+    pub fn parse<'a>(
+        modes: &str,
+        args: impl IntoIterator<Item = &'a str>,
+        mode_args: &ModeArgs,
+    ) -> Vec<ModeChange> {
+        let mut args = args.into_iter();
+        let mut adding = true;
+        let mut changes = vec![];
+        for mode in modes.chars() {
+            match mode {
+                '+' => adding = true,
+                '-' => adding = false,
+                _ => {
+                    let arg = if mode_args.takes_arg(mode, adding) {
+                        args.next()
+                    } else {
+                        None
+                    };
+                    changes.push(ModeChange::new(adding, mode, arg));
+                }
+            }
+        }
+        changes
+    }
+
+    /// The parameters of a MODE making the changes: the mode string, with a `+` or `-` only
+    /// where it changes, and then the arguments
+    pub fn format(changes: &[ModeChange]) -> Vec<String> {
+        let mut modes = String::new();
+        let mut args = vec![];
+        let mut last_sign = None;
+        for change in changes {
+            if last_sign != Some(change.adding) {
+                modes.push(if change.adding { '+' } else { '-' });
+                last_sign = Some(change.adding);
+            }
+            modes.push(change.mode);
+            args.extend(change.arg.clone());
+        }
+        let mut params = vec![modes];
+        params.append(&mut args);
+        params
+    }
+}
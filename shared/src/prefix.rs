@@ -0,0 +1,113 @@
+use alloc::{
+    string::{String, ToString},
+    sync::Arc,
+};
+use core::fmt::{Display, Formatter};
+
+/// Who a message is from, as given at its start after a colon. The names are shared, so that a
+/// server can put the same prefix on every message from a user without copying it each time.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(into = "String", from = "String")
+)]
+pub enum Prefix {
+    /// A server's name, like `irc.example.com`
+    Server(Arc<str>),
+    /// A user, as `alice!alice@example.com`. Servers can leave out the username and host.
+    User {
+        nick: Arc<str>,
+        user: Option<Arc<str>>,
+        host: Option<Arc<str>>,
+    },
+}
+
+impl Prefix {
+    /// Parse a prefix without its colon. A name with a `.` in it and no `!` or `@` is a server's,
+    /// since nicknames can't contain dots, and anything else is a user's.
+    pub fn parse(prefix: &str) -> Self {
+        if !prefix.contains(['!', '@']) && prefix.contains('.') {
+            return Prefix::Server(prefix.into());
+        }
+        let (rest, host) = match prefix.split_once('@') {
+            Some((rest, host)) => (rest, Some(host.into())),
+            None => (prefix, None),
+        };
+        let (nick, user) = match rest.split_once('!') {
+            Some((nick, user)) => (nick, Some(user.into())),
+            None => (rest, None),
+        };
+        Prefix::User {
+            nick: nick.into(),
+            user,
+            host,
+        }
+    }
+
+    /// The nickname of a user, or the name of a server
+    pub fn name(&self) -> &str {
+        match self {
+            Prefix::Server(name) | Prefix::User { nick: name, .. } => name,
+        }
+    }
+
+    /// The nickname, if the message is from a user
+    pub fn nick(&self) -> Option<&str> {
+        match self {
+            Prefix::User { nick, .. } => Some(nick),
+            Prefix::Server(_) => None,
+        }
+    }
+
+    pub fn user(&self) -> Option<&str> {
+        match self {
+            Prefix::User { user, .. } => user.as_deref(),
+            Prefix::Server(_) => None,
+        }
+    }
+
+    pub fn host(&self) -> Option<&str> {
+        match self {
+            Prefix::User { host, .. } => host.as_deref(),
+            Prefix::Server(_) => None,
+        }
+    }
+}
+
+impl From<&str> for Prefix {
+    fn from(prefix: &str) -> Self {
+        Prefix::parse(prefix)
+    }
+}
+
+impl From<String> for Prefix {
+    fn from(prefix: String) -> Self {
+        Prefix::parse(&prefix)
+    }
+}
+
+/// The prefix as it's sent, without its colon
+impl From<Prefix> for String {
+    fn from(prefix: Prefix) -> Self {
+        prefix.to_string()
+    }
+}
+
+impl Display for Prefix {
+    fn fmt(&self, f: &mut Formatter) -> core::fmt::Result {
+        match self {
+            Prefix::Server(name) => write!(f, "{name}"),
+            Prefix::User { nick, user, host } => {
+                write!(f, "{nick}")?;
+                if let Some(user) = user {
+                    write!(f, "!{user}")?;
+                }
+                if let Some(host) = host {
+                    write!(f, "@{host}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
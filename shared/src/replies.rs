@@ -0,0 +1,1409 @@
+//! Numeric replies with the parameters each one takes, so that they can't be sent with some left
+//! out or in the wrong order. Every reply starts with the nickname of the client it's sent to, or
+//! `*` before the client has one.
+//!
+//! The texts are the ones RFC 2812 gives, except where the reply says why something wasn't
+//! allowed. They can be replaced with [`ReplyTexts`], e.g. to translate them. Trace and other
+//! server-to-server replies only have a [`ReplyCode`].
+
+use crate::{
+    message::{ReplyCode, Response},
+    prefix::Prefix,
+};
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec,
+};
+use core::{fmt, time::Duration};
+
+/// Texts for replies to use instead of their own. A text can have the values that go in it as
+/// `{name}`s, named like in the reply's documentation, e.g. `{date}` in
+/// `003 <nick> :This server was created <date>`.
+pub trait ReplyTexts: fmt::Debug {
+    /// The text for a reply, or `None` for its own
+    fn get(&self, code: ReplyCode) -> Option<&str>;
+}
+
+/// Builds numeric replies from a server, e.g.
+///
+/// ```
+/// use shared::{message::ToIrc, replies::Replies};
+///
+/// let reply = Replies::new("irc.example.com").topic("alice", "#rust", "Rust, every day");
+/// assert_eq!(reply.to_irc().unwrap(), ":irc.example.com 332 alice #rust :Rust, every day\r\n");
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct Replies<'a> {
+    server: &'a str,
+    texts: Option<&'a dyn ReplyTexts>,
+}
+
+impl<'a> Replies<'a> {
+    /// Replies from the server with the name
+    pub fn new(server: &'a str) -> Self {
+        Replies {
+            server,
+            texts: None,
+        }
+    }
+
+    /// Use the texts for the replies that they have one for
+    pub fn with_texts(self, texts: &'a dyn ReplyTexts) -> Self {
+        Replies {
+            texts: Some(texts),
+            ..self
+        }
+    }
+
+    fn reply(&self, code: ReplyCode, params: &[&str]) -> Response {
+        Response::new(self.server, code, params)
+    }
+
+    /// Like [`Replies::reply`], with a text at the end that can be replaced
+    fn reply_text(&self, code: ReplyCode, params: &[&str], text: &str) -> Response {
+        self.reply_text_with(code, params, text, &[])
+    }
+
+    /// Like [`Replies::reply_text`], with values to put in the text in place of their names
+    fn reply_text_with(
+        &self,
+        code: ReplyCode,
+        params: &[&str],
+        text: &str,
+        values: &[(&str, &str)],
+    ) -> Response {
+        let text = self.texts.and_then(|texts| texts.get(code)).unwrap_or(text);
+        let text = fill(text, values);
+        let mut params = params.to_vec();
+        params.push(&text);
+        self.reply(code, &params)
+    }
+
+    /// Like [`Replies::reply`], with a list after the parameters that are always there
+    fn reply_with_list(&self, code: ReplyCode, params: &[&str], list: &[String]) -> Response {
+        let mut params = params.to_vec();
+        params.extend(list.iter().map(String::as_str));
+        self.reply(code, &params)
+    }
+
+    // Registration
+
+    /// `001 <nick> :Welcome to the Internet Relay Network <prefix>`, where the prefix is
+    /// `<nick>!<user>@<host>`
+    pub fn welcome(&self, nick: &str, prefix: &Prefix) -> Response {
+        self.reply_text_with(
+            ReplyCode::RPL_WELCOME,
+            &[nick],
+            "Welcome to the Internet Relay Network {prefix}",
+            &[("nick", nick), ("prefix", &prefix.to_string())],
+        )
+    }
+
+    /// `002 <nick> :Your host is <server>, running version <version>`
+    pub fn your_host(&self, nick: &str, version: &str) -> Response {
+        self.reply_text_with(
+            ReplyCode::RPL_YOURHOST,
+            &[nick],
+            "Your host is {server}, running version {version}",
+            &[("server", self.server), ("version", version)],
+        )
+    }
+
+    /// `003 <nick> :This server was created <date>`
+    pub fn created(&self, nick: &str, date: &str) -> Response {
+        self.reply_text_with(
+            ReplyCode::RPL_CREATED,
+            &[nick],
+            "This server was created {date}",
+            &[("date", date)],
+        )
+    }
+
+    /// `004 <nick> <server> <version> <user modes> <channel modes>`
+    pub fn my_info(
+        &self,
+        nick: &str,
+        version: &str,
+        user_modes: &str,
+        channel_modes: &str,
+    ) -> Response {
+        self.reply(
+            ReplyCode::RPL_MYINFO,
+            &[nick, self.server, version, user_modes, channel_modes],
+        )
+    }
+
+    /// `005 <nick> <token>... :are supported by this server`, with tokens like `CHANTYPES=#&`
+    pub fn isupport(&self, nick: &str, tokens: &[String]) -> Response {
+        let mut params = vec![nick];
+        params.extend(tokens.iter().map(String::as_str));
+        self.reply_text(
+            ReplyCode::RPL_ISUPPORT,
+            &params,
+            "are supported by this server",
+        )
+    }
+
+    /// `008 <nick> <mask> :Server notice mask`
+    pub fn snomask(&self, nick: &str, mask: &str) -> Response {
+        self.reply_text(ReplyCode::RPL_SNOMASK, &[nick, mask], "Server notice mask")
+    }
+
+    // Server information and statistics
+
+    /// `212 <nick> <command> <count> <bytes> <remote count>`
+    pub fn stats_commands(
+        &self,
+        nick: &str,
+        command: &str,
+        count: u64,
+        bytes: u64,
+        remote_count: u64,
+    ) -> Response {
+        self.reply(
+            ReplyCode::RPL_STATSCOMMANDS,
+            &[
+                nick,
+                command,
+                &count.to_string(),
+                &bytes.to_string(),
+                &remote_count.to_string(),
+            ],
+        )
+    }
+
+    /// `217 <nick> q <mask> :<reason>`, a reserved nickname
+    pub fn stats_qline(&self, nick: &str, mask: &str, reason: &str) -> Response {
+        self.reply(ReplyCode::RPL_STATSQLINE, &[nick, "q", mask, reason])
+    }
+
+    /// `219 <nick> <letter> :End of STATS report`
+    pub fn end_of_stats(&self, nick: &str, letter: &str) -> Response {
+        self.reply_text(
+            ReplyCode::RPL_ENDOFSTATS,
+            &[nick, letter],
+            "End of STATS report",
+        )
+    }
+
+    /// `221 <nick> <modes>`
+    pub fn umode_is(&self, nick: &str, modes: &str) -> Response {
+        self.reply(ReplyCode::RPL_UMODEIS, &[nick, modes])
+    }
+
+    /// `242 <nick> :Server Up <days> days <hours>:<minutes>:<seconds>`
+    pub fn stats_uptime(&self, nick: &str, uptime: Duration) -> Response {
+        let seconds = uptime.as_secs();
+        self.reply_text_with(
+            ReplyCode::RPL_STATSUPTIME,
+            &[nick],
+            "Server Up {days} days {hours}:{minutes}:{seconds}",
+            &[
+                ("days", &(seconds / 86400).to_string()),
+                ("hours", &(seconds / 3600 % 24).to_string()),
+                ("minutes", &format!("{:02}", seconds / 60 % 60)),
+                ("seconds", &format!("{:02}", seconds % 60)),
+            ],
+        )
+    }
+
+    /// `243 <nick> O <host mask> * <name>`
+    pub fn stats_oline(&self, nick: &str, host_mask: &str, name: &str) -> Response {
+        self.reply(
+            ReplyCode::RPL_STATSOLINE,
+            &[nick, "O", host_mask, "*", name],
+        )
+    }
+
+    /// `249 <nick> <letter> :<text>`, a line of a STATS report without a numeric of its own
+    pub fn stats_debug(&self, nick: &str, letter: char, text: &str) -> Response {
+        self.reply(
+            ReplyCode::RPL_STATSDEBUG,
+            &[nick, &letter.to_string(), text],
+        )
+    }
+
+    /// `250 <nick> :<text>`, how many connections there have been
+    pub fn stats_conn(&self, nick: &str, text: &str) -> Response {
+        self.reply(ReplyCode::RPL_STATSCONN, &[nick, text])
+    }
+
+    /// `251 <nick> :There are <users> users and <invisible> invisible on <servers> servers`
+    pub fn luser_client(
+        &self,
+        nick: &str,
+        users: usize,
+        invisible: usize,
+        servers: usize,
+    ) -> Response {
+        self.reply_text_with(
+            ReplyCode::RPL_LUSERCLIENT,
+            &[nick],
+            "There are {users} users and {invisible} invisible on {servers} servers",
+            &[
+                ("users", &users.to_string()),
+                ("invisible", &invisible.to_string()),
+                ("servers", &servers.to_string()),
+            ],
+        )
+    }
+
+    /// `252 <nick> <count> :operator(s) online`
+    pub fn luser_op(&self, nick: &str, count: usize) -> Response {
+        self.reply_text(
+            ReplyCode::RPL_LUSEROP,
+            &[nick, &count.to_string()],
+            "operator(s) online",
+        )
+    }
+
+    /// `253 <nick> <count> :unknown connection(s)`
+    pub fn luser_unknown(&self, nick: &str, count: usize) -> Response {
+        self.reply_text(
+            ReplyCode::RPL_LUSERUNKNOWN,
+            &[nick, &count.to_string()],
+            "unknown connection(s)",
+        )
+    }
+
+    /// `254 <nick> <count> :channels formed`
+    pub fn luser_channels(&self, nick: &str, count: usize) -> Response {
+        self.reply_text(
+            ReplyCode::RPL_LUSERCHANNELS,
+            &[nick, &count.to_string()],
+            "channels formed",
+        )
+    }
+
+    /// `255 <nick> :I have <clients> clients and <servers> servers`
+    pub fn luser_me(&self, nick: &str, clients: usize, servers: usize) -> Response {
+        self.reply_text_with(
+            ReplyCode::RPL_LUSERME,
+            &[nick],
+            "I have {clients} clients and {servers} servers",
+            &[
+                ("clients", &clients.to_string()),
+                ("servers", &servers.to_string()),
+            ],
+        )
+    }
+
+    /// `256 <nick> <server> :Administrative info`
+    pub fn admin_me(&self, nick: &str) -> Response {
+        self.reply_text(
+            ReplyCode::RPL_ADMINME,
+            &[nick, self.server],
+            "Administrative info",
+        )
+    }
+
+    /// `257 <nick> :<text>`, usually where the server is
+    pub fn admin_loc1(&self, nick: &str, text: &str) -> Response {
+        self.reply(ReplyCode::RPL_ADMINLOC1, &[nick, text])
+    }
+
+    /// `258 <nick> :<text>`, usually who runs the server
+    pub fn admin_loc2(&self, nick: &str, text: &str) -> Response {
+        self.reply(ReplyCode::RPL_ADMINLOC2, &[nick, text])
+    }
+
+    /// `259 <nick> :<email>`
+    pub fn admin_email(&self, nick: &str, email: &str) -> Response {
+        self.reply(ReplyCode::RPL_ADMINEMAIL, &[nick, email])
+    }
+
+    /// `263 <nick> <command> :Please wait a while and try again.`
+    pub fn try_again(&self, nick: &str, command: &str) -> Response {
+        self.reply_text(
+            ReplyCode::RPL_TRYAGAIN,
+            &[nick, command],
+            "Please wait a while and try again.",
+        )
+    }
+
+    /// `265 <nick> <current> <max> :Current local users <current>, max <max>`
+    pub fn local_users(&self, nick: &str, current: usize, max: usize) -> Response {
+        let (current, max) = (current.to_string(), max.to_string());
+        self.reply_text_with(
+            ReplyCode::RPL_LOCALUSERS,
+            &[nick, &current, &max],
+            "Current local users {current}, max {max}",
+            &[("current", &current), ("max", &max)],
+        )
+    }
+
+    /// `266 <nick> <current> <max> :Current global users <current>, max <max>`
+    pub fn global_users(&self, nick: &str, current: usize, max: usize) -> Response {
+        let (current, max) = (current.to_string(), max.to_string());
+        self.reply_text_with(
+            ReplyCode::RPL_GLOBALUSERS,
+            &[nick, &current, &max],
+            "Current global users {current}, max {max}",
+            &[("current", &current), ("max", &max)],
+        )
+    }
+
+    /// `351 <nick> <version> <server> :<comments>`
+    pub fn version(&self, nick: &str, version: &str, comments: &str) -> Response {
+        self.reply(
+            ReplyCode::RPL_VERSION,
+            &[nick, version, self.server, comments],
+        )
+    }
+
+    /// `364 <nick> <mask> <server> :<hop count> <info>`
+    pub fn links(
+        &self,
+        nick: &str,
+        mask: &str,
+        server: &str,
+        hop_count: u32,
+        info: &str,
+    ) -> Response {
+        self.reply(
+            ReplyCode::RPL_LINKS,
+            &[nick, mask, server, &format!("{hop_count} {info}")],
+        )
+    }
+
+    /// `365 <nick> <mask> :End of LINKS list`
+    pub fn end_of_links(&self, nick: &str, mask: &str) -> Response {
+        self.reply_text(
+            ReplyCode::RPL_ENDOFLINKS,
+            &[nick, mask],
+            "End of LINKS list",
+        )
+    }
+
+    /// `371 <nick> :<text>`
+    pub fn info(&self, nick: &str, text: &str) -> Response {
+        self.reply(ReplyCode::RPL_INFO, &[nick, text])
+    }
+
+    /// `374 <nick> :End of INFO list`
+    pub fn end_of_info(&self, nick: &str) -> Response {
+        self.reply_text(ReplyCode::RPL_ENDOFINFO, &[nick], "End of INFO list")
+    }
+
+    /// `375 <nick> :- <server> Message of the day - `
+    pub fn motd_start(&self, nick: &str) -> Response {
+        self.reply_text_with(
+            ReplyCode::RPL_MOTDSTART,
+            &[nick],
+            "- {server} Message of the day - ",
+            &[("server", self.server)],
+        )
+    }
+
+    /// `372 <nick> :- <line>`
+    pub fn motd(&self, nick: &str, line: &str) -> Response {
+        self.reply(ReplyCode::RPL_MOTD, &[nick, &format!("- {line}")])
+    }
+
+    /// `376 <nick> :End of MOTD command`
+    pub fn end_of_motd(&self, nick: &str) -> Response {
+        self.reply_text(ReplyCode::RPL_ENDOFMOTD, &[nick], "End of MOTD command")
+    }
+
+    /// `381 <nick> :You are now an IRC operator`
+    pub fn youre_oper(&self, nick: &str) -> Response {
+        self.reply_text(
+            ReplyCode::RPL_YOUREOPER,
+            &[nick],
+            "You are now an IRC operator",
+        )
+    }
+
+    /// `382 <nick> <config file> :Rehashing`
+    pub fn rehashing(&self, nick: &str, config_file: &str) -> Response {
+        self.reply_text(ReplyCode::RPL_REHASHING, &[nick, config_file], "Rehashing")
+    }
+
+    /// `383 <nick> :You are service <name>`
+    pub fn youre_service(&self, nick: &str, name: &str) -> Response {
+        self.reply_text_with(
+            ReplyCode::RPL_YOURESERVICE,
+            &[nick],
+            "You are service {name}",
+            &[("name", name)],
+        )
+    }
+
+    /// `391 <nick> <server> :<time>`
+    pub fn time(&self, nick: &str, time: &str) -> Response {
+        self.reply(ReplyCode::RPL_TIME, &[nick, self.server, time])
+    }
+
+    /// `396 <nick> <host> :is now your displayed host`
+    pub fn host_hidden(&self, nick: &str, host: &str) -> Response {
+        self.reply_text(
+            ReplyCode::RPL_HOSTHIDDEN,
+            &[nick, host],
+            "is now your displayed host",
+        )
+    }
+
+    // Users
+
+    /// `301 <nick> <target> :<away message>`
+    pub fn away(&self, nick: &str, target: &str, message: &str) -> Response {
+        self.reply(ReplyCode::RPL_AWAY, &[nick, target, message])
+    }
+
+    /// `302 <nick> :<reply>...`, with replies like `alice*=+alice@example.com`
+    pub fn userhost(&self, nick: &str, replies: &[String]) -> Response {
+        self.reply(ReplyCode::RPL_USERHOST, &[nick, &replies.join(" ")])
+    }
+
+    /// `303 <nick> :<nick>...`, the nicknames that are online
+    pub fn ison(&self, nick: &str, nicks: &[String]) -> Response {
+        self.reply(ReplyCode::RPL_ISON, &[nick, &nicks.join(" ")])
+    }
+
+    /// `305 <nick> :You are no longer marked as being away`
+    pub fn unaway(&self, nick: &str) -> Response {
+        self.reply_text(
+            ReplyCode::RPL_UNAWAY,
+            &[nick],
+            "You are no longer marked as being away",
+        )
+    }
+
+    /// `306 <nick> :You have been marked as being away`
+    pub fn now_away(&self, nick: &str) -> Response {
+        self.reply_text(
+            ReplyCode::RPL_NOWAWAY,
+            &[nick],
+            "You have been marked as being away",
+        )
+    }
+
+    /// `276 <nick> <target> :has client certificate fingerprint <fingerprint>`
+    pub fn whois_certfp(&self, nick: &str, target: &str, fingerprint: &str) -> Response {
+        self.reply_text_with(
+            ReplyCode::RPL_WHOISCERTFP,
+            &[nick, target],
+            "has client certificate fingerprint {fingerprint}",
+            &[("fingerprint", fingerprint)],
+        )
+    }
+
+    /// `311 <nick> <target> <user> <host> * :<real name>`
+    pub fn whois_user(
+        &self,
+        nick: &str,
+        target: &str,
+        user: &str,
+        host: &str,
+        realname: &str,
+    ) -> Response {
+        self.reply(
+            ReplyCode::RPL_WHOISUSER,
+            &[nick, target, user, host, "*", realname],
+        )
+    }
+
+    /// `312 <nick> <target> <server> :<server info>`
+    pub fn whois_server(&self, nick: &str, target: &str, server: &str, info: &str) -> Response {
+        self.reply(ReplyCode::RPL_WHOISSERVER, &[nick, target, server, info])
+    }
+
+    /// `313 <nick> <target> :is an IRC operator`
+    pub fn whois_operator(&self, nick: &str, target: &str) -> Response {
+        self.reply_text(
+            ReplyCode::RPL_WHOISOPERATOR,
+            &[nick, target],
+            "is an IRC operator",
+        )
+    }
+
+    /// `314 <nick> <target> <user> <host> * :<real name>`
+    pub fn whowas_user(
+        &self,
+        nick: &str,
+        target: &str,
+        user: &str,
+        host: &str,
+        realname: &str,
+    ) -> Response {
+        self.reply(
+            ReplyCode::RPL_WHOWASUSER,
+            &[nick, target, user, host, "*", realname],
+        )
+    }
+
+    /// `317 <nick> <target> <idle seconds> <signon time> :seconds idle, signon time`
+    pub fn whois_idle(&self, nick: &str, target: &str, idle: u64, signon: i64) -> Response {
+        self.reply_text(
+            ReplyCode::RPL_WHOISIDLE,
+            &[nick, target, &idle.to_string(), &signon.to_string()],
+            "seconds idle, signon time",
+        )
+    }
+
+    /// `318 <nick> <target> :End of WHOIS list`
+    pub fn end_of_whois(&self, nick: &str, target: &str) -> Response {
+        self.reply_text(
+            ReplyCode::RPL_ENDOFWHOIS,
+            &[nick, target],
+            "End of WHOIS list",
+        )
+    }
+
+    /// `319 <nick> <target> :<channel>...`, each with the target's highest prefix like `@#rust`
+    pub fn whois_channels(&self, nick: &str, target: &str, channels: &[String]) -> Response {
+        self.reply(
+            ReplyCode::RPL_WHOISCHANNELS,
+            &[nick, target, &channels.join(" ")],
+        )
+    }
+
+    /// `330 <nick> <target> <account> :is logged in as`
+    pub fn whois_account(&self, nick: &str, target: &str, account: &str) -> Response {
+        self.reply_text(
+            ReplyCode::RPL_WHOISACCOUNT,
+            &[nick, target, account],
+            "is logged in as",
+        )
+    }
+
+    /// `338 <nick> <target> <host> :actually using host`
+    pub fn whois_actually(&self, nick: &str, target: &str, host: &str) -> Response {
+        self.reply_text(
+            ReplyCode::RPL_WHOISACTUALLY,
+            &[nick, target, host],
+            "actually using host",
+        )
+    }
+
+    /// `671 <nick> <target> :is using a secure connection`
+    pub fn whois_secure(&self, nick: &str, target: &str) -> Response {
+        self.reply_text(
+            ReplyCode::RPL_WHOISSECURE,
+            &[nick, target],
+            "is using a secure connection",
+        )
+    }
+
+    /// `369 <nick> <target> :End of WHOWAS`
+    pub fn end_of_whowas(&self, nick: &str, target: &str) -> Response {
+        self.reply_text(ReplyCode::RPL_ENDOFWHOWAS, &[nick, target], "End of WHOWAS")
+    }
+
+    /// `352 <nick> <channel> <user> <host> <server> <target> <flags> :<hop count> <real name>`,
+    /// where the flags are `H` or `G` for here or gone (away), then `*` for IRC operators and
+    /// the highest channel prefix
+    #[allow(clippy::too_many_arguments)]
+    pub fn who_reply(
+        &self,
+        nick: &str,
+        channel: &str,
+        user: &str,
+        host: &str,
+        target: &str,
+        flags: &str,
+        hop_count: u32,
+        realname: &str,
+    ) -> Response {
+        self.reply(
+            ReplyCode::RPL_WHOREPLY,
+            &[
+                nick,
+                channel,
+                user,
+                host,
+                self.server,
+                target,
+                flags,
+                &format!("{hop_count} {realname}"),
+            ],
+        )
+    }
+
+    /// `315 <nick> <mask> :End of WHO list`
+    pub fn end_of_who(&self, nick: &str, mask: &str) -> Response {
+        self.reply_text(ReplyCode::RPL_ENDOFWHO, &[nick, mask], "End of WHO list")
+    }
+
+    // Channels
+
+    /// `321 <nick> Channel :Users  Name`
+    pub fn list_start(&self, nick: &str) -> Response {
+        self.reply_text(ReplyCode::RPL_LISTSTART, &[nick, "Channel"], "Users  Name")
+    }
+
+    /// `322 <nick> <channel> <visible users> :<topic>`
+    pub fn list(&self, nick: &str, channel: &str, visible: usize, topic: &str) -> Response {
+        self.reply(
+            ReplyCode::RPL_LIST,
+            &[nick, channel, &visible.to_string(), topic],
+        )
+    }
+
+    /// `323 <nick> :End of LIST`
+    pub fn list_end(&self, nick: &str) -> Response {
+        self.reply_text(ReplyCode::RPL_LISTEND, &[nick], "End of LIST")
+    }
+
+    /// `324 <nick> <channel> <modes> <mode arguments>...`, with the modes and their arguments
+    /// like `["+W", "30"]`
+    pub fn channel_mode_is(&self, nick: &str, channel: &str, modes: &[String]) -> Response {
+        self.reply_with_list(ReplyCode::RPL_CHANNELMODEIS, &[nick, channel], modes)
+    }
+
+    /// `325 <nick> <channel> <target>`, the channel's creator
+    pub fn uniq_op_is(&self, nick: &str, channel: &str, target: &str) -> Response {
+        self.reply(ReplyCode::RPL_UNIQOPIS, &[nick, channel, target])
+    }
+
+    /// `329 <nick> <channel> <time>`, when the channel was created as a Unix timestamp
+    pub fn creation_time(&self, nick: &str, channel: &str, time: i64) -> Response {
+        self.reply(
+            ReplyCode::RPL_CREATIONTIME,
+            &[nick, channel, &time.to_string()],
+        )
+    }
+
+    /// `331 <nick> <channel> :No topic is set`
+    pub fn no_topic(&self, nick: &str, channel: &str) -> Response {
+        self.reply_text(ReplyCode::RPL_NOTOPIC, &[nick, channel], "No topic is set")
+    }
+
+    /// `332 <nick> <channel> :<topic>`
+    pub fn topic(&self, nick: &str, channel: &str, topic: &str) -> Response {
+        self.reply(ReplyCode::RPL_TOPIC, &[nick, channel, topic])
+    }
+
+    /// `333 <nick> <channel> <setter> <time>`, who set the topic and when as a Unix timestamp
+    pub fn topic_who_time(&self, nick: &str, channel: &str, setter: &str, time: i64) -> Response {
+        self.reply(
+            ReplyCode::RPL_TOPICWHOTIME,
+            &[nick, channel, setter, &time.to_string()],
+        )
+    }
+
+    /// `341 <nick> <target> <channel>`
+    pub fn inviting(&self, nick: &str, target: &str, channel: &str) -> Response {
+        self.reply(ReplyCode::RPL_INVITING, &[nick, target, channel])
+    }
+
+    /// `342 <nick> <user> :Summoning user to IRC`
+    pub fn summoning(&self, nick: &str, user: &str) -> Response {
+        self.reply_text(
+            ReplyCode::RPL_SUMMONING,
+            &[nick, user],
+            "Summoning user to IRC",
+        )
+    }
+
+    /// `346 <nick> <channel> <mask>`
+    pub fn invite_list(&self, nick: &str, channel: &str, mask: &str) -> Response {
+        self.reply(ReplyCode::RPL_INVITELIST, &[nick, channel, mask])
+    }
+
+    /// `347 <nick> <channel> :End of channel invite list`
+    pub fn end_of_invite_list(&self, nick: &str, channel: &str) -> Response {
+        self.reply_text(
+            ReplyCode::RPL_ENDOFINVITELIST,
+            &[nick, channel],
+            "End of channel invite list",
+        )
+    }
+
+    /// `348 <nick> <channel> <mask>`
+    pub fn except_list(&self, nick: &str, channel: &str, mask: &str) -> Response {
+        self.reply(ReplyCode::RPL_EXCEPTLIST, &[nick, channel, mask])
+    }
+
+    /// `349 <nick> <channel> :End of channel exception list`
+    pub fn end_of_except_list(&self, nick: &str, channel: &str) -> Response {
+        self.reply_text(
+            ReplyCode::RPL_ENDOFEXCEPTLIST,
+            &[nick, channel],
+            "End of channel exception list",
+        )
+    }
+
+    /// `353 <nick> <symbol> <channel> :<name>...`, where the symbol is `=` for public channels,
+    /// `@` for secret ones and `*` for private ones, and each name has the user's prefixes
+    pub fn name_reply(
+        &self,
+        nick: &str,
+        symbol: char,
+        channel: &str,
+        names: &[String],
+    ) -> Response {
+        self.reply(
+            ReplyCode::RPL_NAMREPLY,
+            &[nick, &symbol.to_string(), channel, &names.join(" ")],
+        )
+    }
+
+    /// `366 <nick> <channel> :End of NAMES list`
+    pub fn end_of_names(&self, nick: &str, channel: &str) -> Response {
+        self.reply_text(
+            ReplyCode::RPL_ENDOFNAMES,
+            &[nick, channel],
+            "End of NAMES list",
+        )
+    }
+
+    /// `367 <nick> <channel> <mask>`
+    pub fn ban_list(&self, nick: &str, channel: &str, mask: &str) -> Response {
+        self.reply(ReplyCode::RPL_BANLIST, &[nick, channel, mask])
+    }
+
+    /// `368 <nick> <channel> :End of channel ban list`
+    pub fn end_of_ban_list(&self, nick: &str, channel: &str) -> Response {
+        self.reply_text(
+            ReplyCode::RPL_ENDOFBANLIST,
+            &[nick, channel],
+            "End of channel ban list",
+        )
+    }
+
+    /// `728 <nick> <channel> q <mask>`
+    pub fn quiet_list(&self, nick: &str, channel: &str, mask: &str) -> Response {
+        self.reply(ReplyCode::RPL_QUIETLIST, &[nick, channel, "q", mask])
+    }
+
+    /// `729 <nick> <channel> q :End of channel quiet list`
+    pub fn end_of_quiet_list(&self, nick: &str, channel: &str) -> Response {
+        self.reply_text(
+            ReplyCode::RPL_ENDOFQUIETLIST,
+            &[nick, channel, "q"],
+            "End of channel quiet list",
+        )
+    }
+
+    // Monitoring
+
+    /// `730 <nick> :<target>,...`, with the targets' whole prefixes
+    pub fn mon_online(&self, nick: &str, targets: &[String]) -> Response {
+        self.reply(ReplyCode::RPL_MONONLINE, &[nick, &targets.join(",")])
+    }
+
+    /// `731 <nick> :<target>,...`
+    pub fn mon_offline(&self, nick: &str, targets: &[String]) -> Response {
+        self.reply(ReplyCode::RPL_MONOFFLINE, &[nick, &targets.join(",")])
+    }
+
+    /// `732 <nick> :<target>,...`
+    pub fn mon_list(&self, nick: &str, targets: &[String]) -> Response {
+        self.reply(ReplyCode::RPL_MONLIST, &[nick, &targets.join(",")])
+    }
+
+    /// `733 <nick> :End of MONITOR list`
+    pub fn end_of_mon_list(&self, nick: &str) -> Response {
+        self.reply_text(ReplyCode::RPL_ENDOFMONLIST, &[nick], "End of MONITOR list")
+    }
+
+    /// `734 <nick> <limit> <target>,... :Monitor list is full.`
+    pub fn mon_list_full(&self, nick: &str, limit: usize, targets: &[String]) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_MONLISTFULL,
+            &[nick, &limit.to_string(), &targets.join(",")],
+            "Monitor list is full.",
+        )
+    }
+
+    // Accounts and TLS
+
+    /// `670 <nick> :STARTTLS successful, proceed with TLS handshake`
+    pub fn starttls(&self, nick: &str) -> Response {
+        self.reply_text(
+            ReplyCode::RPL_STARTTLS,
+            &[nick],
+            "STARTTLS successful, proceed with TLS handshake",
+        )
+    }
+
+    /// `691 <nick> :STARTTLS failed (Wrong moon phase)`
+    pub fn starttls_failed(&self, nick: &str) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_STARTTLS,
+            &[nick],
+            "STARTTLS failed (Wrong moon phase)",
+        )
+    }
+
+    /// `900 <nick> <prefix> <account> :You are now logged in as <account>`
+    pub fn logged_in(&self, nick: &str, prefix: &Prefix, account: &str) -> Response {
+        self.reply_text_with(
+            ReplyCode::RPL_LOGGEDIN,
+            &[nick, &prefix.to_string(), account],
+            "You are now logged in as {account}",
+            &[("account", account)],
+        )
+    }
+
+    /// `901 <nick> <prefix> :You are now logged out`
+    pub fn logged_out(&self, nick: &str, prefix: &Prefix) -> Response {
+        self.reply_text(
+            ReplyCode::RPL_LOGGEDOUT,
+            &[nick, &prefix.to_string()],
+            "You are now logged out",
+        )
+    }
+
+    /// `902 <nick> :You must use a nick assigned to you`
+    pub fn nick_locked(&self, nick: &str) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_NICKLOCKED,
+            &[nick],
+            "You must use a nick assigned to you",
+        )
+    }
+
+    /// `903 <nick> :SASL authentication successful`
+    pub fn sasl_success(&self, nick: &str) -> Response {
+        self.reply_text(
+            ReplyCode::RPL_SASLSUCCESS,
+            &[nick],
+            "SASL authentication successful",
+        )
+    }
+
+    /// `904 <nick> :SASL authentication failed`
+    pub fn sasl_fail(&self, nick: &str) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_SASLFAIL,
+            &[nick],
+            "SASL authentication failed",
+        )
+    }
+
+    /// `905 <nick> :SASL message too long`
+    pub fn sasl_too_long(&self, nick: &str) -> Response {
+        self.reply_text(ReplyCode::ERR_SASLTOOLONG, &[nick], "SASL message too long")
+    }
+
+    /// `906 <nick> :SASL authentication aborted`
+    pub fn sasl_aborted(&self, nick: &str) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_SASLABORTED,
+            &[nick],
+            "SASL authentication aborted",
+        )
+    }
+
+    /// `907 <nick> :You have already authenticated using SASL`
+    pub fn sasl_already(&self, nick: &str) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_SASLALREADY,
+            &[nick],
+            "You have already authenticated using SASL",
+        )
+    }
+
+    /// `908 <nick> <mechanism>,... :are available SASL mechanisms`
+    pub fn sasl_mechs(&self, nick: &str, mechanisms: &[String]) -> Response {
+        self.reply_text(
+            ReplyCode::RPL_SASLMECHS,
+            &[nick, &mechanisms.join(",")],
+            "are available SASL mechanisms",
+        )
+    }
+
+    // Errors
+
+    /// `401 <nick> <target> :No such nick/channel`
+    pub fn no_such_nick(&self, nick: &str, target: &str) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_NOSUCHNICK,
+            &[nick, target],
+            "No such nick/channel",
+        )
+    }
+
+    /// `402 <nick> <server> :No such server`
+    pub fn no_such_server(&self, nick: &str, server: &str) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_NOSUCHSERVER,
+            &[nick, server],
+            "No such server",
+        )
+    }
+
+    /// `403 <nick> <channel> :No such channel`
+    pub fn no_such_channel(&self, nick: &str, channel: &str) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_NOSUCHCHANNEL,
+            &[nick, channel],
+            "No such channel",
+        )
+    }
+
+    /// `404 <nick> <channel> :<reason>`
+    pub fn cannot_send_to_chan(&self, nick: &str, channel: &str, reason: &str) -> Response {
+        self.reply(ReplyCode::ERR_CANNOTSENDTOCHAN, &[nick, channel, reason])
+    }
+
+    /// `405 <nick> <channel> :You have joined too many channels`
+    pub fn too_many_channels(&self, nick: &str, channel: &str) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_TOOMANYCHANNELS,
+            &[nick, channel],
+            "You have joined too many channels",
+        )
+    }
+
+    /// `406 <nick> <target> :There was no such nickname`
+    pub fn was_no_such_nick(&self, nick: &str, target: &str) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_WASNOSUCHNICK,
+            &[nick, target],
+            "There was no such nickname",
+        )
+    }
+
+    /// `407 <nick> <target> :<reason>`
+    pub fn too_many_targets(&self, nick: &str, target: &str, reason: &str) -> Response {
+        self.reply(ReplyCode::ERR_TOOMANYTARGETS, &[nick, target, reason])
+    }
+
+    /// `408 <nick> <service> :No such service`
+    pub fn no_such_service(&self, nick: &str, service: &str) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_NOSUCHSERVICE,
+            &[nick, service],
+            "No such service",
+        )
+    }
+
+    /// `409 <nick> :No origin specified`
+    pub fn no_origin(&self, nick: &str) -> Response {
+        self.reply_text(ReplyCode::ERR_NOORIGIN, &[nick], "No origin specified")
+    }
+
+    /// `410 <nick> <subcommand> :Invalid CAP command`
+    pub fn invalid_cap_cmd(&self, nick: &str, subcommand: &str) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_INVALIDCAPCMD,
+            &[nick, subcommand],
+            "Invalid CAP command",
+        )
+    }
+
+    /// `411 <nick> :No recipient given (<command>)`
+    pub fn no_recipient(&self, nick: &str, command: &str) -> Response {
+        self.reply_text_with(
+            ReplyCode::ERR_NORECIPIENT,
+            &[nick],
+            "No recipient given ({command})",
+            &[("command", command)],
+        )
+    }
+
+    /// `412 <nick> :No text to send`
+    pub fn no_text_to_send(&self, nick: &str) -> Response {
+        self.reply_text(ReplyCode::ERR_NOTEXTTOSEND, &[nick], "No text to send")
+    }
+
+    /// `413 <nick> <mask> :No toplevel domain specified`
+    pub fn no_top_level(&self, nick: &str, mask: &str) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_NOTOPLEVEL,
+            &[nick, mask],
+            "No toplevel domain specified",
+        )
+    }
+
+    /// `414 <nick> <mask> :Wildcard in toplevel domain`
+    pub fn wild_top_level(&self, nick: &str, mask: &str) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_WILDTOPLEVEL,
+            &[nick, mask],
+            "Wildcard in toplevel domain",
+        )
+    }
+
+    /// `415 <nick> <mask> :Bad Server/host mask`
+    pub fn bad_mask(&self, nick: &str, mask: &str) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_BADMASK,
+            &[nick, mask],
+            "Bad Server/host mask",
+        )
+    }
+
+    /// `417 <nick> :Input line was too long`
+    pub fn input_too_long(&self, nick: &str) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_INPUTTOOLONG,
+            &[nick],
+            "Input line was too long",
+        )
+    }
+
+    /// `421 <nick> <command> :Unknown command`
+    pub fn unknown_command(&self, nick: &str, command: &str) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_UNKNOWNCOMMAND,
+            &[nick, command],
+            "Unknown command",
+        )
+    }
+
+    /// `422 <nick> :MOTD File is missing`
+    pub fn no_motd(&self, nick: &str) -> Response {
+        self.reply_text(ReplyCode::ERR_NOMOTD, &[nick], "MOTD File is missing")
+    }
+
+    /// `423 <nick> <server> :No administrative info available`
+    pub fn no_admin_info(&self, nick: &str) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_NOADMININFO,
+            &[nick, self.server],
+            "No administrative info available",
+        )
+    }
+
+    /// `424 <nick> :File error doing <operation> on <file>`
+    pub fn file_error(&self, nick: &str, operation: &str, file: &str) -> Response {
+        self.reply_text_with(
+            ReplyCode::ERR_FILEERROR,
+            &[nick],
+            "File error doing {operation} on {file}",
+            &[("operation", operation), ("file", file)],
+        )
+    }
+
+    /// `431 <nick> :No nickname given`
+    pub fn no_nickname_given(&self, nick: &str) -> Response {
+        self.reply_text(ReplyCode::ERR_NONICKNAMEGIVEN, &[nick], "No nickname given")
+    }
+
+    /// `432 <nick> <new nick> :<reason>`
+    pub fn erroneous_nickname(&self, nick: &str, new_nick: &str, reason: &str) -> Response {
+        self.reply(ReplyCode::ERR_ERRONEUSNICKNAME, &[nick, new_nick, reason])
+    }
+
+    /// `433 <nick> <new nick> :Nickname is already in use`
+    pub fn nickname_in_use(&self, nick: &str, new_nick: &str) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_NICKNAMEINUSE,
+            &[nick, new_nick],
+            "Nickname is already in use",
+        )
+    }
+
+    /// `436 <nick> <new nick> :Nickname collision KILL from <user>@<host>`
+    pub fn nick_collision(&self, nick: &str, new_nick: &str, user: &str, host: &str) -> Response {
+        self.reply_text_with(
+            ReplyCode::ERR_NICKCOLLISION,
+            &[nick, new_nick],
+            "Nickname collision KILL from {user}@{host}",
+            &[("user", user), ("host", host)],
+        )
+    }
+
+    /// `437 <nick> <nick or channel> :<reason>`
+    pub fn unavail_resource(&self, nick: &str, name: &str, reason: &str) -> Response {
+        self.reply(ReplyCode::ERR_UNAVAILRESOURCE, &[nick, name, reason])
+    }
+
+    /// `441 <nick> <target> <channel> :They aren't on that channel`
+    pub fn user_not_in_channel(&self, nick: &str, target: &str, channel: &str) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_USERNOTINCHANNEL,
+            &[nick, target, channel],
+            "They aren't on that channel",
+        )
+    }
+
+    /// `442 <nick> <channel> :You're not on that channel`
+    pub fn not_on_channel(&self, nick: &str, channel: &str) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_NOTONCHANNEL,
+            &[nick, channel],
+            "You're not on that channel",
+        )
+    }
+
+    /// `443 <nick> <target> <channel> :is already on channel`
+    pub fn user_on_channel(&self, nick: &str, target: &str, channel: &str) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_USERONCHANNEL,
+            &[nick, target, channel],
+            "is already on channel",
+        )
+    }
+
+    /// `444 <nick> <user> :User not logged in`
+    pub fn no_login(&self, nick: &str, user: &str) -> Response {
+        self.reply_text(ReplyCode::ERR_NOLOGIN, &[nick, user], "User not logged in")
+    }
+
+    /// `445 <nick> :SUMMON has been disabled`
+    pub fn summon_disabled(&self, nick: &str) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_SUMMONDISABLED,
+            &[nick],
+            "SUMMON has been disabled",
+        )
+    }
+
+    /// `446 <nick> :USERS has been disabled`
+    pub fn users_disabled(&self, nick: &str) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_USERSDISABLED,
+            &[nick],
+            "USERS has been disabled",
+        )
+    }
+
+    /// `451 <nick> :You have not registered`
+    pub fn not_registered(&self, nick: &str) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_NOTREGISTERED,
+            &[nick],
+            "You have not registered",
+        )
+    }
+
+    /// `461 <nick> <command> :Not enough parameters`
+    pub fn need_more_params(&self, nick: &str, command: &str) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_NEEDMOREPARAMS,
+            &[nick, command],
+            "Not enough parameters",
+        )
+    }
+
+    /// `462 <nick> :Unauthorized command (already registered)`
+    pub fn already_registered(&self, nick: &str) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_ALREADYREGISTRED,
+            &[nick],
+            "Unauthorized command (already registered)",
+        )
+    }
+
+    /// `463 <nick> :Your host isn't among the privileged`
+    pub fn no_perm_for_host(&self, nick: &str) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_NOPERMFORHOST,
+            &[nick],
+            "Your host isn't among the privileged",
+        )
+    }
+
+    /// `464 <nick> :Password incorrect`
+    pub fn passwd_mismatch(&self, nick: &str) -> Response {
+        self.reply_text(ReplyCode::ERR_PASSWDMISMATCH, &[nick], "Password incorrect")
+    }
+
+    /// `465 <nick> :<reason>`
+    pub fn youre_banned_creep(&self, nick: &str, reason: &str) -> Response {
+        self.reply(ReplyCode::ERR_YOUREBANNEDCREEP, &[nick, reason])
+    }
+
+    /// `466 <nick> :You will be banned`
+    pub fn you_will_be_banned(&self, nick: &str) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_YOUWILLBEBANNED,
+            &[nick],
+            "You will be banned",
+        )
+    }
+
+    /// `467 <nick> <channel> :Channel key already set`
+    pub fn key_set(&self, nick: &str, channel: &str) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_KEYSET,
+            &[nick, channel],
+            "Channel key already set",
+        )
+    }
+
+    /// `471 <nick> <channel> :Cannot join channel (+l)`
+    pub fn channel_is_full(&self, nick: &str, channel: &str) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_CHANNELISFULL,
+            &[nick, channel],
+            "Cannot join channel (+l)",
+        )
+    }
+
+    /// `472 <nick> <mode> :is unknown mode char to me for <channel>`
+    pub fn unknown_mode(&self, nick: &str, mode: char, channel: &str) -> Response {
+        self.reply_text_with(
+            ReplyCode::ERR_UNKNOWNMODE,
+            &[nick, &mode.to_string()],
+            "is unknown mode char to me for {channel}",
+            &[("channel", channel)],
+        )
+    }
+
+    /// `473 <nick> <channel> :Cannot join channel (+i)`
+    pub fn invite_only_chan(&self, nick: &str, channel: &str) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_INVITEONLYCHAN,
+            &[nick, channel],
+            "Cannot join channel (+i)",
+        )
+    }
+
+    /// `474 <nick> <channel> :Cannot join channel (+b)`
+    pub fn banned_from_chan(&self, nick: &str, channel: &str) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_BANNEDFROMCHAN,
+            &[nick, channel],
+            "Cannot join channel (+b)",
+        )
+    }
+
+    /// `475 <nick> <channel> :Cannot join channel (+k)`
+    pub fn bad_channel_key(&self, nick: &str, channel: &str) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_BADCHANNELKEY,
+            &[nick, channel],
+            "Cannot join channel (+k)",
+        )
+    }
+
+    /// `476 <nick> <channel> :Bad Channel Mask`
+    pub fn bad_chan_mask(&self, nick: &str, channel: &str) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_BADCHANMASK,
+            &[nick, channel],
+            "Bad Channel Mask",
+        )
+    }
+
+    /// `477 <nick> <channel> :Channel doesn't support modes`
+    pub fn no_chan_modes(&self, nick: &str, channel: &str) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_NOCHANMODES,
+            &[nick, channel],
+            "Channel doesn't support modes",
+        )
+    }
+
+    /// `477 <nick> <channel> :Cannot join channel (+r) - you need to be logged into your account`
+    pub fn need_regged_nick(&self, nick: &str, channel: &str) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_NEEDREGGEDNICK,
+            &[nick, channel],
+            "Cannot join channel (+r) - you need to be logged into your account",
+        )
+    }
+
+    /// `478 <nick> <channel> <mode> :Channel list is full`
+    pub fn ban_list_full(&self, nick: &str, channel: &str, mode: char) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_BANLISTFULL,
+            &[nick, channel, &mode.to_string()],
+            "Channel list is full",
+        )
+    }
+
+    /// `481 <nick> :Permission Denied- You're not an IRC operator`
+    pub fn no_privileges(&self, nick: &str) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_NOPRIVILEGES,
+            &[nick],
+            "Permission Denied- You're not an IRC operator",
+        )
+    }
+
+    /// `482 <nick> <channel> :You're not channel operator`
+    pub fn chanop_privs_needed(&self, nick: &str, channel: &str) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_CHANOPRIVSNEEDED,
+            &[nick, channel],
+            "You're not channel operator",
+        )
+    }
+
+    /// `483 <nick> :You can't kill a server!`
+    pub fn cant_kill_server(&self, nick: &str) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_CANTKILLSERVER,
+            &[nick],
+            "You can't kill a server!",
+        )
+    }
+
+    /// `484 <nick> :Your connection is restricted!`
+    pub fn restricted(&self, nick: &str) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_RESTRICTED,
+            &[nick],
+            "Your connection is restricted!",
+        )
+    }
+
+    /// `485 <nick> :You're not the original channel operator`
+    pub fn uniq_op_privs_needed(&self, nick: &str) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_UNIQOPPRIVSNEEDED,
+            &[nick],
+            "You're not the original channel operator",
+        )
+    }
+
+    /// `491 <nick> :No O-lines for your host`
+    pub fn no_oper_host(&self, nick: &str) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_NOOPERHOST,
+            &[nick],
+            "No O-lines for your host",
+        )
+    }
+
+    /// `501 <nick> :Unknown MODE flag`
+    pub fn umode_unknown_flag(&self, nick: &str) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_UMODEUNKNOWNFLAG,
+            &[nick],
+            "Unknown MODE flag",
+        )
+    }
+
+    /// `502 <nick> :Cannot change mode for other users`
+    pub fn users_dont_match(&self, nick: &str) -> Response {
+        self.reply_text(
+            ReplyCode::ERR_USERSDONTMATCH,
+            &[nick],
+            "Cannot change mode for other users",
+        )
+    }
+}
+
+/// Put values in a text in place of their names in braces, e.g. `{date}`. Names without a value
+/// are left as they are.
+///
+/// ```
+/// use shared::replies::fill;
+///
+/// let text = fill("Current local users {current}, max {max}", &[("current", "3"), ("max", "5")]);
+/// assert_eq!(text, "Current local users 3, max 5");
+/// ```
+pub fn fill(text: &str, values: &[(&str, &str)]) -> String {
+    let mut filled = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('{') {
+        filled.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let value = rest.find('}').and_then(|end| {
+            let name = &rest[1..end];
+            let (_, value) = values.iter().find(|(key, _)| *key == name)?;
+            Some((value, end))
+        });
+        match value {
+            Some((value, end)) => {
+                filled.push_str(value);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                filled.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    filled.push_str(rest);
+    filled
+}
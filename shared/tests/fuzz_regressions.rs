@@ -0,0 +1,25 @@
+//! Lines found by the `parse_message` fuzz target, checked the same way it does.
+
+use shared::message::{Message, ParseError};
+
+/// Parse a line, and check that what it's written as parses the same
+fn round_trip(line: &str) -> Result<Message, ParseError> {
+    let message = Message::from(line)?;
+    let written = message.to_string();
+    assert_eq!(
+        message.validate(),
+        Ok(()),
+        "{line:?} was written as {written:?}"
+    );
+    assert_eq!(Message::from(&written).as_ref(), Ok(&message), "{line:?}");
+    Ok(message)
+}
+
+#[test]
+fn command_that_looks_like_tags() {
+    // Written without the empty tags, this was read back as tags without a command
+    assert_eq!(
+        round_trip("@ @D0"),
+        Err(ParseError::BadCommand("@D0".to_string()))
+    );
+}
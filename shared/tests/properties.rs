@@ -0,0 +1,167 @@
+//! Properties of parsing and writing messages, and of the casemapping and validation helpers,
+//! checked against generated input.
+
+use proptest::{collection::vec, option, prelude::*};
+use shared::{
+    casemap::{IrcCasemap, IrcString},
+    message::{Command, MAX_PARAMS, Message, ParseError},
+    prefix::Prefix,
+};
+use std::{
+    cmp::Ordering,
+    hash::{BuildHasher, RandomState},
+};
+
+fn casemap() -> impl Strategy<Value = IrcCasemap> {
+    prop_oneof![
+        Just(IrcCasemap::Ascii),
+        Just(IrcCasemap::Rfc1459),
+        Just(IrcCasemap::StrictRfc1459),
+    ]
+}
+
+/// Names made mostly of the characters casemappings care about
+fn name() -> impl Strategy<Value = String> {
+    "[a-zA-Z\\[\\]\\\\~{}|^é0-9]{0,12}"
+}
+
+fn prefix() -> impl Strategy<Value = Prefix> {
+    prop_oneof![
+        "[a-z]{1,8}\\.[a-z]{2,5}".prop_map(|name| Prefix::Server(name.into())),
+        (
+            "[a-zA-Z\\[\\]{}|^_][a-zA-Z0-9\\[\\]{}|^_-]{0,8}",
+            option::of("~?[a-z]{1,8}"),
+            option::of("[a-z0-9.:-]{1,16}"),
+        )
+            .prop_map(
+                |(nick, user, host): (String, Option<String>, Option<String>)| {
+                    Prefix::User {
+                        nick: nick.into(),
+                        user: user.map(Into::into),
+                        host: host.map(Into::into),
+                    }
+                }
+            ),
+    ]
+}
+
+/// Known commands, numerics and unknown ones, the way they're parsed
+fn command() -> impl Strategy<Value = Command> {
+    prop_oneof![
+        prop::sample::select(vec![
+            "PRIVMSG", "NOTICE", "JOIN", "PART", "MODE", "TOPIC", "PING", "CAP", "TAGMSG",
+        ])
+        .prop_map(Command::from),
+        "[A-Za-z]{1,12}|[0-9]{3}".prop_map(Command::from),
+    ]
+}
+
+/// Parameters that can be written as they are, with a last one that may need a colon
+fn params() -> impl Strategy<Value = Vec<String>> {
+    (
+        vec("[^\0\r\n :][^\0\r\n ]{0,10}", 0..MAX_PARAMS),
+        option::of("[^\0\r\n]{0,30}"),
+    )
+        .prop_map(|(mut params, last)| {
+            params.extend(last);
+            params
+        })
+}
+
+/// Tags with any value but NUL, which are escaped when written
+fn tags() -> impl Strategy<Value = Vec<(String, String)>> {
+    vec(("\\+?[a-z][a-z0-9-]{0,10}", "[^\0]{0,20}"), 0..4)
+}
+
+fn message() -> impl Strategy<Value = Message> {
+    (tags(), option::of(prefix()), command(), params()).prop_map(
+        |(tags, prefix, command, params)| Message {
+            tags,
+            prefix,
+            command,
+            params,
+        },
+    )
+}
+
+proptest! {
+    #[test]
+    fn written_messages_parse_the_same(message in message()) {
+        prop_assert_eq!(message.validate(), Ok(()));
+        let line = message.to_string();
+        let parsed = Message::from(&line).unwrap();
+        prop_assert_eq!(&parsed, &message);
+        // Writing it again gives the same line
+        prop_assert_eq!(parsed.to_string(), line);
+    }
+
+    #[test]
+    fn valid_messages_parse_the_same(
+        command in command(),
+        params in vec("[^\0]{0,8}", 0..20),
+    ) {
+        // Any parameters at all, which only have to be read back right if they're valid
+        let message = Message { tags: vec![], prefix: None, command, params };
+        if message.validate().is_ok() {
+            prop_assert_eq!(Message::from(&message.to_string()), Ok(message));
+        }
+    }
+
+    #[test]
+    fn too_many_params_are_invalid(extra in vec("[a-z]{1,5}", 1..5), message in message()) {
+        let mut message = message;
+        message.params = vec!["x".to_string(); MAX_PARAMS];
+        message.params.extend(extra);
+        prop_assert_eq!(message.validate(), Err(ParseError::TooManyParams));
+        prop_assert_eq!(Message::from(&message.to_string()), Err(ParseError::TooManyParams));
+    }
+
+    #[test]
+    fn middle_params_with_spaces_are_invalid(
+        before in "[a-z]{1,5}",
+        after in "[a-z]{0,5}",
+        index in 0..MAX_PARAMS - 1,
+    ) {
+        let mut params = vec!["x".to_string(); index + 2];
+        params[index] = format!("{before} {after}");
+        let message = Message { tags: vec![], prefix: None, command: Command::PrivMsg, params };
+        prop_assert_eq!(message.validate(), Err(ParseError::BadParam { index }));
+    }
+
+    #[test]
+    fn line_breaks_are_invalid(
+        text in "[a-z ]{0,10}",
+        line_break in "[\0\r\n]",
+        rest in "[a-z ]{0,10}",
+    ) {
+        let message = Message::privmsg("#rust", &format!("{text}{line_break}{rest}"));
+        let is_illegal = matches!(message.validate(), Err(ParseError::IllegalCharacter { .. }));
+        prop_assert!(is_illegal);
+    }
+
+    #[test]
+    fn lowercase_is_idempotent(casemap in casemap(), name in name()) {
+        let lowercase = casemap.to_lowercase(&name);
+        prop_assert_eq!(casemap.to_lowercase(&lowercase), lowercase.clone());
+        prop_assert!(casemap.eq_ignore_case(&name, &lowercase));
+    }
+
+    #[test]
+    fn names_are_equal_when_their_lowercase_is(casemap in casemap(), a in name(), b in name()) {
+        let same = casemap.to_lowercase(&a) == casemap.to_lowercase(&b);
+        prop_assert_eq!(casemap.eq_ignore_case(&a, &b), same);
+        prop_assert_eq!(casemap.cmp_ignore_case(&a, &b) == Ordering::Equal, same);
+        prop_assert_eq!(casemap.cmp_ignore_case(&a, &b), casemap.cmp_ignore_case(&b, &a).reverse());
+    }
+
+    #[test]
+    fn equal_irc_strings_hash_the_same(casemap in casemap(), name in name()) {
+        let a = IrcString::new(name.as_str(), casemap);
+        let b = IrcString::new(casemap.to_lowercase(&name), casemap);
+        let hasher = RandomState::new();
+        prop_assert_eq!(&a, &b);
+        prop_assert_eq!(hasher.hash_one(&a), hasher.hash_one(&b));
+        // The case it was written in is kept
+        prop_assert_eq!(a.as_str(), name.as_str());
+    }
+}